@@ -1,14 +1,189 @@
 use std::{error::Error, fmt};
 
+use crate::assembler::expressions::ExprError;
+
 #[derive(Debug, Clone)]
 pub enum AssemblerError {
-    NoSegmentDeclarationFound { instruction: u32 },
-    StringConstantDeclaredWithoutLabel { instruction: u32 },
+    NoSegmentDeclarationFound {
+        instruction: u32,
+    },
+    StringConstantDeclaredWithoutLabel {
+        instruction: u32,
+    },
     SymbolAlreadyDeclared,
-    UnknownDirectiveFound { directive: String },
+    UnknownDirectiveFound {
+        directive: String,
+    },
     NonOpcodeInOpcodeField,
     InsufficientSections,
-    ParseError { error: String },
+    ParseError {
+        error: String,
+    },
+    StringLiteralError {
+        line: u32,
+        column: u32,
+        message: String,
+    },
+    InvalidDirectiveOperand {
+        directive: String,
+        line: u32,
+    },
+    DataValueOutOfRange {
+        directive: String,
+        value: i64,
+        line: u32,
+    },
+    UndefinedSymbol {
+        name: String,
+        line: u32,
+    },
+    OperandOutOfRange {
+        value: i32,
+        line: u32,
+    },
+    /// A value outside -32768..=32767 for an instruction that sign-extends
+    /// it.
+    SignedOperandOutOfRange {
+        value: i32,
+        line: u32,
+    },
+    ExpressionOverflow {
+        line: u32,
+    },
+    DivisionByZero {
+        line: u32,
+    },
+    UnterminatedMacro {
+        name: String,
+        line: u32,
+    },
+    UnmatchedEndm {
+        line: u32,
+    },
+    NestedMacroDefinition {
+        line: u32,
+    },
+    MacroAlreadyDefined {
+        name: String,
+        line: u32,
+    },
+    MacroArguments {
+        name: String,
+        expected: usize,
+        found: usize,
+        line: u32,
+    },
+    UndefinedMacroParameter {
+        name: String,
+        line: u32,
+    },
+    MacroRecursion {
+        name: String,
+        line: u32,
+    },
+    /// `.elif`, `.else` or `.endif` outside a conditional block.
+    UnmatchedConditional {
+        directive: String,
+        line: u32,
+    },
+    ConditionalAfterElse {
+        directive: String,
+        line: u32,
+    },
+    UnterminatedConditional {
+        line: u32,
+    },
+    /// Raised by an `.error "message"` directive that was assembled.
+    UserError {
+        message: String,
+        line: u32,
+    },
+    /// A `.name` label before any label it could belong to.
+    LocalLabelWithoutScope {
+        name: String,
+        line: u32,
+    },
+    /// A pseudo-instruction with operands it does not take.
+    PseudoOperands {
+        name: String,
+        usage: String,
+        line: u32,
+    },
+    /// A pseudo-instruction given the register its expansion overwrites.
+    ScratchRegister {
+        name: String,
+        line: u32,
+    },
+    /// A value an object file cannot record a relocation for.
+    NotRelocatable {
+        line: u32,
+    },
+    IncludeFailed {
+        path: String,
+        reason: String,
+        line: u32,
+    },
+    /// Files including each other, listed from the first to the repeat.
+    IncludeCycle {
+        files: Vec<String>,
+        line: u32,
+    },
+    /// An error in the body of a macro, with the call it was expanded from.
+    InMacro {
+        error: Box<AssemblerError>,
+        name: String,
+        line: u32,
+        /// The included file of the call.
+        file: Option<String>,
+    },
+    /// An error in an included file.
+    InFile {
+        error: Box<AssemblerError>,
+        file: String,
+    },
+}
+
+impl AssemblerError {
+    pub fn from_expr_error(error: ExprError, line: u32) -> AssemblerError {
+        match error {
+            ExprError::Undefined(name) => AssemblerError::UndefinedSymbol { name, line },
+            ExprError::Overflow => AssemblerError::ExpressionOverflow { line },
+            ExprError::DivisionByZero => AssemblerError::DivisionByZero { line },
+        }
+    }
+
+    /// The 1-based source line the error was found on, if it is known.
+    pub fn line(&self) -> Option<u32> {
+        match *self {
+            AssemblerError::StringLiteralError { line, .. }
+            | AssemblerError::InvalidDirectiveOperand { line, .. }
+            | AssemblerError::DataValueOutOfRange { line, .. }
+            | AssemblerError::UndefinedSymbol { line, .. }
+            | AssemblerError::OperandOutOfRange { line, .. }
+            | AssemblerError::SignedOperandOutOfRange { line, .. }
+            | AssemblerError::ExpressionOverflow { line }
+            | AssemblerError::DivisionByZero { line }
+            | AssemblerError::UnterminatedMacro { line, .. }
+            | AssemblerError::UnmatchedEndm { line }
+            | AssemblerError::NestedMacroDefinition { line }
+            | AssemblerError::MacroAlreadyDefined { line, .. }
+            | AssemblerError::MacroArguments { line, .. }
+            | AssemblerError::UndefinedMacroParameter { line, .. }
+            | AssemblerError::MacroRecursion { line, .. }
+            | AssemblerError::UnmatchedConditional { line, .. }
+            | AssemblerError::ConditionalAfterElse { line, .. }
+            | AssemblerError::UnterminatedConditional { line }
+            | AssemblerError::UserError { line, .. }
+            | AssemblerError::LocalLabelWithoutScope { line, .. }
+            | AssemblerError::PseudoOperands { line, .. }
+            | AssemblerError::ScratchRegister { line, .. }
+            | AssemblerError::NotRelocatable { line }
+            | AssemblerError::IncludeFailed { line, .. }
+            | AssemblerError::IncludeCycle { line, .. }
+            | AssemblerError::InMacro { line, .. } => Some(line),
+            _ => None,
+        }
+    }
 }
 
 impl fmt::Display for AssemblerError {
@@ -35,6 +210,92 @@ impl fmt::Display for AssemblerError {
             AssemblerError::ParseError { ref error  } => {
                 f.write_str(&format!("There was an error parsing the code: {}", error))
             }
+            AssemblerError::StringLiteralError { line, column, ref message } => {
+                f.write_str(&format!("{} at line {}, column {}", message, line, column))
+            }
+            AssemblerError::InvalidDirectiveOperand { ref directive, line } => {
+                f.write_str(&format!("Invalid operands for directive .{} on line {}", directive, line))
+            }
+            AssemblerError::DataValueOutOfRange { ref directive, value, line } => {
+                f.write_str(&format!("Value {} does not fit in a .{} on line {}", value, directive, line))
+            }
+            AssemblerError::UndefinedSymbol { ref name, line } => {
+                f.write_str(&format!("Undefined symbol {} on line {}", name, line))
+            }
+            AssemblerError::OperandOutOfRange { value, line } => {
+                f.write_str(&format!("Operand {} does not fit in 16 bits on line {}", value, line))
+            }
+            AssemblerError::SignedOperandOutOfRange { value, line } => {
+                f.write_str(&format!("Operand {} does not fit in 16 bits signed on line {}", value, line))
+            }
+            AssemblerError::ExpressionOverflow { line } => {
+                f.write_str(&format!("Arithmetic overflow in expression on line {}", line))
+            }
+            AssemblerError::DivisionByZero { line } => {
+                f.write_str(&format!("Division by zero in expression on line {}", line))
+            }
+            AssemblerError::UnterminatedMacro { ref name, line } => {
+                f.write_str(&format!("Macro {} defined on line {} has no .endm", name, line))
+            }
+            AssemblerError::UnmatchedEndm { line } => {
+                f.write_str(&format!(".endm without a .macro on line {}", line))
+            }
+            AssemblerError::NestedMacroDefinition { line } => {
+                f.write_str(&format!("Macro definitions cannot be nested, found .macro on line {}", line))
+            }
+            AssemblerError::MacroAlreadyDefined { ref name, line } => {
+                f.write_str(&format!("Macro {} on line {} is already defined or is an instruction", name, line))
+            }
+            AssemblerError::MacroArguments { ref name, expected, found, line } => {
+                f.write_str(&format!("Macro {} takes {} argument(s), {} given on line {}", name, expected, found, line))
+            }
+            AssemblerError::UndefinedMacroParameter { ref name, line } => {
+                f.write_str(&format!("Unknown macro parameter \\{} on line {}", name, line))
+            }
+            AssemblerError::MacroRecursion { ref name, line } => {
+                f.write_str(&format!("Macro calls nested more than 64 deep expanding {} on line {}", name, line))
+            }
+            AssemblerError::UnmatchedConditional { ref directive, line } => {
+                f.write_str(&format!(".{} without .if on line {}", directive, line))
+            }
+            AssemblerError::ConditionalAfterElse { ref directive, line } => {
+                f.write_str(&format!(".{} after .else on line {}", directive, line))
+            }
+            AssemblerError::UnterminatedConditional { line } => {
+                f.write_str(&format!(".if on line {} is never closed with .endif", line))
+            }
+            AssemblerError::UserError { ref message, line } => {
+                f.write_str(&format!("{} on line {}", message, line))
+            }
+            AssemblerError::LocalLabelWithoutScope { ref name, line } => f.write_str(&format!(
+                "Local label {} on line {} has no label before it",
+                name, line
+            )),
+            AssemblerError::PseudoOperands { ref name, ref usage, line } => {
+                f.write_str(&format!("{} on line {} is written {}", name, line, usage))
+            }
+            AssemblerError::ScratchRegister { ref name, line } => {
+                f.write_str(&format!("{} on line {} cannot use $31, which its expansion overwrites", name, line))
+            }
+            AssemblerError::NotRelocatable { line } => {
+                f.write_str(&format!("The value on line {} cannot be relocated, use a label plus or minus a constant", line))
+            }
+            AssemblerError::IncludeFailed { ref path, ref reason, line } => {
+                f.write_str(&format!("Cannot include {} on line {}: {}", path, line, reason))
+            }
+            AssemblerError::IncludeCycle { ref files, line } => {
+                f.write_str(&format!("Include cycle {} on line {}", files.join(" -> "), line))
+            }
+            AssemblerError::InMacro { ref error, ref name, line, ref file } => {
+                f.write_str(&format!("{}, in macro {} called on line {}", error, name, line))?;
+                match file {
+                    Some(file) => f.write_str(&format!(" of {}", file)),
+                    None => Ok(()),
+                }
+            }
+            AssemblerError::InFile { ref error, ref file } => {
+                f.write_str(&format!("{}: {}", file, error))
+            }
         }
     }
 }
@@ -63,6 +324,87 @@ impl Error for AssemblerError {
             AssemblerError::ParseError{ .. } => {
                 "There was an error parsing the code"
             }
+            AssemblerError::StringLiteralError{ .. } => {
+                "A string literal is malformed"
+            }
+            AssemblerError::InvalidDirectiveOperand{ .. } => {
+                "A directive was given operands it does not accept"
+            }
+            AssemblerError::DataValueOutOfRange{ .. } => {
+                "A data value does not fit in its directive's width"
+            }
+            AssemblerError::UndefinedSymbol{ .. } => {
+                "A symbol was used but never defined"
+            }
+            AssemblerError::OperandOutOfRange{ .. } => {
+                "An immediate operand does not fit in 16 bits"
+            }
+            AssemblerError::SignedOperandOutOfRange{ .. } => {
+                "A sign-extended immediate operand does not fit in 16 bits"
+            }
+            AssemblerError::ExpressionOverflow{ .. } => {
+                "An expression overflowed 32-bit arithmetic"
+            }
+            AssemblerError::DivisionByZero{ .. } => {
+                "An expression divided by zero"
+            }
+            AssemblerError::UnterminatedMacro{ .. } => {
+                "A macro definition has no .endm"
+            }
+            AssemblerError::UnmatchedEndm{ .. } => {
+                "An .endm has no .macro"
+            }
+            AssemblerError::NestedMacroDefinition{ .. } => {
+                "A macro was defined inside another"
+            }
+            AssemblerError::MacroAlreadyDefined{ .. } => {
+                "A macro name is already taken"
+            }
+            AssemblerError::MacroArguments{ .. } => {
+                "A macro was called with the wrong number of arguments"
+            }
+            AssemblerError::UndefinedMacroParameter{ .. } => {
+                "A macro body uses a parameter it does not have"
+            }
+            AssemblerError::MacroRecursion{ .. } => {
+                "Macro calls are nested too deeply"
+            }
+            AssemblerError::UnmatchedConditional{ .. } => {
+                "An .elif, .else or .endif has no .if"
+            }
+            AssemblerError::ConditionalAfterElse{ .. } => {
+                "A conditional block continues after its .else"
+            }
+            AssemblerError::UnterminatedConditional{ .. } => {
+                "A conditional block has no .endif"
+            }
+            AssemblerError::UserError{ .. } => {
+                "The program raised an error with .error"
+            }
+            AssemblerError::LocalLabelWithoutScope{ .. } => {
+                "A local label must follow a label it belongs to"
+            }
+            AssemblerError::PseudoOperands{ .. } => {
+                "A pseudo-instruction was given operands it does not take"
+            }
+            AssemblerError::ScratchRegister{ .. } => {
+                "A pseudo-instruction was given the scratch register it overwrites"
+            }
+            AssemblerError::NotRelocatable{ .. } => {
+                "A value in an object file depends on labels in a way linking cannot fix"
+            }
+            AssemblerError::IncludeFailed{ .. } => {
+                "An included file could not be read"
+            }
+            AssemblerError::IncludeCycle{ .. } => {
+                "Files include each other without an include guard"
+            }
+            AssemblerError::InMacro{ .. } => {
+                "There was an error in a macro expansion"
+            }
+            AssemblerError::InFile{ .. } => {
+                "There was an error in an included file"
+            }
         }
     }
 }
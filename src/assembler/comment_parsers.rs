@@ -0,0 +1,129 @@
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_until},
+    character::complete::{char, multispace0, not_line_ending, space0},
+    combinator::map,
+    multi::many0,
+    sequence::{delimited, preceded, terminated},
+    IResult,
+};
+
+use crate::assembler::{instruction_parsers::AssemblerInstruction, Token};
+
+/// `; text` up to the end of the line.
+pub fn line_comment(input: &str) -> IResult<&str, Token> {
+    map(preceded(char(';'), not_line_ending), |text: &str| {
+        Token::Comment {
+            text: text.trim().to_string(),
+            block: false,
+        }
+    })(input)
+}
+
+/// `/* text */`, which may span several lines.
+pub fn block_comment(input: &str) -> IResult<&str, Token> {
+    map(
+        delimited(tag("/*"), take_until("*/"), tag("*/")),
+        |text: &str| Token::Comment {
+            text: text.trim().to_string(),
+            block: true,
+        },
+    )(input)
+}
+
+pub fn comment(input: &str) -> IResult<&str, Token> {
+    alt((line_comment, block_comment))(input)
+}
+
+/// Spaces and block comments between the tokens of one line.
+pub fn inline_space(input: &str) -> IResult<&str, Vec<Token>> {
+    preceded(space0, many0(terminated(block_comment, space0)))(input)
+}
+
+/// Whitespace, newlines and comments of any kind, e.g. between a label on
+/// its own line and the instruction it names.
+pub fn any_space(input: &str) -> IResult<&str, Vec<Token>> {
+    preceded(multispace0, many0(terminated(comment, multispace0)))(input)
+}
+
+/// Block comments and an optional `;` comment ending an instruction.
+pub fn trailing_comments(input: &str) -> IResult<&str, Vec<Token>> {
+    let (input, mut comments) = inline_space(input)?;
+    let (input, line) = many0(line_comment)(input)?;
+    comments.extend(line);
+    Ok((input, comments))
+}
+
+/// A line holding nothing but a comment.
+pub fn comment_line(input: &str) -> IResult<&str, AssemblerInstruction> {
+    let (input, _) = multispace0(input)?;
+    let (input, first) = comment(input)?;
+    let (input, mut comments) = trailing_comments(input)?;
+    comments.insert(0, first);
+    Ok((
+        input,
+        AssemblerInstruction {
+            opcode: None,
+            label: None,
+            directive: None,
+            operand1: None,
+            operand2: None,
+            operand3: None,
+            comments,
+            line: 0,
+            expanded_from: vec![],
+            file: None,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(text: &str) -> Token {
+        Token::Comment {
+            text: text.to_string(),
+            block: false,
+        }
+    }
+
+    fn block(text: &str) -> Token {
+        Token::Comment {
+            text: text.to_string(),
+            block: true,
+        }
+    }
+
+    #[test]
+    fn test_line_comment() {
+        assert_eq!(line_comment("; hello\nhlt"), Ok(("\nhlt", line("hello"))));
+        assert!(line_comment("hlt").is_err());
+    }
+
+    #[test]
+    fn test_block_comment() {
+        assert_eq!(
+            block_comment("/* one\n   two */hlt"),
+            Ok(("hlt", block("one\n   two")))
+        );
+        assert!(block_comment("/* unterminated").is_err());
+    }
+
+    #[test]
+    fn test_trailing_comments_stop_at_the_line_end() {
+        assert_eq!(
+            trailing_comments(" /* a */ ; b\n; c"),
+            Ok(("\n; c", vec![block("a"), line("b")]))
+        );
+        assert_eq!(trailing_comments("\n; c"), Ok(("\n; c", vec![])));
+    }
+
+    #[test]
+    fn test_comment_line() {
+        let (rest, instruction) = comment_line("  ; only a comment\nhlt").unwrap();
+        assert_eq!(rest, "\nhlt");
+        assert_eq!(instruction.comments, vec![line("only a comment")]);
+        assert!(!instruction.is_opcode() && !instruction.is_directive());
+    }
+}
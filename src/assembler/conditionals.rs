@@ -0,0 +1,169 @@
+use crate::assembler::assembler_errors::AssemblerError;
+
+/// One `.if` block being assembled.
+#[derive(Debug)]
+struct Block {
+    /// Line of the `.if`, `.ifdef` or `.ifndef`.
+    line: u32,
+    /// Whether the enclosing code is assembled at all.
+    enclosing: bool,
+    /// Whether one of the branches so far was taken.
+    taken: bool,
+    /// Whether the current branch is assembled.
+    active: bool,
+    seen_else: bool,
+}
+
+/// Tracks nested `.if/.elif/.else/.endif` blocks during the first phase.
+#[derive(Debug, Default)]
+pub struct Conditionals {
+    blocks: Vec<Block>,
+}
+
+impl Conditionals {
+    pub fn new() -> Conditionals {
+        Conditionals { blocks: vec![] }
+    }
+
+    /// Whether instructions at this point are assembled.
+    pub fn is_active(&self) -> bool {
+        self.blocks.last().is_none_or(|block| block.active)
+    }
+
+    /// Whether the condition of the next `.elif` decides anything. It is
+    /// not evaluated otherwise, so it may use names that are not defined.
+    pub fn needs_condition(&self) -> bool {
+        self.blocks
+            .last()
+            .is_some_and(|block| block.enclosing && !block.taken)
+    }
+
+    /// Starts a block; `condition` is ignored inside a skipped one.
+    pub fn open(&mut self, condition: bool, line: u32) {
+        let enclosing = self.is_active();
+        self.blocks.push(Block {
+            line,
+            enclosing,
+            taken: enclosing && condition,
+            active: enclosing && condition,
+            seen_else: false,
+        });
+    }
+
+    pub fn elif(&mut self, condition: bool, line: u32) -> Result<(), AssemblerError> {
+        let block = self.current("elif", line)?;
+        block.active = block.enclosing && !block.taken && condition;
+        block.taken |= block.active;
+        Ok(())
+    }
+
+    pub fn otherwise(&mut self, line: u32) -> Result<(), AssemblerError> {
+        let block = self.current("else", line)?;
+        block.seen_else = true;
+        block.active = block.enclosing && !block.taken;
+        block.taken = true;
+        Ok(())
+    }
+
+    pub fn close(&mut self, line: u32) -> Result<(), AssemblerError> {
+        match self.blocks.pop() {
+            Some(_) => Ok(()),
+            None => Err(AssemblerError::UnmatchedConditional {
+                directive: "endif".to_string(),
+                line,
+            }),
+        }
+    }
+
+    /// Errors for the blocks left open at the end of the program.
+    pub fn finish(&mut self) -> Vec<AssemblerError> {
+        self.blocks
+            .drain(..)
+            .map(|block| AssemblerError::UnterminatedConditional { line: block.line })
+            .collect()
+    }
+
+    /// The innermost block, which `.elif` or `.else` continues.
+    fn current(&mut self, directive: &str, line: u32) -> Result<&mut Block, AssemblerError> {
+        match self.blocks.last_mut() {
+            Some(block) if block.seen_else => Err(AssemblerError::ConditionalAfterElse {
+                directive: directive.to_string(),
+                line,
+            }),
+            Some(block) => Ok(block),
+            None => Err(AssemblerError::UnmatchedConditional {
+                directive: directive.to_string(),
+                line,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_branches() {
+        let mut conditionals = Conditionals::new();
+        assert!(conditionals.is_active());
+        conditionals.open(false, 1);
+        assert!(!conditionals.is_active());
+        assert!(conditionals.needs_condition());
+        conditionals.elif(true, 2).unwrap();
+        assert!(conditionals.is_active());
+        assert!(!conditionals.needs_condition());
+        conditionals.elif(true, 3).unwrap();
+        assert!(!conditionals.is_active());
+        conditionals.otherwise(4).unwrap();
+        assert!(!conditionals.is_active());
+        conditionals.close(5).unwrap();
+        assert!(conditionals.is_active());
+    }
+
+    #[test]
+    fn test_nested_blocks_in_skipped_code() {
+        let mut conditionals = Conditionals::new();
+        conditionals.open(false, 1);
+        conditionals.open(true, 2);
+        assert!(!conditionals.is_active());
+        assert!(!conditionals.needs_condition());
+        conditionals.otherwise(3).unwrap();
+        assert!(!conditionals.is_active());
+        conditionals.close(4).unwrap();
+        conditionals.otherwise(5).unwrap();
+        assert!(conditionals.is_active());
+        conditionals.close(6).unwrap();
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut conditionals = Conditionals::new();
+        assert_eq!(
+            conditionals.otherwise(1).unwrap_err().to_string(),
+            ".else without .if on line 1"
+        );
+        conditionals.open(true, 2);
+        conditionals.otherwise(3).unwrap();
+        assert_eq!(
+            conditionals.elif(true, 4).unwrap_err().to_string(),
+            ".elif after .else on line 4"
+        );
+        conditionals.open(true, 5);
+        assert_eq!(
+            conditionals
+                .finish()
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                ".if on line 2 is never closed with .endif",
+                ".if on line 5 is never closed with .endif",
+            ]
+        );
+        assert_eq!(
+            conditionals.close(6).unwrap_err().to_string(),
+            ".endif without .if on line 6"
+        );
+    }
+}
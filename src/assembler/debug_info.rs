@@ -0,0 +1,156 @@
+use serde::{Deserialize, Serialize};
+
+use crate::assembler::source_map::SourceMap;
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum DebugSection {
+    Code,
+    Data,
+}
+
+/// A symbol and the address range it covers. Code addresses are program
+/// offsets, data addresses are read-only data offsets.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct DebugSymbol {
+    pub name: String,
+    pub section: DebugSection,
+    pub start: u32,
+    pub end: u32,
+}
+
+/// An instruction whose integer operand was written as a label.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct LabelRef {
+    pub pc: u32,
+    pub name: String,
+}
+
+/// Everything needed to map bytecode back to the source it came from.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct DebugInfo {
+    pub file: String,
+    /// Files included by `file`, numbered from 1 in the source map.
+    #[serde(default)]
+    pub includes: Vec<String>,
+    pub source_map: SourceMap,
+    pub symbols: Vec<DebugSymbol>,
+    pub label_refs: Vec<LabelRef>,
+}
+
+impl DebugInfo {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("Debug info is always serializable")
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<DebugInfo, String> {
+        serde_json::from_slice(bytes).map_err(|e| e.to_string())
+    }
+
+    /// `file:line` of the instruction at `pc`.
+    pub fn location(&self, pc: u32) -> Option<String> {
+        self.source_map
+            .location_for(pc)
+            .map(|(file, line)| format!("{}:{}", self.file_name(file), line))
+    }
+
+    /// The name of file number `index` in the source map.
+    pub fn file_name(&self, index: u32) -> &str {
+        match index {
+            0 => &self.file,
+            n => self
+                .includes
+                .get(n as usize - 1)
+                .map_or(&self.file, |name| name),
+        }
+    }
+
+    /// The file the instruction at `pc` was written in.
+    pub fn file_for(&self, pc: u32) -> &str {
+        let file = self.source_map.location_for(pc).map_or(0, |(file, _)| file);
+        self.file_name(file)
+    }
+
+    pub fn label_ref(&self, pc: u32) -> Option<&str> {
+        self.label_refs
+            .iter()
+            .find(|r| r.pc == pc)
+            .map(|r| r.name.as_str())
+    }
+
+    /// Code labels sorted by address.
+    pub fn code_labels(&self) -> Vec<(String, u32)> {
+        let mut labels: Vec<(String, u32)> = self
+            .symbols
+            .iter()
+            .filter(|s| s.section == DebugSection::Code)
+            .map(|s| (s.name.clone(), s.start))
+            .collect();
+        labels.sort_by_key(|(_, start)| *start);
+        labels
+    }
+
+    /// The code symbol whose range contains `pc`.
+    pub fn function_at(&self, pc: u32) -> Option<&DebugSymbol> {
+        self.symbols
+            .iter()
+            .find(|s| s.section == DebugSection::Code && s.start <= pc && pc < s.end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+
+    #[test]
+    fn test_debug_info_from_assembler() {
+        let mut asm = Assembler::new();
+        let source = ".data\nhello: .asciiz 'Hello'\nbye: .asciiz 'Bye'\n.code\nload $0 @start\nstart: prts @hello\nend: hlt";
+        asm.assemble(source).unwrap();
+        let info = asm.debug_info("hello.sy");
+
+        assert_eq!(info.location(69), Some("hello.sy:6".to_string()));
+        assert_eq!(info.location(70), None);
+        assert_eq!(info.label_ref(65), Some("start"));
+        assert_eq!(info.label_ref(69), Some("hello"));
+        assert_eq!(
+            info.symbols,
+            vec![
+                DebugSymbol {
+                    name: "hello".to_string(),
+                    section: DebugSection::Data,
+                    start: 0,
+                    end: 6
+                },
+                DebugSymbol {
+                    name: "bye".to_string(),
+                    section: DebugSection::Data,
+                    start: 6,
+                    end: 10
+                },
+                DebugSymbol {
+                    name: "start".to_string(),
+                    section: DebugSection::Code,
+                    start: 69,
+                    end: 73
+                },
+                DebugSymbol {
+                    name: "end".to_string(),
+                    section: DebugSection::Code,
+                    start: 73,
+                    end: 77
+                },
+            ]
+        );
+        assert_eq!(info.function_at(70).map(|s| s.name.as_str()), Some("start"));
+        assert_eq!(info.function_at(65), None);
+    }
+
+    #[test]
+    fn test_debug_info_round_trip() {
+        let mut asm = Assembler::new();
+        asm.assemble(".data\n.code\nstart: hlt").unwrap();
+        let info = asm.debug_info("test.sy");
+        assert_eq!(DebugInfo::from_bytes(&info.to_bytes()), Ok(info));
+    }
+}
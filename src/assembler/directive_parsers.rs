@@ -1,9 +1,13 @@
 use crate::assembler::{
-    instruction_parsers::AssemblerInstruction, label_parsers::label_declaration,
-    operand_parsers::operand, Token,
+    comment_parsers::{any_space, inline_space, trailing_comments},
+    expressions::spaced_expression,
+    instruction_parsers::AssemblerInstruction,
+    label_parsers::label_declaration,
+    operand_parsers::directive_operand,
+    Token,
 };
 use nom::{
-    character::complete::{alpha1, char, multispace0},
+    character::complete::{alpha1, char},
     combinator::opt,
     sequence::preceded,
     IResult,
@@ -14,21 +18,24 @@ fn directive_declaration(input: &str) -> IResult<&str, Token> {
     Ok((
         input,
         Token::Directive {
-            name: name.to_string(),
+            name: name.to_lowercase(),
         },
     ))
 }
 
 fn directive_combined(input: &str) -> IResult<&str, AssemblerInstruction> {
     let (input, l) = opt(label_declaration)(input)?;
-    let (input, _) = opt(multispace0)(input)?;
+    let (input, mut comments) = any_space(input)?;
     let (input, name) = directive_declaration(input)?;
-    let (input, _) = opt(multispace0)(input)?;
-    let (input, o1) = opt(operand)(input)?;
-    let (input, _) = opt(multispace0)(input)?;
-    let (input, o2) = opt(operand)(input)?;
-    let (input, _) = opt(multispace0)(input)?;
-    let (input, o3) = opt(operand)(input)?;
+    let (input, c) = inline_space(input)?;
+    comments.extend(c);
+    let (input, (o1, o2, o3, c)) = match name {
+        Token::Directive { ref name } if name == "if" || name == "elif" => condition(input)?,
+        _ => operand_list(input)?,
+    };
+    comments.extend(c);
+    let (input, c) = trailing_comments(input)?;
+    comments.extend(c);
 
     let directive = AssemblerInstruction {
         opcode: None,
@@ -37,10 +44,64 @@ fn directive_combined(input: &str) -> IResult<&str, AssemblerInstruction> {
         operand1: o1,
         operand2: o2,
         operand3: o3,
+        comments,
+        line: 0,
+        expanded_from: vec![],
+        file: None,
     };
     Ok((input, directive))
 }
 
+/// Operands separated by spaces or commas, as directives and macro calls
+/// take them. Up to three space-separated operands fill the usual operand
+/// slots; a comma or a fourth operand makes the first slot a `Token::List`
+/// of all of them. Block comments between them are returned as well.
+#[allow(clippy::type_complexity)]
+pub fn operand_list(
+    input: &str,
+) -> IResult<&str, (Option<Token>, Option<Token>, Option<Token>, Vec<Token>)> {
+    let mut comments = vec![];
+    let mut items = vec![];
+    let mut comma = false;
+    let (mut input, first) = opt(directive_operand)(input)?;
+    if let Some(first) = first {
+        items.push(first);
+        loop {
+            let (rest, c) = inline_space(input)?;
+            comments.extend(c);
+            let (rest, separator) = opt(char(','))(rest)?;
+            let (rest, c) = inline_space(rest)?;
+            match directive_operand(rest) {
+                Ok((rest, item)) => {
+                    comments.extend(c);
+                    comma |= separator.is_some();
+                    items.push(item);
+                    input = rest;
+                }
+                Err(_) => break,
+            }
+        }
+    }
+
+    if comma || items.len() > 3 {
+        return Ok((input, (Some(Token::List { items }), None, None, comments)));
+    }
+    let mut items = items.into_iter();
+    Ok((input, (items.next(), items.next(), items.next(), comments)))
+}
+
+/// The condition of `.if` or `.elif`, the whole expression up to the end
+/// of the line, so it may be written with spaces as in `.if LEVEL > 2`.
+#[allow(clippy::type_complexity)]
+fn condition(
+    input: &str,
+) -> IResult<&str, (Option<Token>, Option<Token>, Option<Token>, Vec<Token>)> {
+    match spaced_expression(input) {
+        Ok((input, expr)) => Ok((input, (Some(expr.into_token()), None, None, vec![]))),
+        Err(_) => operand_list(input),
+    }
+}
+
 pub fn directive(input: &str) -> IResult<&str, AssemblerInstruction> {
     directive_combined(input)
 }
@@ -68,7 +129,48 @@ mod tests {
             }),
             operand2: None,
             operand3: None,
+            comments: vec![],
+            line: 0,
+            expanded_from: vec![],
+            file: None,
         };
         assert_eq!(directive, correct_instruction);
     }
+
+    #[test]
+    fn test_list_directive() {
+        let (rest, directive) =
+            directive_combined("table: .word 1, -2 ,@end, 0x10 ; four\nhlt").unwrap();
+        assert_eq!(rest, "\nhlt");
+        assert_eq!(
+            directive.operand1,
+            Some(Token::List {
+                items: vec![
+                    Token::IntegerOperand { value: 1 },
+                    Token::IntegerOperand { value: -2 },
+                    Token::LabelUsage {
+                        name: "end".to_string()
+                    },
+                    Token::IntegerOperand { value: 16 },
+                ]
+            })
+        );
+        assert_eq!(directive.operand2, None);
+        assert_eq!(directive.comments.len(), 1);
+
+        let (rest, directive) = directive_combined(".macro save a, b c d\n").unwrap();
+        assert_eq!(rest, "\n");
+        let names: Vec<String> = directive
+            .directive_operands()
+            .iter()
+            .map(|t| t.to_string())
+            .collect();
+        assert_eq!(names, vec!["#save", "#a", "#b", "#c", "#d"]);
+
+        let (_, directive) = directive_combined(".space 16").unwrap();
+        assert_eq!(
+            directive.operand1,
+            Some(Token::IntegerOperand { value: 16 })
+        );
+    }
 }
@@ -0,0 +1,451 @@
+use std::fmt;
+
+use nom::{
+    bytes::complete::take_while,
+    character::complete::{char, satisfy, space0},
+    error::{Error, ErrorKind},
+    sequence::preceded,
+    Err, IResult,
+};
+
+use crate::assembler::{label_parsers::label_name, operand_parsers::integer, SymbolTable, Token};
+
+/// An operand computed by the assembler, e.g. `BUF_SIZE*2+1` or
+/// `@end-@start`.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Expr {
+    Number(i32),
+    /// A name defined with `.equ` or `.set`.
+    Constant(String),
+    /// The offset of a label, written `@name`.
+    Label(String),
+    Negate(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BinaryOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Or,
+    Xor,
+    And,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+/// Binary operators from the loosest binding to the tightest.
+/// Comparisons give 1 or 0.
+const OPERATORS: &[(&str, BinaryOp, u8)] = &[
+    ("==", BinaryOp::Eq, 1),
+    ("!=", BinaryOp::Ne, 1),
+    ("<", BinaryOp::Lt, 2),
+    ("<=", BinaryOp::Le, 2),
+    (">", BinaryOp::Gt, 2),
+    (">=", BinaryOp::Ge, 2),
+    ("|", BinaryOp::Or, 3),
+    ("^", BinaryOp::Xor, 4),
+    ("&", BinaryOp::And, 5),
+    ("<<", BinaryOp::Shl, 6),
+    (">>", BinaryOp::Shr, 6),
+    ("+", BinaryOp::Add, 7),
+    ("-", BinaryOp::Sub, 7),
+    ("*", BinaryOp::Mul, 8),
+    ("/", BinaryOp::Div, 8),
+    ("%", BinaryOp::Rem, 8),
+];
+
+/// Binds tighter than every binary operator.
+const UNARY_PRECEDENCE: u8 = 9;
+
+impl BinaryOp {
+    fn symbol(self) -> &'static str {
+        OPERATORS.iter().find(|(_, op, _)| *op == self).unwrap().0
+    }
+
+    fn precedence(self) -> u8 {
+        OPERATORS.iter().find(|(_, op, _)| *op == self).unwrap().2
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ExprError {
+    Undefined(String),
+    Overflow,
+    DivisionByZero,
+}
+
+impl Expr {
+    /// Evaluates the expression in 32-bit arithmetic, failing on overflow
+    /// rather than wrapping.
+    pub fn evaluate(&self, symbols: &SymbolTable) -> Result<i32, ExprError> {
+        match self {
+            Expr::Number(value) => Ok(*value),
+            Expr::Constant(name) => symbols
+                .constant(name)
+                .ok_or_else(|| ExprError::Undefined(name.clone())),
+            Expr::Label(name) => match symbols.symbol_value(name) {
+                Some(value) => i32::try_from(value).map_err(|_| ExprError::Overflow),
+                None => Err(ExprError::Undefined(name.clone())),
+            },
+            Expr::Negate(operand) => operand
+                .evaluate(symbols)?
+                .checked_neg()
+                .ok_or(ExprError::Overflow),
+            Expr::Not(operand) => Ok(!operand.evaluate(symbols)?),
+            Expr::Binary(op, left, right) => {
+                let (left, right) = (left.evaluate(symbols)?, right.evaluate(symbols)?);
+                if matches!(op, BinaryOp::Div | BinaryOp::Rem) && right == 0 {
+                    return Err(ExprError::DivisionByZero);
+                }
+                let result = match op {
+                    BinaryOp::Eq => Some((left == right) as i32),
+                    BinaryOp::Ne => Some((left != right) as i32),
+                    BinaryOp::Lt => Some((left < right) as i32),
+                    BinaryOp::Le => Some((left <= right) as i32),
+                    BinaryOp::Gt => Some((left > right) as i32),
+                    BinaryOp::Ge => Some((left >= right) as i32),
+                    BinaryOp::Or => Some(left | right),
+                    BinaryOp::Xor => Some(left ^ right),
+                    BinaryOp::And => Some(left & right),
+                    BinaryOp::Shl => u32::try_from(right)
+                        .ok()
+                        .and_then(|shift| left.checked_shl(shift)),
+                    BinaryOp::Shr => u32::try_from(right)
+                        .ok()
+                        .and_then(|shift| left.checked_shr(shift)),
+                    BinaryOp::Add => left.checked_add(right),
+                    BinaryOp::Sub => left.checked_sub(right),
+                    BinaryOp::Mul => left.checked_mul(right),
+                    BinaryOp::Div => left.checked_div(right),
+                    BinaryOp::Rem => left.checked_rem(right),
+                };
+                result.ok_or(ExprError::Overflow)
+            }
+        }
+    }
+
+    /// The labels the value depends on, with how many times each is added,
+    /// e.g. `[(end, 1), (start, -1)]` for `@end-@start`. `None` if it
+    /// depends on a label other than by adding or subtracting it, as in
+    /// `@a*@b` or `@a&0xff`.
+    pub fn label_terms(&self, symbols: &SymbolTable) -> Option<Vec<(String, i32)>> {
+        match self {
+            Expr::Number(_) | Expr::Constant(_) => Some(vec![]),
+            Expr::Label(name) => Some(vec![(name.clone(), 1)]),
+            Expr::Negate(operand) => Some(
+                operand
+                    .label_terms(symbols)?
+                    .into_iter()
+                    .map(|(name, times)| (name, -times))
+                    .collect(),
+            ),
+            Expr::Binary(op, left, right) => {
+                let (left_terms, right_terms) =
+                    (left.label_terms(symbols)?, right.label_terms(symbols)?);
+                let scale = |terms: Vec<(String, i32)>, by: &Expr| {
+                    let by = by.evaluate(symbols).ok()?;
+                    Some(
+                        terms
+                            .into_iter()
+                            .map(|(name, times)| (name, times * by))
+                            .collect(),
+                    )
+                };
+                match op {
+                    BinaryOp::Add => Some([left_terms, right_terms].concat()),
+                    BinaryOp::Sub => Some(
+                        left_terms
+                            .into_iter()
+                            .chain(right_terms.into_iter().map(|(name, times)| (name, -times)))
+                            .collect(),
+                    ),
+                    BinaryOp::Mul if right_terms.is_empty() => scale(left_terms, right),
+                    BinaryOp::Mul if left_terms.is_empty() => scale(right_terms, left),
+                    _ if left_terms.is_empty() && right_terms.is_empty() => Some(vec![]),
+                    _ => None,
+                }
+            }
+            Expr::Not(operand) => operand.label_terms(symbols)?.is_empty().then(Vec::new),
+        }
+    }
+
+    /// Whether the expression is printed starting with a label, so it
+    /// needs no `#` in front of it.
+    pub fn starts_with_label(&self) -> bool {
+        match self {
+            Expr::Label(_) => true,
+            Expr::Binary(_, left, _) => left.starts_with_label(),
+            _ => false,
+        }
+    }
+
+    /// The simplest token for the expression: plain numbers and labels
+    /// keep their own tokens.
+    pub fn into_token(self) -> Token {
+        match self {
+            Expr::Number(value) => Token::IntegerOperand { value },
+            Expr::Label(name) => Token::LabelUsage { name },
+            expr => Token::Expression { expr },
+        }
+    }
+
+    fn write(&self, f: &mut fmt::Formatter, parent: u8, right: bool) -> fmt::Result {
+        match self {
+            Expr::Number(value) => write!(f, "{}", value),
+            Expr::Constant(name) => f.write_str(name),
+            Expr::Label(name) => write!(f, "@{}", name),
+            Expr::Negate(operand) => {
+                f.write_str("-")?;
+                operand.write(f, UNARY_PRECEDENCE, false)
+            }
+            Expr::Not(operand) => {
+                f.write_str("~")?;
+                operand.write(f, UNARY_PRECEDENCE, false)
+            }
+            Expr::Binary(op, left, right_operand) => {
+                let precedence = op.precedence();
+                let parenthesize = precedence < parent || (precedence == parent && right);
+                if parenthesize {
+                    f.write_str("(")?;
+                }
+                left.write(f, precedence, false)?;
+                f.write_str(op.symbol())?;
+                right_operand.write(f, precedence, true)?;
+                if parenthesize {
+                    f.write_str(")")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Prints the expression with only the parentheses it needs.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, 0, false)
+    }
+}
+
+/// An expression without spaces, since spaces separate operands. Spaces
+/// are allowed inside parentheses.
+pub fn expression(input: &str) -> IResult<&str, Expr> {
+    binary(input, 1, false)
+}
+
+/// An expression that may have spaces around its operators, as the
+/// condition of `.if`, which is the only operand.
+pub fn spaced_expression(input: &str) -> IResult<&str, Expr> {
+    binary(input, 1, true)
+}
+
+fn binary(input: &str, min_precedence: u8, spaced: bool) -> IResult<&str, Expr> {
+    let (mut input, mut left) = unary(input)?;
+    loop {
+        let after_space = if spaced { space0(input)?.0 } else { input };
+        let operator = OPERATORS
+            .iter()
+            .filter(|(symbol, _, precedence)| {
+                *precedence >= min_precedence && after_space.starts_with(symbol)
+            })
+            .max_by_key(|(symbol, _, _)| symbol.len());
+        let (symbol, op, precedence) = match operator {
+            Some(operator) => *operator,
+            None => return Ok((input, left)),
+        };
+        let rest = &after_space[symbol.len()..];
+        let rest = if spaced { space0(rest)?.0 } else { rest };
+        let (rest, right) = binary(rest, precedence + 1, spaced)?;
+        left = Expr::Binary(op, Box::new(left), Box::new(right));
+        input = rest;
+    }
+}
+
+fn unary(input: &str) -> IResult<&str, Expr> {
+    if let Some(rest) = input.strip_prefix('-') {
+        if rest.starts_with(|c: char| c.is_ascii_digit()) {
+            let (rest, value) = integer(input)?;
+            return Ok((rest, Expr::Number(value)));
+        }
+        let (rest, operand) = unary(rest)?;
+        return Ok((rest, Expr::Negate(Box::new(operand))));
+    }
+    if let Some(rest) = input.strip_prefix('~') {
+        let (rest, operand) = unary(rest)?;
+        return Ok((rest, Expr::Not(Box::new(operand))));
+    }
+    primary(input)
+}
+
+fn primary(input: &str) -> IResult<&str, Expr> {
+    if let Some(rest) = input.strip_prefix('(') {
+        let (rest, _) = space0(rest)?;
+        let (rest, expr) = binary(rest, 1, true)?;
+        let (rest, _) = space0(rest)?;
+        let (rest, _) = char(')')(rest)?;
+        return Ok((rest, expr));
+    }
+    if let Ok((rest, name)) = preceded(char::<&str, Error<&str>>('@'), label_name)(input) {
+        return Ok((rest, Expr::Label(name.to_string())));
+    }
+    if let Ok((rest, name)) = identifier(input) {
+        return Ok((rest, Expr::Constant(name.to_string())));
+    }
+    match integer(input) {
+        Ok((rest, value)) if !input.starts_with('-') => Ok((rest, Expr::Number(value))),
+        _ => Err(Err::Error(Error::new(input, ErrorKind::Digit))),
+    }
+}
+
+/// A constant name: letters, digits and underscores, not starting with a
+/// digit.
+pub fn identifier(input: &str) -> IResult<&str, &str> {
+    let (rest, _) = satisfy(|c| c.is_ascii_alphabetic() || c == '_')(input)?;
+    let (rest, _) = take_while(|c: char| c.is_ascii_alphanumeric() || c == '_')(rest)?;
+    Ok((rest, &input[..input.len() - rest.len()]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::symbols::{Symbol, SymbolType};
+
+    fn parse(input: &str) -> Expr {
+        let (rest, expr) = expression(input).unwrap();
+        assert_eq!(rest, "", "unparsed input in {}", input);
+        expr
+    }
+
+    fn symbols() -> SymbolTable {
+        let mut symbols = SymbolTable::new();
+        symbols.add_symbol(Symbol::new_with_offset(
+            "BUF_SIZE".to_string(),
+            SymbolType::Integer,
+            16,
+        ));
+        symbols.add_symbol(Symbol::new_with_offset(
+            "start".to_string(),
+            SymbolType::Label,
+            69,
+        ));
+        symbols.add_symbol(Symbol::new_with_offset(
+            "end".to_string(),
+            SymbolType::Label,
+            81,
+        ));
+        symbols
+    }
+
+    fn evaluate(input: &str) -> Result<i32, ExprError> {
+        parse(input).evaluate(&symbols())
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(evaluate("BUF_SIZE*2+1"), Ok(33));
+        assert_eq!(evaluate("1+2*3"), Ok(7));
+        assert_eq!(evaluate("(1+2)*3"), Ok(9));
+        assert_eq!(evaluate("( 1 + 2 )*3"), Ok(9));
+        assert_eq!(evaluate("10-4-3"), Ok(3));
+        assert_eq!(evaluate("1<<4|1"), Ok(17));
+        assert_eq!(evaluate("-BUF_SIZE"), Ok(-16));
+        assert_eq!(evaluate("~0&0xff"), Ok(255));
+        assert_eq!(evaluate("17%5"), Ok(2));
+        assert_eq!(evaluate("-2147483648"), Ok(i32::MIN));
+        assert_eq!(evaluate("-(1+2)"), Ok(-3));
+        assert_eq!(evaluate("BUF_SIZE>=16"), Ok(1));
+        assert_eq!(evaluate("1<<2<4"), Ok(0));
+        assert_eq!(evaluate("2>1==1<2"), Ok(1));
+        assert_eq!(evaluate("BUF_SIZE!=16|1"), Ok(1));
+    }
+
+    #[test]
+    fn test_labels() {
+        assert_eq!(evaluate("@end-@start"), Ok(12));
+        assert_eq!(evaluate("(@end-@start)/4"), Ok(3));
+        assert_eq!(evaluate("@start+4"), Ok(73));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(evaluate("2147483647+1"), Err(ExprError::Overflow));
+        assert_eq!(evaluate("1<<32"), Err(ExprError::Overflow));
+        assert_eq!(evaluate("1/(BUF_SIZE-16)"), Err(ExprError::DivisionByZero));
+        assert_eq!(
+            evaluate("MISSING+1"),
+            Err(ExprError::Undefined("MISSING".to_string()))
+        );
+        assert_eq!(
+            evaluate("@nowhere"),
+            Err(ExprError::Undefined("nowhere".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_label_terms() {
+        let terms = |input: &str| parse(input).label_terms(&symbols());
+        let term = |name: &str, times: i32| (name.to_string(), times);
+        assert_eq!(terms("BUF_SIZE*2"), Some(vec![]));
+        assert_eq!(
+            terms("@end-@start+4"),
+            Some(vec![term("end", 1), term("start", -1)])
+        );
+        assert_eq!(terms("-(@end*2)"), Some(vec![term("end", -2)]));
+        assert_eq!(terms("@end*@start"), None);
+        assert_eq!(terms("@end&0xff"), None);
+        assert_eq!(terms("~@end"), None);
+    }
+
+    #[test]
+    fn test_stops_at_spaces() {
+        assert_eq!(expression("1 +2"), Ok((" +2", Expr::Number(1))));
+        assert_eq!(expression("-5,"), Ok((",", Expr::Number(-5))));
+        assert!(expression("(1+2").is_err());
+    }
+
+    #[test]
+    fn test_spaced_expression() {
+        assert_eq!(
+            spaced_expression("LEVEL > 2 ; comment"),
+            Ok((
+                " ; comment",
+                Expr::Binary(
+                    BinaryOp::Gt,
+                    Box::new(Expr::Constant("LEVEL".to_string())),
+                    Box::new(Expr::Number(2))
+                )
+            ))
+        );
+    }
+
+    #[test]
+    fn test_display_round_trips() {
+        for text in [
+            "BUF_SIZE*2+1",
+            "(1+2)*3",
+            "10-(4-3)",
+            "@end-@start",
+            "-(@start+1)",
+            "~X&0xff",
+            "1<<4|1",
+            "A==(B<C)",
+            "A<=B>>1",
+        ] {
+            let expr = parse(text);
+            assert_eq!(parse(&expr.to_string()), expr, "{}", text);
+        }
+        assert_eq!(parse("(1+2)*3").to_string(), "(1+2)*3");
+        assert_eq!(parse("(1*2)+3").to_string(), "1*2+3");
+    }
+}
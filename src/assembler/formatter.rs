@@ -0,0 +1,363 @@
+use std::fmt;
+
+use crate::assembler::{
+    instruction_parsers::AssemblerInstruction,
+    operand_parsers::string_error,
+    program_parsers::{location, program, Program},
+    Token,
+};
+
+/// Narrowest label column, so unlabelled code is still indented.
+const MIN_LABEL_WIDTH: usize = 4;
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Op { code } => f.write_str(code.mnemonic()),
+            Token::Register { reg_num } => write!(f, "${}", reg_num),
+            Token::IntegerOperand { value } => write!(f, "#{}", value),
+            Token::LabelDeclaration { name } => write!(f, "{}:", name),
+            Token::LabelUsage { name } => write!(f, "@{}", name),
+            Token::Directive { name } => write!(f, ".{}", name),
+            Token::SyString { name } => write!(f, "'{}'", escape(name)),
+            Token::LibraryPath { name } => write!(f, "<{}>", name),
+            Token::Expression { expr } if expr.starts_with_label() => write!(f, "{}", expr),
+            Token::Expression { expr } => write!(f, "#{}", expr),
+            Token::MacroCall { name } => f.write_str(name),
+            Token::MacroParam { name } => write!(f, "\\{}", name),
+            Token::List { items } => {
+                let items: Vec<String> = items.iter().map(|i| i.to_string()).collect();
+                f.write_str(&items.join(", "))
+            }
+            Token::Comment { text, block: false } if text.is_empty() => f.write_str(";"),
+            Token::Comment { text, block: false } => write!(f, "; {}", text),
+            Token::Comment { text, block: true } => write!(f, "/* {} */", text),
+        }
+    }
+}
+
+/// Escapes `text` for a single-quoted string literal.
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            '\0' => escaped.push_str("\\0"),
+            '\\' | '\'' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c if c.is_ascii_control() => escaped.push_str(&format!("\\x{:02x}", c as u8)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Directive operands print numbers and expressions without the `#`.
+fn directive_operand_text(token: &Token) -> String {
+    match token {
+        Token::IntegerOperand { value } => value.to_string(),
+        Token::Expression { expr } => expr.to_string(),
+        Token::List { items } => items
+            .iter()
+            .map(directive_operand_text)
+            .collect::<Vec<String>>()
+            .join(", "),
+        token => token.to_string(),
+    }
+}
+
+impl AssemblerInstruction {
+    /// The mnemonic or directive, e.g. `load` or `.asciiz`.
+    fn keyword(&self) -> String {
+        match (&self.opcode, &self.directive) {
+            (Some(token), _) | (None, Some(token)) => token.to_string(),
+            (None, None) => String::new(),
+        }
+    }
+
+    fn operands_text(&self) -> String {
+        [&self.operand1, &self.operand2, &self.operand3]
+            .iter()
+            .filter_map(|operand| {
+                operand
+                    .as_ref()
+                    .map(|o| match self.is_directive() || self.is_macro_call() {
+                        true => directive_operand_text(o),
+                        false => o.to_string(),
+                    })
+            })
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    /// `.data` and `.code`, which start a section.
+    pub fn is_section_header(&self) -> bool {
+        !self.is_label()
+            && !self.has_operands()
+            && matches!(&self.directive, Some(Token::Directive { name }) if name == "data" || name == "code")
+    }
+
+    fn comments_text(&self) -> String {
+        self.comments
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    /// The label, keyword and operands padded to the given column widths.
+    fn code_text(&self, label_width: usize, keyword_width: usize) -> String {
+        let label = self
+            .label
+            .as_ref()
+            .map(|l| l.to_string())
+            .unwrap_or_default();
+        let operands = self.operands_text();
+        let line = if operands.is_empty() {
+            format!("{:label_width$}{}", label, self.keyword())
+        } else {
+            format!(
+                "{:label_width$}{:keyword_width$}{}",
+                label,
+                self.keyword(),
+                operands
+            )
+        };
+        line.trim_end().to_string()
+    }
+
+    /// Prints the code followed by its comments, which start at
+    /// `comment_column` or one space after the code if it is longer.
+    fn write_aligned(
+        &self,
+        f: &mut fmt::Formatter,
+        label_width: usize,
+        keyword_width: usize,
+        comment_column: usize,
+    ) -> fmt::Result {
+        let code = self.code_text(label_width, keyword_width);
+        if self.comments.is_empty() {
+            return f.write_str(&code);
+        }
+        if code.is_empty() {
+            return f.write_str(&self.comments_text());
+        }
+        let width = comment_column.max(code.len() + 1);
+        write!(f, "{:width$}{}", code, self.comments_text())
+    }
+}
+
+impl fmt::Display for AssemblerInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let label_width = self
+            .label
+            .as_ref()
+            .map(|l| l.to_string().len() + 1)
+            .unwrap_or(0);
+        self.write_aligned(f, label_width, self.keyword().len() + 1, 0)
+    }
+}
+
+/// The canonical layout: section headers in the first column with a blank
+/// line between sections, labels in their own column, and mnemonics,
+/// operands and end-of-line comments aligned across the file. Comment-only
+/// lines start in the first column and stay with the line below them.
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let instructions = &self.instructions;
+        let body = instructions.iter().filter(|i| !i.is_section_header());
+        let label_width = body
+            .clone()
+            .filter_map(|i| i.label.as_ref().map(|l| l.to_string().len() + 1))
+            .max()
+            .unwrap_or(0)
+            .max(MIN_LABEL_WIDTH);
+        let keyword_width = body
+            .clone()
+            .map(|i| i.keyword().len() + 1)
+            .max()
+            .unwrap_or(0);
+        let comment_column = body
+            .map(|i| i.code_text(label_width, keyword_width).len() + 1)
+            .max()
+            .unwrap_or(0);
+
+        // A blank line goes before every section but the first, above any
+        // comments leading into it.
+        let mut blank_before = vec![false; instructions.len()];
+        let mut seen_section = false;
+        for (index, instruction) in instructions.iter().enumerate() {
+            if !instruction.is_section_header() {
+                continue;
+            }
+            if seen_section {
+                let mut start = index;
+                while start > 0 && instructions[start - 1].is_comment_only() {
+                    start -= 1;
+                }
+                blank_before[start] = true;
+            }
+            seen_section = true;
+        }
+
+        for (index, instruction) in instructions.iter().enumerate() {
+            if blank_before[index] {
+                writeln!(f)?;
+            }
+            if instruction.is_section_header() {
+                instruction.write_aligned(f, 0, 0, 0)?;
+            } else {
+                instruction.write_aligned(f, label_width, keyword_width, comment_column)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses `source` and prints it in the canonical style. Fails rather than
+/// dropping anything the parser could not read.
+pub fn format_source(source: &str) -> Result<String, String> {
+    if source.trim().is_empty() {
+        return Ok(String::new());
+    }
+    let (rest, parsed) = match program(source) {
+        Ok(result) => result,
+        Err(nom::Err::Failure(failure)) if string_error(&failure).is_some() => {
+            let (line, column) = location(source, failure.input);
+            let message = string_error(&failure).unwrap_or_default();
+            return Err(format!("{} at line {}, column {}", message, line, column));
+        }
+        Err(e) => return Err(format!("Unable to parse the program: {}", e)),
+    };
+    if !rest.trim().is_empty() {
+        let line = source[..source.len() - rest.trim_start().len()]
+            .matches('\n')
+            .count()
+            + 1;
+        return Err(format!("Unable to parse line {}", line));
+    }
+    Ok(parsed.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSY: &str = ".DATA\nhello:   .asciiz 'Hello'\nbye: .asciiz \"Bye\"\n  .code\n LOAD $0   #100\nloop:inc $0\n    NEQ $0 $1\nlonglabel:   jeq   @loop\nhlt";
+
+    const CANONICAL: &str = ".data
+hello:     .asciiz 'Hello'
+bye:       .asciiz 'Bye'
+
+.code
+           load    $0 #100
+loop:      inc     $0
+           neq     $0 $1
+longlabel: jeq     @loop
+           hlt
+";
+
+    #[test]
+    fn test_format_source() {
+        assert_eq!(format_source(MESSY), Ok(CANONICAL.to_string()));
+        assert_eq!(format_source(CANONICAL), Ok(CANONICAL.to_string()));
+    }
+
+    #[test]
+    fn test_format_round_trips() {
+        let (_, original) = program(MESSY).unwrap();
+        let (_, reparsed) = program(&original.to_string()).unwrap();
+        let strip_lines = |p: Program| {
+            p.instructions
+                .into_iter()
+                .map(|mut i| {
+                    i.line = 0;
+                    i
+                })
+                .collect::<Vec<AssemblerInstruction>>()
+        };
+        assert_eq!(strip_lines(original), strip_lines(reparsed));
+    }
+
+    const COMMENTED: &str = "; Counts to ten
+.data
+.code  ; entry point
+load $0 #0 ; counter
+load $1 #10
+/* the loop
+   body */
+loop: inc $0   /* step */
+neq $0 $1
+; keep going
+jeq @loop
+hlt";
+
+    const COMMENTED_CANONICAL: &str = "; Counts to ten
+.data
+
+.code ; entry point
+      load $0 #0  ; counter
+      load $1 #10
+/* the loop
+   body */
+loop: inc  $0     /* step */
+      neq  $0 $1
+; keep going
+      jeq  @loop
+      hlt
+";
+
+    #[test]
+    fn test_format_keeps_comments() {
+        assert_eq!(
+            format_source(COMMENTED),
+            Ok(COMMENTED_CANONICAL.to_string())
+        );
+        assert_eq!(
+            format_source(COMMENTED_CANONICAL),
+            Ok(COMMENTED_CANONICAL.to_string())
+        );
+    }
+
+    #[test]
+    fn test_instruction_display() {
+        let (_, parsed) = program("start:   ADD $0 $1   $2").unwrap();
+        assert_eq!(parsed.instructions[0].to_string(), "start: add $0 $1 $2");
+        let (_, parsed) = program("hlt").unwrap();
+        assert_eq!(parsed.instructions[0].to_string(), "hlt");
+    }
+
+    #[test]
+    fn test_format_escapes_strings() {
+        let source = ".data\nmsg: .asciiz \"It's \\x01 \\u{e9}\\t\\\\n\\n\"\n.code\nhlt\n";
+        let formatted = format_source(source).unwrap();
+        assert!(formatted.contains(r"'It\'s \x01 é\t\\n\n'"));
+        let (_, original) = program(source).unwrap();
+        let (_, reparsed) = program(&formatted).unwrap();
+        assert_eq!(
+            original.instructions[1].operand1,
+            reparsed.instructions[1].operand1
+        );
+        assert_eq!(
+            format_source(".data\nmsg: .asciiz 'oops\n.code\nhlt\n"),
+            Err("Unterminated string literal at line 2, column 14".to_string())
+        );
+    }
+
+    #[test]
+    fn test_format_refuses_to_drop_code() {
+        assert_eq!(
+            format_source(".data\n.code\nhlt\n%%%\n"),
+            Err("Unable to parse line 4".to_string())
+        );
+        assert_eq!(
+            format_source(".data\n.code\nFoo $0,$1\n"),
+            Ok(".data\n\n.code\n    Foo $0, $1\n".to_string())
+        );
+    }
+}
@@ -0,0 +1,371 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::assembler::{
+    assembler_errors::AssemblerError,
+    instruction_parsers::AssemblerInstruction,
+    library,
+    program_parsers::{parse_error, program, Program},
+    Token,
+};
+
+/// Replaces `.include "path"` directives with the instructions of the
+/// files they name. A relative path is looked up next to the file that
+/// includes it, then in each search path.
+///
+/// A file wrapped in an include guard, `.ifndef NAME` to the matching
+/// `.endif`, is only included once; including any other file into itself
+/// is an error.
+#[derive(Debug, Default)]
+pub struct IncludeExpander {
+    search_paths: Vec<PathBuf>,
+    /// The files being included, outermost first, by canonical path and
+    /// name.
+    stack: Vec<(PathBuf, String)>,
+    guarded: HashSet<PathBuf>,
+    /// Names of the included files, in the order they were first included.
+    pub files: Vec<String>,
+    errors: Vec<AssemblerError>,
+}
+
+impl IncludeExpander {
+    pub fn new(search_paths: Vec<PathBuf>) -> IncludeExpander {
+        IncludeExpander {
+            search_paths,
+            ..Default::default()
+        }
+    }
+
+    /// Expands the includes of `program`, which was read from `source` if
+    /// it came from a file.
+    pub fn expand(
+        &mut self,
+        program: Program,
+        source: Option<&Path>,
+    ) -> Result<Program, Vec<AssemblerError>> {
+        let dir = match source {
+            Some(source) => {
+                let name = source.to_string_lossy().to_string();
+                self.stack.push((canonical(source), name));
+                source.parent().unwrap_or(Path::new("")).to_path_buf()
+            }
+            None => PathBuf::new(),
+        };
+        let mut instructions = vec![];
+        self.expand_instructions(program.instructions, &dir, &mut instructions);
+        self.stack.clear();
+        if !self.errors.is_empty() {
+            return Err(std::mem::take(&mut self.errors));
+        }
+        Ok(Program { instructions })
+    }
+
+    fn expand_instructions(
+        &mut self,
+        instructions: Vec<AssemblerInstruction>,
+        dir: &Path,
+        output: &mut Vec<AssemblerInstruction>,
+    ) {
+        for instruction in instructions {
+            if instruction.get_directive_name().as_deref() == Some("include") {
+                if let Err(error) = self.include(&instruction, dir, output) {
+                    self.errors.push(in_file(error, &instruction.file));
+                }
+            } else {
+                output.push(instruction);
+            }
+        }
+    }
+
+    fn include(
+        &mut self,
+        instruction: &AssemblerInstruction,
+        dir: &Path,
+        output: &mut Vec<AssemblerInstruction>,
+    ) -> Result<(), AssemblerError> {
+        let line = instruction.line;
+        let (written, library) = match instruction.directive_operands()[..] {
+            [Token::SyString { name }] => (name.clone(), false),
+            [Token::LibraryPath { name }] => (name.clone(), true),
+            _ => {
+                return Err(AssemblerError::InvalidDirectiveOperand {
+                    directive: "include".to_string(),
+                    line,
+                })
+            }
+        };
+        let failed = |reason: String| AssemblerError::IncludeFailed {
+            path: written.clone(),
+            reason,
+            line,
+        };
+
+        // A library path is looked up in the search paths, so a project
+        // can replace a bundled file, and then in the bundled library.
+        let (path, source) = match self.resolve(&written, dir, library) {
+            Some(path) => {
+                let source = fs::read_to_string(&path).map_err(|e| failed(e.to_string()))?;
+                (path, source)
+            }
+            None => match library::source(&written).filter(|_| library) {
+                Some(source) => (PathBuf::from(format!("<{}>", written)), source.to_string()),
+                None => return Err(failed("file not found".to_string())),
+            },
+        };
+        let name = path.to_string_lossy().to_string();
+        let parsed = match program(&source) {
+            Ok((_, parsed)) => parsed,
+            Err(e) => return Err(in_file(parse_error(&source, &e), &Some(name))),
+        };
+
+        let key = canonical(&path);
+        let guarded = is_guarded(&parsed.instructions);
+        if guarded && self.guarded.contains(&key) {
+            return Ok(());
+        }
+        if self.stack.iter().any(|(open, _)| *open == key) {
+            let mut files: Vec<String> = self.stack.iter().map(|(_, name)| name.clone()).collect();
+            files.push(name);
+            return Err(AssemblerError::IncludeCycle { files, line });
+        }
+        if guarded {
+            self.guarded.insert(key.clone());
+        }
+        if !self.files.contains(&name) {
+            self.files.push(name.clone());
+        }
+
+        let instructions = parsed
+            .instructions
+            .into_iter()
+            .map(|mut instruction| {
+                instruction.file = Some(name.clone());
+                instruction
+            })
+            .collect();
+        self.stack.push((key, name));
+        let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        self.expand_instructions(instructions, &dir, output);
+        self.stack.pop();
+        Ok(())
+    }
+
+    /// The file `written` names, looked up next to the including file in
+    /// `dir`, unless it is a library path, and then in the search paths.
+    fn resolve(&self, written: &str, dir: &Path, library: bool) -> Option<PathBuf> {
+        let path = Path::new(written);
+        if path.is_absolute() {
+            return Some(path.to_path_buf()).filter(|path| path.is_file());
+        }
+        std::iter::once(dir)
+            .filter(|_| !library)
+            .chain(self.search_paths.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(path))
+            .find(|candidate| candidate.is_file())
+    }
+}
+
+/// `path` made canonical, or as it is if no file has it, like the
+/// `<path>` names of bundled files.
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Whether the file starts with `.ifndef` and ends with its `.endif`,
+/// comments aside.
+fn is_guarded(instructions: &[AssemblerInstruction]) -> bool {
+    let directives: Vec<Option<String>> = instructions
+        .iter()
+        .filter(|i| !i.is_comment_only())
+        .map(|i| i.get_directive_name())
+        .collect();
+    if directives.first().and_then(|d| d.as_deref()) != Some("ifndef") {
+        return false;
+    }
+    let mut depth = 0;
+    for (index, directive) in directives.iter().enumerate() {
+        match directive.as_deref() {
+            Some("if" | "ifdef" | "ifndef") => depth += 1,
+            Some("endif") => {
+                depth -= 1;
+                if depth == 0 {
+                    return index == directives.len() - 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Names the included file an error happened in; errors in the file being
+/// assembled are left as they are.
+pub fn in_file(error: AssemblerError, file: &Option<String>) -> AssemblerError {
+    match file {
+        Some(file) => AssemblerError::InFile {
+            error: Box::new(error),
+            file: file.clone(),
+        },
+        None => error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    /// Writes `files` to a new directory and expands the includes of the
+    /// first one.
+    fn expand(test: &str, files: &[(&str, &str)]) -> Result<Vec<String>, Vec<String>> {
+        let dir = env::temp_dir().join(format!("synthia-include-{}-{}", test, std::process::id()));
+        for (name, source) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        }
+        let main = dir.join(files[0].0);
+        let (_, parsed) = program(files[0].1).unwrap();
+        let mut expander = IncludeExpander::new(vec![dir.join("lib")]);
+        let result = expander.expand(parsed, Some(&main));
+        let strip = |text: String| text.replace(&format!("{}/", dir.display()), "");
+        let result = match result {
+            Ok(expanded) => Ok(expanded
+                .instructions
+                .iter()
+                .map(|i| {
+                    let file = i.file.clone().map(strip).unwrap_or_default();
+                    format!("{}:{}: {}", file, i.line, i)
+                })
+                .collect()),
+            Err(errors) => Err(errors.into_iter().map(|e| strip(e.to_string())).collect()),
+        };
+        fs::remove_dir_all(dir).unwrap();
+        result
+    }
+
+    #[test]
+    fn test_include_search_paths() {
+        assert_eq!(
+            expand(
+                "paths",
+                &[
+                    (
+                        "main.sy",
+                        ".data\n.include \"util/a.sy\"\n.code\n.include 'io.sy'\nhlt\n"
+                    ),
+                    ("util/a.sy", "one: .byte 1\n.include \"b.sy\"\n"),
+                    ("util/b.sy", "two: .byte 2\n"),
+                    ("lib/io.sy", "\nputs: prts @one\n"),
+                ]
+            ),
+            Ok(vec![
+                ":1: .data".to_string(),
+                "util/a.sy:1: one: .byte 1".to_string(),
+                "util/b.sy:1: two: .byte 2".to_string(),
+                ":3: .code".to_string(),
+                "lib/io.sy:2: puts: prts @one".to_string(),
+                ":5: hlt".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_include_guards() {
+        let guarded = ".ifndef IO\n.equ IO 1\n.include 'main.sy'\nputs: hlt\n.endif\n";
+        let expanded = expand(
+            "guards",
+            &[
+                ("main.sy", ".include 'io.sy'\n.include 'io.sy'\n"),
+                ("io.sy", guarded),
+            ],
+        );
+        assert_eq!(
+            expanded,
+            Err(vec![
+                "io.sy: Include cycle main.sy -> io.sy -> main.sy on line 3".to_string()
+            ])
+        );
+        let expanded = expand(
+            "guards2",
+            &[
+                ("main.sy", ".include 'io.sy'\n.include 'io.sy'\n"),
+                ("io.sy", &guarded.replace("main.sy", "other.sy")),
+                (
+                    "other.sy",
+                    "; guarded\n.ifndef OTHER\n.include 'io.sy'\n.endif\n",
+                ),
+            ],
+        )
+        .unwrap();
+        assert_eq!(expanded.len(), 7);
+        assert_eq!(expanded[4], "other.sy:4: .endif");
+    }
+
+    #[test]
+    fn test_include_errors() {
+        assert_eq!(
+            expand(
+                "errors",
+                &[
+                    (
+                        "main.sy",
+                        ".include 'missing.sy'\n.include #1\n.include 'bad.sy'\n"
+                    ),
+                    ("bad.sy", ".data\nmsg: .asciiz 'open\n"),
+                ]
+            ),
+            Err(vec![
+                "Cannot include missing.sy on line 1: file not found".to_string(),
+                "Invalid operands for directive .include on line 2".to_string(),
+                "bad.sy: Unterminated string literal at line 2, column 14".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_include_library() {
+        let expanded = expand(
+            "library",
+            &[
+                (
+                    "main.sy",
+                    ".data\n.code\n.include <std/math.sy>\n.include <std/math.sy>\n.include <std/nope.sy>\n",
+                ),
+                ("lib/std/mem.sy", "hlt\n"),
+            ],
+        );
+        assert_eq!(
+            expanded,
+            Err(vec![
+                "Cannot include std/nope.sy on line 5: file not found".to_string()
+            ])
+        );
+        let expanded = expand(
+            "library2",
+            &[
+                (
+                    "main.sy",
+                    ".data\n.code\n.include <std/math.sy>\n.include <std/mem.sy>\n",
+                ),
+                ("lib/std/mem.sy", "hlt\n"),
+                ("std/math.sy", "nop\n"),
+            ],
+        )
+        .unwrap();
+        assert!(expanded.contains(&"<std/math.sy>:13: mulshift: load $3 #0".to_string()));
+        assert_eq!(expanded.last().unwrap(), "lib/std/mem.sy:1: hlt");
+    }
+
+    #[test]
+    fn test_is_guarded() {
+        let guarded = |source: &str| is_guarded(&program(source).unwrap().1.instructions);
+        assert!(guarded("; io\n.ifndef IO\n.if #1\n.endif\nhlt\n.endif\n"));
+        assert!(!guarded(".ifndef IO\n.endif\nhlt\n"));
+        assert!(!guarded(".ifdef IO\nhlt\n.endif\n"));
+        assert!(!guarded("hlt\n"));
+    }
+}
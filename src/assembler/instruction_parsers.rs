@@ -1,16 +1,16 @@
-use nom::{
-    branch::alt,
-    character::complete::{multispace0, newline},
-    combinator::opt,
-    IResult,
-};
+use nom::{branch::alt, character::complete::newline, combinator::opt, IResult};
 
 use crate::assembler::{
-    directive_parsers::directive, label_parsers::label_declaration, opcode_parsers::*,
-    operand_parsers::operand, SymbolTable, Token,
+    comment_parsers::{any_space, comment_line, inline_space, trailing_comments},
+    directive_parsers::{directive, operand_list},
+    label_parsers::label_declaration,
+    macros::MacroCall,
+    opcode_parsers::*,
+    operand_parsers::operand,
+    SymbolTable, Token,
 };
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct AssemblerInstruction {
     pub opcode: Option<Token>,
     pub label: Option<Token>,
@@ -18,11 +18,26 @@ pub struct AssemblerInstruction {
     pub operand1: Option<Token>,
     pub operand2: Option<Token>,
     pub operand3: Option<Token>,
+    /// Comments on the instruction's line, or the comment a comment-only
+    /// line holds.
+    pub comments: Vec<Token>,
+    /// 1-based source line, filled in by `program`. Zero when the
+    /// instruction was parsed on its own.
+    pub line: u32,
+    /// The macro calls this instruction was expanded from, outermost
+    /// first. Empty for instructions written out in the source.
+    pub expanded_from: Vec<MacroCall>,
+    /// The included file the instruction was written in, or `None` for the
+    /// file being assembled.
+    pub file: Option<String>,
 }
 
 impl AssemblerInstruction {
     pub fn to_bytes(&self, symbols: &SymbolTable) -> Vec<u8> {
         let mut results = vec![];
+        if self.is_comment_only() {
+            return results;
+        }
         match self.opcode {
             Some(ref token) => match token {
                 Token::Op { code } => match code {
@@ -55,13 +70,42 @@ impl AssemblerInstruction {
     }
 
     pub fn is_opcode(&self) -> bool {
-        self.opcode.is_some()
+        matches!(self.opcode, Some(Token::Op { .. }))
+    }
+
+    pub fn is_macro_call(&self) -> bool {
+        matches!(self.opcode, Some(Token::MacroCall { .. }))
+    }
+
+    /// The line reported for the instruction in debug info: the outermost
+    /// macro call for expanded instructions.
+    pub fn source_line(&self) -> u32 {
+        self.expanded_from
+            .first()
+            .map(|call| call.line)
+            .unwrap_or(self.line)
+    }
+
+    /// The file of `source_line`.
+    pub fn source_file(&self) -> Option<&str> {
+        match self.expanded_from.first() {
+            Some(call) => call.file.as_deref(),
+            None => self.file.as_deref(),
+        }
     }
 
     pub fn is_directive(&self) -> bool {
         self.directive.is_some()
     }
 
+    /// A line holding nothing but comments.
+    pub fn is_comment_only(&self) -> bool {
+        !self.is_label()
+            && self.opcode.is_none()
+            && !self.is_directive()
+            && !self.comments.is_empty()
+    }
+
     pub fn get_label_name(&self) -> Option<String> {
         match &self.label {
             Some(l) => match l {
@@ -86,6 +130,16 @@ impl AssemblerInstruction {
         self.operand1.is_some() || self.operand2.is_some() || self.operand3.is_some()
     }
 
+    /// Name of the first label used as an operand.
+    pub fn get_label_usage(&self) -> Option<String> {
+        [&self.operand1, &self.operand2, &self.operand3]
+            .iter()
+            .find_map(|operand| match operand {
+                Some(Token::LabelUsage { name }) => Some(name.clone()),
+                _ => None,
+            })
+    }
+
     pub fn get_string_constant(&self) -> Option<String> {
         match &self.operand1 {
             Some(d) => match d {
@@ -96,6 +150,18 @@ impl AssemblerInstruction {
         }
     }
 
+    /// The operands of a directive, with a comma-separated list flattened.
+    pub fn directive_operands(&self) -> Vec<&Token> {
+        [&self.operand1, &self.operand2, &self.operand3]
+            .into_iter()
+            .flatten()
+            .flat_map(|operand| match operand {
+                Token::List { items } => items.iter().collect(),
+                operand => vec![operand],
+            })
+            .collect()
+    }
+
     fn extract_operand(t: &Token, results: &mut Vec<u8>, symbols: &SymbolTable) {
         match t {
             Token::Register { reg_num } => {
@@ -108,6 +174,13 @@ impl AssemblerInstruction {
                 results.push(byte2 as u8);
                 results.push(byte1 as u8);
             }
+            Token::Expression { expr } => {
+                let converted = expr.evaluate(symbols).unwrap_or(0) as u16;
+                results.push((converted >> 8) as u8);
+                results.push(converted as u8);
+            }
+            // Reported by the assembler, outside a macro or in an `igl`.
+            Token::MacroParam { .. } | Token::List { .. } => {}
             Token::LabelUsage { name } => match symbols.symbol_value(name) {
                 Some(value) => {
                     let byte1 = value;
@@ -115,7 +188,8 @@ impl AssemblerInstruction {
                     results.push(byte2 as u8);
                     results.push(byte1 as u8);
                 }
-                None => {}
+                // Reported as undefined by the assembler.
+                None => results.extend([0, 0]),
             },
             _ => {
                 println!("Opcode not found in opcode field");
@@ -127,19 +201,46 @@ impl AssemblerInstruction {
 
 fn instruction_combined(input: &str) -> IResult<&str, AssemblerInstruction> {
     let (input, l) = opt(label_declaration)(input)?;
-    let (input, _) = opt(multispace0)(input)?;
+    let (input, mut comments) = any_space(input)?;
 
     let (input, o) = opcode(input)?;
-    let (input, _) = multispace0(input)?;
+    let (input, c) = inline_space(input)?;
+    comments.extend(c);
+
+    if let Token::MacroCall { .. } = o {
+        let (input, (o1, o2, o3, c)) = operand_list(input)?;
+        comments.extend(c);
+        let (input, c) = trailing_comments(input)?;
+        comments.extend(c);
+        let (input, _) = opt(newline)(input)?;
+        return Ok((
+            input,
+            AssemblerInstruction {
+                opcode: Some(o),
+                label: l,
+                directive: None,
+                operand1: o1,
+                operand2: o2,
+                operand3: o3,
+                comments,
+                line: 0,
+                expanded_from: vec![],
+                file: None,
+            },
+        ));
+    }
 
     let (input, o1) = opt(operand)(input)?;
-    let (input, _) = opt(multispace0)(input)?;
+    let (input, c) = inline_space(input)?;
+    comments.extend(c);
 
     let (input, o2) = opt(operand)(input)?;
-    let (input, _) = opt(multispace0)(input)?;
+    let (input, c) = inline_space(input)?;
+    comments.extend(c);
 
     let (input, o3) = opt(operand)(input)?;
-    let (input, _) = opt(multispace0)(input)?;
+    let (input, c) = trailing_comments(input)?;
+    comments.extend(c);
 
     let (input, _) = opt(newline)(input)?;
 
@@ -152,12 +253,16 @@ fn instruction_combined(input: &str) -> IResult<&str, AssemblerInstruction> {
             operand1: o1,
             operand2: o2,
             operand3: o3,
+            comments,
+            line: 0,
+            expanded_from: vec![],
+            file: None,
         },
     ))
 }
 
 pub fn instruction(input: &str) -> IResult<&str, AssemblerInstruction> {
-    alt((instruction_combined, directive))(input)
+    alt((comment_line, instruction_combined, directive))(input)
 }
 
 #[cfg(test)]
@@ -180,7 +285,11 @@ mod tests {
                     operand2: Some(Token::LabelUsage {
                         name: "test1".to_string()
                     }),
-                    operand3: None
+                    operand3: None,
+                    comments: vec![],
+                    line: 0,
+                    expanded_from: vec![],
+                    file: None,
                 }
             ))
         )
@@ -199,7 +308,11 @@ mod tests {
                     directive: None,
                     operand1: None,
                     operand2: None,
-                    operand3: None
+                    operand3: None,
+                    comments: vec![],
+                    line: 0,
+                    expanded_from: vec![],
+                    file: None,
                 }
             ))
         )
@@ -218,7 +331,11 @@ mod tests {
                     directive: None,
                     operand1: Some(Token::Register { reg_num: 0 }),
                     operand2: Some(Token::Register { reg_num: 1 }),
-                    operand3: Some(Token::Register { reg_num: 2 })
+                    operand3: Some(Token::Register { reg_num: 2 }),
+                    comments: vec![],
+                    line: 0,
+                    expanded_from: vec![],
+                    file: None,
                 }
             ))
         )
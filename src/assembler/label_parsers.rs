@@ -1,15 +1,25 @@
 use crate::assembler::Token;
 use nom::{
     bytes::complete::tag,
-    character::complete::{alphanumeric1, multispace0, multispace1, newline},
-    combinator::opt,
-    sequence::tuple,
+    character::complete::{alphanumeric1, char, multispace0, multispace1},
+    combinator::{opt, recognize},
+    multi::separated_list1,
+    sequence::{pair, tuple},
     IResult,
 };
 
+/// A label name, with a leading `.` for a local label. A local label may
+/// also be named after the label it belongs to, as in `main.loop`.
+pub fn label_name(input: &str) -> IResult<&str, &str> {
+    recognize(pair(
+        opt(char('.')),
+        separated_list1(char('.'), alphanumeric1),
+    ))(input)
+}
+
 pub fn label_declaration(input: &str) -> IResult<&str, Token> {
     let (input, (_, name, _, _)) =
-        tuple((multispace0, alphanumeric1, tag(":"), opt(multispace1)))(input)?;
+        tuple((multispace0, label_name, tag(":"), opt(multispace1)))(input)?;
 
     Ok((
         input,
@@ -20,8 +30,7 @@ pub fn label_declaration(input: &str) -> IResult<&str, Token> {
 }
 
 pub fn label_usage(input: &str) -> IResult<&str, Token> {
-    let (input, (_, _, name, _)) =
-        tuple((opt(multispace0), tag("@"), alphanumeric1, opt(newline)))(input)?;
+    let (input, (_, _, name)) = tuple((opt(multispace0), tag("@"), label_name))(input)?;
 
     Ok((
         input,
@@ -54,4 +63,27 @@ mod tests {
         let result = label_usage(input);
         assert_eq!(result, Ok(("", expected_token)));
     }
+
+    #[test]
+    fn test_parse_local_labels() {
+        assert_eq!(
+            label_declaration(".loop: inc $0"),
+            Ok((
+                "inc $0",
+                Token::LabelDeclaration {
+                    name: ".loop".to_string()
+                }
+            ))
+        );
+        assert_eq!(
+            label_usage("@1f"),
+            Ok((
+                "",
+                Token::LabelUsage {
+                    name: "1f".to_string()
+                }
+            ))
+        );
+        assert!(label_declaration(".data").is_err());
+    }
 }
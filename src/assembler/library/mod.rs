@@ -0,0 +1,183 @@
+/// The bundled library, by the path `.include <path>` names it with.
+pub const FILES: &[(&str, &str)] = &[
+    ("std/string.sy", include_str!("std/string.sy")),
+    ("std/mem.sy", include_str!("std/mem.sy")),
+    ("std/math.sy", include_str!("std/math.sy")),
+    ("std/heap.sy", include_str!("std/heap.sy")),
+];
+
+/// The source of the library file at `path`.
+pub fn source(path: &str) -> Option<&'static str> {
+    FILES
+        .iter()
+        .find(|(name, _)| *name == path)
+        .map(|(_, source)| *source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assembler::Assembler, vm::VM};
+
+    /// A program that calls `routine` of the library file `path` once.
+    fn program(path: &str, routine: &str) -> Vec<u8> {
+        let source = format!(
+            ".data\n.code\nload $31 @{}\ncall $31\nhlt\n.include <{}>\n",
+            routine, path
+        );
+        Assembler::new().assemble(&source).unwrap()
+    }
+
+    /// Runs `routine` with the arguments `args`, a heap holding `heap` and
+    /// read-only data `ro_data`, returning $0 and the heap.
+    fn call(program: &[u8], args: &[i32], heap: &[u8], ro_data: &[u8]) -> (i32, Vec<u8>) {
+        let mut vm = VM::new();
+        vm.add_bytes(program.to_vec());
+        vm.ro_data = ro_data.to_vec();
+        vm.registers[31] = heap.len() as i32;
+        vm.start().unwrap();
+        // Grow the heap with `aloc $31` ahead of the program.
+        let pc = vm.pc();
+        vm.program.splice(pc..pc, [18, 31, 0, 0]);
+        vm.execute_instructions().unwrap();
+        vm.program.drain(pc..pc + 4);
+        vm.set_pc(pc);
+        vm.heap_mut().copy_from_slice(heap);
+        vm.registers[..args.len()].copy_from_slice(args);
+        while !vm.execute_instructions().unwrap() {}
+        (vm.registers[0], vm.heap().to_vec())
+    }
+
+    /// Numbers for the routines to agree with Rust on.
+    fn samples() -> Vec<i32> {
+        let mut samples = vec![0, 1, -1, 7, -7, 10, 255, 65535, i32::MAX, i32::MIN];
+        let mut state: u32 = 12345;
+        for _ in 0..40 {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            samples.push(state as i32 >> (state % 24));
+        }
+        samples
+    }
+
+    fn strings() -> Vec<&'static [u8]> {
+        vec![
+            b"",
+            b"a",
+            b"ab",
+            b"abc",
+            b"abd",
+            b"b",
+            b"hello world",
+            b"\xff",
+        ]
+    }
+
+    #[test]
+    fn test_library_files_are_objects() {
+        for (path, source) in FILES {
+            let object = Assembler::new().assemble_object(source, path).unwrap();
+            assert!(object.globals().count() > 0, "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_strlen() {
+        let program = program("std/string.sy", "strlen");
+        for string in strings() {
+            let mut heap = vec![9, 9];
+            heap.extend(string);
+            heap.push(0);
+            let (length, _) = call(&program, &[2], &heap, &[]);
+            assert_eq!(length as usize, string.len());
+        }
+    }
+
+    #[test]
+    fn test_strcmp() {
+        let program = program("std/string.sy", "strcmp");
+        for a in strings() {
+            for b in strings() {
+                let mut heap = a.to_vec();
+                heap.push(0);
+                heap.extend(b);
+                heap.push(0);
+                let (result, _) = call(&program, &[0, a.len() as i32 + 1], &heap, &[]);
+                assert_eq!(result, a.cmp(b) as i32, "{:?} {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rostrcpy() {
+        let program = program("std/string.sy", "rostrcpy");
+        let (length, heap) = call(&program, &[3, 1], &[7; 8], b"xx\0hello\0");
+        assert_eq!(length, 5);
+        assert_eq!(heap, b"\x07hello\0\x07");
+    }
+
+    #[test]
+    fn test_itoa() {
+        let program = program("std/string.sy", "itoa");
+        for value in samples() {
+            let (length, heap) = call(&program, &[value, 1], &[7; 16], &[]);
+            let expected = value.to_string();
+            assert_eq!(length as usize, expected.len());
+            assert_eq!(&heap[1..=expected.len()], expected.as_bytes());
+            assert_eq!(heap[expected.len() + 1], 0);
+        }
+    }
+
+    #[test]
+    fn test_memcpy_and_memset() {
+        let source: Vec<u8> = (1..=20).collect();
+        let memcpy = program("std/mem.sy", "memcpy");
+        for n in [0, 1, 5, 8] {
+            let (dst, heap) = call(&memcpy, &[10, 2, n], &source, &[]);
+            let mut expected = source.clone();
+            expected.copy_within(2..2 + n as usize, 10);
+            assert_eq!((dst, heap), (10, expected));
+        }
+        let memset = program("std/mem.sy", "memset");
+        let (_, heap) = call(&memset, &[3, 0x1ff, 4], &source, &[]);
+        let mut expected = source.clone();
+        expected[3..7].fill(0xff);
+        assert_eq!(heap, expected);
+    }
+
+    #[test]
+    fn test_memcmp() {
+        let program = program("std/mem.sy", "memcmp");
+        let heap = b"abcdabzd\xff";
+        for (a, b, n) in [(0, 4, 2), (0, 4, 3), (4, 0, 3), (0, 4, 0), (0, 8, 1)] {
+            let (result, _) = call(&program, &[a, b, n], heap, &[]);
+            let (a, b, n) = (a as usize, b as usize, n as usize);
+            assert_eq!(result, heap[a..a + n].cmp(&heap[b..b + n]) as i32);
+        }
+    }
+
+    #[test]
+    fn test_mulshift_and_rem() {
+        let mulshift = program("std/math.sy", "mulshift");
+        let rem = program("std/math.sy", "rem");
+        let samples = samples();
+        for (a, b) in samples.iter().zip(samples.iter().rev()) {
+            let (product, _) = call(&mulshift, &[*a, *b], &[], &[]);
+            assert_eq!(product, a.wrapping_mul(*b), "{} * {}", a, b);
+            if *b != 0 {
+                let (remainder, _) = call(&rem, &[*a, *b], &[], &[]);
+                assert_eq!(remainder, a.wrapping_rem(*b), "{} % {}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_heap() {
+        let source = ".data\n.code\nload $31 @heapinit\ncall $31\nload $31 @malloc\nload $0 #10\ncall $31\nadd $0 $12 $10\nload $0 #300\ncall $31\nadd $0 $12 $11\nhlt\n.include <std/heap.sy>\n";
+        let mut vm = VM::new();
+        vm.add_bytes(Assembler::new().assemble(source).unwrap());
+        vm.run().unwrap();
+        assert_eq!((vm.registers[10], vm.registers[11]), (4, 14));
+        assert_eq!(vm.heap().len(), 314);
+        assert_eq!(vm.heap()[..4], 314u32.to_be_bytes());
+    }
+}
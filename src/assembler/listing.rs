@@ -0,0 +1,250 @@
+use std::fmt::Write;
+
+use crate::assembler::{
+    debug_info::DebugSection, instruction_parsers::AssemblerInstruction, symbols::Visibility,
+    Assembler,
+};
+
+/// Bytes shown on each row of a listing, and the rows shown for one entry
+/// before the rest are left out, as for a large `.space`.
+const BYTES_PER_ROW: usize = 4;
+const MAX_ROWS: usize = 4;
+
+/// What one assembled instruction or data directive put where.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ListingEntry {
+    /// The file of `line`, `None` for the assembled file.
+    pub file: Option<String>,
+    /// The source line, the outermost macro call for expanded instructions.
+    pub line: u32,
+    pub section: DebugSection,
+    /// Program address for code, read-only data offset for data.
+    pub address: u32,
+    pub bytes: Vec<u8>,
+    /// The instruction as assembled, after macros and pseudo-instructions
+    /// are expanded.
+    pub text: String,
+}
+
+impl ListingEntry {
+    pub fn new(i: &AssemblerInstruction, section: DebugSection, address: u32) -> ListingEntry {
+        ListingEntry {
+            file: i.source_file().map(str::to_string),
+            line: i.source_line(),
+            section,
+            address,
+            bytes: vec![],
+            text: i.to_string(),
+        }
+    }
+}
+
+impl Assembler {
+    /// Each line of `source`, the file just assembled, with the address and
+    /// bytes it produced. Lines expanding to several instructions are
+    /// followed by them, and instructions from included files are shown
+    /// where they were included.
+    pub fn listing(&self, source: &str) -> String {
+        let lines: Vec<&str> = source.lines().map(str::trim_end).collect();
+        let mut out = String::new();
+        let mut printed = 0;
+        let mut entries = self.listing.iter().peekable();
+        while let Some(entry) = entries.next() {
+            let mut group = vec![entry];
+            while let Some(next) =
+                entries.next_if(|next| next.file == entry.file && next.line == entry.line)
+            {
+                group.push(next);
+            }
+            if let Some(file) = &entry.file {
+                for entry in group {
+                    let text = format!("{}:{}: {}", file, entry.line, entry.text);
+                    push_rows(&mut out, "", entry, &text);
+                }
+                continue;
+            }
+
+            let line = entry.line as usize;
+            while printed + 1 < line {
+                printed += 1;
+                push_source(&mut out, printed, lines.get(printed - 1).unwrap_or(&""));
+            }
+            if line > printed {
+                printed = line;
+                let text = lines.get(line - 1).unwrap_or(&"");
+                if let [entry] = group[..] {
+                    push_rows(&mut out, &line.to_string(), entry, text);
+                    continue;
+                }
+                push_source(&mut out, line, text);
+            }
+            for entry in group {
+                push_rows(&mut out, "", entry, &format!("    {}", entry.text));
+            }
+        }
+        while printed < lines.len() {
+            printed += 1;
+            push_source(&mut out, printed, lines[printed - 1]);
+        }
+        out
+    }
+
+    /// Every symbol with its section, address, size and type: labels with
+    /// the bytes up to the next label, then constants, whose value is
+    /// given as their address, and external symbols.
+    pub fn symbol_map(&self) -> String {
+        let mut rows = vec![[
+            "Symbol".to_string(),
+            "Section".to_string(),
+            "Address".to_string(),
+            "Size".to_string(),
+            "Type".to_string(),
+        ]];
+        for symbol in self.debug_info("").symbols {
+            let visibility = self
+                .symbols
+                .symbol(&symbol.name)
+                .map_or(Visibility::Local, |s| s.visibility());
+            rows.push([
+                symbol.name,
+                section_name(symbol.section).to_string(),
+                format!("{:04x}", symbol.start),
+                (symbol.end - symbol.start).to_string(),
+                match visibility {
+                    Visibility::Global => "global",
+                    _ => "local",
+                }
+                .to_string(),
+            ]);
+        }
+        for symbol in self.symbols.symbols() {
+            let (section, address, kind) = match symbol.offset() {
+                Some(value) if symbol.is_constant() => {
+                    ("abs", format!("{:04x}", value), "constant")
+                }
+                _ if symbol.visibility() == Visibility::Extern => ("-", "-".to_string(), "extern"),
+                _ => continue,
+            };
+            rows.push([
+                symbol.name().to_string(),
+                section.to_string(),
+                address,
+                "-".to_string(),
+                kind.to_string(),
+            ]);
+        }
+
+        let name_width = rows.iter().map(|row| row[0].len()).max().unwrap_or(0);
+        let mut out = String::new();
+        for [name, section, address, size, kind] in rows {
+            let row = format!(
+                "{:name_width$}  {:7}  {:>8}  {:>5}  {}",
+                name, section, address, size, kind
+            );
+            writeln!(out, "{}", row.trim_end()).unwrap();
+        }
+        out
+    }
+}
+
+fn section_name(section: DebugSection) -> &'static str {
+    match section {
+        DebugSection::Code => "code",
+        DebugSection::Data => "data",
+    }
+}
+
+/// A source line that produced no bytes of its own.
+fn push_source(out: &mut String, line: usize, text: &str) {
+    let row = format!("{:>5}  {:9}  {:11}  {}", line, "", "", text);
+    writeln!(out, "{}", row.trim_end()).unwrap();
+}
+
+/// The bytes of `entry`, `BYTES_PER_ROW` to a row, the first row labelled
+/// with `line` and `text`.
+fn push_rows(out: &mut String, line: &str, entry: &ListingEntry, text: &str) {
+    let section = section_name(entry.section);
+    for (row, chunk) in entry.bytes.chunks(BYTES_PER_ROW).enumerate() {
+        if row == MAX_ROWS {
+            writeln!(out, "{:5}  {:9}  ...", "", "").unwrap();
+            break;
+        }
+        let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let (line, text) = if row == 0 { (line, text) } else { ("", "") };
+        let address = entry.address as usize + row * BYTES_PER_ROW;
+        let row = format!(
+            "{:>5}  {}:{:04x}  {:11}  {}",
+            line,
+            section,
+            address,
+            hex.join(" "),
+            text
+        );
+        writeln!(out, "{}", row.trim_end()).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::Assembler;
+
+    #[test]
+    fn test_listing() {
+        let source = ".data\nmsg: .asciiz \"Hi\"\n.code\n; start\nmain: load $0 #5\nli $1 #70000\nprts @msg\nhlt\n";
+        let mut asm = Assembler::new();
+        asm.assemble(source).unwrap();
+        assert_eq!(
+            asm.listing(source),
+            [
+                "    1                          .data",
+                "    2  data:0000  48 69 00     msg: .asciiz \"Hi\"",
+                "    3                          .code",
+                "    4                          ; start",
+                "    5  code:0041  00 00 00 05  main: load $0 #5",
+                "    6                          li $1 #70000",
+                "       code:0045  00 01 00 01      load $1 #1",
+                "       code:0049  00 1f 01 00      load $31 #256",
+                "       code:004d  03 01 1f 01      mul $1 $31 $1",
+                "       code:0051  03 01 1f 01      mul $1 $31 $1",
+                "       code:0055  00 1f 11 70      load $31 #4464",
+                "       code:0059  01 01 1f 01      add $1 $31 $1",
+                "    7  code:005d  17 00 00 00  prts @msg",
+                "    8  code:0061  05 00 00 00  hlt",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_listing_of_long_data() {
+        let source = ".data\nbuf: .space 20\n.code\nhlt";
+        let mut asm = Assembler::new();
+        asm.assemble(source).unwrap();
+        let listing = asm.listing(source);
+        let rows: Vec<&str> = listing.lines().collect();
+        assert_eq!(rows[1], "    2  data:0000  00 00 00 00  buf: .space 20");
+        assert_eq!(rows[2], "       data:0004  00 00 00 00");
+        assert_eq!(rows[5], "                  ...");
+        assert_eq!(rows[6], "    3                          .code");
+    }
+
+    #[test]
+    fn test_symbol_map() {
+        let source = ".data\n.equ SIZE 4\nmsg: .asciiz \"Hi\"\n.code\n.global main\nmain: load $0 #SIZE\n.loop: dec $0\nhlt\n";
+        let mut asm = Assembler::new();
+        asm.assemble(source).unwrap();
+        assert_eq!(
+            asm.symbol_map(),
+            [
+                "Symbol     Section   Address   Size  Type",
+                "msg        data         0000      3  local",
+                "main       code         0041      4  global",
+                "main.loop  code         0045      8  local",
+                "SIZE       abs          0004      -  constant",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+}
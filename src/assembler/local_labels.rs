@@ -0,0 +1,291 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::assembler::{
+    assembler_errors::AssemblerError,
+    expressions::Expr,
+    includes::in_file,
+    instruction_parsers::AssemblerInstruction,
+    macros::{in_macro, rename_labels},
+    program_parsers::Program,
+    Token,
+};
+
+/// Whether `name` is a local label, `.loop`, which belongs to the label
+/// before it.
+pub fn is_local(name: &str) -> bool {
+    name.starts_with('.')
+}
+
+/// Whether `name` is an anonymous label, `1`, which is referred to as `1b`
+/// before or `1f` after it.
+pub fn is_anonymous(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Gives local labels the name of the label they belong to, `main.loop`,
+/// and each anonymous label a name of its own, `1.2` for the second `1`,
+/// renaming the references to them to match. Labels from macro expansion
+/// do not start a scope.
+pub fn scope_labels(program: Program) -> Result<Program, Vec<AssemblerError>> {
+    let mut scopes = vec![];
+    let mut anonymous: HashMap<String, Vec<(usize, String)>> = HashMap::new();
+    let mut locals = HashSet::new();
+    let mut scope: Option<String> = None;
+    for (index, instruction) in program.instructions.iter().enumerate() {
+        if let Some(name) = instruction.get_label_name() {
+            if is_anonymous(&name) {
+                let declared = anonymous.entry(name.clone()).or_default();
+                let renamed = format!("{}.{}", name, declared.len() + 1);
+                declared.push((index, renamed));
+            } else if is_local(&name) {
+                if let Some(scope) = &scope {
+                    locals.insert(format!("{}{}", scope, name));
+                }
+            } else if instruction.expanded_from.is_empty() {
+                scope = Some(name);
+            }
+        }
+        scopes.push(scope.clone());
+    }
+    let labels = Labels { anonymous, locals };
+
+    let mut errors = vec![];
+    let mut instructions = vec![];
+    for (index, mut instruction) in program.instructions.into_iter().enumerate() {
+        let mut renames = HashMap::new();
+        let mut names: Vec<String> = instruction.get_label_name().into_iter().collect();
+        for operand in [
+            &instruction.operand1,
+            &instruction.operand2,
+            &instruction.operand3,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            label_names(operand, &mut names);
+        }
+        for name in names {
+            match labels.resolve(&name, index, instruction.line, &scopes[index]) {
+                Ok(Some(renamed)) => {
+                    renames.insert(name, renamed);
+                }
+                Ok(None) => {}
+                Err(error) => errors.push(in_macro(
+                    in_file(error, &instruction.file),
+                    &instruction.expanded_from,
+                )),
+            }
+        }
+        if !renames.is_empty() {
+            rename_instruction(&mut instruction, &renames);
+        }
+        instructions.push(instruction);
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(Program { instructions })
+}
+
+/// The anonymous labels declared, with the index of the instruction
+/// declaring each and its new name, and the scoped names of local labels.
+struct Labels {
+    anonymous: HashMap<String, Vec<(usize, String)>>,
+    locals: HashSet<String>,
+}
+
+impl Labels {
+    /// The name the label `name`, used or declared by the instruction at
+    /// `index`, is given, or `None` if it keeps its own.
+    fn resolve(
+        &self,
+        name: &str,
+        index: usize,
+        line: u32,
+        scope: &Option<String>,
+    ) -> Result<Option<String>, AssemblerError> {
+        let undefined = || AssemblerError::UndefinedSymbol {
+            name: name.to_string(),
+            line,
+        };
+        if is_local(name) {
+            return match scope {
+                Some(scope) => {
+                    let renamed = format!("{}{}", scope, name);
+                    match self.locals.contains(&renamed) {
+                        true => Ok(Some(renamed)),
+                        false => Err(AssemblerError::UndefinedSymbol {
+                            name: renamed,
+                            line,
+                        }),
+                    }
+                }
+                None => Err(AssemblerError::LocalLabelWithoutScope {
+                    name: name.to_string(),
+                    line,
+                }),
+            };
+        }
+        let declared = |number: &str| self.anonymous.get(number).map_or(&[][..], Vec::as_slice);
+        if is_anonymous(name) {
+            let renamed = declared(name).iter().find(|(at, _)| *at == index);
+            return Ok(renamed.map(|(_, renamed)| renamed.clone()));
+        }
+        let found = match name.split_at(name.len() - 1) {
+            (number, "b") if is_anonymous(number) => {
+                declared(number).iter().rev().find(|(at, _)| *at <= index)
+            }
+            (number, "f") if is_anonymous(number) => {
+                declared(number).iter().find(|(at, _)| *at > index)
+            }
+            _ => return Ok(None),
+        };
+        match found {
+            Some((_, renamed)) => Ok(Some(renamed.clone())),
+            None => Err(undefined()),
+        }
+    }
+}
+
+/// Adds the labels `token` refers to to `names`.
+fn label_names(token: &Token, names: &mut Vec<String>) {
+    match token {
+        Token::LabelUsage { name } => names.push(name.clone()),
+        Token::Expression { expr } => expr_label_names(expr, names),
+        Token::List { items } => items.iter().for_each(|item| label_names(item, names)),
+        _ => {}
+    }
+}
+
+fn expr_label_names(expr: &Expr, names: &mut Vec<String>) {
+    match expr {
+        Expr::Label(name) => names.push(name.clone()),
+        Expr::Negate(operand) | Expr::Not(operand) => expr_label_names(operand, names),
+        Expr::Binary(_, left, right) => {
+            expr_label_names(left, names);
+            expr_label_names(right, names);
+        }
+        Expr::Number(_) | Expr::Constant(_) => {}
+    }
+}
+
+fn rename_instruction(instruction: &mut AssemblerInstruction, renames: &HashMap<String, String>) {
+    if let Some(Token::LabelDeclaration { name }) = &mut instruction.label {
+        if let Some(renamed) = renames.get(name) {
+            *name = renamed.clone();
+        }
+    }
+    for operand in [
+        &mut instruction.operand1,
+        &mut instruction.operand2,
+        &mut instruction.operand3,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        rename_token(operand, renames);
+    }
+}
+
+fn rename_token(token: &mut Token, renames: &HashMap<String, String>) {
+    match token {
+        Token::LabelUsage { name } => {
+            if let Some(renamed) = renames.get(name) {
+                *name = renamed.clone();
+            }
+        }
+        Token::Expression { expr } => *expr = rename_labels(expr, renames),
+        Token::List { items } => items
+            .iter_mut()
+            .for_each(|item| rename_token(item, renames)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::program_parsers::program;
+
+    fn scope(source: &str) -> Result<Vec<String>, Vec<String>> {
+        let (_, parsed) = program(source).unwrap();
+        match scope_labels(parsed) {
+            Ok(scoped) => Ok(scoped.instructions.iter().map(|i| i.to_string()).collect()),
+            Err(errors) => Err(errors.iter().map(|e| e.to_string()).collect()),
+        }
+    }
+
+    #[test]
+    fn test_local_labels() {
+        assert_eq!(
+            scope("first: load $0 @.loop\n.loop: inc $0\nsecond: load $1 @.loop+4\n.loop: hlt\n.word @first.loop"),
+            Ok(vec![
+                "first: load $0 @first.loop".to_string(),
+                "first.loop: inc $0".to_string(),
+                "second: load $1 @second.loop+4".to_string(),
+                "second.loop: hlt".to_string(),
+                ".word @first.loop".to_string(),
+            ])
+        );
+        assert_eq!(
+            scope(".loop: hlt\n"),
+            Err(vec![
+                "Local label .loop on line 1 has no label before it".to_string()
+            ])
+        );
+        assert_eq!(
+            scope("first: load $0 @.loop\nsecond: hlt\n.loop: hlt"),
+            Err(vec!["Undefined symbol first.loop on line 1".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_macro_labels_keep_the_scope() {
+        let source = ".data\n.macro spin reg\ntop: dec \\reg\nload $30 @top\njgt $30\n.endm\n.code\nmain: load $1 #2\n.start: spin $1\nb @.start\n";
+        let mut asm = crate::assembler::Assembler::new();
+        asm.assemble(source).unwrap();
+        assert_eq!(asm.symbols.symbol_value("main.start"), Some(69));
+        let listing = asm.listing(source);
+        assert!(listing.contains("load $31 @main.start"), "{}", listing);
+    }
+
+    #[test]
+    fn test_anonymous_labels() {
+        assert_eq!(
+            scope("1: load $0 @1f\n1: load $1 @1b\nload $2 @1b\n2: load $3 @1f\n1: jmp $0"),
+            Ok(vec![
+                "1.1: load $0 @1.2".to_string(),
+                "1.2: load $1 @1.2".to_string(),
+                "load $2 @1.2".to_string(),
+                "2.1: load $3 @1.3".to_string(),
+                "1.3: jmp $0".to_string(),
+            ])
+        );
+        assert_eq!(
+            scope("1: load $0 @1f\nload $1 @2b\nload $1 @1\n"),
+            Err(vec![
+                "Undefined symbol 1f on line 1".to_string(),
+                "Undefined symbol 2b on line 2".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_scoped_names_in_debug_info() {
+        let source = ".data\n.code\nmain: load $0 #3\nload $1 @.loop\n.loop: dec $0\nload $2 #0\nload $3 @1f\neq $0 $2\njeq $3\njmp $1\n1: hlt\n";
+        let mut asm = crate::assembler::Assembler::new();
+        let program = asm.assemble(source).unwrap();
+        let info = asm.debug_info("loop.sy");
+        assert_eq!(
+            info.code_labels(),
+            vec![
+                ("main".to_string(), 65),
+                ("main.loop".to_string(), 73),
+                ("1.1".to_string(), 97),
+            ]
+        );
+        let listing = crate::disassembler::listing(&program, 65, Some(&info));
+        assert!(listing.contains("main.loop:\n"), "{}", listing);
+        assert!(listing.contains("load $3 @1.1"), "{}", listing);
+    }
+}
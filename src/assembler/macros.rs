@@ -0,0 +1,440 @@
+use std::collections::HashMap;
+
+use crate::{
+    assembler::{
+        assembler_errors::AssemblerError, expressions::Expr, includes::in_file,
+        instruction_parsers::AssemblerInstruction, program_parsers::Program, pseudo, Token,
+    },
+    instruction::Opcode,
+};
+
+/// How deeply macro calls may nest, which also stops recursive macros.
+const MAX_DEPTH: usize = 64;
+
+/// A macro call an instruction was expanded from.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MacroCall {
+    pub name: String,
+    /// Line of the call.
+    pub line: u32,
+    /// The included file of the call, `None` for the file being assembled.
+    pub file: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<AssemblerInstruction>,
+}
+
+/// Expands `.macro name param, ... .endm` definitions at their calls.
+/// Parameters are written `\param` in the body, and labels declared in
+/// the body are renamed for every expansion so each call gets its own.
+/// Pseudo-instructions, which macros may shadow, are lowered here too.
+#[derive(Debug, Default)]
+pub struct MacroExpander {
+    macros: HashMap<String, Macro>,
+    expansions: u32,
+    errors: Vec<AssemblerError>,
+}
+
+impl MacroExpander {
+    pub fn new() -> MacroExpander {
+        MacroExpander::default()
+    }
+
+    /// Removes the macro definitions from `program` and replaces every
+    /// call with the body of its macro.
+    pub fn expand(mut self, program: Program) -> Result<Program, Vec<AssemblerError>> {
+        let rest = self.collect_definitions(program.instructions);
+        let mut instructions = vec![];
+        for instruction in rest {
+            self.expand_instruction(instruction, &[], &mut instructions);
+        }
+        if !self.errors.is_empty() {
+            return Err(self.errors);
+        }
+        Ok(Program { instructions })
+    }
+
+    fn collect_definitions(
+        &mut self,
+        instructions: Vec<AssemblerInstruction>,
+    ) -> Vec<AssemblerInstruction> {
+        let mut rest = vec![];
+        // The macro being defined; the name is empty if its `.macro` line
+        // was invalid, so the body is skipped.
+        let mut current: Option<(String, u32, Option<String>, Macro)> = None;
+        for instruction in instructions {
+            match instruction.get_directive_name().as_deref() {
+                Some("macro") if current.is_some() => {
+                    let error = AssemblerError::NestedMacroDefinition {
+                        line: instruction.line,
+                    };
+                    self.errors.push(in_file(error, &instruction.file));
+                }
+                Some("macro") => {
+                    let (name, params) = self.definition(&instruction).unwrap_or_default();
+                    let body = Macro {
+                        params,
+                        body: vec![],
+                    };
+                    current = Some((name, instruction.line, instruction.file.clone(), body));
+                }
+                Some("endm") => match current.take() {
+                    Some((name, _, _, _)) if name.is_empty() => {}
+                    Some((name, line, file, definition)) => {
+                        let is_instruction = Opcode::from(name.to_lowercase().as_str())
+                            != Opcode::IGL
+                            || name.eq_ignore_ascii_case("igl");
+                        if is_instruction || self.macros.contains_key(&name) {
+                            let error = AssemblerError::MacroAlreadyDefined { name, line };
+                            self.errors.push(in_file(error, &file));
+                        } else {
+                            self.macros.insert(name, definition);
+                        }
+                    }
+                    None => {
+                        let error = AssemblerError::UnmatchedEndm {
+                            line: instruction.line,
+                        };
+                        self.errors.push(in_file(error, &instruction.file));
+                    }
+                },
+                _ => match &mut current {
+                    Some((_, _, _, definition)) => definition.body.push(instruction),
+                    None => rest.push(instruction),
+                },
+            }
+        }
+        if let Some((name, line, file, _)) = current {
+            let error = AssemblerError::UnterminatedMacro { name, line };
+            self.errors.push(in_file(error, &file));
+        }
+        rest
+    }
+
+    /// The name and parameters of a `.macro` line.
+    fn definition(&mut self, instruction: &AssemblerInstruction) -> Option<(String, Vec<String>)> {
+        let names: Option<Vec<String>> = instruction
+            .directive_operands()
+            .into_iter()
+            .map(|operand| match operand {
+                Token::Expression {
+                    expr: Expr::Constant(name),
+                } => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+        match names {
+            Some(mut names) if !names.is_empty() => {
+                let name = names.remove(0);
+                Some((name, names))
+            }
+            _ => {
+                let error = AssemblerError::InvalidDirectiveOperand {
+                    directive: "macro".to_string(),
+                    line: instruction.line,
+                };
+                self.errors.push(in_file(error, &instruction.file));
+                None
+            }
+        }
+    }
+
+    /// Appends `instruction` to `output`, expanding it first if it calls a
+    /// macro. `calls` are the calls being expanded, outermost first.
+    fn expand_instruction(
+        &mut self,
+        instruction: AssemblerInstruction,
+        calls: &[MacroCall],
+        output: &mut Vec<AssemblerInstruction>,
+    ) {
+        let (name, definition) = match &instruction.opcode {
+            Some(Token::MacroCall { name }) if self.macros.contains_key(name) => {
+                (name.clone(), self.macros[name].clone())
+            }
+            _ => {
+                self.push_instruction(instruction, calls, output);
+                return;
+            }
+        };
+        let line = instruction.line;
+        if calls.len() >= MAX_DEPTH {
+            let error = AssemblerError::MacroRecursion { name, line };
+            self.errors
+                .push(in_macro(in_file(error, &instruction.file), calls));
+            return;
+        }
+        let args: Vec<Token> = instruction
+            .directive_operands()
+            .into_iter()
+            .cloned()
+            .collect();
+        if args.len() != definition.params.len() {
+            let error = AssemblerError::MacroArguments {
+                name,
+                expected: definition.params.len(),
+                found: args.len(),
+                line,
+            };
+            self.errors
+                .push(in_macro(in_file(error, &instruction.file), calls));
+            return;
+        }
+
+        self.expansions += 1;
+        let mut renames: HashMap<String, String> = definition
+            .body
+            .iter()
+            .filter_map(|i| i.get_label_name())
+            .map(|label| {
+                let renamed = format!("{}.{}.{}", name, self.expansions, label);
+                (label, renamed)
+            })
+            .collect();
+        let mut call_label = instruction.get_label_name();
+        if let Some(label) = &call_label {
+            // The call's label names the first instruction of the
+            // expansion, standing in for any local label already there.
+            let first = definition.body.iter().find(|i| !i.is_comment_only());
+            if let Some(local) = first.and_then(|i| i.get_label_name()) {
+                renames.insert(local, label.clone());
+                call_label = None;
+            }
+        }
+
+        let mut chain = calls.to_vec();
+        chain.push(MacroCall {
+            name,
+            line,
+            file: instruction.file.clone(),
+        });
+        for mut body_instruction in definition.body {
+            if let Some(Token::LabelDeclaration { name }) = &mut body_instruction.label {
+                if let Some(renamed) = renames.get(name) {
+                    *name = renamed.clone();
+                }
+            }
+            if call_label.is_some() && !body_instruction.is_comment_only() {
+                body_instruction.label = call_label
+                    .take()
+                    .map(|name| Token::LabelDeclaration { name });
+            }
+            for operand in [
+                &mut body_instruction.operand1,
+                &mut body_instruction.operand2,
+                &mut body_instruction.operand3,
+            ]
+            .into_iter()
+            .flatten()
+            {
+                match substitute(operand, &definition.params, &args, &renames) {
+                    Ok(substituted) => *operand = substituted,
+                    Err(param) => {
+                        let error = AssemblerError::UndefinedMacroParameter {
+                            name: param,
+                            line: body_instruction.line,
+                        };
+                        let error = in_file(error, &body_instruction.file);
+                        self.errors.push(in_macro(error, &chain));
+                    }
+                }
+            }
+            body_instruction.expanded_from = chain.clone();
+            self.expand_instruction(body_instruction, &chain, output);
+        }
+    }
+
+    /// Appends `instruction`, which calls no macro, to `output`, lowering
+    /// it if it is a pseudo-instruction.
+    fn push_instruction(
+        &mut self,
+        instruction: AssemblerInstruction,
+        calls: &[MacroCall],
+        output: &mut Vec<AssemblerInstruction>,
+    ) {
+        match pseudo::expand(&instruction) {
+            Some(Ok(lowered)) => output.extend(lowered),
+            Some(Err(error)) => self
+                .errors
+                .push(in_macro(in_file(error, &instruction.file), calls)),
+            None if instruction.is_macro_call() => output.push(unknown_instruction(instruction)),
+            None => output.push(instruction),
+        }
+    }
+}
+
+/// A call to a macro that does not exist assembles to `igl`, as unknown
+/// mnemonics always have.
+fn unknown_instruction(mut instruction: AssemblerInstruction) -> AssemblerInstruction {
+    instruction.opcode = Some(Token::Op { code: Opcode::IGL });
+    if let Some(Token::List { items }) = instruction.operand1.take() {
+        let mut items = items.into_iter();
+        instruction.operand1 = items.next();
+        instruction.operand2 = items.next();
+        instruction.operand3 = items.next();
+    }
+    instruction
+}
+
+/// Replaces parameters with the call's arguments and local labels with
+/// their names for this expansion. Fails with the name of an unknown
+/// parameter.
+fn substitute(
+    token: &Token,
+    params: &[String],
+    args: &[Token],
+    renames: &HashMap<String, String>,
+) -> Result<Token, String> {
+    Ok(match token {
+        Token::MacroParam { name } => match params.iter().position(|p| p == name) {
+            Some(index) => args[index].clone(),
+            None => return Err(name.clone()),
+        },
+        Token::LabelUsage { name } => Token::LabelUsage {
+            name: renames.get(name).unwrap_or(name).clone(),
+        },
+        Token::Expression { expr } => Token::Expression {
+            expr: rename_labels(expr, renames),
+        },
+        Token::List { items } => Token::List {
+            items: items
+                .iter()
+                .map(|item| substitute(item, params, args, renames))
+                .collect::<Result<Vec<Token>, String>>()?,
+        },
+        token => token.clone(),
+    })
+}
+
+/// `expr` with the labels in `renames` renamed.
+pub fn rename_labels(expr: &Expr, renames: &HashMap<String, String>) -> Expr {
+    match expr {
+        Expr::Label(name) => Expr::Label(renames.get(name).unwrap_or(name).clone()),
+        Expr::Negate(operand) => Expr::Negate(Box::new(rename_labels(operand, renames))),
+        Expr::Not(operand) => Expr::Not(Box::new(rename_labels(operand, renames))),
+        Expr::Binary(op, left, right) => Expr::Binary(
+            *op,
+            Box::new(rename_labels(left, renames)),
+            Box::new(rename_labels(right, renames)),
+        ),
+        expr => expr.clone(),
+    }
+}
+
+/// Adds the macro calls an error happened in, so it reports the call site
+/// as well as the line in the macro body.
+pub fn in_macro(error: AssemblerError, calls: &[MacroCall]) -> AssemblerError {
+    calls
+        .iter()
+        .rev()
+        .fold(error, |error, call| AssemblerError::InMacro {
+            error: Box::new(error),
+            name: call.name.clone(),
+            line: call.line,
+            file: call.file.clone(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::program_parsers::program;
+
+    fn expand(source: &str) -> Result<Vec<String>, Vec<String>> {
+        let (_, parsed) = program(source).unwrap();
+        match MacroExpander::new().expand(parsed) {
+            Ok(expanded) => Ok(expanded
+                .instructions
+                .iter()
+                .map(|i| format!("{}: {}", i.source_line(), i))
+                .collect()),
+            Err(errors) => Err(errors.iter().map(|e| e.to_string()).collect()),
+        }
+    }
+
+    const COUNTDOWN: &str = ".macro countdown reg, from
+    load \\reg \\from
+loop: dec \\reg
+    jneq @loop
+.endm
+.data
+.code
+countdown $1, #3
+start: countdown $2 #5
+hlt
+";
+
+    #[test]
+    fn test_parameters_and_local_labels() {
+        assert_eq!(
+            expand(COUNTDOWN),
+            Ok(vec![
+                "6: .data".to_string(),
+                "7: .code".to_string(),
+                "8: load $1 #3".to_string(),
+                "8: countdown.1.loop: dec $1".to_string(),
+                "8: jneq @countdown.1.loop".to_string(),
+                "9: start: load $2 #5".to_string(),
+                "9: countdown.2.loop: dec $2".to_string(),
+                "9: jneq @countdown.2.loop".to_string(),
+                "10: hlt".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_call_label_replaces_first_local_label() {
+        let source = ".macro spin\ntop: jmp @top\n.endm\nforever: spin\n";
+        assert_eq!(
+            expand(source),
+            Ok(vec!["4: forever: jmp @forever".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_unknown_names_stay_illegal_instructions() {
+        assert_eq!(
+            expand("nothing $1, $2\n"),
+            Ok(vec!["1: igl $1 $2".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_nested_calls() {
+        let source = ".macro twice r\ninc \\r\ninc \\r\n.endm\n.macro four r\ntwice \\r\ntwice \\r\n.endm\nfour $3\n";
+        let expanded = expand(source).unwrap();
+        assert_eq!(expanded.len(), 4);
+        assert!(expanded.iter().all(|line| line == "9: inc $3"));
+    }
+
+    #[test]
+    fn test_errors_point_at_call_and_body() {
+        let source = ".macro bad r\nload \\r \\value\n.endm\n.macro outer\nbad $1\n.endm\nouter\n";
+        assert_eq!(
+            expand(source),
+            Err(vec![
+                "Unknown macro parameter \\value on line 2, in macro bad called on line 5, in macro outer called on line 7".to_string()
+            ])
+        );
+        assert_eq!(
+            expand(".macro one a\n.endm\none\n"),
+            Err(vec![
+                "Macro one takes 1 argument(s), 0 given on line 3".to_string()
+            ])
+        );
+        assert!(
+            expand(".macro loopy\nloopy\n.endm\nloopy\n").unwrap_err()[0]
+                .starts_with("Macro calls nested more than 64 deep expanding loopy on line 2")
+        );
+        assert_eq!(
+            expand(".macro load\n.endm\n.endm\n.macro open\n"),
+            Err(vec![
+                "Macro load on line 1 is already defined or is an instruction".to_string(),
+                ".endm without a .macro on line 3".to_string(),
+                "Macro open defined on line 4 has no .endm".to_string(),
+            ])
+        );
+    }
+}
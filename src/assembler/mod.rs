@@ -1,34 +1,103 @@
 pub mod assembler_errors;
+pub mod comment_parsers;
+pub mod conditionals;
+pub mod debug_info;
 pub mod directive_parsers;
+pub mod expressions;
+pub mod formatter;
+pub mod includes;
 pub mod instruction_parsers;
 pub mod label_parsers;
+pub mod library;
+pub mod listing;
+pub mod local_labels;
+pub mod macros;
+pub mod object;
 pub mod opcode_parsers;
 pub mod operand_parsers;
 pub mod program_parsers;
+pub mod pseudo;
 pub mod register_parsers;
+pub mod source_map;
 pub mod symbols;
 
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+};
+
 use crate::instruction::Opcode;
 
 use self::{
     assembler_errors::AssemblerError,
+    conditionals::Conditionals,
+    debug_info::{DebugInfo, DebugSection, DebugSymbol, LabelRef},
+    expressions::{identifier, Expr, ExprError},
+    includes::{in_file, IncludeExpander},
     instruction_parsers::AssemblerInstruction,
-    program_parsers::{program, Program},
-    symbols::{Symbol, SymbolTable, SymbolType},
+    listing::ListingEntry,
+    local_labels::scope_labels,
+    macros::{in_macro, MacroCall, MacroExpander},
+    object::{ObjectFile, ObjectSection, ObjectSymbol, Relocation, RelocationTarget},
+    operand_parsers::integer,
+    program_parsers::{parse_error, program, unparsed_error, Program},
+    source_map::SourceMap,
+    symbols::{Symbol, SymbolTable, SymbolType, Visibility},
 };
 
 pub const PIE_HEADER_PREFIX: [u8; 4] = [45, 50, 49, 45];
 pub const PIE_HEADER_LENGTH: usize = 64;
+/// Address of the first instruction, directly after the header.
+pub const PIE_CODE_START: usize = PIE_HEADER_LENGTH + 1;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Token {
-    Op { code: Opcode },
-    Register { reg_num: u8 },
-    IntegerOperand { value: i32 },
-    LabelDeclaration { name: String },
-    LabelUsage { name: String },
-    Directive { name: String },
-    SyString { name: String },
+    Op {
+        code: Opcode,
+    },
+    Register {
+        reg_num: u8,
+    },
+    IntegerOperand {
+        value: i32,
+    },
+    LabelDeclaration {
+        name: String,
+    },
+    LabelUsage {
+        name: String,
+    },
+    Directive {
+        name: String,
+    },
+    SyString {
+        name: String,
+    },
+    /// `<std/string.sy>`, a file of the bundled library for `.include`.
+    LibraryPath {
+        name: String,
+    },
+    /// An operand the assembler computes, e.g. `#SIZE*2` or `@table+4`.
+    Expression {
+        expr: Expr,
+    },
+    /// The comma-separated operands of a data directive.
+    List {
+        items: Vec<Token>,
+    },
+    /// The name of a macro being called, in place of a mnemonic.
+    MacroCall {
+        name: String,
+    },
+    /// `\name`, a parameter in the body of a macro.
+    MacroParam {
+        name: String,
+    },
+    /// A `;` line comment, or a `/* */` block comment when `block` is set.
+    Comment {
+        text: String,
+        block: bool,
+    },
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -43,16 +112,80 @@ impl Default for AssemblerPhase {
     }
 }
 
-#[derive(Debug, Default)]
 pub struct Assembler {
     pub phase: AssemblerPhase,
     pub symbols: SymbolTable,
+    pub source_map: SourceMap,
+    pub label_refs: Vec<LabelRef>,
     pub ro: Vec<u8>,
     ro_offset: u32,
+    code_offset: u32,
     sections: Vec<AssemblerSection>,
     current_section: Option<AssemblerSection>,
     current_instruction: u32,
     errors: Vec<AssemblerError>,
+    data_refs: Vec<DataRef>,
+    conditionals: Conditionals,
+    source_path: Option<PathBuf>,
+    include_paths: Vec<PathBuf>,
+    /// Names of the included files, numbered from 1 in the source map.
+    files: Vec<String>,
+    /// Whether an object file is being assembled, so `.extern` symbols
+    /// are allowed and relocations are recorded.
+    relocatable: bool,
+    relocations: Vec<Relocation>,
+    /// Names given to `.global` and their lines, checked once every label
+    /// is declared.
+    globals: Vec<(String, u32)>,
+    /// Where each instruction and data directive was put, for listings.
+    pub listing: Vec<ListingEntry>,
+    /// Where the assembler's own messages go, stdout unless replaced.
+    pub messages: Box<dyn Write>,
+}
+
+/// A label or expression used as a `.byte`, `.half` or `.word` value,
+/// filled in after the first phase.
+#[derive(Debug)]
+struct DataRef {
+    offset: u32,
+    width: usize,
+    value: Expr,
+    directive: String,
+    line: u32,
+    expanded_from: Vec<MacroCall>,
+    file: Option<String>,
+}
+
+/// Range of the 16-bit immediate operands of instructions, signed or
+/// unsigned.
+const IMMEDIATE_RANGE: std::ops::RangeInclusive<i32> = -32768..=65535;
+
+/// Range of the immediate operands that are sign-extended.
+const SIGNED_IMMEDIATE_RANGE: std::ops::RangeInclusive<i32> = -32768..=32767;
+
+/// Whether `value` fits in `width` bytes as a signed or unsigned number.
+fn fits(value: i64, width: usize) -> bool {
+    let bits = width as u32 * 8;
+    value >= -(1 << (bits - 1)) && value < (1 << bits)
+}
+
+/// Parses a `-D` flag, `NAME=value` or just `NAME` for 1.
+pub fn parse_define(s: &str) -> Result<(String, i32), String> {
+    let (name, value) = s.split_once('=').unwrap_or((s, "1"));
+    match identifier(name) {
+        Ok(("", _)) => {}
+        _ => return Err(format!("Invalid constant name {}", name)),
+    }
+    match integer(value) {
+        Ok(("", value)) => Ok((name.to_string(), value)),
+        _ => Err(format!("Invalid value {} for {}", value, name)),
+    }
+}
+
+impl Default for Assembler {
+    fn default() -> Assembler {
+        Assembler::new()
+    }
 }
 
 impl Assembler {
@@ -60,47 +193,150 @@ impl Assembler {
         Assembler {
             phase: AssemblerPhase::First,
             symbols: SymbolTable::new(),
+            source_map: SourceMap::new(),
+            label_refs: vec![],
             ro: vec![],
             ro_offset: 0,
+            code_offset: PIE_CODE_START as u32,
             sections: vec![],
             current_section: None,
             errors: vec![],
             current_instruction: 0,
+            data_refs: vec![],
+            conditionals: Conditionals::new(),
+            source_path: None,
+            include_paths: vec![],
+            files: vec![],
+            relocatable: false,
+            relocations: vec![],
+            globals: vec![],
+            listing: vec![],
+            messages: Box::new(io::stdout()),
         }
     }
 
+    /// Sets the file the source is read from, which relative `.include`
+    /// paths start from. Without it they start from the current directory.
+    pub fn set_source_path(&mut self, path: &str) {
+        self.source_path = Some(PathBuf::from(path));
+    }
+
+    /// Adds a directory to look for included files in, after the directory
+    /// of the including file, e.g. from a `-I dir` flag.
+    pub fn add_include_path(&mut self, path: &str) {
+        self.include_paths.push(PathBuf::from(path));
+    }
+
+    /// Defines a constant before assembling, as if by `.equ`, e.g. from a
+    /// `-D NAME=value` flag.
+    pub fn define(&mut self, name: &str, value: i32) {
+        self.symbols.add_symbol(Symbol::new_with_offset(
+            name.to_string(),
+            SymbolType::Integer,
+            value as u32,
+        ));
+    }
+
     pub fn assemble(&mut self, raw: &str) -> Result<Vec<u8>, Vec<AssemblerError>> {
         match program(raw) {
+            Ok((rest, _)) if !rest.trim().is_empty() => {
+                self.errors.push(unparsed_error(raw, rest));
+                Err(self.errors.clone())
+            }
             Ok((_, program)) => {
+                let mut includes = IncludeExpander::new(self.include_paths.clone());
+                let program = includes
+                    .expand(program, self.source_path.as_deref())
+                    .and_then(|program| MacroExpander::new().expand(program))
+                    .and_then(scope_labels);
+                self.files = includes.files;
+                let program = match program {
+                    Ok(program) => program,
+                    Err(errors) => {
+                        self.errors = errors;
+                        return Err(self.errors.clone());
+                    }
+                };
                 let mut assembled_program = self.write_pie_heade();
-                self.process_first_phase(&program);
+                let program = self.process_first_phase(&program);
 
                 if !self.errors.is_empty() {
                     return Err(self.errors.clone());
                 }
 
-                if self.sections.len() != 2 {
-                    println!("Did not find at least two sections");
+                if self.sections.len() < 2 {
+                    let _ = writeln!(self.messages, "Did not find at least two sections");
                     self.errors.push(AssemblerError::InsufficientSections);
                     return Err(self.errors.clone());
                 }
 
                 let mut body = self.process_second_phase(&program);
+                if !self.errors.is_empty() {
+                    return Err(self.errors.clone());
+                }
 
                 assembled_program.append(&mut body);
                 Ok(assembled_program)
             }
             Err(e) => {
-                println!("There was an error assembling the code: {:?}", e);
-                Err(vec![AssemblerError::ParseError {
-                    error: e.to_string(),
-                }])
+                let _ = writeln!(
+                    self.messages,
+                    "There was an error assembling the code: {:?}",
+                    e
+                );
+                Err(vec![parse_error(raw, &e)])
             }
         }
     }
 
-    fn process_first_phase(&mut self, p: &Program) {
+    /// Assembles a relocatable object file for `synthia link`. `file` is
+    /// the name reported in its debug information.
+    pub fn assemble_object(
+        &mut self,
+        raw: &str,
+        file: &str,
+    ) -> Result<ObjectFile, Vec<AssemblerError>> {
+        self.relocatable = true;
+        let program = self.assemble(raw)?;
+        let symbols = self
+            .symbols
+            .symbols()
+            .iter()
+            .filter(|symbol| !symbol.is_constant())
+            .map(|symbol| ObjectSymbol {
+                name: symbol.name().to_string(),
+                visibility: symbol.visibility(),
+                section: match symbol.visibility() {
+                    Visibility::Extern => None,
+                    _ if symbol.is_code() => Some(ObjectSection::Code),
+                    _ => Some(ObjectSection::Data),
+                },
+                offset: symbol.offset().unwrap_or(0),
+            })
+            .collect();
+        Ok(ObjectFile {
+            code: program[PIE_CODE_START..].to_vec(),
+            ro_data: self.ro.clone(),
+            symbols,
+            relocations: self.relocations.clone(),
+            debug_info: self.debug_info(file),
+        })
+    }
+
+    /// Returns the instructions that are assembled, without those skipped
+    /// by conditional directives.
+    fn process_first_phase(&mut self, p: &Program) -> Program {
+        let mut assembled = vec![];
         for i in &p.instructions {
+            let errors = self.errors.len();
+            if self.process_conditional(i) {
+                self.add_macro_calls(i, errors);
+                continue;
+            }
+            if !self.conditionals.is_active() {
+                continue;
+            }
+
             if i.is_label() {
                 if !self.sections.is_empty() {
                     self.process_label_declarations(&i);
@@ -112,50 +348,230 @@ impl Assembler {
             }
 
             if i.is_directive() {
+                let start = self.ro_offset;
                 self.process_directive(i);
+                if self.ro_offset > start {
+                    let mut entry = ListingEntry::new(i, DebugSection::Data, start);
+                    entry.bytes = self.ro[start as usize..].to_vec();
+                    self.listing.push(entry);
+                }
+            }
+
+            if i.is_opcode() {
+                self.listing
+                    .push(ListingEntry::new(i, DebugSection::Code, self.code_offset));
+                self.code_offset += 4;
             }
 
+            self.add_macro_calls(i, errors);
             self.current_instruction += 1;
+            assembled.push(i.clone());
+        }
+        let mut unclosed = self.conditionals.finish();
+        self.errors.append(&mut unclosed);
+        self.export_globals();
+        self.resolve_data_refs();
+        for entry in &mut self.listing {
+            if entry.section == DebugSection::Data {
+                let start = entry.address as usize;
+                entry.bytes = self.ro[start..start + entry.bytes.len()].to_vec();
+            }
         }
         self.phase = AssemblerPhase::Second;
+        Program {
+            instructions: assembled,
+        }
     }
 
     fn process_second_phase(&mut self, p: &Program) -> Vec<u8> {
         self.current_instruction = 0;
         let mut program = vec![];
+        let mut code_entries = (0..self.listing.len())
+            .filter(|index| self.listing[*index].section == DebugSection::Code)
+            .collect::<Vec<usize>>()
+            .into_iter();
         for i in &p.instructions {
+            let errors = self.errors.len();
             if i.is_opcode() {
+                let pc = (PIE_CODE_START + program.len()) as u32;
+                let file = self.file_number(i.source_file());
+                self.source_map.add_entry_in(pc, file, i.source_line());
+                if let Some(name) = i.get_label_usage() {
+                    self.label_refs.push(LabelRef { pc, name });
+                }
+                self.check_immediates(i);
+                if self.relocatable {
+                    self.add_relocations(i, program.len() as u32);
+                }
                 let mut bytes = i.to_bytes(&self.symbols);
+                if let Some(index) = code_entries.next() {
+                    self.listing[index].bytes = bytes.clone();
+                }
                 program.append(&mut bytes);
             }
             if i.is_directive() {
                 self.process_directive(i);
             }
+            self.add_macro_calls(i, errors);
         }
         program
     }
 
+    /// Adds the file and macro calls of `i` to the errors it caused, those
+    /// from `first_error` on.
+    fn add_macro_calls(&mut self, i: &AssemblerInstruction, first_error: usize) {
+        for error in &mut self.errors[first_error..] {
+            *error = in_macro(in_file(error.clone(), &i.file), &i.expanded_from);
+        }
+    }
+
+    /// The number of `file` in the source map: 0 for the assembled file.
+    fn file_number(&self, file: Option<&str>) -> u32 {
+        file.and_then(|file| self.files.iter().position(|name| name == file))
+            .map_or(0, |index| index as u32 + 1)
+    }
+
+    /// Debug information for the most recently assembled program. `file` is
+    /// the name reported in source locations.
+    pub fn debug_info(&self, file: &str) -> DebugInfo {
+        let mut symbols = vec![];
+        let sections = [
+            (
+                DebugSection::Data,
+                self.symbols.data_labels(),
+                self.ro_offset,
+            ),
+            (
+                DebugSection::Code,
+                self.symbols.code_labels(),
+                self.code_offset,
+            ),
+        ];
+        for (section, labels, section_end) in sections {
+            for (index, (name, start)) in labels.iter().enumerate() {
+                let end = match labels.get(index + 1) {
+                    Some((_, next)) => *next,
+                    None => section_end,
+                };
+                symbols.push(DebugSymbol {
+                    name: name.clone(),
+                    section,
+                    start: *start,
+                    end,
+                });
+            }
+        }
+
+        DebugInfo {
+            file: file.to_string(),
+            includes: self.files.clone(),
+            source_map: self.source_map.clone(),
+            symbols,
+            label_refs: self.label_refs.clone(),
+        }
+    }
+
     fn process_directive(&mut self, i: &AssemblerInstruction) {
         let directive_name = match i.get_directive_name() {
             Some(name) => name,
             None => {
-                println!("Directive has an invalid name: {:?}", i);
+                let _ = writeln!(self.messages, "Directive has an invalid name: {:?}", i);
                 return;
             }
         };
 
-        if i.has_operands() {
-            match directive_name.as_ref() {
-                "asciiz" => self.handle_asciiz(i),
-                _ => {
-                    self.errors.push(AssemblerError::UnknownDirectiveFound {
-                        directive: directive_name.clone(),
-                    });
-                    return;
+        match directive_name.as_ref() {
+            "asciiz" | "ascii" | "byte" | "half" | "word" | "space" | "zero" | "align" => {
+                self.handle_data(&directive_name, i)
+            }
+            "equ" | "set" => self.handle_constant(&directive_name, i),
+            "global" | "extern" => self.handle_visibility(&directive_name, i),
+            _ if !i.has_operands() => self.process_section_header(&directive_name),
+            _ => {
+                self.errors.push(AssemblerError::UnknownDirectiveFound {
+                    directive: directive_name.clone(),
+                });
+            }
+        }
+    }
+
+    /// Handles `.if`, `.ifdef`, `.ifndef`, `.elif`, `.else`, `.endif` and
+    /// `.error`, returning whether `i` was one of them.
+    fn process_conditional(&mut self, i: &AssemblerInstruction) -> bool {
+        let directive = match i.get_directive_name() {
+            Some(name) => name,
+            None => return false,
+        };
+        let result = match directive.as_ref() {
+            "if" => {
+                let condition = self.conditionals.is_active() && self.condition(&directive, i);
+                self.conditionals.open(condition, i.line);
+                Ok(())
+            }
+            "ifdef" | "ifndef" => {
+                let condition = self.conditionals.is_active()
+                    && self.is_defined(&directive, i) == (directive == "ifdef");
+                self.conditionals.open(condition, i.line);
+                Ok(())
+            }
+            "elif" => {
+                let condition =
+                    self.conditionals.needs_condition() && self.condition(&directive, i);
+                self.conditionals.elif(condition, i.line)
+            }
+            "else" => self.conditionals.otherwise(i.line),
+            "endif" => self.conditionals.close(i.line),
+            "error" => {
+                if self.conditionals.is_active() {
+                    match i.directive_operands()[..] {
+                        [Token::SyString { name }] => self.errors.push(AssemblerError::UserError {
+                            message: name.clone(),
+                            line: i.line,
+                        }),
+                        _ => self.errors.push(AssemblerError::InvalidDirectiveOperand {
+                            directive,
+                            line: i.line,
+                        }),
+                    }
                 }
+                Ok(())
+            }
+            _ => return false,
+        };
+        if let Err(error) = result {
+            self.errors.push(error);
+        }
+        true
+    }
+
+    /// Whether the single operand of `.if` or `.elif` is non-zero.
+    fn condition(&mut self, directive: &str, i: &AssemblerInstruction) -> bool {
+        match i.directive_operands()[..] {
+            [value] => self.evaluate(value, directive, i.line).unwrap_or(0) != 0,
+            _ => {
+                self.errors.push(AssemblerError::InvalidDirectiveOperand {
+                    directive: directive.to_string(),
+                    line: i.line,
+                });
+                false
+            }
+        }
+    }
+
+    /// Whether the name given to `.ifdef` or `.ifndef` is a constant or a
+    /// label declared so far.
+    fn is_defined(&mut self, directive: &str, i: &AssemblerInstruction) -> bool {
+        match i.directive_operands()[..] {
+            [Token::Expression {
+                expr: Expr::Constant(name),
+            }] => self.symbols.has_symbol(name),
+            _ => {
+                self.errors.push(AssemblerError::InvalidDirectiveOperand {
+                    directive: directive.to_string(),
+                    line: i.line,
+                });
+                false
             }
-        } else {
-            self.process_section_header(&directive_name);
         }
     }
 
@@ -176,37 +592,422 @@ impl Assembler {
             return;
         }
 
-        let symbol = Symbol::new(name, SymbolType::Label);
-        self.symbols.add_symbol(symbol);
+        let symbol = if i.is_opcode() {
+            Symbol::new_with_offset(name, SymbolType::Label, self.code_offset)
+        } else {
+            Symbol::new(name, SymbolType::Label)
+        };
+        self.symbols
+            .add_symbol(symbol.in_section(self.current_section.clone()));
+    }
+
+    /// Defines the constant of `.equ NAME value`, or of `.set NAME value`
+    /// which may redefine it. `.set` takes effect again in the second phase
+    /// so instructions see the value in force where they are written.
+    fn handle_constant(&mut self, directive: &str, i: &AssemblerInstruction) {
+        let (name, value) = match i.directive_operands()[..] {
+            [Token::Expression {
+                expr: Expr::Constant(name),
+            }, value] => (name.clone(), value),
+            _ => {
+                self.errors.push(AssemblerError::InvalidDirectiveOperand {
+                    directive: directive.to_string(),
+                    line: i.line,
+                });
+                return;
+            }
+        };
+
+        if self.phase == AssemblerPhase::Second {
+            if directive == "set" {
+                if let Some(value) = self.value_of(value).and_then(|v| v.ok()) {
+                    self.symbols.set_symbol_offset(&name, value as u32);
+                }
+            }
+            return;
+        }
+
+        let value = match self.evaluate(value, directive, i.line) {
+            Some(value) => value,
+            None => return,
+        };
+        if self.symbols.has_symbol(&name) {
+            if directive == "set" && self.symbols.constant(&name).is_some() {
+                self.symbols.set_symbol_offset(&name, value as u32);
+            } else {
+                self.errors.push(AssemblerError::SymbolAlreadyDeclared);
+            }
+            return;
+        }
+        self.symbols.add_symbol(Symbol::new_with_offset(
+            name,
+            SymbolType::Integer,
+            value as u32,
+        ));
     }
 
-    fn handle_asciiz(&mut self, i: &AssemblerInstruction) {
+    /// Records the names of `.global` for `export_globals`, and declares
+    /// the names of `.extern` as symbols defined elsewhere.
+    fn handle_visibility(&mut self, directive: &str, i: &AssemblerInstruction) {
         if self.phase != AssemblerPhase::First {
             return;
         }
+        let names: Option<Vec<String>> = i
+            .directive_operands()
+            .into_iter()
+            .map(|operand| match operand {
+                Token::Expression {
+                    expr: Expr::Constant(name),
+                }
+                | Token::LabelUsage { name } => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+        let names = match names {
+            Some(names) if !names.is_empty() => names,
+            _ => {
+                self.errors.push(AssemblerError::InvalidDirectiveOperand {
+                    directive: directive.to_string(),
+                    line: i.line,
+                });
+                return;
+            }
+        };
+        for name in names {
+            if directive == "global" {
+                self.globals.push((name, i.line));
+            } else if self.symbols.has_symbol(&name) {
+                self.errors.push(AssemblerError::SymbolAlreadyDeclared);
+            } else {
+                // Extern symbols only have a value, zero, in object files,
+                // where relocations replace it when linking.
+                let symbol = match self.relocatable {
+                    true => Symbol::new_with_offset(name, SymbolType::Label, 0),
+                    false => Symbol::new(name, SymbolType::Label),
+                };
+                self.symbols
+                    .add_symbol(symbol.with_visibility(Visibility::Extern));
+            }
+        }
+    }
+
+    /// Makes the labels named by `.global` visible to other objects.
+    fn export_globals(&mut self) {
+        for (name, line) in std::mem::take(&mut self.globals) {
+            let is_label = matches!(
+                self.symbols.symbol(&name),
+                Some(symbol) if !symbol.is_constant() && symbol.visibility() != Visibility::Extern
+            );
+            if is_label {
+                self.symbols
+                    .set_symbol_visibility(&name, Visibility::Global);
+            } else {
+                self.errors
+                    .push(AssemblerError::UndefinedSymbol { name, line });
+            }
+        }
+    }
 
-        match i.get_string_constant() {
-            Some(s) => {
-                match i.get_label_name() {
-                    Some(name) => {
-                        self.symbols.set_symbol_offset(&name, self.ro_offset);
+    /// Records a relocation for each label operand of `i`, which starts at
+    /// `offset` in the code.
+    fn add_relocations(&mut self, i: &AssemblerInstruction, offset: u32) {
+        // Operands follow the opcode byte: registers take one byte, the
+        // other operands two.
+        let mut position = offset + 1;
+        for operand in [&i.operand1, &i.operand2, &i.operand3]
+            .into_iter()
+            .flatten()
+        {
+            let value = match operand {
+                Token::Register { .. } => {
+                    position += 1;
+                    continue;
+                }
+                Token::LabelUsage { name } => Expr::Label(name.clone()),
+                Token::Expression { expr } => expr.clone(),
+                _ => {
+                    position += 2;
+                    continue;
+                }
+            };
+            match self.relocation_target(&value, i.line) {
+                Ok(Some(target)) => self.relocations.push(Relocation {
+                    section: ObjectSection::Code,
+                    offset: position,
+                    width: 2,
+                    target,
+                    line: i.line,
+                }),
+                Ok(None) => {}
+                Err(error) => self.errors.push(error),
+            }
+            position += 2;
+        }
+    }
+
+    /// What the value of `expr` is relative to, or `None` if it does not
+    /// change when linking, like a number or the distance between labels.
+    fn relocation_target(
+        &self,
+        expr: &Expr,
+        line: u32,
+    ) -> Result<Option<RelocationTarget>, AssemblerError> {
+        let not_relocatable = AssemblerError::NotRelocatable { line };
+        let terms = expr
+            .label_terms(&self.symbols)
+            .ok_or(not_relocatable.clone())?;
+        let (mut code, mut data) = (0, 0);
+        let mut externs: Vec<(String, i32)> = vec![];
+        for (name, times) in terms {
+            match self.symbols.symbol(&name) {
+                Some(symbol) if symbol.visibility() == Visibility::Extern => {
+                    match externs
+                        .iter_mut()
+                        .find(|(extern_name, _)| *extern_name == name)
+                    {
+                        Some((_, total)) => *total += times,
+                        None => externs.push((name, times)),
+                    }
+                }
+                Some(symbol) if symbol.is_code() => code += times,
+                Some(_) => data += times,
+                // Reported as undefined when the value is evaluated.
+                None => {}
+            }
+        }
+        externs.retain(|(_, times)| *times != 0);
+        match (&externs[..], code, data) {
+            ([], 0, 0) => Ok(None),
+            ([], 1, 0) => Ok(Some(RelocationTarget::Section(ObjectSection::Code))),
+            ([], 0, 1) => Ok(Some(RelocationTarget::Section(ObjectSection::Data))),
+            ([(name, 1)], 0, 0) => Ok(Some(RelocationTarget::Symbol(name.clone()))),
+            _ => Err(not_relocatable),
+        }
+    }
+
+    /// Emits the read-only data of a data directive and gives its label the
+    /// offset of the first byte, after any `.align` padding.
+    fn handle_data(&mut self, directive: &str, i: &AssemblerInstruction) {
+        if self.phase != AssemblerPhase::First {
+            return;
+        }
+
+        let values = i.directive_operands();
+        let invalid = AssemblerError::InvalidDirectiveOperand {
+            directive: directive.to_string(),
+            line: i.line,
+        };
+        if values.is_empty() {
+            self.errors.push(invalid);
+            return;
+        }
+
+        if directive == "align" {
+            match values[..] {
+                [value] => match self.evaluate(value, directive, i.line) {
+                    Some(value) if value > 0 && (value as u32).is_power_of_two() => {
+                        let padding = (value as u32 - self.ro_offset % value as u32) % value as u32;
+                        self.emit(&vec![0; padding as usize]);
                     }
-                    None => {
-                        println!("Found a string with no associated label!");
+                    Some(_) => {
+                        self.errors.push(invalid);
                         return;
                     }
-                };
+                    None => return,
+                },
+                _ => {
+                    self.errors.push(invalid);
+                    return;
+                }
+            }
+        }
+        if let Some(name) = i.get_label_name() {
+            self.symbols.set_symbol_offset(&name, self.ro_offset);
+        }
 
-                for byte in s.as_bytes() {
-                    self.ro.push(*byte);
-                    self.ro_offset += 1;
+        match directive {
+            "asciiz" | "ascii" => {
+                for value in values {
+                    match value {
+                        Token::SyString { name } => {
+                            self.emit(name.as_bytes());
+                            if directive == "asciiz" {
+                                self.emit(&[0]);
+                            }
+                        }
+                        _ => self.errors.push(invalid.clone()),
+                    }
                 }
+            }
+            "byte" | "half" | "word" => {
+                let width = match directive {
+                    "byte" => 1,
+                    "half" => 2,
+                    _ => 4,
+                };
+                for value in values {
+                    let value = match value {
+                        Token::IntegerOperand { value } => Expr::Number(*value),
+                        Token::LabelUsage { name } => Expr::Label(name.clone()),
+                        Token::Expression { expr } => expr.clone(),
+                        _ => {
+                            self.errors.push(invalid.clone());
+                            continue;
+                        }
+                    };
+                    self.data_refs.push(DataRef {
+                        offset: self.ro_offset,
+                        width,
+                        value,
+                        directive: directive.to_string(),
+                        line: i.line,
+                        expanded_from: i.expanded_from.clone(),
+                        file: i.file.clone(),
+                    });
+                    self.emit(&vec![0; width]);
+                }
+            }
+            "space" | "zero" => match values[..] {
+                [value] => match self.evaluate(value, directive, i.line) {
+                    Some(value) if value >= 0 => self.emit(&vec![0; value as usize]),
+                    Some(_) => self.errors.push(invalid),
+                    None => {}
+                },
+                _ => self.errors.push(invalid),
+            },
+            _ => {}
+        }
+    }
+
+    /// Appends `bytes` to the read-only section.
+    fn emit(&mut self, bytes: &[u8]) {
+        self.ro.extend_from_slice(bytes);
+        self.ro_offset += bytes.len() as u32;
+    }
 
-                self.ro.push(0);
-                self.ro_offset += 1;
+    /// The value of a numeric token, or `None` if `token` is not a number,
+    /// label or expression.
+    fn value_of(&self, token: &Token) -> Option<Result<i32, ExprError>> {
+        match token {
+            Token::IntegerOperand { value } => Some(Ok(*value)),
+            Token::LabelUsage { name } => Some(Expr::Label(name.clone()).evaluate(&self.symbols)),
+            Token::Expression { expr } => Some(expr.evaluate(&self.symbols)),
+            _ => None,
+        }
+    }
+
+    /// Evaluates a directive operand now, recording an error if it is not
+    /// a number or cannot be evaluated yet.
+    fn evaluate(&mut self, token: &Token, directive: &str, line: u32) -> Option<i32> {
+        match self.value_of(token) {
+            Some(Ok(value)) => Some(value),
+            Some(Err(error)) => {
+                self.errors
+                    .push(AssemblerError::from_expr_error(error, line));
+                None
             }
             None => {
-                println!("String constant following an .asciiz was empty");
+                self.errors.push(AssemblerError::InvalidDirectiveOperand {
+                    directive: directive.to_string(),
+                    line,
+                });
+                None
+            }
+        }
+    }
+
+    /// Checks that the immediate operands of an instruction evaluate and
+    /// fit in 16 bits, and that the labels it uses are defined, or extern
+    /// in an object file.
+    fn check_immediates(&mut self, i: &AssemblerInstruction) {
+        let signed = matches!(&i.opcode, Some(Token::Op { code }) if code.has_signed_immediate());
+        for operand in [&i.operand1, &i.operand2, &i.operand3]
+            .into_iter()
+            .flatten()
+        {
+            if let Token::LabelUsage { name } = operand {
+                let defined = match self.symbols.symbol(name) {
+                    Some(symbol) if symbol.visibility() == Visibility::Extern => self.relocatable,
+                    _ => self.symbols.symbol_value(name).is_some(),
+                };
+                if !defined {
+                    self.errors.push(AssemblerError::UndefinedSymbol {
+                        name: name.clone(),
+                        line: i.line,
+                    });
+                }
+                continue;
+            }
+            match self.value_of(operand) {
+                Some(Ok(value)) if signed && !SIGNED_IMMEDIATE_RANGE.contains(&value) => {
+                    self.errors.push(AssemblerError::SignedOperandOutOfRange {
+                        value,
+                        line: i.line,
+                    });
+                }
+                Some(Ok(value)) if !IMMEDIATE_RANGE.contains(&value) => {
+                    self.errors.push(AssemblerError::OperandOutOfRange {
+                        value,
+                        line: i.line,
+                    });
+                }
+                Some(Err(error)) => {
+                    self.errors
+                        .push(AssemblerError::from_expr_error(error, i.line));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Fills in the labels and expressions used as data values, once the
+    /// first phase has given every label its offset.
+    fn resolve_data_refs(&mut self) {
+        for data_ref in std::mem::take(&mut self.data_refs) {
+            let value = match data_ref.value.evaluate(&self.symbols) {
+                Ok(value) => value,
+                Err(error) => {
+                    let error = AssemblerError::from_expr_error(error, data_ref.line);
+                    self.errors.push(in_macro(
+                        in_file(error, &data_ref.file),
+                        &data_ref.expanded_from,
+                    ));
+                    continue;
+                }
+            };
+            if !fits(value as i64, data_ref.width) {
+                let error = AssemblerError::DataValueOutOfRange {
+                    directive: data_ref.directive,
+                    value: value as i64,
+                    line: data_ref.line,
+                };
+                self.errors.push(in_macro(
+                    in_file(error, &data_ref.file),
+                    &data_ref.expanded_from,
+                ));
+                continue;
+            }
+            let start = data_ref.offset as usize;
+            self.ro[start..start + data_ref.width]
+                .copy_from_slice(&(value as u32).to_be_bytes()[4 - data_ref.width..]);
+            if !self.relocatable {
+                continue;
+            }
+            match self.relocation_target(&data_ref.value, data_ref.line) {
+                Ok(Some(target)) => self.relocations.push(Relocation {
+                    section: ObjectSection::Data,
+                    offset: data_ref.offset,
+                    width: data_ref.width as u8,
+                    target,
+                    line: data_ref.line,
+                }),
+                Ok(None) => {}
+                Err(error) => {
+                    self.errors.push(in_macro(
+                        in_file(error, &data_ref.file),
+                        &data_ref.expanded_from,
+                    ));
+                }
             }
         }
     }
@@ -214,7 +1015,11 @@ impl Assembler {
     fn process_section_header(&mut self, header_name: &str) {
         let new_section: AssemblerSection = header_name.into();
         if new_section == AssemblerSection::Unknown {
-            println!("Found an section header that is unknown: {:?}", header_name);
+            let _ = writeln!(
+                self.messages,
+                "Found an section header that is unknown: {:?}",
+                header_name
+            );
             return;
         }
 
@@ -277,4 +1082,286 @@ mod tests {
         vm.add_bytes(program);
         assert_eq!(vm.program.len(), 93);
     }
+
+    #[test]
+    fn test_code_label_offsets() {
+        let mut asm = Assembler::new();
+        let test_string = ".data\nhello: .asciiz 'Hi'\n.code\nload $0 #1\nloop: inc $0\nend: hlt";
+        asm.assemble(test_string).unwrap();
+        assert_eq!(asm.symbols.symbol_value("hello"), Some(0));
+        assert_eq!(asm.symbols.symbol_value("loop"), Some(69));
+        assert_eq!(
+            asm.symbols.code_labels(),
+            vec![("loop".to_string(), 69), ("end".to_string(), 73)]
+        );
+    }
+
+    #[test]
+    fn test_source_map() {
+        let mut asm = Assembler::new();
+        let test_string = ".data\n.code\nload $0 #1\n\ninc $0\nhlt";
+        asm.assemble(test_string).unwrap();
+        assert_eq!(asm.source_map.line_for(65), Some(3));
+        assert_eq!(asm.source_map.line_for(69), Some(5));
+        assert_eq!(asm.source_map.line_for(73), Some(6));
+    }
+
+    #[test]
+    fn test_data_directives() {
+        let mut asm = Assembler::new();
+        let test_string = ".data
+greeting: .ascii 'Hi', \"!\"
+bytes: .byte 1, -1, 0xff
+.align 4
+halves: .half 0x1234 -2
+gap: .zero 3
+words: .word @main, @halves, -1
+name: .asciiz 'ok'
+.code
+main: hlt";
+        asm.assemble(test_string).unwrap();
+        assert_eq!(
+            asm.ro,
+            vec![
+                b'H', b'i', b'!', 1, 0xff, 0xff, 0, 0, // padding to 8
+                0x12, 0x34, 0xff, 0xfe, 0, 0, 0, // .zero 3
+                0, 0, 0, 65, 0, 0, 0, 8, 0xff, 0xff, 0xff, 0xff, b'o', b'k', 0,
+            ]
+        );
+        assert_eq!(asm.symbols.symbol_value("greeting"), Some(0));
+        assert_eq!(asm.symbols.symbol_value("bytes"), Some(3));
+        assert_eq!(asm.symbols.symbol_value("halves"), Some(8));
+        assert_eq!(asm.symbols.symbol_value("gap"), Some(12));
+        assert_eq!(asm.symbols.symbol_value("words"), Some(15));
+        assert_eq!(asm.symbols.symbol_value("name"), Some(27));
+    }
+
+    #[test]
+    fn test_data_directive_errors() {
+        let errors = |source: &str| {
+            Assembler::new()
+                .assemble(source)
+                .unwrap_err()
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(
+            errors(".data\nb: .byte 256\nh: .half -32769\n.code\nhlt"),
+            vec![
+                "Value 256 does not fit in a .byte on line 2",
+                "Value -32769 does not fit in a .half on line 3"
+            ]
+        );
+        assert_eq!(
+            errors(".data\n.align 3\n.space 'x'\n.word @nowhere\n.code\nhlt"),
+            vec![
+                "Invalid operands for directive .align on line 2",
+                "Invalid operands for directive .space on line 3",
+                "Undefined symbol nowhere on line 4"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_constants_and_expressions() {
+        let mut asm = Assembler::new();
+        let test_string = ".data
+.equ BUF_SIZE 16
+.set STEP, 1
+table: .word @end-@start, BUF_SIZE*2+1
+buffer: .space BUF_SIZE
+.code
+start: load $0 #BUF_SIZE*2+1
+load $1 @table+4
+load $2 #STEP
+.set STEP 2
+load $3 #STEP
+end: hlt";
+        let program = asm.assemble(test_string).unwrap();
+        assert_eq!(asm.ro[..8], [0, 0, 0, 16, 0, 0, 0, 33]);
+        assert_eq!(asm.ro.len(), 8 + 16);
+        assert_eq!(
+            program[PIE_CODE_START..PIE_CODE_START + 16],
+            [0, 0, 0, 33, 0, 1, 0, 4, 0, 2, 0, 1, 0, 3, 0, 2]
+        );
+        assert_eq!(asm.symbols.constant("BUF_SIZE"), Some(16));
+        assert!(asm
+            .symbols
+            .data_labels()
+            .iter()
+            .all(|(name, _)| name != "BUF_SIZE"));
+    }
+
+    #[test]
+    fn test_expression_errors() {
+        let errors = |source: &str| {
+            Assembler::new()
+                .assemble(source)
+                .unwrap_err()
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(
+            errors(".data\n.equ BIG 0x7fffffff\n.equ SIZE BIG+1\n.code\nhlt"),
+            vec!["Arithmetic overflow in expression on line 3"]
+        );
+        assert_eq!(
+            errors(".data\n.equ A 1\n.equ A 2\n.code\nload $0 #1/(A-1)\nhlt"),
+            vec!["This symbol was previously declared"]
+        );
+        assert_eq!(
+            errors(
+                ".data\n.equ A 1\n.code\nload $0 #1/(A-1)\nload $1 #MISSING\nload $2 #70000\nhlt"
+            ),
+            vec![
+                "Division by zero in expression on line 4",
+                "Undefined symbol MISSING on line 5",
+                "Operand 70000 does not fit in 16 bits on line 6",
+            ]
+        );
+        assert_eq!(
+            errors(".data\n.code\nload $0 @nowhere\nload $1 @nowhere+4\nhlt"),
+            vec![
+                "Undefined symbol nowhere on line 3",
+                "Undefined symbol nowhere on line 4",
+            ]
+        );
+        assert!(Assembler::new()
+            .assemble_object(".data\n.code\n.extern f\nload $0 @f\nhlt", "f.sy")
+            .is_ok());
+        assert_eq!(
+            errors(".data\n.code\nload $0 #1 + 2\nload $1 #5\nhlt"),
+            vec!["There was an error parsing the code: unexpected `+ 2` at line 3, column 12"]
+        );
+        assert_eq!(
+            errors(".data\n.equ X 1 + 2\n.code\nhlt"),
+            vec!["There was an error parsing the code: unexpected `+ 2` at line 2, column 10"]
+        );
+    }
+
+    #[test]
+    fn test_conditional_assembly() {
+        let source = ".data
+.ifndef LEVEL
+.equ LEVEL 0
+.endif
+.if LEVEL >= 2
+.asciiz 'verbose'
+.elif LEVEL == 1 ; spaced
+.asciiz 'on'
+.else
+.ifdef QUIET
+.error 'never checked'
+.endif
+.endif
+.code
+.if LEVEL
+load $0 #LEVEL
+.endif
+hlt";
+        let mut asm = Assembler::new();
+        let program = asm.assemble(source).unwrap();
+        assert_eq!(asm.ro, Vec::<u8>::new());
+        assert_eq!(program.len(), PIE_CODE_START + 4);
+
+        let mut asm = Assembler::new();
+        asm.define("LEVEL", 1);
+        let program = asm.assemble(source).unwrap();
+        assert_eq!(asm.ro, b"on\0");
+        assert_eq!(program[PIE_CODE_START..PIE_CODE_START + 4], [0, 0, 0, 1]);
+
+        let mut asm = Assembler::new();
+        asm.define("LEVEL", 7);
+        asm.assemble(source).unwrap();
+        assert_eq!(asm.ro, b"verbose\0");
+    }
+
+    #[test]
+    fn test_conditional_errors() {
+        let errors = |source: &str| {
+            Assembler::new()
+                .assemble(source)
+                .unwrap_err()
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(
+            errors(
+                ".data\n.equ SIZE 3\n.if SIZE>2\n.error \"SIZE is too big\"\n.endif\n.code\nhlt"
+            ),
+            vec!["SIZE is too big on line 4"]
+        );
+        assert_eq!(
+            errors(".data\n.if #1\n.else\n.else\n.code\nhlt\n.endif\n.endif"),
+            vec![
+                ".else after .else on line 4",
+                ".endif without .if on line 8"
+            ]
+        );
+        assert_eq!(
+            errors(".data\n.if MISSING\n.endif\n.if #1\n.code\nhlt"),
+            vec![
+                "Undefined symbol MISSING on line 2",
+                ".if on line 4 is never closed with .endif",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_define() {
+        assert_eq!(parse_define("DEBUG"), Ok(("DEBUG".to_string(), 1)));
+        assert_eq!(parse_define("LEVEL=0x10"), Ok(("LEVEL".to_string(), 16)));
+        assert_eq!(parse_define("MIN=-3"), Ok(("MIN".to_string(), -3)));
+        assert_eq!(
+            parse_define("2X=1"),
+            Err("Invalid constant name 2X".to_string())
+        );
+        assert_eq!(
+            parse_define("X=two"),
+            Err("Invalid value two for X".to_string())
+        );
+    }
+
+    #[test]
+    fn test_include_files() {
+        let dir = std::env::temp_dir().join(format!("synthia-asm-include-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(
+            dir.join("lib/io.sy"),
+            ".data\nmsg: .asciiz 'Hi'\n.code\n.macro say text\nprts \\text\n.endm\nputs: say @msg\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("lib/bad.sy"), ".code\nload $0 #BIG\n").unwrap();
+        let main = dir.join("main.sy");
+        let io = dir.join("lib/io.sy").to_string_lossy().to_string();
+
+        let mut asm = Assembler::new();
+        asm.set_source_path(&main.to_string_lossy());
+        let program = asm
+            .assemble(".data\n.include 'lib/io.sy'\n.code\nhlt\n")
+            .unwrap();
+        assert_eq!(program.len(), PIE_CODE_START + 8);
+        assert_eq!(asm.ro, b"Hi\0");
+        let info = asm.debug_info("main.sy");
+        assert_eq!(info.includes, vec![io.clone()]);
+        assert_eq!(info.location(65), Some(format!("{}:7", io)));
+        assert_eq!(info.location(69), Some("main.sy:4".to_string()));
+
+        let mut asm = Assembler::new();
+        asm.add_include_path(&dir.join("lib").to_string_lossy());
+        let errors = asm
+            .assemble(".data\n.code\n.include 'bad.sy'\nhlt\n")
+            .unwrap_err();
+        assert_eq!(
+            errors[0].to_string(),
+            format!(
+                "{}: Undefined symbol BIG on line 2",
+                dir.join("lib/bad.sy").display()
+            )
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 }
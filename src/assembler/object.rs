@@ -0,0 +1,217 @@
+use serde::{Deserialize, Serialize};
+
+use crate::assembler::{debug_info::DebugInfo, symbols::Visibility};
+
+/// The first bytes of an object file, `-21O`.
+pub const OBJECT_HEADER_PREFIX: [u8; 4] = [45, 50, 49, 79];
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum ObjectSection {
+    Code,
+    Data,
+}
+
+/// A label of an object file. Code offsets count from the start of the
+/// object's code as if it were linked first, so they include the header;
+/// data offsets count from the start of its read-only data.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ObjectSymbol {
+    pub name: String,
+    pub visibility: Visibility,
+    /// `None` for `.extern` symbols.
+    pub section: Option<ObjectSection>,
+    pub offset: u32,
+}
+
+/// What a relocated value is relative to.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub enum RelocationTarget {
+    /// A label of the object itself: the value moves with its section.
+    Section(ObjectSection),
+    /// A symbol of another object: the value holds the addend.
+    Symbol(String),
+}
+
+/// A big-endian value of `width` bytes at `offset` in `section` that the
+/// linker adjusts once the sections are placed.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Relocation {
+    pub section: ObjectSection,
+    pub offset: u32,
+    pub width: u8,
+    pub target: RelocationTarget,
+    /// The source line, for errors.
+    pub line: u32,
+}
+
+/// A relocatable object file, as written by `synthia assemble -c`: code
+/// without a header, read-only data, symbols and relocations.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ObjectFile {
+    pub code: Vec<u8>,
+    pub ro_data: Vec<u8>,
+    pub symbols: Vec<ObjectSymbol>,
+    pub relocations: Vec<Relocation>,
+    pub debug_info: DebugInfo,
+}
+
+impl ObjectFile {
+    pub fn is_object(bytes: &[u8]) -> bool {
+        bytes.len() >= 4 && bytes[0..4] == OBJECT_HEADER_PREFIX
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = OBJECT_HEADER_PREFIX.to_vec();
+        bytes.extend(serde_json::to_vec(self).expect("Object files are always serializable"));
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<ObjectFile, String> {
+        if !ObjectFile::is_object(bytes) {
+            return Err("not an object file".to_string());
+        }
+        serde_json::from_slice(&bytes[4..]).map_err(|e| e.to_string())
+    }
+
+    /// The global symbols the object defines.
+    pub fn globals(&self) -> impl Iterator<Item = &ObjectSymbol> {
+        self.symbols
+            .iter()
+            .filter(|symbol| symbol.visibility == Visibility::Global)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+
+    fn assemble(source: &str) -> ObjectFile {
+        Assembler::new().assemble_object(source, "test.sy").unwrap()
+    }
+
+    #[test]
+    fn test_object_symbols_and_relocations() {
+        let object = assemble(
+            ".data
+.global greeting
+.extern count
+greeting: .asciiz 'Hi'
+table: .word @greeting+1, @count, 7
+.code
+.global main
+.extern print
+main: prts @greeting
+load $0 @print
+load $1 #@loop-@main
+loop: jmp @count+2
+hlt",
+        );
+        assert_eq!(object.code.len(), 20);
+        assert_eq!(
+            object.ro_data,
+            [72, 105, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 7]
+        );
+        assert_eq!(
+            object.symbols,
+            vec![
+                ObjectSymbol {
+                    name: "count".to_string(),
+                    visibility: Visibility::Extern,
+                    section: None,
+                    offset: 0,
+                },
+                ObjectSymbol {
+                    name: "greeting".to_string(),
+                    visibility: Visibility::Global,
+                    section: Some(ObjectSection::Data),
+                    offset: 0,
+                },
+                ObjectSymbol {
+                    name: "table".to_string(),
+                    visibility: Visibility::Local,
+                    section: Some(ObjectSection::Data),
+                    offset: 3,
+                },
+                ObjectSymbol {
+                    name: "print".to_string(),
+                    visibility: Visibility::Extern,
+                    section: None,
+                    offset: 0,
+                },
+                ObjectSymbol {
+                    name: "main".to_string(),
+                    visibility: Visibility::Global,
+                    section: Some(ObjectSection::Code),
+                    offset: 65,
+                },
+                ObjectSymbol {
+                    name: "loop".to_string(),
+                    visibility: Visibility::Local,
+                    section: Some(ObjectSection::Code),
+                    offset: 77,
+                },
+            ]
+        );
+        let relocations: Vec<(ObjectSection, u32, u8, RelocationTarget)> = object
+            .relocations
+            .iter()
+            .map(|r| (r.section, r.offset, r.width, r.target.clone()))
+            .collect();
+        let symbol = |name: &str| RelocationTarget::Symbol(name.to_string());
+        assert_eq!(
+            relocations,
+            vec![
+                (
+                    ObjectSection::Data,
+                    3,
+                    4,
+                    RelocationTarget::Section(ObjectSection::Data)
+                ),
+                (ObjectSection::Data, 7, 4, symbol("count")),
+                (
+                    ObjectSection::Code,
+                    1,
+                    2,
+                    RelocationTarget::Section(ObjectSection::Data)
+                ),
+                (ObjectSection::Code, 6, 2, symbol("print")),
+                (ObjectSection::Code, 13, 2, symbol("count")),
+            ]
+        );
+        assert_eq!(object.code[13..15], [0, 2]);
+        assert_eq!(ObjectFile::from_bytes(&object.to_bytes()), Ok(object));
+    }
+
+    #[test]
+    fn test_object_errors() {
+        let errors = |source: &str| {
+            Assembler::new()
+                .assemble_object(source, "test.sy")
+                .unwrap_err()
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(
+            errors(".data\n.global y\n.code\nhlt"),
+            vec!["Undefined symbol y on line 2"]
+        );
+        assert_eq!(
+            errors(".data\n.extern x\n.code\nload $0 #@x*2\nload $1 #@x\nhlt"),
+            vec!["The value on line 4 cannot be relocated, use a label plus or minus a constant"]
+        );
+        assert_eq!(
+            errors(".data\n.code\n.extern x\nx: hlt"),
+            vec!["This symbol was previously declared"]
+        );
+    }
+
+    #[test]
+    fn test_extern_symbols_need_linking() {
+        let errors = Assembler::new()
+            .assemble(".data\n.extern print\n.code\nload $0 @print\nhlt")
+            .unwrap_err();
+        assert_eq!(errors[0].to_string(), "Undefined symbol print on line 4");
+    }
+}
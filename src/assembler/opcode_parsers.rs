@@ -1,9 +1,19 @@
-use crate::{assembler::Token, instruction::Opcode};
-use nom::{character::complete::alpha1, combinator::map, IResult};
+use crate::{
+    assembler::{expressions::identifier, Token},
+    instruction::Opcode,
+};
+use nom::{combinator::map, IResult};
 
+/// A mnemonic, which is case-insensitive, or the name of a macro to call.
 pub fn opcode(input: &str) -> IResult<&str, Token> {
-    map(alpha1, |opcode| Token::Op {
-        code: Opcode::from(opcode),
+    map(identifier, |name: &str| {
+        let lowercase = name.to_lowercase();
+        match Opcode::from(lowercase.as_str()) {
+            Opcode::IGL if lowercase != "igl" => Token::MacroCall {
+                name: name.to_string(),
+            },
+            code => Token::Op { code },
+        }
     })(input)
 }
 
@@ -15,7 +25,19 @@ mod tests {
     fn test_opcode_load() {
         let result = opcode("load");
         assert_eq!(result, Ok(("", Token::Op { code: Opcode::LOAD })));
-        let result = opcode("aold");
+        let result = opcode("LOAD");
+        assert_eq!(result, Ok(("", Token::Op { code: Opcode::LOAD })));
+        let result = opcode("igl");
         assert_eq!(result, Ok(("", Token::Op { code: Opcode::IGL })));
+        let result = opcode("print_nl $0");
+        assert_eq!(
+            result,
+            Ok((
+                " $0",
+                Token::MacroCall {
+                    name: "print_nl".to_string()
+                }
+            ))
+        );
     }
 }
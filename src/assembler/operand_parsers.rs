@@ -1,40 +1,188 @@
 use nom::{
     branch::alt,
-    character::complete::{alphanumeric0, char, digit1},
-    combinator::map_res,
-    IResult,
+    bytes::complete::{is_not, tag_no_case},
+    character::complete::{char, digit1, hex_digit1},
+    combinator::{map, map_res, opt},
+    error::{Error, ErrorKind},
+    sequence::{delimited, preceded},
+    Err, IResult,
 };
 use std::str::FromStr;
 
-use crate::assembler::{label_parsers::label_usage, register_parsers::register, Token};
+use crate::assembler::{
+    expressions::{expression, identifier, Expr},
+    register_parsers::register,
+    Token,
+};
 
+/// `#` followed by a number or a constant expression.
 pub fn integer_operand(input: &str) -> IResult<&str, Token> {
     let (input, _) = char('#')(input)?;
-    let (input, value) = map_res(digit1, i32::from_str)(input)?;
+    let (input, expr) = expression(input)?;
+
+    Ok((input, expr.into_token()))
+}
+
+/// A label, or an expression starting with one such as `@table+4`.
+fn label_operand(input: &str) -> IResult<&str, Token> {
+    let (_, _) = char('@')(input)?;
+    let (input, expr) = expression(input)?;
+
+    Ok((input, expr.into_token()))
+}
 
-    Ok((input, Token::IntegerOperand { value }))
+/// A decimal or `0x` hexadecimal integer with an optional minus sign.
+/// Anything from `i32::MIN` to `u32::MAX` is accepted, the upper half
+/// wrapping to negative values.
+pub fn integer(input: &str) -> IResult<&str, i32> {
+    let (input, negative) = opt(char('-'))(input)?;
+    let (input, magnitude) = alt((
+        map_res(preceded(tag_no_case("0x"), hex_digit1), |digits| {
+            i64::from_str_radix(digits, 16)
+        }),
+        map_res(digit1, i64::from_str),
+    ))(input)?;
+    let value = if negative.is_some() {
+        -magnitude
+    } else {
+        magnitude
+    };
+    if value < i32::MIN as i64 || value > u32::MAX as i64 {
+        return Err(Err::Error(Error::new(input, ErrorKind::TooLarge)));
+    }
+    Ok((input, value as i32))
 }
 
+/// A quoted string with either quote style. Escapes are decoded. A string
+/// that is not closed on its line fails without backtracking, at the
+/// opening quote, and an invalid escape fails at its backslash; see
+/// `string_error`.
 fn systring(input: &str) -> IResult<&str, Token> {
-    let (input, quote) = alt((char('\''), char('"')))(input)?;
-    let (input, content) = (alphanumeric0)(input)?;
-    let (input, _) = char(quote)(input)?;
+    let quote = match input.chars().next() {
+        Some(quote @ ('\'' | '"')) => quote,
+        _ => return Err(Err::Error(Error::new(input, ErrorKind::Char))),
+    };
+    let mut content = String::new();
+    let mut chars = input.char_indices().skip(1);
+    while let Some((index, c)) = chars.next() {
+        match c {
+            _ if c == quote => {
+                return Ok((&input[index + 1..], Token::SyString { name: content }));
+            }
+            '\n' => break,
+            '\\' => match unescape(&input[index..]) {
+                Some((decoded, length)) => {
+                    content.push(decoded);
+                    chars.nth(length - 2);
+                }
+                None => {
+                    return Err(Err::Failure(Error::new(
+                        &input[index..],
+                        ErrorKind::Escaped,
+                    )))
+                }
+            },
+            _ => content.push(c),
+        }
+    }
+    Err(Err::Failure(Error::new(input, ErrorKind::Char)))
+}
+
+/// Decodes the escape at the start of `input`, returning the character and
+/// the length of the escape in characters.
+fn unescape(input: &str) -> Option<(char, usize)> {
+    let mut chars = input.chars().skip(1);
+    let decoded = match chars.next()? {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        '0' => '\0',
+        c @ ('\\' | '\'' | '"') => c,
+        'x' => {
+            let digits: String = chars.take(2).collect();
+            if digits.len() != 2 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+                return None;
+            }
+            let value = u8::from_str_radix(&digits, 16).ok()?;
+            if value > 0x7f {
+                return None;
+            }
+            return Some((value as char, 4));
+        }
+        'u' => {
+            let rest = input.get(2..)?.strip_prefix('{')?;
+            let digits = &rest[..rest.find('}')?];
+            if digits.is_empty()
+                || digits.len() > 6
+                || !digits.chars().all(|c| c.is_ascii_hexdigit())
+            {
+                return None;
+            }
+            let decoded = char::from_u32(u32::from_str_radix(digits, 16).ok()?)?;
+            return Some((decoded, digits.chars().count() + 4));
+        }
+        _ => return None,
+    };
+    Some((decoded, 2))
+}
+
+/// Describes a parse failure raised by a string literal, or `None` if the
+/// failure came from elsewhere.
+pub fn string_error(error: &Error<&str>) -> Option<String> {
+    match (error.code, error.input.chars().next()) {
+        (ErrorKind::Char, Some('\'' | '"')) => Some("Unterminated string literal".to_string()),
+        (ErrorKind::Escaped, Some('\\')) => {
+            let escape: String = error.input.chars().take(2).collect();
+            Some(format!("Invalid escape {} in string literal", escape))
+        }
+        _ => None,
+    }
+}
+
+/// `\name`, a parameter in the body of a macro.
+fn macro_param(input: &str) -> IResult<&str, Token> {
+    let (input, name) = preceded(char('\\'), identifier)(input)?;
 
     Ok((
         input,
-        Token::SyString {
-            name: content.to_string(),
+        Token::MacroParam {
+            name: name.to_string(),
+        },
+    ))
+}
+
+/// `<path>`, naming a file of the bundled library.
+fn library_path(input: &str) -> IResult<&str, Token> {
+    let (input, name) = delimited(char('<'), is_not(">\n"), char('>'))(input)?;
+
+    Ok((
+        input,
+        Token::LibraryPath {
+            name: name.trim().to_string(),
         },
     ))
 }
 
 pub fn operand(input: &str) -> IResult<&str, Token> {
-    alt((integer_operand, register, label_usage, systring))(input)
+    alt((
+        integer_operand,
+        register,
+        label_operand,
+        systring,
+        macro_param,
+    ))(input)
+}
+
+/// An operand of a directive, where numbers and constant expressions may
+/// also be written without the `#`.
+pub fn directive_operand(input: &str) -> IResult<&str, Token> {
+    alt((operand, library_path, map(expression, Expr::into_token)))(input)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::assembler::expressions::BinaryOp;
 
     #[test]
     fn test_parse_integar_operand() {
@@ -42,10 +190,97 @@ mod tests {
         assert_eq!(result, Ok(("", Token::IntegerOperand { value: 10 })));
         let result = integer_operand("10");
         assert_ne!(result, Ok(("", Token::IntegerOperand { value: 10 })));
-        let result = integer_operand("#a");
+        let result = integer_operand("#-5");
+        assert_eq!(result, Ok(("", Token::IntegerOperand { value: -5 })));
+        let result = integer_operand("#$");
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_parse_integer() {
+        assert_eq!(integer("42"), Ok(("", 42)));
+        assert_eq!(integer("-7,"), Ok((",", -7)));
+        assert_eq!(integer("0x1F"), Ok(("", 31)));
+        assert_eq!(integer("0xffffffff"), Ok(("", -1)));
+        assert_eq!(integer("-2147483648"), Ok(("", i32::MIN)));
+        assert!(integer("4294967296").is_err());
+        assert_eq!(
+            directive_operand("16"),
+            Ok(("", Token::IntegerOperand { value: 16 }))
+        );
+    }
+
+    #[test]
+    fn test_parse_macro_param() {
+        assert_eq!(
+            operand("\\count $1"),
+            Ok((
+                " $1",
+                Token::MacroParam {
+                    name: "count".to_string()
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_expression_operands() {
+        assert_eq!(
+            operand("@table+4 $1"),
+            Ok((
+                " $1",
+                Token::Expression {
+                    expr: Expr::Binary(
+                        BinaryOp::Add,
+                        Box::new(Expr::Label("table".to_string())),
+                        Box::new(Expr::Number(4))
+                    )
+                }
+            ))
+        );
+        assert_eq!(
+            operand("@table"),
+            Ok((
+                "",
+                Token::LabelUsage {
+                    name: "table".to_string()
+                }
+            ))
+        );
+        assert_eq!(
+            operand("#SIZE"),
+            Ok((
+                "",
+                Token::Expression {
+                    expr: Expr::Constant("SIZE".to_string())
+                }
+            ))
+        );
+        assert_eq!(
+            directive_operand("SIZE"),
+            Ok((
+                "",
+                Token::Expression {
+                    expr: Expr::Constant("SIZE".to_string())
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_library_path() {
+        assert_eq!(
+            directive_operand("<std/string.sy> ; strings"),
+            Ok((
+                " ; strings",
+                Token::LibraryPath {
+                    name: "std/string.sy".to_string()
+                }
+            ))
+        );
+        assert!(directive_operand("<std/string.sy").is_err());
+    }
+
     #[test]
     fn test_parse_string_single_quotes() {
         let result = systring("'hello'");
@@ -57,4 +292,61 @@ mod tests {
         let result = systring("\"hello\"");
         assert!(result.is_ok());
     }
+
+    fn string(name: &str) -> Token {
+        Token::SyString {
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_string_contents() {
+        assert_eq!(
+            systring("\"Hello, world!\\n\" ; rest"),
+            Ok((" ; rest", string("Hello, world!\n")))
+        );
+        assert_eq!(systring("'it''s'"), Ok(("'s'", string("it"))));
+        assert_eq!(
+            systring("'caf\u{e9} \u{1F600}'"),
+            Ok(("", string("café 😀")))
+        );
+        assert_eq!(systring("'héllo wörld'"), Ok(("", string("héllo wörld"))));
+        assert_eq!(
+            systring(r#"'\t\\\"\'\0\x41\r'"#),
+            Ok(("", string("\t\\\"'\0A\r")))
+        );
+        assert_eq!(systring("''"), Ok(("", string(""))));
+    }
+
+    #[test]
+    fn test_string_errors() {
+        let failure = |input| match systring(input) {
+            Err(Err::Failure(e)) => (e.input, string_error(&e)),
+            other => panic!("expected a failure, got {:?}", other),
+        };
+        assert_eq!(
+            failure("'open\nhlt"),
+            (
+                "'open\nhlt",
+                Some("Unterminated string literal".to_string())
+            )
+        );
+        assert_eq!(
+            failure("\"open"),
+            ("\"open", Some("Unterminated string literal".to_string()))
+        );
+        assert_eq!(
+            failure("'a\\qb'"),
+            (
+                "\\qb'",
+                Some("Invalid escape \\q in string literal".to_string())
+            )
+        );
+        assert_eq!(failure("'\\xff'").0, "\\xff'");
+        assert_eq!(failure("'\\u{110000}'").0, "\\u{110000}'");
+        assert_eq!(failure("'\\u{}'").0, "\\u{}'");
+        assert_eq!(failure("'\\x+4'").0, "\\x+4'");
+        assert_eq!(failure("'\\u{+41}'").0, "\\u{+41}'");
+        assert!(matches!(systring("hello"), Err(Err::Error(_))));
+    }
 }
@@ -1,10 +1,17 @@
-use nom::{multi::many1, IResult};
+use nom::{combinator::consumed, error::Error, multi::many1, Err, IResult};
 
 use crate::assembler::{
+    assembler_errors::AssemblerError,
+    comment_parsers::any_space,
     instruction_parsers::{instruction, AssemblerInstruction},
+    label_parsers::label_declaration,
+    operand_parsers::string_error,
     SymbolTable,
 };
 
+#[cfg(test)]
+use crate::assembler::Token;
+
 #[derive(Debug, PartialEq)]
 pub struct Program {
     pub instructions: Vec<AssemblerInstruction>,
@@ -21,8 +28,86 @@ impl Program {
 }
 
 pub fn program(input: &str) -> IResult<&str, Program> {
-    let (input, instructions) = many1(instruction)(input)?;
-    Ok((input, Program { instructions }))
+    let (rest, spanned) = spanned_program(input)?;
+    let instructions = spanned
+        .into_iter()
+        .map(|(_, instruction)| instruction)
+        .collect();
+    Ok((rest, Program { instructions }))
+}
+
+/// The instructions of `input`, each with the line it starts on, which is
+/// before its own line when its label is on a line of its own.
+pub fn spanned_program(input: &str) -> IResult<&str, Vec<(u32, AssemblerInstruction)>> {
+    let (rest, parsed) = many1(consumed(instruction))(input)?;
+    let instructions = parsed
+        .into_iter()
+        .map(|(text, mut instruction)| {
+            instruction.line = line_number(input, text);
+            (first_line(input, text), instruction)
+        })
+        .collect();
+    Ok((rest, instructions))
+}
+
+/// The error for `source` failing to parse, precise for malformed
+/// strings.
+pub fn parse_error(source: &str, error: &Err<Error<&str>>) -> AssemblerError {
+    if let Err::Failure(failure) = error {
+        if let Some(message) = string_error(failure) {
+            let (line, column) = location(source, failure.input);
+            return AssemblerError::StringLiteralError {
+                line,
+                column,
+                message,
+            };
+        }
+    }
+    AssemblerError::ParseError {
+        error: error.to_string(),
+    }
+}
+
+/// The error for the text the parser stopped at, `rest`, which must be a
+/// slice of `source`, when it is not just whitespace.
+pub fn unparsed_error(source: &str, rest: &str) -> AssemblerError {
+    let rest = rest.trim_start();
+    let (line, column) = location(source, rest);
+    let text = rest.lines().next().unwrap_or_default().trim_end();
+    AssemblerError::ParseError {
+        error: format!("unexpected `{}` at line {}, column {}", text, line, column),
+    }
+}
+
+/// 1-based line and column of the start of `rest`, which must be a slice
+/// of `source`.
+pub fn location(source: &str, rest: &str) -> (u32, u32) {
+    let offset = rest.as_ptr() as usize - source.as_ptr() as usize;
+    let before = &source[..offset];
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let line = before.matches('\n').count() as u32 + 1;
+    (line, before[line_start..].chars().count() as u32 + 1)
+}
+
+/// Line of the opcode or directive of the instruction parsed from `text`,
+/// which must be a slice of `source`, past any label on a line of its own.
+fn line_number(source: &str, text: &str) -> u32 {
+    let start = match label_declaration(text) {
+        Ok((rest, _)) => match any_space(rest) {
+            Ok((rest, _)) if !rest.trim().is_empty() => rest,
+            _ => text,
+        },
+        Err(_) => text,
+    };
+    first_line(source, start)
+}
+
+/// Line of the first non-whitespace character of `text`, which must be a
+/// slice of `source`.
+fn first_line(source: &str, text: &str) -> u32 {
+    let leading = text.len() - text.trim_start().len();
+    let offset = text.as_ptr() as usize - source.as_ptr() as usize + leading;
+    source[..offset].matches('\n').count() as u32 + 1
 }
 
 #[cfg(test)]
@@ -48,6 +133,48 @@ mod tests {
         println!("{:?}", bytecode)
     }
 
+    #[test]
+    fn test_program_line_numbers() {
+        let (_, program) = program(".data\n\n  hello: .asciiz 'Hi'\n.code\nhlt\n").unwrap();
+        let lines: Vec<u32> = program.instructions.iter().map(|i| i.line).collect();
+        assert_eq!(lines, vec![1, 3, 4, 5]);
+
+        let (_, labelled) =
+            super::program(".code\nmain:\n  load $0 #1\nloop: ; next\n\ninc $0\n").unwrap();
+        let lines: Vec<u32> = labelled.instructions.iter().map(|i| i.line).collect();
+        assert_eq!(lines, vec![1, 3, 6]);
+    }
+
+    #[test]
+    fn test_program_with_comments() {
+        let source = "; header\n.data\n.code ; main\n/* two\nlines */\nload $0 #1 ; one\nhlt\n";
+        let (rest, program) = program(source).unwrap();
+        assert_eq!(rest.trim(), "");
+        let lines: Vec<u32> = program.instructions.iter().map(|i| i.line).collect();
+        assert_eq!(lines, vec![1, 2, 3, 4, 6, 7]);
+        assert!(program.instructions[0].is_comment_only());
+        assert_eq!(program.instructions[2].comments.len(), 1);
+        assert!(program.instructions[3].is_comment_only());
+        assert_eq!(
+            program.instructions[4].comments,
+            vec![Token::Comment {
+                text: "one".to_string(),
+                block: false
+            }]
+        );
+        let bytes = program.to_bytes(&SymbolTable::new());
+        assert_eq!(bytes.len(), 4 * 4);
+        assert_eq!(bytes[8..], [0, 0, 0, 1, 5, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_location() {
+        let source = ".data\nmsg: .asciiz 'é'\n";
+        assert_eq!(location(source, &source[0..]), (1, 1));
+        assert_eq!(location(source, &source[11..]), (2, 6));
+        assert_eq!(location(source, &source[source.len()..]), (3, 1));
+    }
+
     #[test]
     fn test_complete_program() {
         let test_program = ".data\nhello: .ascizz 'Hello Everyone!'\n.code\nhlt";
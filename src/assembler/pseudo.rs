@@ -0,0 +1,387 @@
+use crate::{
+    assembler::{
+        assembler_errors::AssemblerError,
+        expressions::{BinaryOp, Expr},
+        instruction_parsers::AssemblerInstruction,
+        Token,
+    },
+    instruction::Opcode,
+};
+
+/// The register pseudo-instructions overwrite when they need a spare one.
+pub const SCRATCH_REGISTER: u8 = 31;
+
+/// Mnemonics the assembler lowers to real instructions, with their usage
+/// and what they do. `call` is also a real instruction, taking a register.
+pub const PSEUDO_INSTRUCTIONS: [(&str, &str, &str); 8] = [
+    ("mov", "mov $dst $src", "Copy a register into another"),
+    (
+        "li",
+        "li $reg #value",
+        "Load any 32-bit value into a register",
+    ),
+    ("clr", "clr $reg", "Set a register to zero"),
+    (
+        "not",
+        "not $dst $src",
+        "Invert the bits of a register into another",
+    ),
+    ("neg", "neg $dst $src", "Negate a register into another"),
+    (
+        "beq",
+        "beq $reg1 $reg2 @label",
+        "Jump to a label if two registers are equal",
+    ),
+    ("b", "b @label", "Jump to a label"),
+    ("call", "call @label", "Call the function at a label"),
+];
+
+/// Whether `name` is the mnemonic of a pseudo-instruction.
+pub fn is_pseudo(name: &str) -> bool {
+    let name = name.to_lowercase();
+    PSEUDO_INSTRUCTIONS
+        .iter()
+        .any(|(pseudo, _, _)| *pseudo == name)
+}
+
+/// The real instructions `instruction` stands for, or `None` if it is not
+/// a pseudo-instruction or an instruction written with a value in place
+/// of a register that has an immediate form. They keep its line, so
+/// listings and the source map show them at the original instruction,
+/// which labels the first one.
+pub fn expand(
+    instruction: &AssemblerInstruction,
+) -> Option<Result<Vec<AssemblerInstruction>, AssemblerError>> {
+    let lowered = match &instruction.opcode {
+        Some(Token::MacroCall { name }) if is_pseudo(name) => {
+            lower(&name.to_lowercase(), instruction)
+        }
+        Some(Token::Op { code: Opcode::CALL }) => match &instruction.operand1 {
+            Some(Token::Register { .. }) | None => return None,
+            Some(_) => lower("call", instruction),
+        },
+        Some(Token::Op { code }) => Ok(immediate(*code, instruction)?),
+        _ => return None,
+    };
+    Some(lowered.map(|lowered| instructions(lowered, instruction)))
+}
+
+/// `add $a #n $a` as `addi $a #n` and `eq $a #n` as `eqi $a #n`. With
+/// another destination, the value is loaded into it if `load` can hold
+/// it, and otherwise the source is copied to it first.
+fn immediate(code: Opcode, instruction: &AssemblerInstruction) -> Option<Vec<Lowered>> {
+    let form = code.immediate_form()?;
+    let register = |token: &Option<Token>| matches!(token, Some(Token::Register { .. }));
+    let value = match &instruction.operand2 {
+        Some(Token::Register { .. }) | None => return None,
+        Some(value) => value.clone(),
+    };
+    let (src, dst) = match (&instruction.operand1, &instruction.operand3) {
+        (Some(src), None) if register(&instruction.operand1) && code.operands().len() == 2 => {
+            return Some(vec![(form, vec![src.clone(), value])]);
+        }
+        (Some(src), Some(dst))
+            if register(&instruction.operand1) && register(&instruction.operand3) =>
+        {
+            (src.clone(), dst.clone())
+        }
+        _ => return None,
+    };
+    if src == dst {
+        return Some(vec![(form, vec![src, value])]);
+    }
+    if let Token::IntegerOperand { value: 0..=65535 } | Token::LabelUsage { .. } = value {
+        return Some(vec![
+            (Opcode::LOAD, vec![dst.clone(), value]),
+            (code, vec![src, dst.clone(), dst]),
+        ]);
+    }
+    Some(vec![
+        (
+            Opcode::LOAD,
+            vec![dst.clone(), Token::IntegerOperand { value: 0 }],
+        ),
+        (Opcode::ADD, vec![src, dst.clone(), dst.clone()]),
+        (form, vec![dst, value]),
+    ])
+}
+
+/// A real instruction and its operands.
+type Lowered = (Opcode, Vec<Token>);
+
+fn lower(name: &str, instruction: &AssemblerInstruction) -> Result<Vec<Lowered>, AssemblerError> {
+    let operands = instruction.directive_operands();
+    let scratch = Token::Register {
+        reg_num: SCRATCH_REGISTER,
+    };
+    let zero = Token::IntegerOperand { value: 0 };
+    let register = |token: &Token| matches!(token, Token::Register { .. });
+    let target = |token: &Token| {
+        matches!(
+            token,
+            Token::LabelUsage { .. } | Token::Expression { .. } | Token::IntegerOperand { .. }
+        )
+    };
+    let uses_scratch = operands.iter().any(|operand| **operand == scratch);
+    let scratch_error = || AssemblerError::ScratchRegister {
+        name: name.to_string(),
+        line: instruction.line,
+    };
+
+    let lowered = match (name, operands.as_slice()) {
+        ("mov", [dst, src]) if register(dst) && register(src) => {
+            if uses_scratch {
+                return Err(scratch_error());
+            }
+            vec![
+                (Opcode::LOAD, vec![scratch.clone(), zero]),
+                (Opcode::ADD, vec![(*src).clone(), scratch, (*dst).clone()]),
+            ]
+        }
+        ("li", [reg, value]) if register(reg) && target(value) => match value {
+            Token::IntegerOperand { value: 0..=65535 } | Token::LabelUsage { .. } => {
+                vec![(Opcode::LOAD, vec![(*reg).clone(), (*value).clone()])]
+            }
+            _ if uses_scratch => return Err(scratch_error()),
+            // The high half, shifted up a byte at a time as `load` takes
+            // 16 bits, plus the low half.
+            _ => vec![
+                (Opcode::LOAD, vec![(*reg).clone(), half(value, 16)]),
+                (
+                    Opcode::LOAD,
+                    vec![scratch.clone(), Token::IntegerOperand { value: 256 }],
+                ),
+                (
+                    Opcode::MUL,
+                    vec![(*reg).clone(), scratch.clone(), (*reg).clone()],
+                ),
+                (
+                    Opcode::MUL,
+                    vec![(*reg).clone(), scratch.clone(), (*reg).clone()],
+                ),
+                (Opcode::LOAD, vec![scratch.clone(), half(value, 0)]),
+                (Opcode::ADD, vec![(*reg).clone(), scratch, (*reg).clone()]),
+            ],
+        },
+        ("clr", [reg]) if register(reg) => vec![(Opcode::LOAD, vec![(*reg).clone(), zero])],
+        ("not" | "neg", [dst, src]) if register(dst) && register(src) => {
+            if uses_scratch {
+                return Err(scratch_error());
+            }
+            let mut lowered = vec![(Opcode::LOAD, vec![scratch.clone(), zero])];
+            // All bits set, -1, less the value inverts it.
+            if name == "not" {
+                lowered.push((Opcode::DEC, vec![scratch.clone()]));
+            }
+            lowered.push((Opcode::SUB, vec![scratch, (*src).clone(), (*dst).clone()]));
+            lowered
+        }
+        ("beq", [left, right, label]) if register(left) && register(right) && target(label) => {
+            if uses_scratch {
+                return Err(scratch_error());
+            }
+            vec![
+                (Opcode::EQ, vec![(*left).clone(), (*right).clone()]),
+                (Opcode::LOAD, vec![scratch.clone(), (*label).clone()]),
+                (Opcode::JEQ, vec![scratch]),
+            ]
+        }
+        ("b" | "call", [label]) if target(label) => {
+            let jump = match name {
+                "b" => Opcode::JMP,
+                _ => Opcode::CALL,
+            };
+            vec![
+                (Opcode::LOAD, vec![scratch.clone(), (*label).clone()]),
+                (jump, vec![scratch]),
+            ]
+        }
+        _ => {
+            let usage = PSEUDO_INSTRUCTIONS
+                .iter()
+                .find(|(pseudo, _, _)| *pseudo == name)
+                .map_or("", |(_, usage, _)| usage);
+            return Err(AssemblerError::PseudoOperands {
+                name: name.to_string(),
+                usage: usage.to_string(),
+                line: instruction.line,
+            });
+        }
+    };
+
+    Ok(lowered)
+}
+
+/// `lowered` as instructions of the line `instruction` is on.
+fn instructions(
+    lowered: Vec<Lowered>,
+    instruction: &AssemblerInstruction,
+) -> Vec<AssemblerInstruction> {
+    lowered
+        .into_iter()
+        .enumerate()
+        .map(|(index, (code, operands))| {
+            let mut operands = operands.into_iter();
+            let first = index == 0;
+            AssemblerInstruction {
+                opcode: Some(Token::Op { code }),
+                label: instruction.label.clone().filter(|_| first),
+                directive: None,
+                operand1: operands.next(),
+                operand2: operands.next(),
+                operand3: operands.next(),
+                comments: if first {
+                    instruction.comments.clone()
+                } else {
+                    vec![]
+                },
+                line: instruction.line,
+                expanded_from: instruction.expanded_from.clone(),
+                file: instruction.file.clone(),
+            }
+        })
+        .collect()
+}
+
+/// The 16 bits of `value` starting at bit `shift`.
+fn half(value: &Token, shift: i32) -> Token {
+    match value {
+        Token::IntegerOperand { value } => Token::IntegerOperand {
+            value: value >> shift & 0xffff,
+        },
+        Token::Expression { expr } => {
+            let shifted = Expr::Binary(
+                BinaryOp::Shr,
+                Box::new(expr.clone()),
+                Box::new(Expr::Number(shift)),
+            );
+            Token::Expression {
+                expr: Expr::Binary(
+                    BinaryOp::And,
+                    Box::new(shifted),
+                    Box::new(Expr::Number(0xffff)),
+                ),
+            }
+        }
+        value => value.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assembler::Assembler, vm::VM};
+
+    fn parse(source: &str) -> AssemblerInstruction {
+        let (_, parsed) = crate::assembler::program_parsers::program(source).unwrap();
+        parsed.instructions[0].clone()
+    }
+
+    fn lowered(source: &str) -> Result<Vec<String>, String> {
+        match expand(&parse(source)).unwrap() {
+            Ok(instructions) => Ok(instructions.iter().map(|i| i.to_string()).collect()),
+            Err(error) => Err(error.to_string()),
+        }
+    }
+
+    fn run(source: &str) -> VM {
+        let mut vm = VM::new();
+        vm.add_bytes(Assembler::new().assemble(source).unwrap());
+        vm.run().unwrap();
+        vm
+    }
+
+    #[test]
+    fn test_lower_pseudo_instructions() {
+        assert_eq!(
+            lowered("top: mov $1 $2 ; copy"),
+            Ok(vec![
+                "top: load $31 #0 ; copy".to_string(),
+                "add $2 $31 $1".to_string(),
+            ])
+        );
+        assert_eq!(lowered("li $3 #70"), Ok(vec!["load $3 #70".to_string()]));
+        assert_eq!(
+            lowered("beq $1 $2 @done"),
+            Ok(vec![
+                "eq $1 $2".to_string(),
+                "load $31 @done".to_string(),
+                "jeq $31".to_string(),
+            ])
+        );
+        assert_eq!(
+            lowered("call @f"),
+            Ok(vec!["load $31 @f".to_string(), "call $31".to_string()])
+        );
+        assert!(expand(&parse("call $4")).is_none());
+        assert_eq!(
+            lowered("mov $1 #2"),
+            Err("mov on line 1 is written mov $dst $src".to_string())
+        );
+        assert_eq!(
+            lowered("neg $1 $31"),
+            Err("neg on line 1 cannot use $31, which its expansion overwrites".to_string())
+        );
+    }
+
+    #[test]
+    fn test_pick_immediate_forms() {
+        assert_eq!(lowered("add $1 #5 $1"), Ok(vec!["addi $1 #5".to_string()]));
+        assert_eq!(
+            lowered("sub $1 #-2 $3"),
+            Ok(vec![
+                "load $3 #0".to_string(),
+                "add $1 $3 $3".to_string(),
+                "subi $3 #-2".to_string(),
+            ])
+        );
+        assert_eq!(
+            lowered("sub $1 #2 $3"),
+            Ok(vec!["load $3 #2".to_string(), "sub $1 $3 $3".to_string()])
+        );
+        assert_eq!(lowered("lt $1 #SIZE"), Ok(vec!["lti $1 #SIZE".to_string()]));
+        assert!(expand(&parse("add $1 $2 $3")).is_none());
+        assert!(expand(&parse("andi $1 #255")).is_none());
+
+        let vm = run(
+            ".data\n.code\nload $0 #10\nmul $0 #-3 $1\nsub $0 #4 $3\nadd $0 #1 $0\ngt $1 #-31\nload $2 @done\njeq $2\nclr $0\ndone: hlt",
+        );
+        assert_eq!(vm.registers[0], 11);
+        assert_eq!(vm.registers[1], -30);
+        assert_eq!(vm.registers[3], 6);
+
+        let errors = Assembler::new()
+            .assemble(".data\n.code\nadd $0 #40000 $0\nandi $0 #40000\n")
+            .unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "Operand 40000 does not fit in 16 bits signed on line 3"
+        );
+    }
+
+    #[test]
+    fn test_run_pseudo_instructions() {
+        let vm = run(".data\n.code\nli $0 #-5\nli $1 #305419896\nneg $2 $0\nnot $3 $0\nmov $4 $1\nclr $1\nhlt");
+        assert_eq!(vm.registers[0], -5);
+        assert_eq!(vm.registers[1], 0);
+        assert_eq!(vm.registers[2], 5);
+        assert_eq!(vm.registers[3], 4);
+        assert_eq!(vm.registers[4], 305419896);
+
+        let vm = run(".data\n.code\nload $0 #3\nload $1 #3\nbeq $0 $1 @equal\nhlt\nequal: call @double\nb @.end\n.end: hlt\ndouble: add $0 $0 $0\nret");
+        assert_eq!(vm.registers[0], 6);
+    }
+
+    #[test]
+    fn test_expansions_map_to_their_line() {
+        let mut asm = Assembler::new();
+        asm.assemble(".data\n.code\nload $0 #1\nli $1 #-1\nhlt")
+            .unwrap();
+        let info = asm.debug_info("li.sy");
+        // Six instructions for `li`, then `hlt`.
+        assert_eq!(info.location(69), Some("li.sy:4".to_string()));
+        assert_eq!(info.location(89), Some("li.sy:4".to_string()));
+        assert_eq!(info.location(93), Some("li.sy:5".to_string()));
+    }
+}
@@ -0,0 +1,101 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub struct LineEntry {
+    pub pc: u32,
+    pub line: u32,
+    /// The file of the line: 0 for the assembled file, `n` for the `n`th
+    /// included one.
+    #[serde(default)]
+    pub file: u32,
+}
+
+/// Maps the address of every emitted instruction back to the source line it
+/// was assembled from.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct SourceMap {
+    entries: Vec<LineEntry>,
+}
+
+impl SourceMap {
+    pub fn new() -> SourceMap {
+        SourceMap { entries: vec![] }
+    }
+
+    pub fn add_entry(&mut self, pc: u32, line: u32) {
+        self.add_entry_in(pc, 0, line);
+    }
+
+    pub fn add_entry_in(&mut self, pc: u32, file: u32, line: u32) {
+        self.entries.push(LineEntry { pc, line, file });
+    }
+
+    pub fn entries(&self) -> &[LineEntry] {
+        &self.entries
+    }
+
+    pub fn line_for(&self, pc: u32) -> Option<u32> {
+        self.entries
+            .iter()
+            .find(|entry| entry.pc == pc)
+            .map(|entry| entry.line)
+    }
+
+    /// The file and line of the instruction at `pc`.
+    pub fn location_for(&self, pc: u32) -> Option<(u32, u32)> {
+        self.entries
+            .iter()
+            .find(|entry| entry.pc == pc)
+            .map(|entry| (entry.file, entry.line))
+    }
+
+    /// Addresses of the instructions from `line` of the assembled file.
+    pub fn pcs_for_line(&self, line: u32) -> Vec<u32> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.file == 0 && entry.line == line)
+            .map(|entry| entry.pc)
+            .collect()
+    }
+
+    /// Lines of the assembled file that produced at least one instruction,
+    /// in order.
+    pub fn lines(&self) -> Vec<u32> {
+        let mut lines: Vec<u32> = self
+            .entries
+            .iter()
+            .filter(|entry| entry.file == 0)
+            .map(|entry| entry.line)
+            .collect();
+        lines.sort();
+        lines.dedup();
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_map_lookup() {
+        let mut map = SourceMap::new();
+        map.add_entry(65, 3);
+        map.add_entry(69, 5);
+        map.add_entry(73, 5);
+        assert_eq!(map.line_for(69), Some(5));
+        assert_eq!(map.line_for(70), None);
+        assert_eq!(map.pcs_for_line(5), vec![69, 73]);
+        assert_eq!(map.lines(), vec![3, 5]);
+    }
+
+    #[test]
+    fn test_included_lines() {
+        let mut map = SourceMap::new();
+        map.add_entry(65, 3);
+        map.add_entry_in(69, 1, 3);
+        assert_eq!(map.location_for(69), Some((1, 3)));
+        assert_eq!(map.pcs_for_line(3), vec![65]);
+        assert_eq!(map.lines(), vec![3]);
+    }
+}
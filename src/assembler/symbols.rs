@@ -1,15 +1,31 @@
+use serde::{Deserialize, Serialize};
+
+use crate::assembler::AssemblerSection;
+
 #[derive(Debug)]
 pub enum SymbolType {
     Label,
     Integer,
 }
 
+/// Whether a label is seen by other object files when linking.
+#[derive(Debug, PartialEq, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum Visibility {
+    #[default]
+    Local,
+    /// Declared with `.global`, so other objects may use it.
+    Global,
+    /// Declared with `.extern`, defined in another object.
+    Extern,
+}
+
 #[derive(Debug)]
 pub struct Symbol {
     name: String,
     offset: Option<u32>,
-    #[allow(dead_code)]
     symbol_type: SymbolType,
+    section: Option<AssemblerSection>,
+    visibility: Visibility,
 }
 
 impl Symbol {
@@ -18,6 +34,8 @@ impl Symbol {
             name,
             offset: None,
             symbol_type,
+            section: None,
+            visibility: Visibility::Local,
         }
     }
 
@@ -26,8 +44,44 @@ impl Symbol {
             name,
             offset: Some(offset),
             symbol_type,
+            section: None,
+            visibility: Visibility::Local,
         }
     }
+
+    pub fn in_section(mut self, section: Option<AssemblerSection>) -> Symbol {
+        self.section = section;
+        self
+    }
+
+    pub fn with_visibility(mut self, visibility: Visibility) -> Symbol {
+        self.visibility = visibility;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn offset(&self) -> Option<u32> {
+        self.offset
+    }
+
+    pub fn is_constant(&self) -> bool {
+        matches!(self.symbol_type, SymbolType::Integer)
+    }
+
+    pub fn is_code(&self) -> bool {
+        matches!(self.section, Some(AssemblerSection::Code { .. }))
+    }
+
+    pub fn is_data(&self) -> bool {
+        matches!(self.section, Some(AssemblerSection::Data { .. }))
+    }
+
+    pub fn visibility(&self) -> Visibility {
+        self.visibility
+    }
 }
 
 #[derive(Debug, Default)]
@@ -63,6 +117,55 @@ impl SymbolTable {
         false
     }
 
+    pub fn set_symbol_visibility(&mut self, s: &str, visibility: Visibility) -> bool {
+        for symbol in &mut self.symbols {
+            if symbol.name == s {
+                symbol.visibility = visibility;
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn symbol(&self, s: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| symbol.name == s)
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    /// Code labels that have been given an address, sorted by address.
+    pub fn code_labels(&self) -> Vec<(String, u32)> {
+        self.sorted_labels(Symbol::is_code)
+    }
+
+    /// Data labels that have been given a read-only data offset, sorted by
+    /// offset.
+    pub fn data_labels(&self) -> Vec<(String, u32)> {
+        self.sorted_labels(Symbol::is_data)
+    }
+
+    fn sorted_labels(&self, filter: fn(&Symbol) -> bool) -> Vec<(String, u32)> {
+        let mut labels: Vec<(String, u32)> = self
+            .symbols
+            .iter()
+            .filter(|s| filter(s))
+            .filter_map(|s| s.offset.map(|offset| (s.name.clone(), offset)))
+            .collect();
+        labels.sort_by_key(|(_, offset)| *offset);
+        labels
+    }
+
+    /// The value of a constant defined with `.equ` or `.set`.
+    pub fn constant(&self, s: &str) -> Option<i32> {
+        self.symbols
+            .iter()
+            .find(|symbol| symbol.name == s && symbol.is_constant())
+            .and_then(|symbol| symbol.offset)
+            .map(|value| value as i32)
+    }
+
     pub fn symbol_value(&self, s: &str) -> Option<u32> {
         for symbol in &self.symbols {
             if symbol.name == s {
@@ -0,0 +1,679 @@
+pub mod protocol;
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeSet, VecDeque},
+    io::{self, BufReader, Write},
+    path::Path,
+    rc::Rc,
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+use log::warn;
+use serde_json::{json, Value};
+
+use crate::{
+    assembler::Assembler,
+    vm::{backtrace::Backtrace, container::Container, VM},
+};
+
+use self::protocol::{read_message, write_message, Request};
+
+const THREAD_ID: i64 = 1;
+const REGISTERS_REFERENCE: i64 = 1;
+const HEAP_REFERENCE: i64 = 2;
+/// Heap bytes shown per variable.
+const HEAP_ROW_LENGTH: usize = 16;
+/// How many instructions run between checks for a `pause` request.
+const POLL_INTERVAL: usize = 1024;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum StepMode {
+    Continue,
+    In,
+    Over,
+    Out,
+}
+
+#[derive(Debug, PartialEq)]
+enum Stop {
+    Breakpoint,
+    Step,
+    Pause,
+    Exception(String),
+    Exited(i64),
+}
+
+/// Collects what the program prints so it can be sent as `output` events.
+#[derive(Clone, Default)]
+struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A launched program.
+struct Session {
+    vm: VM,
+    output: SharedOutput,
+    faulted: bool,
+}
+
+/// Debug Adapter Protocol server. Requests arrive on a channel so that a
+/// running program can notice `pause` without blocking on input. Replies
+/// go to `writer`, stdout for `synthia dap`, which is why the assembler and
+/// the VM log their diagnostics rather than printing them.
+pub struct DapServer<W: Write> {
+    writer: W,
+    seq: i64,
+    requests: Receiver<Request>,
+    pending: VecDeque<Request>,
+    session: Option<Session>,
+    breakpoint_lines: Vec<u32>,
+    stop_on_entry: bool,
+}
+
+impl<W: Write> DapServer<W> {
+    pub fn new(writer: W, requests: Receiver<Request>) -> DapServer<W> {
+        DapServer {
+            writer,
+            seq: 0,
+            requests,
+            pending: VecDeque::new(),
+            session: None,
+            breakpoint_lines: vec![],
+            stop_on_entry: false,
+        }
+    }
+
+    /// Handles requests until the client disconnects or input ends.
+    pub fn run(&mut self) -> io::Result<()> {
+        loop {
+            let request = match self.pending.pop_front() {
+                Some(request) => request,
+                None => match self.requests.recv() {
+                    Ok(request) => request,
+                    Err(_) => return Ok(()),
+                },
+            };
+            if !self.handle(&request)? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Handles one request, returning false once the session is over.
+    fn handle(&mut self, request: &Request) -> io::Result<bool> {
+        let args = &request.arguments;
+        match request.command.as_str() {
+            "initialize" => self.respond(
+                request,
+                json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsTerminateRequest": true,
+                }),
+            )?,
+            "launch" => self.launch(request)?,
+            "setBreakpoints" => {
+                self.breakpoint_lines = args["breakpoints"]
+                    .as_array()
+                    .map(|breakpoints| {
+                        breakpoints
+                            .iter()
+                            .filter_map(|b| b["line"].as_u64())
+                            .map(|line| line as u32)
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let breakpoints = self.resolved_breakpoints();
+                self.respond(request, json!({ "breakpoints": breakpoints }))?;
+            }
+            "setExceptionBreakpoints" => self.respond(request, json!({}))?,
+            "configurationDone" => {
+                self.respond(request, json!({}))?;
+                if self.stop_on_entry {
+                    self.stopped("entry", None)?;
+                } else if self.at_breakpoint() {
+                    // Execution only checks for breakpoints after each step.
+                    self.stopped("breakpoint", None)?;
+                } else {
+                    self.resume(StepMode::Continue)?;
+                }
+            }
+            "threads" => self.respond(
+                request,
+                json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] }),
+            )?,
+            "stackTrace" => match self.stack_frames() {
+                Some(frames) => {
+                    let total = frames.len();
+                    self.respond(
+                        request,
+                        json!({ "stackFrames": frames, "totalFrames": total }),
+                    )?
+                }
+                None => self.respond_error(request, "No program is running")?,
+            },
+            "scopes" => self.respond(
+                request,
+                json!({ "scopes": [
+                    { "name": "Registers", "variablesReference": REGISTERS_REFERENCE, "expensive": false },
+                    { "name": "Heap", "variablesReference": HEAP_REFERENCE, "expensive": false },
+                ]}),
+            )?,
+            "variables" => match self.variables(args) {
+                Some(variables) => self.respond(request, json!({ "variables": variables }))?,
+                None => self.respond_error(request, "No program is running")?,
+            },
+            "continue" | "next" | "stepIn" | "stepOut" => {
+                if self.session.is_none() {
+                    self.respond_error(request, "No program is running")?;
+                    return Ok(true);
+                }
+                let mode = match request.command.as_str() {
+                    "next" => StepMode::Over,
+                    "stepIn" => StepMode::In,
+                    "stepOut" => StepMode::Out,
+                    _ => StepMode::Continue,
+                };
+                self.respond(request, json!({ "allThreadsContinued": true }))?;
+                self.resume(mode)?;
+            }
+            "pause" => {
+                // Requests are only handled while the program is stopped.
+                self.respond(request, json!({}))?;
+                self.stopped("pause", None)?;
+            }
+            "disconnect" | "terminate" => {
+                self.respond(request, json!({}))?;
+                return Ok(false);
+            }
+            _ => self.respond_error(request, &format!("Unsupported command {}", request.command))?,
+        }
+        Ok(true)
+    }
+
+    fn launch(&mut self, request: &Request) -> io::Result<()> {
+        let program = match request.arguments["program"].as_str() {
+            Some(program) => program,
+            None => return self.respond_error(request, "launch needs a program"),
+        };
+        match load(program) {
+            Ok(session) => {
+                self.session = Some(session);
+                self.stop_on_entry = request.arguments["stopOnEntry"].as_bool().unwrap_or(false);
+                self.respond(request, json!({}))?;
+                self.event("initialized", json!({}))
+            }
+            Err(e) => self.respond_error(request, &e),
+        }
+    }
+
+    fn resume(&mut self, mode: StepMode) -> io::Result<()> {
+        let faulted = match &self.session {
+            Some(session) => session.faulted,
+            None => return Ok(()),
+        };
+        let stop = if faulted {
+            // A fault cannot be stepped past, so resuming ends the program.
+            Stop::Exited(1)
+        } else {
+            self.execute(mode)?
+        };
+        self.flush_output()?;
+        match stop {
+            Stop::Breakpoint => self.stopped("breakpoint", None),
+            Stop::Step => self.stopped("step", None),
+            Stop::Pause => self.stopped("pause", None),
+            Stop::Exception(description) => {
+                if let Some(session) = &mut self.session {
+                    session.faulted = true;
+                }
+                self.stopped("exception", Some(description))
+            }
+            Stop::Exited(code) => {
+                self.session = None;
+                self.event("exited", json!({ "exitCode": code }))?;
+                self.event("terminated", json!({}))
+            }
+        }
+    }
+
+    /// Runs the program until `mode` says to stop.
+    fn execute(&mut self, mode: StepMode) -> io::Result<Stop> {
+        let breakpoints = self.breakpoint_pcs();
+        let mut executed = 0;
+        let (start_line, start_depth) = match &self.session {
+            Some(session) => (
+                line_at(&session.vm, session.vm.pc()),
+                session.vm.call_stack.len(),
+            ),
+            None => return Ok(Stop::Exited(0)),
+        };
+        loop {
+            let session = self.session.as_mut().expect("Only called with a session");
+            let vm = &mut session.vm;
+            match vm.execute_instructions() {
+                Ok(true) => return Ok(Stop::Exited(0)),
+                Ok(false) => {}
+                Err(e) => {
+                    if let Some(pc) = e.pc() {
+                        vm.set_pc(pc);
+                    }
+                    return Ok(Stop::Exception(e.to_string()));
+                }
+            }
+
+            let pc = vm.pc();
+            let depth = vm.call_stack.len();
+            let line = line_at(vm, pc);
+            if breakpoints.contains(&pc) {
+                return Ok(Stop::Breakpoint);
+            }
+            let new_line = line.is_some() && line != start_line;
+            let done = match mode {
+                StepMode::Continue => false,
+                StepMode::In => line.is_some() && (new_line || depth != start_depth),
+                StepMode::Over => depth < start_depth || (depth == start_depth && new_line),
+                StepMode::Out => depth < start_depth,
+            };
+            if done {
+                return Ok(Stop::Step);
+            }
+
+            executed += 1;
+            if executed % POLL_INTERVAL == 0 {
+                self.flush_output()?;
+                while let Ok(request) = self.requests.try_recv() {
+                    if request.command == "pause" {
+                        self.respond(&request, json!({}))?;
+                        return Ok(Stop::Pause);
+                    }
+                    self.pending.push_back(request);
+                }
+            }
+        }
+    }
+
+    /// Whether the next instruction to run has a breakpoint.
+    fn at_breakpoint(&self) -> bool {
+        self.session
+            .as_ref()
+            .is_some_and(|session| self.breakpoint_pcs().contains(&session.vm.pc()))
+    }
+
+    /// Code addresses of the requested breakpoint lines.
+    fn breakpoint_pcs(&self) -> BTreeSet<usize> {
+        let session = match &self.session {
+            Some(session) => session,
+            None => return BTreeSet::new(),
+        };
+        self.breakpoint_lines
+            .iter()
+            .filter_map(|line| resolve_line(&session.vm, *line))
+            .map(|(_, pc)| pc)
+            .collect()
+    }
+
+    fn resolved_breakpoints(&self) -> Vec<Value> {
+        self.breakpoint_lines
+            .iter()
+            .enumerate()
+            .map(|(index, line)| {
+                let resolved = self
+                    .session
+                    .as_ref()
+                    .and_then(|session| resolve_line(&session.vm, *line));
+                match resolved {
+                    Some((actual, _)) => {
+                        json!({ "id": index + 1, "verified": true, "line": actual })
+                    }
+                    None => json!({
+                        "id": index + 1,
+                        "verified": false,
+                        "line": line,
+                        "message": "No code at or after this line",
+                    }),
+                }
+            })
+            .collect()
+    }
+
+    fn stack_frames(&self) -> Option<Vec<Value>> {
+        let vm = &self.session.as_ref()?.vm;
+        let source = |pc: usize| {
+            vm.debug_info.as_ref().map(|info| {
+                let file = info.file_for(pc as u32);
+                let name = Path::new(file)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_else(|| file.to_string());
+                json!({ "name": name, "path": file })
+            })
+        };
+        let frames = Backtrace::frames(vm, vm.pc())
+            .into_iter()
+            .enumerate()
+            .map(|(index, frame)| {
+                let mut value = json!({
+                    "id": index,
+                    "name": frame.function.unwrap_or_else(|| "main".to_string()),
+                    "line": line_at(vm, frame.pc).unwrap_or(0),
+                    "column": 1,
+                    "instructionPointerReference": format!("{:#06x}", frame.pc),
+                });
+                if let Some(source) = source(frame.pc) {
+                    value["source"] = source;
+                }
+                value
+            })
+            .collect();
+        Some(frames)
+    }
+
+    fn variables(&self, args: &Value) -> Option<Vec<Value>> {
+        let vm = &self.session.as_ref()?.vm;
+        let variable = |name: String, value: String| json!({ "name": name, "value": value, "variablesReference": 0 });
+        let variables = match args["variablesReference"].as_i64() {
+            Some(REGISTERS_REFERENCE) => {
+                let mut variables: Vec<Value> = vm
+                    .registers
+                    .iter()
+                    .enumerate()
+                    .map(|(index, value)| variable(format!("${}", index), value.to_string()))
+                    .collect();
+                variables.push(variable("pc".to_string(), format!("{:#06x}", vm.pc())));
+                variables.push(variable(
+                    "equal_flag".to_string(),
+                    vm.equal_flag().to_string(),
+                ));
+                variables.push(variable(
+                    "remainder".to_string(),
+                    vm.remainder().to_string(),
+                ));
+                variables
+            }
+            Some(HEAP_REFERENCE) => {
+                let start = args["start"].as_u64().unwrap_or(0) as usize;
+                let count = match args["count"].as_u64() {
+                    Some(0) | None => usize::MAX,
+                    Some(count) => count as usize,
+                };
+                vm.heap()
+                    .chunks(HEAP_ROW_LENGTH)
+                    .enumerate()
+                    .skip(start)
+                    .take(count)
+                    .map(|(row, bytes)| {
+                        let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                        variable(format!("{:#06x}", row * HEAP_ROW_LENGTH), hex.join(" "))
+                    })
+                    .collect()
+            }
+            _ => vec![],
+        };
+        Some(variables)
+    }
+
+    fn flush_output(&mut self) -> io::Result<()> {
+        let text = match &self.session {
+            Some(session) => {
+                let bytes: Vec<u8> = session.output.0.borrow_mut().drain(..).collect();
+                String::from_utf8_lossy(&bytes).to_string()
+            }
+            None => return Ok(()),
+        };
+        if text.is_empty() {
+            return Ok(());
+        }
+        self.event("output", json!({ "category": "stdout", "output": text }))
+    }
+
+    fn stopped(&mut self, reason: &str, description: Option<String>) -> io::Result<()> {
+        let mut body = json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        });
+        if let Some(description) = description {
+            body["description"] = json!(description);
+            body["text"] = json!(description);
+        }
+        self.event("stopped", body)
+    }
+
+    fn respond(&mut self, request: &Request, body: Value) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request.seq,
+            "success": true,
+            "command": request.command,
+            "body": body,
+        }))
+    }
+
+    fn respond_error(&mut self, request: &Request, message: &str) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request.seq,
+            "success": false,
+            "command": request.command,
+            "message": message,
+        }))
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        write_message(&mut self.writer, &message)
+    }
+}
+
+/// Loads a `.syb` program, or assembles `.sy` source with debug info.
+fn load(path: &str) -> Result<Session, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Unable to read {}: {}", path, e))?;
+    let container = if Container::is_container(&bytes) {
+        Container::from_bytes(&bytes).map_err(|e| e.to_string())?
+    } else {
+        let source = String::from_utf8(bytes).map_err(|e| e.to_string())?;
+        let mut asm = Assembler::new();
+        asm.messages = Box::new(io::sink());
+        asm.set_source_path(path);
+        let program = asm.assemble(&source).map_err(|errors| {
+            errors
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<String>>()
+                .join("\n")
+        })?;
+        Container::new(program, asm.ro.clone(), Some(asm.debug_info(path)))
+    };
+
+    let output = SharedOutput::default();
+    let mut vm = VM::new();
+    vm.add_bytes(container.program);
+    vm.ro_data = container.ro_data;
+    vm.debug_info = container.debug_info;
+    vm.output = Box::new(output.clone());
+    vm.messages = Box::new(io::sink());
+    vm.start().map_err(|e| e.to_string())?;
+    Ok(Session {
+        vm,
+        output,
+        faulted: false,
+    })
+}
+
+fn line_at(vm: &VM, pc: usize) -> Option<u32> {
+    vm.debug_info.as_ref()?.source_map.line_for(pc as u32)
+}
+
+/// The first line at or after `line` that has code, and its address.
+fn resolve_line(vm: &VM, line: u32) -> Option<(u32, usize)> {
+    let source_map = &vm.debug_info.as_ref()?.source_map;
+    let actual = source_map.lines().into_iter().find(|l| *l >= line)?;
+    let pc = *source_map.pcs_for_line(actual).first()?;
+    Some((actual, pc as usize))
+}
+
+/// Serves a debug session over stdin and stdout.
+pub fn serve_stdio() -> io::Result<()> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut reader = BufReader::new(io::stdin());
+        while let Ok(Some(message)) = read_message(&mut reader) {
+            match serde_json::from_str::<Request>(&message) {
+                Ok(request) => {
+                    if sender.send(request).is_err() {
+                        return;
+                    }
+                }
+                Err(e) => warn!("Ignoring malformed DAP message: {}", e),
+            }
+        }
+    });
+    DapServer::new(io::stdout(), receiver).run()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(seq: i64, command: &str, arguments: Value) -> Request {
+        Request {
+            seq,
+            command: command.to_string(),
+            arguments,
+        }
+    }
+
+    /// Runs `requests` through a server and returns everything it sent.
+    fn exchange(requests: Vec<Request>) -> Vec<Value> {
+        let (sender, receiver) = mpsc::channel();
+        for request in requests {
+            sender.send(request).unwrap();
+        }
+        drop(sender);
+        let mut output = vec![];
+        DapServer::new(&mut output, receiver).run().unwrap();
+
+        let mut reader = io::Cursor::new(output);
+        let mut messages = vec![];
+        while let Some(message) = read_message(&mut reader).unwrap() {
+            messages.push(serde_json::from_str(&message).unwrap());
+        }
+        messages
+    }
+
+    fn write_program(name: &str, source: &str) -> String {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, source).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    fn events<'a>(messages: &'a [Value], name: &str) -> Vec<&'a Value> {
+        messages.iter().filter(|m| m["event"] == name).collect()
+    }
+
+    #[test]
+    fn test_pause_interrupts_running_program() {
+        let program = write_program(
+            "synthia_dap_spin.sy",
+            ".data\n.code\nload $0 @spin\nspin: jmp $0",
+        );
+        let messages = exchange(vec![
+            request(1, "launch", json!({ "program": program })),
+            request(2, "configurationDone", json!({})),
+            request(3, "pause", json!({})),
+            request(4, "disconnect", json!({})),
+        ]);
+        let stopped = events(&messages, "stopped");
+        assert_eq!(stopped.len(), 1);
+        assert_eq!(stopped[0]["body"]["reason"], "pause");
+    }
+
+    #[test]
+    fn test_fault_stops_with_exception() {
+        let program = write_program(
+            "synthia_dap_fault.sy",
+            ".data\n.code\nload $1 #0\ndiv $0 $1 $2",
+        );
+        let messages = exchange(vec![
+            request(1, "launch", json!({ "program": program })),
+            request(2, "configurationDone", json!({})),
+            request(3, "stackTrace", json!({ "threadId": 1 })),
+            request(4, "continue", json!({ "threadId": 1 })),
+        ]);
+        let stopped = events(&messages, "stopped");
+        assert_eq!(stopped[0]["body"]["reason"], "exception");
+        assert_eq!(stopped[0]["body"]["description"], "Division by zero");
+        let trace = messages
+            .iter()
+            .find(|m| m["command"] == "stackTrace")
+            .unwrap();
+        assert_eq!(trace["body"]["stackFrames"][0]["line"], 4);
+        assert_eq!(events(&messages, "exited")[0]["body"]["exitCode"], 1);
+    }
+
+    #[test]
+    fn test_breakpoints_resolve_to_code_lines() {
+        let program = write_program("synthia_dap_lines.sy", ".data\n.code\nload $0 #1\n\nhlt");
+        let messages = exchange(vec![
+            request(1, "launch", json!({ "program": program })),
+            request(
+                2,
+                "setBreakpoints",
+                json!({ "breakpoints": [{ "line": 4 }, { "line": 9 }] }),
+            ),
+        ]);
+        assert_eq!(
+            messages[2]["body"]["breakpoints"],
+            json!([
+                { "id": 1, "verified": true, "line": 5 },
+                { "id": 2, "verified": false, "line": 9, "message": "No code at or after this line" },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_breakpoint_on_the_first_instruction() {
+        let program = write_program("synthia_dap_first.sy", ".data\n.code\nload $0 #3\nhlt");
+        let messages = exchange(vec![
+            request(1, "launch", json!({ "program": program })),
+            request(
+                2,
+                "setBreakpoints",
+                json!({ "breakpoints": [{ "line": 3 }] }),
+            ),
+            request(3, "configurationDone", json!({})),
+            request(4, "continue", json!({ "threadId": 1 })),
+        ]);
+        let stopped = events(&messages, "stopped");
+        assert_eq!(stopped.len(), 1);
+        assert_eq!(stopped[0]["body"]["reason"], "breakpoint");
+        assert_eq!(events(&messages, "exited")[0]["body"]["exitCode"], 0);
+    }
+
+    #[test]
+    fn test_requests_without_a_program() {
+        let messages = exchange(vec![
+            request(1, "stackTrace", json!({})),
+            request(2, "launch", json!({ "program": "/nonexistent/file.sy" })),
+            request(3, "evaluate", json!({})),
+        ]);
+        assert_eq!(messages.len(), 3);
+        assert!(messages.iter().all(|m| m["success"] == false));
+        assert_eq!(messages[2]["message"], "Unsupported command evaluate");
+    }
+}
@@ -0,0 +1,71 @@
+use std::io::{self, BufRead, Write};
+
+use serde::Deserialize;
+use serde_json::Value;
+
+/// A request from the client. Only the fields the server reads are kept.
+#[derive(Debug, PartialEq, Clone, Deserialize)]
+pub struct Request {
+    pub seq: i64,
+    pub command: String,
+    #[serde(default)]
+    pub arguments: Value,
+}
+
+/// Reads one `Content-Length` framed message. Returns `None` at end of input.
+pub fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let length = match length {
+        Some(length) => length,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Message without a Content-Length header",
+            ))
+        }
+    };
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    String::from_utf8(body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn write_message<W: Write>(writer: &mut W, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_message_round_trip() {
+        let mut buffer = vec![];
+        write_message(&mut buffer, &json!({"seq": 1, "command": "threads"})).unwrap();
+        assert!(buffer.starts_with(b"Content-Length: 29\r\n\r\n"));
+
+        let mut reader = io::Cursor::new(buffer);
+        let message = read_message(&mut reader).unwrap().unwrap();
+        let request: Request = serde_json::from_str(&message).unwrap();
+        assert_eq!(request.command, "threads");
+        assert_eq!(request.arguments, Value::Null);
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+    }
+}
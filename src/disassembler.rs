@@ -0,0 +1,228 @@
+use std::fmt::{self, Write};
+
+use crate::{
+    assembler::debug_info::DebugInfo,
+    instruction::{Opcode, OperandKind},
+};
+
+pub const INSTRUCTION_LENGTH: usize = 4;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Operand {
+    Register(u8),
+    Integer(u16),
+    Byte(u8),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct DecodedInstruction {
+    pub opcode: Opcode,
+    pub operands: Vec<Operand>,
+}
+
+impl DecodedInstruction {
+    /// Decodes the instruction at the start of `bytes`. Bytes missing at the
+    /// end of a truncated program are read as zero.
+    pub fn decode(bytes: &[u8]) -> DecodedInstruction {
+        let byte_at = |i: usize| bytes.get(i).copied().unwrap_or(0);
+        let opcode = Opcode::from(byte_at(0));
+        let mut operands = vec![];
+        let mut offset = 1;
+        for kind in opcode.operands() {
+            match kind {
+                OperandKind::Register => {
+                    operands.push(Operand::Register(byte_at(offset)));
+                    offset += 1;
+                }
+                OperandKind::Integer => {
+                    let value = ((byte_at(offset) as u16) << 8) | byte_at(offset + 1) as u16;
+                    operands.push(Operand::Integer(value));
+                    offset += 2;
+                }
+                OperandKind::Byte => {
+                    operands.push(Operand::Byte(byte_at(offset)));
+                    offset += 1;
+                }
+            }
+        }
+        DecodedInstruction { opcode, operands }
+    }
+
+    /// Like `to_string`, but integer operands that were written as a label
+    /// are shown as that label when debug info is available.
+    pub fn to_symbolic_string(&self, pc: usize, debug_info: Option<&DebugInfo>) -> String {
+        let label = match debug_info.and_then(|info| info.label_ref(pc as u32)) {
+            Some(label) => label,
+            None => return self.to_string(),
+        };
+        let mut result = self.opcode.mnemonic().to_string();
+        let mut replaced = false;
+        for operand in &self.operands {
+            match operand {
+                Operand::Integer(_) if !replaced => {
+                    result.push_str(&format!(" @{}", label));
+                    replaced = true;
+                }
+                _ => result.push_str(&format!(" {}", self.operand_text(operand))),
+            }
+        }
+        result
+    }
+
+    /// `operand` as written in source, with sign-extended values negative.
+    fn operand_text(&self, operand: &Operand) -> String {
+        match operand {
+            Operand::Integer(value) if self.opcode.has_signed_immediate() => {
+                format!("#{}", *value as i16)
+            }
+            operand => operand.to_string(),
+        }
+    }
+
+    pub fn registers(&self) -> Vec<u8> {
+        self.operands
+            .iter()
+            .filter_map(|operand| match operand {
+                Operand::Register(reg_num) => Some(*reg_num),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Register(reg_num) => write!(f, "${}", reg_num),
+            Operand::Integer(value) => write!(f, "#{}", value),
+            Operand::Byte(value) => write!(f, "#{}", value),
+        }
+    }
+}
+
+impl fmt::Display for DecodedInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.opcode.mnemonic())?;
+        for operand in &self.operands {
+            write!(f, " {}", self.operand_text(operand))?;
+        }
+        Ok(())
+    }
+}
+
+/// One line description of the instruction at `pc`, e.g.
+/// `hello.sy:7: prts @hello`, or `0x004d: prts #0` without debug info.
+pub fn describe(program: &[u8], pc: usize, debug_info: Option<&DebugInfo>) -> String {
+    let instruction = DecodedInstruction::decode(program.get(pc..).unwrap_or(&[]));
+    let text = instruction.to_symbolic_string(pc, debug_info);
+    match debug_info.and_then(|info| info.location(pc as u32)) {
+        Some(location) => format!("{}: {}", location, text),
+        None => format!("{:#06x}: {}", pc, text),
+    }
+}
+
+/// Full listing of `program` from `start`, with a line for every code label
+/// when debug info is available.
+pub fn listing(program: &[u8], start: usize, debug_info: Option<&DebugInfo>) -> String {
+    let mut out = String::new();
+    let labels = debug_info
+        .map(|info| info.code_labels())
+        .unwrap_or_default();
+    for (pc, instruction) in disassemble(program, start) {
+        for (name, _) in labels.iter().filter(|(_, offset)| *offset as usize == pc) {
+            let _ = writeln!(out, "{}:", name);
+        }
+        let text = instruction.to_symbolic_string(pc, debug_info);
+        match debug_info.and_then(|info| info.location(pc as u32)) {
+            Some(location) => {
+                let _ = writeln!(out, "  {:#06x}  {}: {}", pc, location, text);
+            }
+            None => {
+                let _ = writeln!(out, "  {:#06x}  {}", pc, text);
+            }
+        }
+    }
+    out
+}
+
+/// Disassembles `program` from `start` onwards, returning each instruction
+/// together with its address.
+pub fn disassemble(program: &[u8], start: usize) -> Vec<(usize, DecodedInstruction)> {
+    let mut results = vec![];
+    let mut pc = start;
+    while pc < program.len() {
+        results.push((pc, DecodedInstruction::decode(&program[pc..])));
+        pc += INSTRUCTION_LENGTH;
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+
+    #[test]
+    fn test_decode_load() {
+        let decoded = DecodedInstruction::decode(&[0, 3, 1, 244]);
+        assert_eq!(decoded.opcode, Opcode::LOAD);
+        assert_eq!(
+            decoded.operands,
+            vec![Operand::Register(3), Operand::Integer(500)]
+        );
+        assert_eq!(decoded.registers(), vec![3]);
+        assert_eq!(decoded.to_string(), "load $3 #500");
+    }
+
+    #[test]
+    fn test_decode_signed_immediate() {
+        assert_eq!(
+            DecodedInstruction::decode(&[29, 1, 255, 253]).to_string(),
+            "addi $1 #-3"
+        );
+        assert_eq!(
+            DecodedInstruction::decode(&[33, 1, 255, 253]).to_string(),
+            "andi $1 #65533"
+        );
+    }
+
+    #[test]
+    fn test_decode_truncated() {
+        let decoded = DecodedInstruction::decode(&[1, 0]);
+        assert_eq!(decoded.to_string(), "add $0 $0 $0");
+    }
+
+    #[test]
+    fn test_disassemble_assembled_program() {
+        let mut asm = Assembler::new();
+        let program = asm
+            .assemble(".data\n.code\nload $0 #100\nadd $0 $1 $2\nprts #0\nhlt")
+            .unwrap();
+        let listing: Vec<String> = disassemble(&program, 65)
+            .iter()
+            .map(|(_, i)| i.to_string())
+            .collect();
+        assert_eq!(
+            listing,
+            vec!["load $0 #100", "add $0 $1 $2", "prts #0", "hlt"]
+        );
+    }
+
+    #[test]
+    fn test_describe_with_debug_info() {
+        let mut asm = Assembler::new();
+        let program = asm
+            .assemble(".data\nhello: .asciiz 'Hello'\n.code\nload $0 #100\nprts @hello\nhlt")
+            .unwrap();
+        let info = asm.debug_info("hello.sy");
+        assert_eq!(describe(&program, 69, None), "0x0045: prts #0");
+        assert_eq!(
+            describe(&program, 69, Some(&info)),
+            "hello.sy:5: prts @hello"
+        );
+        assert_eq!(
+            listing(&program, 65, Some(&info)),
+            "  0x0041  hello.sy:4: load $0 #100\n  0x0045  hello.sy:5: prts @hello\n  0x0049  hello.sy:6: hlt\n"
+        );
+    }
+}
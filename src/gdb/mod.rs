@@ -0,0 +1,533 @@
+pub mod packet;
+
+use std::{
+    collections::BTreeSet,
+    io::{self, Read},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::vm::{vm_errors::VmError, VM};
+
+use self::packet::{from_hex, read_packet, to_hex, write_packet, Incoming, INTERRUPT};
+
+/// `$0`-`$31`, then pc, the equal flag and the division remainder.
+pub const REGISTER_COUNT: usize = 35;
+const PC_REGISTER: usize = 32;
+const EQUAL_FLAG_REGISTER: usize = 33;
+const REMAINDER_REGISTER: usize = 34;
+
+/// GDB sees one flat address space: the program (header and code) at 0, the
+/// read-only data and the heap at these bases.
+pub const RO_DATA_BASE: usize = 0x1000_0000;
+pub const HEAP_BASE: usize = 0x2000_0000;
+
+/// How many instructions `continue` runs between checks for Ctrl-C.
+const INTERRUPT_POLL_INTERVAL: usize = 1024;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGFPE: u8 = 8;
+const SIGSEGV: u8 = 11;
+
+#[derive(Debug, PartialEq)]
+enum Stop {
+    Step,
+    Breakpoint,
+    Interrupted,
+    Exited,
+    Fault(VmError),
+}
+
+/// What to send back for a packet.
+#[derive(Debug, PartialEq)]
+pub enum Reply {
+    Packet(String),
+    /// Send the packet, if any, then end the session.
+    Close(Option<String>),
+}
+
+/// A GDB remote serial protocol target backed by a `VM`.
+pub struct GdbStub<'a> {
+    vm: &'a mut VM,
+    breakpoints: BTreeSet<usize>,
+    last_stop: Stop,
+}
+
+impl<'a> GdbStub<'a> {
+    pub fn new(vm: &'a mut VM) -> Result<GdbStub<'a>, VmError> {
+        vm.start()?;
+        Ok(GdbStub {
+            vm,
+            breakpoints: BTreeSet::new(),
+            last_stop: Stop::Step,
+        })
+    }
+
+    /// Handles one packet. `interrupted` is polled while the program runs
+    /// and should return true once GDB has sent Ctrl-C.
+    pub fn handle(&mut self, packet: &str, interrupted: &mut dyn FnMut() -> bool) -> Reply {
+        let reply = match packet.as_bytes().first() {
+            Some(b'?') => stop_reply(&self.last_stop),
+            Some(b'g') => self.read_registers(),
+            Some(b'G') => self.write_registers(&packet[1..]),
+            Some(b'p') => self.read_register(&packet[1..]),
+            Some(b'P') => self.write_register(&packet[1..]),
+            Some(b'm') => self.read_memory(&packet[1..]),
+            Some(b'M') => self.write_memory(&packet[1..]),
+            Some(b's') => self.resume(&packet[1..], true, interrupted),
+            Some(b'c') => self.resume(&packet[1..], false, interrupted),
+            Some(b'Z') => self.breakpoint(&packet[1..], true),
+            Some(b'z') => self.breakpoint(&packet[1..], false),
+            Some(b'H') => "OK".to_string(),
+            Some(b'q') => self.query(&packet[1..]),
+            Some(b'k') => return Reply::Close(None),
+            Some(b'D') => return Reply::Close(Some("OK".to_string())),
+            _ => String::new(),
+        };
+        Reply::Packet(reply)
+    }
+
+    fn register(&self, index: usize) -> u32 {
+        match index {
+            PC_REGISTER => self.vm.pc() as u32,
+            EQUAL_FLAG_REGISTER => self.vm.equal_flag() as u32,
+            REMAINDER_REGISTER => self.vm.remainder() as u32,
+            _ => self.vm.registers[index] as u32,
+        }
+    }
+
+    fn set_register(&mut self, index: usize, value: u32) {
+        match index {
+            PC_REGISTER => self.vm.set_pc(value as usize),
+            EQUAL_FLAG_REGISTER => self.vm.set_equal_flag(value != 0),
+            REMAINDER_REGISTER => self.vm.set_remainder(value as usize),
+            _ => self.vm.registers[index] = value as i32,
+        }
+    }
+
+    fn read_registers(&self) -> String {
+        (0..REGISTER_COUNT)
+            .map(|index| to_hex(&self.register(index).to_le_bytes()))
+            .collect()
+    }
+
+    fn write_registers(&mut self, hex: &str) -> String {
+        match from_hex(hex) {
+            Some(bytes) if bytes.len() == REGISTER_COUNT * 4 => {
+                for (index, chunk) in bytes.chunks(4).enumerate() {
+                    let value = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+                    self.set_register(index, value);
+                }
+                "OK".to_string()
+            }
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn read_register(&self, args: &str) -> String {
+        match usize::from_str_radix(args, 16) {
+            Ok(index) if index < REGISTER_COUNT => to_hex(&self.register(index).to_le_bytes()),
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn write_register(&mut self, args: &str) -> String {
+        let (index, value) = match args.split_once('=') {
+            Some(parts) => parts,
+            None => return "E01".to_string(),
+        };
+        match (usize::from_str_radix(index, 16), from_hex(value)) {
+            (Ok(index), Some(bytes)) if index < REGISTER_COUNT && bytes.len() == 4 => {
+                let value = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                self.set_register(index, value);
+                "OK".to_string()
+            }
+            _ => "E01".to_string(),
+        }
+    }
+
+    /// The memory region containing `address` and the offset into it.
+    fn region(&mut self, address: usize) -> Option<(&mut [u8], usize)> {
+        let (region, offset): (&mut [u8], usize) = if address >= HEAP_BASE {
+            (self.vm.heap_mut(), address - HEAP_BASE)
+        } else if address >= RO_DATA_BASE {
+            (&mut self.vm.ro_data, address - RO_DATA_BASE)
+        } else {
+            (&mut self.vm.program, address)
+        };
+        if offset < region.len() {
+            Some((region, offset))
+        } else {
+            None
+        }
+    }
+
+    fn read_memory(&mut self, args: &str) -> String {
+        let (address, length) = match parse_address_length(args) {
+            Some(parsed) => parsed,
+            None => return "E01".to_string(),
+        };
+        match self.region(address) {
+            Some((region, offset)) => {
+                let end = offset.saturating_add(length).min(region.len());
+                to_hex(&region[offset..end])
+            }
+            None => "E14".to_string(),
+        }
+    }
+
+    fn write_memory(&mut self, args: &str) -> String {
+        let (location, data) = match args.split_once(':') {
+            Some(parts) => parts,
+            None => return "E01".to_string(),
+        };
+        let (address, bytes) = match (parse_address_length(location), from_hex(data)) {
+            (Some((address, length)), Some(bytes)) if bytes.len() == length => (address, bytes),
+            _ => return "E01".to_string(),
+        };
+        if bytes.is_empty() {
+            return "OK".to_string();
+        }
+        match self.region(address) {
+            Some((region, offset)) if offset + bytes.len() <= region.len() => {
+                region[offset..offset + bytes.len()].copy_from_slice(&bytes);
+                "OK".to_string()
+            }
+            _ => "E14".to_string(),
+        }
+    }
+
+    fn breakpoint(&mut self, args: &str, insert: bool) -> String {
+        let mut parts = args.split(',');
+        if parts.next() != Some("0") {
+            // Only software breakpoints are supported.
+            return String::new();
+        }
+        match parts
+            .next()
+            .map(|address| usize::from_str_radix(address, 16))
+        {
+            Some(Ok(address)) => {
+                if insert {
+                    self.breakpoints.insert(address);
+                } else {
+                    self.breakpoints.remove(&address);
+                }
+                "OK".to_string()
+            }
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn resume(
+        &mut self,
+        args: &str,
+        single_step: bool,
+        interrupted: &mut dyn FnMut() -> bool,
+    ) -> String {
+        if let Stop::Exited = self.last_stop {
+            return stop_reply(&self.last_stop);
+        }
+        if !args.is_empty() {
+            match usize::from_str_radix(args, 16) {
+                Ok(address) => self.vm.set_pc(address),
+                Err(_) => return "E01".to_string(),
+            }
+        }
+        self.last_stop = self.run(single_step, interrupted);
+        stop_reply(&self.last_stop)
+    }
+
+    fn run(&mut self, single_step: bool, interrupted: &mut dyn FnMut() -> bool) -> Stop {
+        let mut executed = 0;
+        loop {
+            match self.vm.execute_instructions() {
+                Ok(true) => return Stop::Exited,
+                Ok(false) => {}
+                Err(e) => {
+                    // Leave pc on the faulting instruction.
+                    if let Some(pc) = e.pc() {
+                        self.vm.set_pc(pc);
+                    }
+                    return Stop::Fault(e);
+                }
+            }
+            if single_step {
+                return Stop::Step;
+            }
+            if self.breakpoints.contains(&self.vm.pc()) {
+                return Stop::Breakpoint;
+            }
+            executed += 1;
+            if executed % INTERRUPT_POLL_INTERVAL == 0 && interrupted() {
+                return Stop::Interrupted;
+            }
+        }
+    }
+
+    fn query(&self, query: &str) -> String {
+        if query.starts_with("Supported") {
+            return "PacketSize=1000;qXfer:features:read+;swbreak+".to_string();
+        }
+        if let Some(args) = query.strip_prefix("Xfer:features:read:target.xml:") {
+            return match parse_address_length(args) {
+                Some((offset, length)) => xfer_chunk(&target_description(), offset, length),
+                None => "E01".to_string(),
+            };
+        }
+        match query {
+            "Attached" => "1".to_string(),
+            "C" => "QC1".to_string(),
+            "fThreadInfo" => "m1".to_string(),
+            "sThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+}
+
+fn stop_reply(stop: &Stop) -> String {
+    match stop {
+        Stop::Step => format!("S{:02x}", SIGTRAP),
+        Stop::Breakpoint => format!("T{:02x}swbreak:;", SIGTRAP),
+        Stop::Interrupted => format!("S{:02x}", SIGINT),
+        Stop::Exited => "W00".to_string(),
+        Stop::Fault(e) => format!("S{:02x}", signal_for(e)),
+    }
+}
+
+/// The Unix signal GDB reports for a fault.
+fn signal_for(error: &VmError) -> u8 {
+    match error {
+        VmError::DivideByZero { .. } => SIGFPE,
+        VmError::IllegalOpcode { .. }
+        | VmError::InvalidRegister { .. }
+        | VmError::UnexpectedEndOfProgram { .. } => SIGILL,
+        _ => SIGSEGV,
+    }
+}
+
+fn parse_address_length(args: &str) -> Option<(usize, usize)> {
+    let (address, length) = args.split_once(',')?;
+    Some((
+        usize::from_str_radix(address, 16).ok()?,
+        usize::from_str_radix(length, 16).ok()?,
+    ))
+}
+
+fn xfer_chunk(document: &str, offset: usize, length: usize) -> String {
+    if offset >= document.len() {
+        return "l".to_string();
+    }
+    let end = offset.saturating_add(length).min(document.len());
+    let marker = if end == document.len() { 'l' } else { 'm' };
+    format!("{}{}", marker, &document[offset..end])
+}
+
+/// Target description telling GDB about Synthia's registers.
+pub fn target_description() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n<target version=\"1.0\">\n  <feature name=\"org.synthia.core\">\n",
+    );
+    for index in 0..32 {
+        xml.push_str(&format!(
+            "    <reg name=\"r{}\" bitsize=\"32\" type=\"int32\" regnum=\"{}\"/>\n",
+            index, index
+        ));
+    }
+    xml.push_str("    <reg name=\"pc\" bitsize=\"32\" type=\"code_ptr\" regnum=\"32\"/>\n");
+    xml.push_str("    <reg name=\"eq\" bitsize=\"32\" type=\"uint32\" regnum=\"33\"/>\n");
+    xml.push_str("    <reg name=\"remainder\" bitsize=\"32\" type=\"uint32\" regnum=\"34\"/>\n");
+    xml.push_str("  </feature>\n</target>\n");
+    xml
+}
+
+/// Waits for GDB to connect on `address` and serves a single session.
+pub fn listen(address: &str, vm: &mut VM) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    println!("Waiting for GDB on {}", listener.local_addr()?);
+    let (stream, peer) = listener.accept()?;
+    println!("GDB connected from {}", peer);
+    serve(stream, vm)
+}
+
+pub fn serve(mut stream: TcpStream, vm: &mut VM) -> io::Result<()> {
+    let mut stub = match GdbStub::new(vm) {
+        Ok(stub) => stub,
+        Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+    };
+    let interrupt_source = stream.try_clone()?;
+    let mut interrupted = || poll_interrupt(&interrupt_source);
+    while let Some(incoming) = read_packet(&mut stream)? {
+        let packet = match incoming {
+            Incoming::Packet(packet) => packet,
+            // The program is not running, there is nothing to interrupt.
+            Incoming::Interrupt => continue,
+        };
+        match stub.handle(&packet, &mut interrupted) {
+            Reply::Packet(reply) => write_packet(&mut stream, &reply)?,
+            Reply::Close(reply) => {
+                if let Some(reply) = reply {
+                    write_packet(&mut stream, &reply)?;
+                }
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks, without blocking, whether GDB has sent Ctrl-C.
+fn poll_interrupt(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let mut byte = [0u8; 1];
+    let interrupted = matches!(stream.peek(&mut byte), Ok(1) if byte[0] == INTERRUPT);
+    if interrupted {
+        let _ = (&*stream).read(&mut byte);
+    }
+    let _ = stream.set_nonblocking(false);
+    interrupted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+    use std::{io::Write, thread};
+
+    const COUNTER: &str =
+        ".data\n.code\nload $0 #0\nload $1 #3\nload $2 @loop\nloop: inc $0\nneq $0 $1\njeq $2\nhlt";
+
+    fn vm_for(source: &str) -> VM {
+        let mut asm = Assembler::new();
+        let mut vm = VM::new();
+        vm.add_bytes(asm.assemble(source).unwrap());
+        vm.ro_data = asm.ro.clone();
+        vm
+    }
+
+    fn send(stub: &mut GdbStub, packet: &str) -> String {
+        match stub.handle(packet, &mut || false) {
+            Reply::Packet(reply) => reply,
+            Reply::Close(reply) => reply.unwrap_or_default(),
+        }
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut vm = vm_for(COUNTER);
+        let mut stub = GdbStub::new(&mut vm).unwrap();
+        assert_eq!(send(&mut stub, "p20"), "41000000");
+        assert_eq!(send(&mut stub, "P3=2a000000"), "OK");
+        assert_eq!(send(&mut stub, "p3"), "2a000000");
+        assert_eq!(send(&mut stub, "p23"), "E01");
+
+        let registers = send(&mut stub, "g");
+        assert_eq!(registers.len(), REGISTER_COUNT * 8);
+        assert_eq!(&registers[24..32], "2a000000");
+        let mut changed = registers.clone();
+        changed.replace_range(0..8, "ffffffff");
+        assert_eq!(send(&mut stub, &format!("G{}", changed)), "OK");
+        assert_eq!(stub.vm.registers[0], -1);
+        assert_eq!(send(&mut stub, "G00"), "E01");
+    }
+
+    #[test]
+    fn test_memory() {
+        let mut vm = vm_for(".data\nhello: .asciiz 'Hi'\n.code\nload $0 #16\naloc $0\nhlt");
+        let mut stub = GdbStub::new(&mut vm).unwrap();
+        assert_eq!(send(&mut stub, "m0,4"), "2d32312d");
+        assert_eq!(send(&mut stub, "m41,4"), "00000010");
+        assert_eq!(send(&mut stub, "m10000000,8"), "486900");
+        assert_eq!(send(&mut stub, "m20000000,4"), "E14");
+        assert_eq!(send(&mut stub, "c"), "W00");
+        assert_eq!(send(&mut stub, "M20000002,2:beef"), "OK");
+        assert_eq!(send(&mut stub, "m20000000,4"), "0000beef");
+        assert_eq!(send(&mut stub, "M20000010,1:00"), "E14");
+        assert_eq!(send(&mut stub, "m0,ffffffffffffffff").len(), 2 * 77);
+        assert_eq!(send(&mut stub, "m10000000,ffffffffffffffff"), "486900");
+    }
+
+    #[test]
+    fn test_step_and_breakpoints() {
+        let mut vm = vm_for(COUNTER);
+        let mut stub = GdbStub::new(&mut vm).unwrap();
+        assert_eq!(send(&mut stub, "s"), "S05");
+        assert_eq!(send(&mut stub, "p20"), "45000000");
+
+        assert_eq!(send(&mut stub, "Z0,4d,4"), "OK");
+        assert_eq!(send(&mut stub, "c"), "T05swbreak:;");
+        assert_eq!(send(&mut stub, "p0"), "00000000");
+        assert_eq!(send(&mut stub, "c"), "T05swbreak:;");
+        assert_eq!(send(&mut stub, "p0"), "01000000");
+        assert_eq!(send(&mut stub, "z0,4d,4"), "OK");
+        assert_eq!(send(&mut stub, "c"), "W00");
+        assert_eq!(stub.vm.registers[0], 3);
+        assert_eq!(send(&mut stub, "?"), "W00");
+    }
+
+    #[test]
+    fn test_fault_and_interrupt() {
+        let mut vm = vm_for(".data\n.code\nload $1 #0\ndiv $0 $1 $2");
+        let mut stub = GdbStub::new(&mut vm).unwrap();
+        assert_eq!(send(&mut stub, "c"), "S08");
+        assert_eq!(send(&mut stub, "p20"), "45000000");
+
+        let mut vm = vm_for(".data\n.code\nload $0 @spin\nspin: jmp $0");
+        let mut stub = GdbStub::new(&mut vm).unwrap();
+        assert_eq!(
+            stub.handle("c", &mut || true),
+            Reply::Packet("S02".to_string())
+        );
+    }
+
+    #[test]
+    fn test_target_description() {
+        let mut vm = vm_for(COUNTER);
+        let mut stub = GdbStub::new(&mut vm).unwrap();
+        assert!(send(&mut stub, "qSupported:multiprocess+").contains("qXfer:features:read+"));
+        let document = target_description();
+        let first = send(&mut stub, "qXfer:features:read:target.xml:0,10");
+        assert_eq!(first, format!("m{}", &document[..16]));
+        let rest = send(
+            &mut stub,
+            &format!("qXfer:features:read:target.xml:10,{:x}", document.len()),
+        );
+        assert_eq!(rest, format!("l{}", &document[16..]));
+        let huge = send(
+            &mut stub,
+            "qXfer:features:read:target.xml:10,ffffffffffffffff",
+        );
+        assert_eq!(huge, rest);
+        assert!(
+            document.contains("<reg name=\"r31\" bitsize=\"32\" type=\"int32\" regnum=\"31\"/>")
+        );
+    }
+
+    #[test]
+    fn test_session_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut vm = vm_for(COUNTER);
+            serve(stream, &mut vm).unwrap();
+            vm.registers[0]
+        });
+
+        let mut client = TcpStream::connect(address).unwrap();
+        let mut exchange = |packet: &str| {
+            client.write_all(&packet::encode(packet)).unwrap();
+            match read_packet(&mut client).unwrap() {
+                Some(Incoming::Packet(reply)) => reply,
+                other => panic!("Unexpected reply {:?}", other),
+            }
+        };
+        assert_eq!(exchange("?"), "S05");
+        assert_eq!(exchange("c"), "W00");
+        assert_eq!(exchange("D"), "OK");
+        assert_eq!(server.join().unwrap(), 3);
+    }
+}
@@ -0,0 +1,184 @@
+use std::io::{self, Read, Write};
+
+/// Ctrl-C sent by GDB to interrupt a running target.
+pub const INTERRUPT: u8 = 0x03;
+
+/// What arrived on the connection: a packet, or an out-of-band interrupt.
+#[derive(Debug, PartialEq)]
+pub enum Incoming {
+    Packet(String),
+    Interrupt,
+}
+
+pub fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+/// Frames `data` as `$data#xx`, escaping the characters the protocol
+/// reserves.
+pub fn encode(data: &str) -> Vec<u8> {
+    let body = escape(data.as_bytes());
+    let mut packet = vec![b'$'];
+    packet.extend_from_slice(&body);
+    packet.extend_from_slice(format!("#{:02x}", checksum(&body)).as_bytes());
+    packet
+}
+
+/// Reads the next packet, acknowledging it. Packets with a bad checksum are
+/// nacked and read again. Returns `None` when the connection is closed.
+pub fn read_packet<S: Read + Write>(stream: &mut S) -> io::Result<Option<Incoming>> {
+    loop {
+        let byte = match read_byte(stream)? {
+            Some(byte) => byte,
+            None => return Ok(None),
+        };
+        match byte {
+            INTERRUPT => return Ok(Some(Incoming::Interrupt)),
+            b'$' => {}
+            // Acks for our own packets and stray bytes.
+            _ => continue,
+        }
+
+        let mut body = vec![];
+        loop {
+            match read_byte(stream)? {
+                Some(b'#') => break,
+                Some(b'}') => match read_byte(stream)? {
+                    Some(escaped) => body.push(escaped ^ 0x20),
+                    None => return Ok(None),
+                },
+                Some(byte) => body.push(byte),
+                None => return Ok(None),
+            }
+        }
+        let mut sum = [0u8; 2];
+        stream.read_exact(&mut sum)?;
+        let expected = std::str::from_utf8(&sum)
+            .ok()
+            .and_then(|s| u8::from_str_radix(s, 16).ok());
+        // The checksum covers the bytes as sent, so recompute it over the
+        // escaped form.
+        if expected != Some(checksum(&escape(&body))) {
+            stream.write_all(b"-")?;
+            continue;
+        }
+        stream.write_all(b"+")?;
+        return Ok(Some(Incoming::Packet(
+            String::from_utf8_lossy(&body).to_string(),
+        )));
+    }
+}
+
+pub fn write_packet<S: Write>(stream: &mut S, data: &str) -> io::Result<()> {
+    stream.write_all(&encode(data))?;
+    stream.flush()
+}
+
+fn escape(body: &[u8]) -> Vec<u8> {
+    let mut escaped = vec![];
+    for byte in body {
+        if matches!(byte, b'$' | b'#' | b'}' | b'*') {
+            escaped.push(b'}');
+            escaped.push(byte ^ 0x20);
+        } else {
+            escaped.push(*byte);
+        }
+    }
+    escaped
+}
+
+fn read_byte<S: Read>(stream: &mut S) -> io::Result<Option<u8>> {
+    let mut byte = [0u8; 1];
+    match stream.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// An in-memory connection: reads come from `input`, writes are kept.
+    struct Loopback {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Loopback {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Loopback {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn loopback(input: &[u8]) -> Loopback {
+        Loopback {
+            input: Cursor::new(input.to_vec()),
+            output: vec![],
+        }
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode("OK"), b"$OK#9a");
+        assert_eq!(encode("a#b"), b"$a}\x03b#43");
+    }
+
+    #[test]
+    fn test_read_packet() {
+        let mut stream = loopback(b"+$g#67$m41,4#xx$?#3f\x03");
+        assert_eq!(
+            read_packet(&mut stream).unwrap(),
+            Some(Incoming::Packet("g".to_string()))
+        );
+        assert_eq!(
+            read_packet(&mut stream).unwrap(),
+            Some(Incoming::Packet("?".to_string()))
+        );
+        assert_eq!(read_packet(&mut stream).unwrap(), Some(Incoming::Interrupt));
+        assert_eq!(read_packet(&mut stream).unwrap(), None);
+        assert_eq!(stream.output, b"+-+");
+    }
+
+    #[test]
+    fn test_read_escaped_packet() {
+        let mut stream = loopback(&encode("X0,1:}"));
+        assert_eq!(
+            read_packet(&mut stream).unwrap(),
+            Some(Incoming::Packet("X0,1:}".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(to_hex(&[0, 0xab, 0x10]), "00ab10");
+        assert_eq!(from_hex("00ab10"), Some(vec![0, 0xab, 0x10]));
+        assert_eq!(from_hex("0"), None);
+        assert_eq!(from_hex("zz"), None);
+    }
+}
@@ -1,4 +1,7 @@
-#[derive(Debug, PartialEq, Clone, Copy)]
+/// An instruction. Its number is the first byte of the encoding, so
+/// assembled programs only keep working if new opcodes are added at the
+/// end.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
 pub enum Opcode {
     LOAD,
     ADD,
@@ -8,7 +11,7 @@ pub enum Opcode {
     HLT,
     JMP,
     JMPF,
-    JMPB,
+    JMPB = 9,
     EQ,
     NEQ,
     GT,
@@ -23,7 +26,40 @@ pub enum Opcode {
     DJMPE,
     NOP,
     PRTS,
-    IGL,
+    CALL,
+    RET,
+    LB,
+    SB,
+    LBR,
+    ADDI,
+    SUBI,
+    MULI,
+    DIVI,
+    ANDI,
+    ORI,
+    EQI,
+    NEQI,
+    GTI,
+    LTI,
+    GTEI,
+    LTEI,
+    DIVU,
+    REMU,
+    GTU,
+    LTU,
+    GTEU,
+    LTEU,
+    SHL,
+    SHR,
+    SAR,
+    IGL = 255,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OperandKind {
+    Register,
+    Integer,
+    Byte,
 }
 
 impl From<u8> for Opcode {
@@ -52,6 +88,32 @@ impl From<u8> for Opcode {
             21 => Opcode::DJMPE,
             22 => Opcode::NOP,
             23 => Opcode::PRTS,
+            24 => Opcode::CALL,
+            25 => Opcode::RET,
+            26 => Opcode::LB,
+            27 => Opcode::SB,
+            28 => Opcode::LBR,
+            29 => Opcode::ADDI,
+            30 => Opcode::SUBI,
+            31 => Opcode::MULI,
+            32 => Opcode::DIVI,
+            33 => Opcode::ANDI,
+            34 => Opcode::ORI,
+            35 => Opcode::EQI,
+            36 => Opcode::NEQI,
+            37 => Opcode::GTI,
+            38 => Opcode::LTI,
+            39 => Opcode::GTEI,
+            40 => Opcode::LTEI,
+            41 => Opcode::DIVU,
+            42 => Opcode::REMU,
+            43 => Opcode::GTU,
+            44 => Opcode::LTU,
+            45 => Opcode::GTEU,
+            46 => Opcode::LTEU,
+            47 => Opcode::SHL,
+            48 => Opcode::SHR,
+            49 => Opcode::SAR,
             _ => Opcode::IGL,
         }
     }
@@ -83,11 +145,278 @@ impl<'a> From<&'a str> for Opcode {
             "djmpe" => Opcode::DJMPE,
             "nop" => Opcode::NOP,
             "prts" => Opcode::PRTS,
+            "call" => Opcode::CALL,
+            "ret" => Opcode::RET,
+            "lb" => Opcode::LB,
+            "sb" => Opcode::SB,
+            "lbr" => Opcode::LBR,
+            "addi" => Opcode::ADDI,
+            "subi" => Opcode::SUBI,
+            "muli" => Opcode::MULI,
+            "divi" => Opcode::DIVI,
+            "andi" => Opcode::ANDI,
+            "ori" => Opcode::ORI,
+            "eqi" => Opcode::EQI,
+            "neqi" => Opcode::NEQI,
+            "gti" => Opcode::GTI,
+            "lti" => Opcode::LTI,
+            "gtei" => Opcode::GTEI,
+            "ltei" => Opcode::LTEI,
+            "divu" => Opcode::DIVU,
+            "remu" => Opcode::REMU,
+            "gtu" => Opcode::GTU,
+            "ltu" => Opcode::LTU,
+            "gteu" => Opcode::GTEU,
+            "lteu" => Opcode::LTEU,
+            "shl" => Opcode::SHL,
+            "shr" => Opcode::SHR,
+            "sar" => Opcode::SAR,
             _ => Opcode::IGL,
         }
     }
 }
 
+impl Opcode {
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Opcode::LOAD => "load",
+            Opcode::ADD => "add",
+            Opcode::SUB => "sub",
+            Opcode::MUL => "mul",
+            Opcode::DIV => "div",
+            Opcode::HLT => "hlt",
+            Opcode::JMP => "jmp",
+            Opcode::JMPF => "jmpf",
+            Opcode::JMPB => "jmpb",
+            Opcode::EQ => "eq",
+            Opcode::NEQ => "neq",
+            Opcode::GT => "gt",
+            Opcode::LT => "lt",
+            Opcode::GTE => "gte",
+            Opcode::LTE => "lte",
+            Opcode::JEQ => "jeq",
+            Opcode::JNEQ => "jneq",
+            Opcode::ALOC => "aloc",
+            Opcode::INC => "inc",
+            Opcode::DEC => "dec",
+            Opcode::DJMPE => "djmpe",
+            Opcode::NOP => "nop",
+            Opcode::PRTS => "prts",
+            Opcode::CALL => "call",
+            Opcode::RET => "ret",
+            Opcode::LB => "lb",
+            Opcode::SB => "sb",
+            Opcode::LBR => "lbr",
+            Opcode::ADDI => "addi",
+            Opcode::SUBI => "subi",
+            Opcode::MULI => "muli",
+            Opcode::DIVI => "divi",
+            Opcode::ANDI => "andi",
+            Opcode::ORI => "ori",
+            Opcode::EQI => "eqi",
+            Opcode::NEQI => "neqi",
+            Opcode::GTI => "gti",
+            Opcode::LTI => "lti",
+            Opcode::GTEI => "gtei",
+            Opcode::LTEI => "ltei",
+            Opcode::DIVU => "divu",
+            Opcode::REMU => "remu",
+            Opcode::GTU => "gtu",
+            Opcode::LTU => "ltu",
+            Opcode::GTEU => "gteu",
+            Opcode::LTEU => "lteu",
+            Opcode::SHL => "shl",
+            Opcode::SHR => "shr",
+            Opcode::SAR => "sar",
+            Opcode::IGL => "igl",
+        }
+    }
+
+    /// Every valid opcode, in encoding order.
+    pub fn all() -> impl Iterator<Item = Opcode> {
+        (0..=u8::MAX)
+            .map(Opcode::from)
+            .filter(|opcode| *opcode != Opcode::IGL)
+    }
+
+    /// One line summary of what the instruction does.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Opcode::LOAD => "Load a 16-bit value into a register",
+            Opcode::ADD => "Add two registers into a third",
+            Opcode::SUB => "Subtract the second register from the first into a third",
+            Opcode::MUL => "Multiply two registers into a third",
+            Opcode::DIV => {
+                "Divide the first register by the second into a third, keeping the remainder"
+            }
+            Opcode::HLT => "Stop the program",
+            Opcode::JMP => "Jump to the address in a register",
+            Opcode::JMPF => "Jump forward by the number of bytes in a register",
+            Opcode::JMPB => "Jump backward by the number of bytes in a register",
+            Opcode::EQ => "Set the equal flag if two registers are equal",
+            Opcode::NEQ => "Set the equal flag if two registers differ",
+            Opcode::GT => "Set the equal flag if the first register is greater",
+            Opcode::LT => "Set the equal flag if the first register is less",
+            Opcode::GTE => "Set the equal flag if the first register is greater or equal",
+            Opcode::LTE => "Set the equal flag if the first register is less or equal",
+            Opcode::JEQ => "Jump to the address in a register if the equal flag is set",
+            Opcode::JNEQ => "Jump to the address in a register if the equal flag is clear",
+            Opcode::ALOC => "Grow the heap by the number of bytes in a register",
+            Opcode::INC => "Add one to a register",
+            Opcode::DEC => "Subtract one from a register",
+            Opcode::DJMPE => "Jump to an address if the equal flag is set",
+            Opcode::NOP => "Do nothing",
+            Opcode::PRTS => "Print the string at a read-only data offset",
+            Opcode::CALL => "Call the function at the address in a register",
+            Opcode::RET => "Return from the current function",
+            Opcode::LB => "Load the heap byte at the address in the second register, from 0 to 255",
+            Opcode::SB => "Store the low byte of a register at the heap address in the second",
+            Opcode::LBR => "Load the read-only data byte at the offset in the second register, from 0 to 255",
+            Opcode::ADDI => "Add a signed 16-bit value to a register",
+            Opcode::SUBI => "Subtract a signed 16-bit value from a register",
+            Opcode::MULI => "Multiply a register by a signed 16-bit value",
+            Opcode::DIVI => "Divide a register by a signed 16-bit value, keeping the remainder",
+            Opcode::ANDI => "Clear the bits of a register that are clear in a 16-bit value",
+            Opcode::ORI => "Set the bits of a register that are set in a 16-bit value",
+            Opcode::EQI => "Set the equal flag if a register equals a signed 16-bit value",
+            Opcode::NEQI => "Set the equal flag if a register differs from a signed 16-bit value",
+            Opcode::GTI => "Set the equal flag if a register is greater than a signed 16-bit value",
+            Opcode::LTI => "Set the equal flag if a register is less than a signed 16-bit value",
+            Opcode::GTEI => {
+                "Set the equal flag if a register is greater than or equal to a signed 16-bit value"
+            }
+            Opcode::LTEI => {
+                "Set the equal flag if a register is less than or equal to a signed 16-bit value"
+            }
+            Opcode::DIVU => "Divide the first register by the second as unsigned into a third, keeping the remainder",
+            Opcode::REMU => "Store the unsigned remainder of the first register by the second in a third",
+            Opcode::GTU => "Set the equal flag if the first register is greater, as unsigned",
+            Opcode::LTU => "Set the equal flag if the first register is less, as unsigned",
+            Opcode::GTEU => "Set the equal flag if the first register is greater or equal, as unsigned",
+            Opcode::LTEU => "Set the equal flag if the first register is less or equal, as unsigned",
+            Opcode::SHL => "Shift the first register left by the second into a third",
+            Opcode::SHR => "Shift the first register right by the second into a third, filling with zeros",
+            Opcode::SAR => "Shift the first register right by the second into a third, keeping its sign",
+            Opcode::IGL => "Illegal instruction",
+        }
+    }
+
+    /// Usage with operand placeholders, e.g. `load $reg #value`.
+    pub fn signature(&self) -> String {
+        let mut signature = self.mnemonic().to_string();
+        for (index, kind) in self.operands().iter().enumerate() {
+            let placeholder = match kind {
+                OperandKind::Register => format!(" $reg{}", index + 1),
+                OperandKind::Integer | OperandKind::Byte => " #value".to_string(),
+            };
+            signature.push_str(&placeholder);
+        }
+        signature
+    }
+
+    pub fn is_jump(&self) -> bool {
+        matches!(
+            self,
+            Opcode::JMP | Opcode::JMPF | Opcode::JMPB | Opcode::JEQ | Opcode::JNEQ | Opcode::DJMPE
+        )
+    }
+
+    pub fn is_conditional_jump(&self) -> bool {
+        matches!(self, Opcode::JEQ | Opcode::JNEQ | Opcode::DJMPE)
+    }
+
+    /// The form taking a 16-bit value in place of the second register,
+    /// which it changes in place for arithmetic.
+    pub fn immediate_form(&self) -> Option<Opcode> {
+        match self {
+            Opcode::ADD => Some(Opcode::ADDI),
+            Opcode::SUB => Some(Opcode::SUBI),
+            Opcode::MUL => Some(Opcode::MULI),
+            Opcode::DIV => Some(Opcode::DIVI),
+            Opcode::EQ => Some(Opcode::EQI),
+            Opcode::NEQ => Some(Opcode::NEQI),
+            Opcode::GT => Some(Opcode::GTI),
+            Opcode::LT => Some(Opcode::LTI),
+            Opcode::GTE => Some(Opcode::GTEI),
+            Opcode::LTE => Some(Opcode::LTEI),
+            _ => None,
+        }
+    }
+
+    /// Whether the 16-bit value is sign-extended, as for arithmetic and
+    /// comparisons, rather than taken as unsigned like `load` and the
+    /// bitwise `andi` and `ori`.
+    pub fn has_signed_immediate(&self) -> bool {
+        matches!(
+            self,
+            Opcode::ADDI
+                | Opcode::SUBI
+                | Opcode::MULI
+                | Opcode::DIVI
+                | Opcode::EQI
+                | Opcode::NEQI
+                | Opcode::GTI
+                | Opcode::LTI
+                | Opcode::GTEI
+                | Opcode::LTEI
+        )
+    }
+
+    /// Operands in the order they are encoded after the opcode byte.
+    pub fn operands(&self) -> &'static [OperandKind] {
+        use OperandKind::*;
+        match self {
+            Opcode::LOAD => &[Register, Integer],
+            Opcode::ADD
+            | Opcode::SUB
+            | Opcode::MUL
+            | Opcode::DIV
+            | Opcode::DIVU
+            | Opcode::REMU
+            | Opcode::SHL
+            | Opcode::SHR
+            | Opcode::SAR => &[Register, Register, Register],
+            Opcode::EQ
+            | Opcode::NEQ
+            | Opcode::GT
+            | Opcode::LT
+            | Opcode::GTE
+            | Opcode::LTE
+            | Opcode::GTU
+            | Opcode::LTU
+            | Opcode::GTEU
+            | Opcode::LTEU
+            | Opcode::LB
+            | Opcode::SB
+            | Opcode::LBR => &[Register, Register],
+            Opcode::ADDI
+            | Opcode::SUBI
+            | Opcode::MULI
+            | Opcode::DIVI
+            | Opcode::ANDI
+            | Opcode::ORI
+            | Opcode::EQI
+            | Opcode::NEQI
+            | Opcode::GTI
+            | Opcode::LTI
+            | Opcode::GTEI
+            | Opcode::LTEI => &[Register, Integer],
+            Opcode::JMP
+            | Opcode::JMPF
+            | Opcode::JMPB
+            | Opcode::JEQ
+            | Opcode::JNEQ
+            | Opcode::ALOC
+            | Opcode::INC
+            | Opcode::DEC
+            | Opcode::CALL => &[Register],
+            Opcode::DJMPE => &[Byte],
+            Opcode::PRTS => &[Integer],
+            Opcode::HLT | Opcode::NOP | Opcode::RET | Opcode::IGL => &[],
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Instruction {
     opcode: Opcode,
@@ -103,6 +432,15 @@ impl Instruction {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_opcode_signature() {
+        assert_eq!(Opcode::LOAD.signature(), "load $reg1 #value");
+        assert_eq!(Opcode::ADD.signature(), "add $reg1 $reg2 $reg3");
+        assert_eq!(Opcode::HLT.signature(), "hlt");
+        assert_eq!(Opcode::all().count(), 49);
+        assert_eq!(Opcode::ADDI.signature(), "addi $reg1 #value");
+    }
+
     #[test]
     fn test_create_hlt() {
         let opcode = Opcode::HLT;
@@ -122,4 +460,26 @@ mod tests {
         let opcode = Opcode::from("illegal");
         assert_eq!(opcode, Opcode::IGL);
     }
+
+    #[test]
+    fn test_opcode_numbers() {
+        assert_eq!(Opcode::RET as u8, 25);
+        assert_eq!(Opcode::LB as u8, 26);
+        assert_eq!(Opcode::SB as u8, 27);
+        assert_eq!(Opcode::LBR as u8, 28);
+        assert_eq!(Opcode::ADDI as u8, 29);
+        assert_eq!(Opcode::DIVU as u8, 41);
+        assert_eq!(Opcode::SAR as u8, 49);
+    }
+
+    #[test]
+    fn test_opcode_encoding_round_trip() {
+        for byte in 0..=u8::MAX {
+            let opcode = Opcode::from(byte);
+            if opcode != Opcode::IGL {
+                assert_eq!(opcode as u8, byte);
+                assert_eq!(Opcode::from(opcode.mnemonic()), opcode);
+            }
+        }
+    }
 }
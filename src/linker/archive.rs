@@ -0,0 +1,216 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    assembler::{
+        library,
+        object::{ObjectFile, RelocationTarget},
+        Assembler,
+    },
+    linker::linker_errors::LinkError,
+};
+
+/// The first bytes of an archive, `-21A`.
+pub const ARCHIVE_HEADER_PREFIX: [u8; 4] = [45, 50, 49, 65];
+
+/// An object file stored in an archive under its file name.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ArchiveMember {
+    pub name: String,
+    pub object: ObjectFile,
+}
+
+/// A static library of object files, as written by `synthia ar`, with an
+/// index of the global symbols each member defines.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Archive {
+    pub members: Vec<ArchiveMember>,
+    /// Global symbols and the index of the member defining them.
+    pub index: Vec<(String, usize)>,
+}
+
+impl Archive {
+    pub fn new(members: Vec<ArchiveMember>) -> Result<Archive, LinkError> {
+        let mut index: Vec<(String, usize)> = vec![];
+        for (position, member) in members.iter().enumerate() {
+            for symbol in member.object.globals() {
+                if let Some((_, first)) = index.iter().find(|(name, _)| *name == symbol.name) {
+                    return Err(LinkError::DuplicateSymbol {
+                        name: symbol.name.clone(),
+                        first: members[*first].name.clone(),
+                        second: member.name.clone(),
+                    });
+                }
+                index.push((symbol.name.clone(), position));
+            }
+        }
+        Ok(Archive { members, index })
+    }
+
+    pub fn is_archive(bytes: &[u8]) -> bool {
+        bytes.len() >= 4 && bytes[0..4] == ARCHIVE_HEADER_PREFIX
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = ARCHIVE_HEADER_PREFIX.to_vec();
+        bytes.extend(serde_json::to_vec(self).expect("Archives are always serializable"));
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Archive, String> {
+        if !Archive::is_archive(bytes) {
+            return Err("not an archive".to_string());
+        }
+        serde_json::from_slice(&bytes[4..]).map_err(|e| e.to_string())
+    }
+
+    /// The member that defines the global symbol `name`.
+    pub fn member_defining(&self, name: &str) -> Option<usize> {
+        self.index
+            .iter()
+            .find(|(symbol, _)| symbol == name)
+            .map(|(_, position)| *position)
+    }
+}
+
+/// The bundled library as an archive with a member for each file.
+pub fn standard_library() -> Archive {
+    let members = library::FILES
+        .iter()
+        .map(|(path, source)| ArchiveMember {
+            name: path.to_string(),
+            object: Assembler::new()
+                .assemble_object(source, &format!("<{}>", path))
+                .expect("The bundled library assembles"),
+        })
+        .collect();
+    Archive::new(members).expect("The bundled library defines each symbol once")
+}
+
+/// Adds to `objects` the archive members that define symbols the objects
+/// use but do not define, like a classic static library: each archive is
+/// searched in turn, until its members resolve nothing more, so members
+/// may use each other. Pulled members are named `archive(member)`.
+pub fn pull_members(
+    mut objects: Vec<(String, ObjectFile)>,
+    archives: &[(String, Archive)],
+) -> Vec<(String, ObjectFile)> {
+    for (file, archive) in archives {
+        let mut pulled = HashSet::new();
+        loop {
+            let wanted: Vec<usize> = undefined_symbols(&objects)
+                .iter()
+                .filter_map(|name| archive.member_defining(name))
+                .filter(|position| pulled.insert(*position))
+                .collect();
+            if wanted.is_empty() {
+                break;
+            }
+            for position in wanted {
+                let member = &archive.members[position];
+                objects.push((format!("{}({})", file, member.name), member.object.clone()));
+            }
+        }
+    }
+    objects
+}
+
+/// Symbols the relocations of `objects` need that none of them defines,
+/// in the order they are first used.
+fn undefined_symbols(objects: &[(String, ObjectFile)]) -> Vec<String> {
+    let defined: HashSet<&str> = objects
+        .iter()
+        .flat_map(|(_, object)| object.globals())
+        .map(|symbol| symbol.name.as_str())
+        .collect();
+    let mut undefined: Vec<String> = vec![];
+    for (_, object) in objects {
+        for relocation in &object.relocations {
+            if let RelocationTarget::Symbol(name) = &relocation.target {
+                if !defined.contains(name.as_str()) && !undefined.contains(name) {
+                    undefined.push(name.clone());
+                }
+            }
+        }
+    }
+    undefined
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+
+    fn member(name: &str, source: &str) -> ArchiveMember {
+        ArchiveMember {
+            name: name.to_string(),
+            object: Assembler::new().assemble_object(source, name).unwrap(),
+        }
+    }
+
+    fn library() -> Archive {
+        Archive::new(vec![
+            member(
+                "print.o",
+                ".data\n.code\n.global puts\n.extern strlen\nputs: load $0 @strlen\nhlt",
+            ),
+            member("strlen.o", ".data\n.code\n.global strlen\nstrlen: hlt"),
+            member("unused.o", ".data\n.code\n.global memset\nmemset: hlt"),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_archive_index() {
+        let archive = library();
+        assert_eq!(archive.member_defining("strlen"), Some(1));
+        assert_eq!(archive.member_defining("main"), None);
+        assert_eq!(Archive::from_bytes(&archive.to_bytes()), Ok(archive));
+
+        let duplicate = Archive::new(vec![
+            member("a.o", ".data\n.code\n.global f\nf: hlt"),
+            member("b.o", ".data\n.code\n.global f\nf: hlt"),
+        ]);
+        assert_eq!(
+            duplicate.unwrap_err().to_string(),
+            "Symbol f is defined in both a.o and b.o"
+        );
+    }
+
+    #[test]
+    fn test_pull_only_needed_members() {
+        let main = Assembler::new()
+            .assemble_object(".data\n.code\n.extern puts\nload $0 @puts\nhlt", "main.sy")
+            .unwrap();
+        let objects = pull_members(
+            vec![("main.o".to_string(), main)],
+            &[("lib.sya".to_string(), library())],
+        );
+        let names: Vec<&str> = objects.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["main.o", "lib.sya(print.o)", "lib.sya(strlen.o)"]
+        );
+        assert!(crate::linker::link(&objects, None).is_ok());
+    }
+
+    #[test]
+    fn test_link_standard_library() {
+        let main = Assembler::new()
+            .assemble_object(
+                ".data\n.code\n.extern strlen\nload $0 #0\nload $8 @strlen\ncall $8\nhlt",
+                "main.sy",
+            )
+            .unwrap();
+        let objects = pull_members(
+            vec![("main.o".to_string(), main)],
+            &[("std".to_string(), standard_library())],
+        );
+        assert_eq!(objects[1].0, "std(std/string.sy)");
+        assert_eq!(objects.len(), 2);
+        let container = crate::linker::link(&objects, None).unwrap();
+        let info = container.debug_info.unwrap();
+        assert_eq!(info.file_name(1), "<std/string.sy>");
+    }
+}
@@ -0,0 +1,74 @@
+use std::{error::Error, fmt};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum LinkError {
+    /// Two objects define the same global symbol.
+    DuplicateSymbol {
+        name: String,
+        first: String,
+        second: String,
+    },
+    /// An object uses an extern symbol no object defines.
+    UndefinedSymbol {
+        name: String,
+        file: String,
+    },
+    /// A relocated value does not fit its field.
+    RelocationOverflow {
+        file: String,
+        line: u32,
+    },
+    NoObjects,
+    /// The entry symbol given is not a global code label of any object.
+    UndefinedEntry {
+        name: String,
+    },
+    /// The entry symbol is too far into the code to jump to.
+    EntryOutOfRange {
+        name: String,
+    },
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LinkError::DuplicateSymbol {
+                ref name,
+                ref first,
+                ref second,
+            } => f.write_str(&format!(
+                "Symbol {} is defined in both {} and {}",
+                name, first, second
+            )),
+            LinkError::UndefinedSymbol { ref name, ref file } => {
+                f.write_str(&format!("Undefined symbol {} used in {}", name, file))
+            }
+            LinkError::RelocationOverflow { ref file, line } => f.write_str(&format!(
+                "{}: The linked value on line {} does not fit its field",
+                file, line
+            )),
+            LinkError::NoObjects => f.write_str("No object files to link"),
+            LinkError::UndefinedEntry { ref name } => f.write_str(&format!(
+                "Entry symbol {} is not a global code label of any object",
+                name
+            )),
+            LinkError::EntryOutOfRange { ref name } => f.write_str(&format!(
+                "Entry symbol {} is too far into the code to jump to",
+                name
+            )),
+        }
+    }
+}
+
+impl Error for LinkError {
+    fn description(&self) -> &str {
+        match self {
+            LinkError::DuplicateSymbol { .. } => "A global symbol is defined more than once",
+            LinkError::UndefinedSymbol { .. } => "An extern symbol is not defined",
+            LinkError::RelocationOverflow { .. } => "A linked value does not fit its field",
+            LinkError::NoObjects => "No object files to link",
+            LinkError::UndefinedEntry { .. } => "The entry symbol is not defined",
+            LinkError::EntryOutOfRange { .. } => "The entry symbol cannot be jumped to",
+        }
+    }
+}
@@ -0,0 +1,386 @@
+use std::collections::HashMap;
+
+use crate::{
+    assembler::{
+        debug_info::{DebugInfo, DebugSection, DebugSymbol, LabelRef},
+        object::{ObjectFile, ObjectSection, Relocation, RelocationTarget},
+        pseudo::SCRATCH_REGISTER,
+        source_map::SourceMap,
+        PIE_CODE_START, PIE_HEADER_LENGTH, PIE_HEADER_PREFIX,
+    },
+    instruction::Opcode,
+    vm::container::Container,
+};
+
+pub mod archive;
+pub mod linker_errors;
+
+use linker_errors::LinkError;
+
+/// Where an object's sections start in the linked program.
+#[derive(Debug, Clone, Copy)]
+struct Placement {
+    /// Added to the object's code addresses, which already count the header.
+    code: u32,
+    /// Added to the object's read-only data offsets.
+    data: u32,
+}
+
+impl Placement {
+    fn shift(&self, section: ObjectSection) -> u32 {
+        match section {
+            ObjectSection::Code => self.code,
+            ObjectSection::Data => self.data,
+        }
+    }
+}
+
+/// Links object files, named for errors, into a runnable program. Their
+/// code and read-only data are placed in the order given, except that the
+/// object defining the global `entry`, or else `main` or `_start`, comes
+/// first. Execution starts there, after a jump to it if it is not the
+/// first instruction of its object, and otherwise at the code of the first
+/// object.
+///
+/// The container always carries the merged debug info; drop it for a
+/// program without one.
+pub fn link(
+    objects: &[(String, ObjectFile)],
+    entry: Option<&str>,
+) -> Result<Container, Vec<LinkError>> {
+    if objects.is_empty() {
+        return Err(vec![LinkError::NoObjects]);
+    }
+    let (objects, entry_jump) = entry_first(objects, entry).map_err(|e| vec![e])?;
+    let start = match entry_jump {
+        Some(_) => ENTRY_JUMP_LENGTH,
+        None => 0,
+    };
+    let mut placements = vec![];
+    let mut placement = Placement {
+        code: start,
+        data: 0,
+    };
+    for (_, object) in &objects {
+        placements.push(placement);
+        placement.code += object.code.len() as u32;
+        placement.data += object.ro_data.len() as u32;
+    }
+
+    let mut errors = vec![];
+    let mut globals: HashMap<&str, (u32, &str)> = HashMap::new();
+    for ((file, object), placement) in objects.iter().zip(&placements) {
+        for symbol in object.globals() {
+            let section = match symbol.section {
+                Some(section) => section,
+                None => continue,
+            };
+            let address = symbol.offset + placement.shift(section);
+            if let Some((_, first)) = globals.insert(&symbol.name, (address, file)) {
+                errors.push(LinkError::DuplicateSymbol {
+                    name: symbol.name.clone(),
+                    first: first.to_string(),
+                    second: file.clone(),
+                });
+            }
+        }
+    }
+
+    let mut code = vec![];
+    if let Some((name, offset)) = entry_jump {
+        match u16::try_from(offset + start) {
+            Ok(address) => {
+                let [high, low] = address.to_be_bytes();
+                code.extend([Opcode::LOAD as u8, SCRATCH_REGISTER, high, low]);
+                code.extend([Opcode::JMP as u8, SCRATCH_REGISTER, 0, 0]);
+            }
+            Err(_) => errors.push(LinkError::EntryOutOfRange { name }),
+        }
+    }
+    let mut ro_data = vec![];
+    for ((file, object), placement) in objects.iter().zip(&placements) {
+        let mut object_code = object.code.clone();
+        let mut object_data = object.ro_data.clone();
+        for relocation in &object.relocations {
+            let delta = match &relocation.target {
+                RelocationTarget::Section(section) => placement.shift(*section),
+                RelocationTarget::Symbol(name) => match globals.get(name.as_str()) {
+                    Some((address, _)) => *address,
+                    None => {
+                        errors.push(LinkError::UndefinedSymbol {
+                            name: name.clone(),
+                            file: file.clone(),
+                        });
+                        continue;
+                    }
+                },
+            };
+            let bytes = match relocation.section {
+                ObjectSection::Code => &mut object_code[..],
+                ObjectSection::Data => &mut object_data[..],
+            };
+            if !relocate(&mut bytes[relocation.offset as usize..], relocation, delta) {
+                errors.push(LinkError::RelocationOverflow {
+                    file: file.clone(),
+                    line: relocation.line,
+                });
+            }
+        }
+        code.extend(object_code);
+        ro_data.extend(object_data);
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let mut program = PIE_HEADER_PREFIX.to_vec();
+    program.resize(PIE_HEADER_LENGTH + 1, 0);
+    program.extend(code);
+    let debug_info = merge_debug_info(&objects, &placements);
+    Ok(Container::new(program, ro_data, Some(debug_info)))
+}
+
+/// Bytes of the `load` and `jmp` to an entry symbol that does not start
+/// its object's code.
+const ENTRY_JUMP_LENGTH: u32 = 8;
+
+/// `objects` with the one defining the entry symbol first, and the symbol
+/// with its code address if a jump to it is needed. An `entry` that no
+/// object defines is an error, while without one `main` or `_start` is
+/// used if defined.
+#[allow(clippy::type_complexity)]
+fn entry_first<'a>(
+    objects: &'a [(String, ObjectFile)],
+    entry: Option<&str>,
+) -> Result<(Vec<&'a (String, ObjectFile)>, Option<(String, u32)>), LinkError> {
+    let names = match entry {
+        Some(entry) => vec![entry],
+        None => vec!["main", "_start"],
+    };
+    let found = names.iter().find_map(|name| {
+        objects.iter().enumerate().find_map(|(index, (_, object))| {
+            object
+                .globals()
+                .find(|symbol| symbol.name == *name && symbol.section == Some(ObjectSection::Code))
+                .map(|symbol| (index, symbol))
+        })
+    });
+    let (index, symbol) = match (found, entry) {
+        (Some(found), _) => found,
+        (None, Some(name)) => {
+            return Err(LinkError::UndefinedEntry {
+                name: name.to_string(),
+            })
+        }
+        (None, None) => return Ok((objects.iter().collect(), None)),
+    };
+    let mut ordered = vec![&objects[index]];
+    ordered.extend(
+        objects
+            .iter()
+            .enumerate()
+            .filter(|(other, _)| *other != index)
+            .map(|(_, object)| object),
+    );
+    let jump =
+        (symbol.offset != PIE_CODE_START as u32).then(|| (symbol.name.clone(), symbol.offset));
+    Ok((ordered, jump))
+}
+
+/// Adds `delta` to the big-endian field of `relocation` at the start of
+/// `bytes`. Values with the top bit set are negative addends, so only
+/// wrapping past zero from a positive value overflows.
+fn relocate(bytes: &mut [u8], relocation: &Relocation, delta: u32) -> bool {
+    let width = relocation.width as usize;
+    let bits = 8 * width as u32;
+    let field = &mut bytes[..width];
+    let value = field
+        .iter()
+        .fold(0u64, |value, byte| value << 8 | *byte as u64);
+    let sum = value + delta as u64;
+    if value < 1 << (bits - 1) && sum >= 1 << bits {
+        return false;
+    }
+    field.copy_from_slice(&(sum as u32).to_be_bytes()[4 - width..]);
+    true
+}
+
+/// One debug info for the linked program, naming the objects' files in
+/// turn and moving their addresses to where the sections were placed.
+fn merge_debug_info(objects: &[&(String, ObjectFile)], placements: &[Placement]) -> DebugInfo {
+    let first = &objects[0].1.debug_info;
+    let mut merged = DebugInfo {
+        file: first.file.clone(),
+        includes: vec![],
+        source_map: SourceMap::new(),
+        symbols: vec![],
+        label_refs: vec![],
+    };
+    for (index, ((_, object), placement)) in objects.iter().zip(placements).enumerate() {
+        let info = &object.debug_info;
+        // Each object's own file is file 0 of its source map, and its
+        // includes follow it.
+        let base = match index {
+            0 => 0,
+            _ => {
+                merged.includes.push(info.file.clone());
+                merged.includes.len() as u32
+            }
+        };
+        merged.includes.extend(info.includes.iter().cloned());
+        for entry in info.source_map.entries() {
+            merged.source_map.add_entry_in(
+                entry.pc + placement.code,
+                base + entry.file,
+                entry.line,
+            );
+        }
+        for symbol in &info.symbols {
+            let shift = match symbol.section {
+                DebugSection::Code => placement.code,
+                DebugSection::Data => placement.data,
+            };
+            merged.symbols.push(DebugSymbol {
+                start: symbol.start + shift,
+                end: symbol.end + shift,
+                ..symbol.clone()
+            });
+        }
+        merged
+            .label_refs
+            .extend(info.label_refs.iter().map(|label_ref| LabelRef {
+                pc: label_ref.pc + placement.code,
+                name: label_ref.name.clone(),
+            }));
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assembler::Assembler, vm::VM};
+
+    fn object(file: &str, source: &str) -> (String, ObjectFile) {
+        let object = Assembler::new().assemble_object(source, file).unwrap();
+        (file.to_string(), object)
+    }
+
+    fn errors(objects: &[(String, ObjectFile)]) -> Vec<String> {
+        link(objects, None)
+            .unwrap_err()
+            .iter()
+            .map(|e| e.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_link_and_run() {
+        let main = object(
+            "main.sy",
+            ".data\n.extern total\nfirst: .word @total\n.code\n.extern addten\nload $0 #5\nload $2 @addten\njmp $2\nback: hlt\n.global back",
+        );
+        let lib = object(
+            "lib.sy",
+            ".data\npad: .byte 9\n.global total\ntotal: .word 3\n.code\n.global addten\n.extern back\naddten: load $1 #10\nadd $0 $1 $0\nload $2 @back\njmp $2",
+        );
+        let container = link(&[main, lib], None).unwrap();
+        // `total` is the second byte of the lib's data, after the main's word.
+        assert_eq!(container.ro_data, [0, 0, 0, 5, 9, 0, 0, 0, 3]);
+
+        let mut vm = VM::new();
+        vm.add_bytes(container.program);
+        vm.ro_data = container.ro_data;
+        vm.run().unwrap();
+        assert_eq!(vm.registers[0], 15);
+
+        let info = container.debug_info.unwrap();
+        assert_eq!(info.includes, vec!["lib.sy".to_string()]);
+        assert_eq!(info.location(65), Some("main.sy:6".to_string()));
+        assert_eq!(info.location(81), Some("lib.sy:8".to_string()));
+        assert_eq!(info.label_ref(89), Some("back"));
+        assert_eq!(info.code_labels()[1], ("addten".to_string(), 81));
+    }
+
+    #[test]
+    fn test_entry_symbol_runs_first() {
+        let run = |container: Container| {
+            let mut vm = VM::new();
+            vm.add_bytes(container.program);
+            vm.run().unwrap();
+            vm.registers[0]
+        };
+        let lib = || object("lib.sy", ".data\n.code\n.global f\nf: load $0 #7\nret");
+        let main = || {
+            object(
+                "main.sy",
+                ".data\n.code\n.global main\n.extern f\nmain: call @f\nhlt",
+            )
+        };
+        let container = link(&[lib(), main()], None).unwrap();
+        assert_eq!(container.program[PIE_CODE_START], Opcode::LOAD as u8);
+        assert_eq!(container.debug_info.as_ref().unwrap().file, "main.sy");
+        assert_eq!(run(container), 7);
+
+        // A jump is needed when the entry is not the object's first
+        // instruction.
+        let start = object(
+            "start.sy",
+            ".data\n.code\n.extern f\nhelper: hlt\n.global begin\nbegin: call @f\nhlt",
+        );
+        let container = link(&[lib(), start], Some("begin")).unwrap();
+        let info = container.debug_info.clone().unwrap();
+        assert_eq!(info.code_labels()[0], ("helper".to_string(), 73));
+        assert_eq!(run(container), 7);
+
+        assert_eq!(
+            link(&[lib(), main()], Some("start"))
+                .unwrap_err()
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<String>>(),
+            vec!["Entry symbol start is not a global code label of any object"]
+        );
+    }
+
+    #[test]
+    fn test_link_errors() {
+        let a = object(
+            "a.sy",
+            ".data\n.code\n.global main\n.extern missing\nmain: load $0 @missing",
+        );
+        let b = object("b.sy", ".data\n.code\n.global main\nmain: hlt");
+        assert_eq!(
+            errors(&[a, b]),
+            vec![
+                "Symbol main is defined in both a.sy and b.sy",
+                "Undefined symbol missing used in a.sy",
+            ]
+        );
+        assert_eq!(errors(&[]), vec!["No object files to link"]);
+    }
+
+    #[test]
+    fn test_relocation_overflow() {
+        let big = object("big.sy", ".data\nbuf: .space 65530\n.code\nhlt");
+        let user = object("user.sy", ".data\nmsg: .byte 1\n.code\nload $0 @msg+6\nhlt");
+        assert_eq!(
+            errors(&[big, user]),
+            vec!["user.sy: The linked value on line 4 does not fit its field"]
+        );
+    }
+
+    #[test]
+    fn test_negative_addends() {
+        let relocation = Relocation {
+            section: ObjectSection::Code,
+            offset: 0,
+            width: 2,
+            target: RelocationTarget::Symbol("x".to_string()),
+            line: 1,
+        };
+        let mut bytes = (-2i16).to_be_bytes();
+        assert!(relocate(&mut bytes, &relocation, 100));
+        assert_eq!(bytes, 98u16.to_be_bytes());
+    }
+}
@@ -0,0 +1,1008 @@
+use serde::Serialize;
+
+use crate::{
+    assembler::{
+        assembler_errors::AssemblerError,
+        expressions::Expr,
+        instruction_parsers::AssemblerInstruction,
+        label_parsers::{label_declaration, label_usage},
+        local_labels::{is_anonymous, is_local},
+        operand_parsers::string_error,
+        program_parsers::spanned_program,
+        pseudo::{is_pseudo, PSEUDO_INSTRUCTIONS},
+        Assembler, Token,
+    },
+    instruction::Opcode,
+};
+
+/// Directives the assembler understands, with a short description.
+pub const DIRECTIVES: &[(&str, &str)] = &[
+    ("data", "Start the read-only data section"),
+    ("code", "Start the code section"),
+    ("asciiz", "Store a zero-terminated string in read-only data"),
+    (
+        "ascii",
+        "Store a string in read-only data without a terminator",
+    ),
+    ("byte", "Store 8-bit values, e.g. .byte 1, 2, 3"),
+    ("half", "Store big-endian 16-bit values"),
+    ("word", "Store big-endian 32-bit values or label offsets"),
+    ("space", "Reserve n zero bytes"),
+    ("zero", "Reserve n zero bytes, like .space"),
+    ("align", "Pad with zeros to a multiple of n bytes"),
+    ("equ", "Define a constant, e.g. .equ SIZE 16"),
+    ("set", "Define a constant that may be redefined later"),
+    ("macro", "Start a macro definition, e.g. .macro push2 a, b"),
+    ("endm", "End a macro definition"),
+    (
+        "if",
+        "Assemble the following lines only if the value is not zero",
+    ),
+    ("elif", "Continue an .if with another condition"),
+    ("else", "Assemble the following lines if no condition held"),
+    ("endif", "End an .if block"),
+    (
+        "ifdef",
+        "Assemble the following lines only if the name is defined",
+    ),
+    (
+        "ifndef",
+        "Assemble the following lines only if the name is not defined",
+    ),
+    (
+        "error",
+        "Stop assembling with a message, e.g. .error \"unsupported\"",
+    ),
+    ("global", "Let other object files link to these labels"),
+    ("extern", "Use labels defined in another object file"),
+];
+
+/// Zero-based line and character, as LSP counts them.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize)]
+pub struct Position {
+    pub line: u32,
+    pub character: u32,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+pub struct Range {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Range {
+    /// The range between the byte offsets `start` and `end` of `text`, the
+    /// text of line `line`.
+    fn on_line(text: &str, line: u32, start: usize, end: usize) -> Range {
+        Range {
+            start: Position {
+                line,
+                character: utf16_column(text, start),
+            },
+            end: Position {
+                line,
+                character: utf16_column(text, end),
+            },
+        }
+    }
+
+    pub fn contains(&self, position: Position) -> bool {
+        self.start <= position && position <= self.end
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+#[serde(into = "u8")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl From<Severity> for u8 {
+    fn from(severity: Severity) -> u8 {
+        match severity {
+            Severity::Error => 1,
+            Severity::Warning => 2,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Diagnostic {
+    pub range: Range,
+    pub severity: Severity,
+    pub source: &'static str,
+    pub message: String,
+}
+
+/// LSP `CompletionItemKind` values used here.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+#[serde(into = "u8")]
+pub enum CompletionKind {
+    Function,
+    Keyword,
+    Constant,
+}
+
+impl From<CompletionKind> for u8 {
+    fn from(kind: CompletionKind) -> u8 {
+        match kind {
+            CompletionKind::Function => 3,
+            CompletionKind::Keyword => 14,
+            CompletionKind::Constant => 21,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Completion {
+    pub label: String,
+    pub kind: CompletionKind,
+    pub detail: String,
+}
+
+/// LSP `SymbolKind` values used here.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+#[serde(into = "u8")]
+pub enum SymbolKind {
+    Namespace,
+    Function,
+    Constant,
+}
+
+impl From<SymbolKind> for u8 {
+    fn from(kind: SymbolKind) -> u8 {
+        match kind {
+            SymbolKind::Namespace => 3,
+            SymbolKind::Function => 12,
+            SymbolKind::Constant => 14,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentSymbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub range: Range,
+    pub selection_range: Range,
+    pub children: Vec<DocumentSymbol>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct Label {
+    name: String,
+    range: Range,
+    is_code: bool,
+    /// The conditional branches the label is declared in.
+    branches: Vec<Branch>,
+}
+
+/// Branch `index` of the `.if` block numbered `block` in the document.
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct Branch {
+    block: usize,
+    index: usize,
+}
+
+impl Label {
+    /// Whether the two labels are never both assembled, being in different
+    /// branches of one block.
+    fn excludes(&self, other: &Label) -> bool {
+        self.branches
+            .iter()
+            .zip(&other.branches)
+            .find(|(a, b)| a != b)
+            .is_some_and(|(a, b)| a.block == b.block)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct Section {
+    name: String,
+    range: Range,
+}
+
+/// Everything the language server knows about one `.sy` document.
+#[derive(Debug, Default)]
+pub struct Analysis {
+    lines: Vec<String>,
+    pub diagnostics: Vec<Diagnostic>,
+    declarations: Vec<Label>,
+    usages: Vec<(String, Range)>,
+    mnemonics: Vec<(Opcode, Range)>,
+    directives: Vec<(String, Range)>,
+    sections: Vec<Section>,
+    macros: Vec<String>,
+    /// The paths of `.include` directives as written, and their ranges.
+    includes: Vec<(String, Range)>,
+    has_includes: bool,
+    /// Labels declared with `.extern`, defined by another object file.
+    externs: Vec<String>,
+    /// The last label that was not local, which local labels belong to.
+    scope: Option<String>,
+    branches: Vec<Branch>,
+    blocks: usize,
+}
+
+impl Analysis {
+    pub fn new(text: &str) -> Analysis {
+        Analysis::for_file(text, None)
+    }
+
+    /// Analyses the document saved at `path`, which its `.include` paths
+    /// are relative to.
+    pub fn for_file(text: &str, path: Option<&str>) -> Analysis {
+        let mut analysis = Analysis {
+            lines: text
+                .split('\n')
+                .map(|line| line.trim_end_matches('\r').to_string())
+                .collect(),
+            ..Default::default()
+        };
+
+        let spanned = match spanned_program(text) {
+            Ok((rest, parsed)) => {
+                if !rest.trim().is_empty() {
+                    let line = analysis.line_of_offset(text.len() - rest.trim_start().len());
+                    analysis.error(analysis.line_range(line), "Unable to parse this line");
+                }
+                parsed
+            }
+            Err(nom::Err::Failure(failure)) if string_error(&failure).is_some() => {
+                let offset = text.len() - failure.input.len();
+                let line = analysis.line_of_offset(offset);
+                let column = offset - text[..offset].rfind('\n').map_or(0, |i| i + 1);
+                let quote = failure.input.chars().next().map_or(1, char::len_utf8);
+                let range =
+                    Range::on_line(&analysis.lines[line as usize], line, column, column + quote);
+                analysis.error(range, &string_error(&failure).unwrap_or_default());
+                return analysis;
+            }
+            Err(e) => {
+                let offset = match &e {
+                    nom::Err::Error(e) | nom::Err::Failure(e) => text.len() - e.input.len(),
+                    nom::Err::Incomplete(_) => 0,
+                };
+                let line = analysis.line_of_offset(offset);
+                analysis.error(analysis.line_range(line), "Unable to parse this line");
+                return analysis;
+            }
+        };
+
+        let (first_lines, instructions): (Vec<u32>, Vec<AssemblerInstruction>) =
+            spanned.into_iter().unzip();
+        analysis.macros = instructions
+            .iter()
+            .filter(|i| i.get_directive_name().as_deref() == Some("macro"))
+            .filter_map(|i| match i.directive_operands().first() {
+                Some(Token::Expression {
+                    expr: Expr::Constant(name),
+                }) => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+        analysis.has_includes = instructions
+            .iter()
+            .any(|i| i.get_directive_name().as_deref() == Some("include"));
+        for (first_line, instruction) in first_lines.into_iter().zip(&instructions) {
+            analysis.index_instruction(first_line, instruction);
+        }
+        analysis.check_labels();
+        analysis.check_operands(&instructions);
+        let mut asm = Assembler::new();
+        if let Some(path) = path {
+            asm.set_source_path(path);
+        }
+        // A file with `.extern` labels is assembled into an object to link.
+        let result = match analysis.externs.is_empty() {
+            true => asm.assemble(text).map(|_| ()),
+            false => asm.assemble_object(text, path.unwrap_or("")).map(|_| ()),
+        };
+        if let Err(errors) = result {
+            for error in errors {
+                analysis.report_assembler_error(&error, &instructions);
+            }
+        }
+        analysis
+    }
+
+    /// Records where the labels, mnemonics and directives of `instruction`
+    /// are, from its label on `first_line` to its own line.
+    fn index_instruction(&mut self, first_line: u32, instruction: &AssemblerInstruction) {
+        let mut line = first_line.saturating_sub(1);
+        let mut text = self.lines.get(line as usize).cloned().unwrap_or_default();
+        let indent = text.len() - text.trim_start().len();
+        let mut column = indent;
+
+        if let Ok((rest, Token::LabelDeclaration { name })) = label_declaration(&text[indent..]) {
+            let range = Range::on_line(&text, line, indent, indent + name.len());
+            // Anonymous labels may be declared any number of times.
+            if !is_anonymous(&name) {
+                if !is_local(&name) {
+                    self.scope = Some(name.clone());
+                }
+                self.declarations.push(Label {
+                    range,
+                    name: self.scoped(name),
+                    is_code: instruction.is_opcode(),
+                    branches: self.branches.clone(),
+                });
+            }
+            column = text.len() - rest.len();
+        }
+        if instruction.line.saturating_sub(1) != line {
+            line = instruction.line.saturating_sub(1);
+            text = self.lines.get(line as usize).cloned().unwrap_or_default();
+            column = text.len() - text.trim_start().len();
+        }
+
+        let word_end = text[column..]
+            .find(|c: char| c.is_whitespace())
+            .map(|end| column + end)
+            .unwrap_or(text.len());
+        let range = Range::on_line(&text, line, column, word_end);
+        match (&instruction.opcode, &instruction.directive) {
+            (Some(Token::Op { code }), _) => {
+                if *code == Opcode::IGL {
+                    self.error(
+                        range,
+                        &format!("Unknown instruction {}", &text[column..word_end]),
+                    );
+                }
+                self.mnemonics.push((*code, range));
+            }
+            // Macros may be defined in included files.
+            (Some(Token::MacroCall { name }), _)
+                if !self.macros.contains(name) && !is_pseudo(name) && !self.has_includes =>
+            {
+                self.error(range, &format!("Unknown instruction {}", name));
+            }
+            (_, Some(Token::Directive { name })) => {
+                if name == "data" || name == "code" {
+                    self.sections.push(Section {
+                        name: format!(".{}", name),
+                        range,
+                    });
+                }
+                match name.as_str() {
+                    "if" | "ifdef" | "ifndef" => {
+                        self.branches.push(Branch {
+                            block: self.blocks,
+                            index: 0,
+                        });
+                        self.blocks += 1;
+                    }
+                    "elif" | "else" => {
+                        if let Some(branch) = self.branches.last_mut() {
+                            branch.index += 1;
+                        }
+                    }
+                    "endif" => {
+                        self.branches.pop();
+                    }
+                    "include" => {
+                        if let [Token::SyString { name } | Token::LibraryPath { name }] =
+                            instruction.directive_operands()[..]
+                        {
+                            self.includes.push((name.clone(), range));
+                        }
+                    }
+                    "extern" => {
+                        for operand in instruction.directive_operands() {
+                            if let Token::Expression {
+                                expr: Expr::Constant(name),
+                            }
+                            | Token::LabelUsage { name } = operand
+                            {
+                                self.externs.push(name.clone());
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+                self.directives.push((name.clone(), range));
+            }
+            _ => {}
+        }
+
+        // Labels mentioned in comments are not usages.
+        let code_end = [text.find(';'), text.find("/*")]
+            .into_iter()
+            .flatten()
+            .min()
+            .unwrap_or(text.len());
+        for (index, _) in text[..code_end].match_indices('@') {
+            if let Ok((_, Token::LabelUsage { name })) = label_usage(&text[index..]) {
+                // The assembler reports `1f` and `1b` with no label to refer to.
+                let (number, direction) = name.split_at(name.len() - 1);
+                if is_anonymous(number) && matches!(direction, "f" | "b") {
+                    continue;
+                }
+                let range = Range::on_line(&text, line, index, index + 1 + name.len());
+                self.usages.push((self.scoped(name), range));
+            }
+        }
+    }
+
+    /// The full name of the label `name` where it is written: a local
+    /// label is named after the label it belongs to.
+    fn scoped(&self, name: String) -> String {
+        match &self.scope {
+            Some(scope) if is_local(&name) => format!("{}{}", scope, name),
+            _ => name,
+        }
+    }
+
+    fn check_labels(&mut self) {
+        for (index, label) in self.declarations.iter().enumerate() {
+            if self.declarations[..index]
+                .iter()
+                .any(|l| l.name == label.name && !l.excludes(label))
+            {
+                let message = format!("Label {} is already declared", label.name);
+                self.diagnostics
+                    .push(diagnostic(label.range, Severity::Error, &message));
+            }
+        }
+        for (name, range) in &self.usages {
+            if !self.declarations.iter().any(|l| &l.name == name) && !self.externs.contains(name) {
+                let message = format!("Undefined label @{}", name);
+                self.diagnostics
+                    .push(diagnostic(*range, Severity::Error, &message));
+            }
+        }
+    }
+
+    fn check_operands(&mut self, instructions: &[AssemblerInstruction]) {
+        let opcodes = instructions.iter().filter(|i| i.is_opcode());
+        for (instruction, (opcode, range)) in opcodes.zip(self.mnemonics.clone()) {
+            let given = [
+                &instruction.operand1,
+                &instruction.operand2,
+                &instruction.operand3,
+            ]
+            .iter()
+            .filter(|operand| operand.is_some())
+            .count();
+            let expected = opcode.operands().len();
+            if opcode != Opcode::IGL && given != expected {
+                let message = format!(
+                    "{} expects {} operand(s), found {}: {}",
+                    opcode.mnemonic(),
+                    expected,
+                    given,
+                    opcode.signature()
+                );
+                self.diagnostics
+                    .push(diagnostic(range, Severity::Warning, &message));
+            }
+        }
+    }
+
+    fn report_assembler_error(
+        &mut self,
+        error: &AssemblerError,
+        instructions: &[AssemblerInstruction],
+    ) {
+        let line_of = |index: u32| {
+            instructions
+                .get(index as usize)
+                .map(|i| i.line.saturating_sub(1))
+                .unwrap_or(0)
+        };
+        let range = match error {
+            // Reported with a location by `check_labels` and `new`.
+            AssemblerError::SymbolAlreadyDeclared | AssemblerError::ParseError { .. } => return,
+            AssemblerError::NoSegmentDeclarationFound { instruction }
+            | AssemblerError::StringConstantDeclaredWithoutLabel { instruction } => {
+                self.line_range(line_of(*instruction))
+            }
+            AssemblerError::UnknownDirectiveFound { directive } => self
+                .directives
+                .iter()
+                .find(|(name, _)| name == directive)
+                .map(|(_, range)| *range)
+                .unwrap_or_else(|| self.line_range(0)),
+            // Reported at the `.include` of the file.
+            AssemblerError::InFile { file, .. } => self
+                .includes
+                .iter()
+                .find(|(written, _)| file.trim_end_matches('>').ends_with(written.as_str()))
+                .or(self.includes.first())
+                .map(|(_, range)| *range)
+                .unwrap_or_else(|| self.line_range(0)),
+            // Already reported as an undefined label at its usage.
+            AssemblerError::UndefinedSymbol { name, .. }
+                if self.usages.iter().any(|(usage, _)| usage == name) =>
+            {
+                return
+            }
+            _ => self.line_range(error.line().unwrap_or(1).saturating_sub(1)),
+        };
+        self.error(range, &error.to_string());
+    }
+
+    fn error(&mut self, range: Range, message: &str) {
+        self.diagnostics
+            .push(diagnostic(range, Severity::Error, message));
+    }
+
+    fn line_of_offset(&self, offset: usize) -> u32 {
+        let mut remaining = offset;
+        for (index, line) in self.lines.iter().enumerate() {
+            if remaining <= line.len() {
+                return index as u32;
+            }
+            remaining -= line.len() + 1;
+        }
+        self.lines.len().saturating_sub(1) as u32
+    }
+
+    fn line_range(&self, line: u32) -> Range {
+        let text = self
+            .lines
+            .get(line as usize)
+            .map(|l| l.as_str())
+            .unwrap_or("");
+        let indent = text.len() - text.trim_start().len();
+        Range::on_line(text, line, indent, text.trim_end().len().max(indent))
+    }
+
+    /// Name of the label declared or used at `position`.
+    fn label_at(&self, position: Position) -> Option<&str> {
+        self.declarations
+            .iter()
+            .find(|l| l.range.contains(position))
+            .map(|l| l.name.as_str())
+            .or_else(|| {
+                self.usages
+                    .iter()
+                    .find(|(_, range)| range.contains(position))
+                    .map(|(name, _)| name.as_str())
+            })
+    }
+
+    pub fn definition(&self, position: Position) -> Option<Range> {
+        let name = self.label_at(position)?;
+        self.declarations
+            .iter()
+            .find(|l| l.name == name)
+            .map(|l| l.range)
+    }
+
+    pub fn references(&self, position: Position, include_declaration: bool) -> Vec<Range> {
+        let name = match self.label_at(position) {
+            Some(name) => name,
+            None => return vec![],
+        };
+        let mut references: Vec<Range> = vec![];
+        if include_declaration {
+            references.extend(
+                self.declarations
+                    .iter()
+                    .filter(|l| l.name == name)
+                    .map(|l| l.range),
+            );
+        }
+        references.extend(
+            self.usages
+                .iter()
+                .filter(|(usage, _)| usage == name)
+                .map(|(_, range)| *range),
+        );
+        references
+    }
+
+    /// Markdown describing the mnemonic, directive or label at `position`.
+    pub fn hover(&self, position: Position) -> Option<String> {
+        if let Some((opcode, _)) = self
+            .mnemonics
+            .iter()
+            .find(|(opcode, range)| *opcode != Opcode::IGL && range.contains(position))
+        {
+            return Some(format!(
+                "`{}`\n\n{}",
+                opcode.signature(),
+                opcode.description()
+            ));
+        }
+        if let Some((name, _)) = self
+            .directives
+            .iter()
+            .find(|(_, range)| range.contains(position))
+        {
+            let description = DIRECTIVES.iter().find(|(d, _)| d == name)?.1;
+            return Some(format!("`.{}`\n\n{}", name, description));
+        }
+        let name = self.label_at(position)?;
+        let label = self.declarations.iter().find(|l| l.name == name)?;
+        let kind = if label.is_code { "Code" } else { "Data" };
+        Some(format!(
+            "{} label `{}`, declared on line {}",
+            kind,
+            label.name,
+            label.range.start.line + 1
+        ))
+    }
+
+    /// Completions for the word being typed at `position`: labels after
+    /// `@`, directives after `.`, mnemonics otherwise.
+    pub fn completions(&self, position: Position) -> Vec<Completion> {
+        let line = self
+            .lines
+            .get(position.line as usize)
+            .map(|l| l.as_str())
+            .unwrap_or("");
+        let before = &line[..byte_offset(line, position.character)];
+        let trigger = before
+            .trim_end_matches(|c: char| c.is_alphanumeric())
+            .chars()
+            .last();
+
+        match trigger {
+            Some('@') => self
+                .declarations
+                .iter()
+                .map(|label| Completion {
+                    label: label.name.clone(),
+                    kind: if label.is_code {
+                        CompletionKind::Function
+                    } else {
+                        CompletionKind::Constant
+                    },
+                    detail: format!("line {}", label.range.start.line + 1),
+                })
+                .collect(),
+            Some('.') => DIRECTIVES
+                .iter()
+                .map(|(name, description)| Completion {
+                    label: name.to_string(),
+                    kind: CompletionKind::Keyword,
+                    detail: description.to_string(),
+                })
+                .collect(),
+            _ => Opcode::all()
+                .map(|opcode| Completion {
+                    label: opcode.mnemonic().to_string(),
+                    kind: CompletionKind::Keyword,
+                    detail: opcode.signature(),
+                })
+                .chain(
+                    PSEUDO_INSTRUCTIONS
+                        .iter()
+                        .filter(|(name, _, _)| Opcode::from(*name) == Opcode::IGL)
+                        .map(|(name, usage, _)| Completion {
+                            label: name.to_string(),
+                            kind: CompletionKind::Keyword,
+                            detail: usage.to_string(),
+                        }),
+                )
+                .collect(),
+        }
+    }
+
+    /// Sections, each containing the labels declared in it.
+    pub fn symbols(&self) -> Vec<DocumentSymbol> {
+        let mut symbols: Vec<DocumentSymbol> = vec![];
+        let last_line = self.lines.len().saturating_sub(1) as u32;
+        for (index, section) in self.sections.iter().enumerate() {
+            let end_line = self
+                .sections
+                .get(index + 1)
+                .map(|next| next.range.start.line.saturating_sub(1))
+                .unwrap_or(last_line);
+            let range = Range {
+                start: section.range.start,
+                end: self.line_range(end_line).end.max(Position {
+                    line: end_line,
+                    character: 0,
+                }),
+            };
+            let children = self
+                .declarations
+                .iter()
+                .filter(|l| range.contains(l.range.start))
+                .map(|label| DocumentSymbol {
+                    name: label.name.clone(),
+                    kind: if label.is_code {
+                        SymbolKind::Function
+                    } else {
+                        SymbolKind::Constant
+                    },
+                    range: self.line_range(label.range.start.line),
+                    selection_range: label.range,
+                    children: vec![],
+                })
+                .collect();
+            symbols.push(DocumentSymbol {
+                name: section.name.clone(),
+                kind: SymbolKind::Namespace,
+                range,
+                selection_range: section.range,
+                children,
+            });
+        }
+        symbols
+    }
+}
+
+/// The LSP column, in UTF-16 code units, of byte offset `byte` of `text`.
+fn utf16_column(text: &str, byte: usize) -> u32 {
+    text[..byte.min(text.len())].encode_utf16().count() as u32
+}
+
+/// The byte offset of the LSP column `character` of `text`, its end if the
+/// line is shorter.
+fn byte_offset(text: &str, character: u32) -> usize {
+    let mut column = 0;
+    for (offset, c) in text.char_indices() {
+        if column >= character as usize {
+            return offset;
+        }
+        column += c.len_utf16();
+    }
+    text.len()
+}
+
+fn diagnostic(range: Range, severity: Severity, message: &str) -> Diagnostic {
+    Diagnostic {
+        range,
+        severity,
+        source: "synthia",
+        message: message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str =
+        ".data\nhello: .asciiz 'Hello'\n.code\nload $0 @start\nstart: prts @hello\njmp $0\n";
+
+    fn position(line: u32, character: u32) -> Position {
+        Position { line, character }
+    }
+
+    fn range(line: u32, start: u32, end: u32) -> Range {
+        Range {
+            start: position(line, start),
+            end: position(line, end),
+        }
+    }
+
+    fn messages(text: &str) -> Vec<String> {
+        Analysis::new(text)
+            .diagnostics
+            .into_iter()
+            .map(|d| {
+                format!(
+                    "{}:{}: {}",
+                    d.range.start.line, d.range.start.character, d.message
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_clean_document_has_no_diagnostics() {
+        assert_eq!(messages(SOURCE), Vec::<String>::new());
+        assert_eq!(
+            messages("; see @nowhere\n.data\n.code /* @gone */\nhlt ; @never\n"),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        assert_eq!(
+            messages(".data\n.code\nfoo $0\nload $0 @nowhere\nadd $0 $1\nx: hlt\nx: hlt\n"),
+            vec![
+                "2:0: Unknown instruction foo",
+                "6:0: Label x is already declared",
+                "3:8: Undefined label @nowhere",
+                "4:0: add expects 3 operand(s), found 2: add $reg1 $reg2 $reg3",
+            ]
+        );
+        assert_eq!(
+            messages(".code\nhlt\n"),
+            vec!["0:0: Less than two sections were found in the code"]
+        );
+        assert_eq!(
+            messages(".data\n.code\nhlt\n$$$\n"),
+            vec!["3:0: Unable to parse this line"]
+        );
+        assert_eq!(
+            messages(".data\nmsg: .asciiz \"Hi\\q\"\n.code\nhlt\n"),
+            vec!["1:16: Invalid escape \\q in string literal"]
+        );
+        assert_eq!(
+            messages(".data\n.equ SIZE 4\n.code\nload $0 #SIZE*SIZE*4096\nload $1 #LIMIT\nhlt\n"),
+            vec![
+                "3:0: Operand 65536 does not fit in 16 bits on line 4",
+                "4:0: Undefined symbol LIMIT on line 5",
+            ]
+        );
+        assert_eq!(
+            messages(".data\n.code\n.macro twice r\nadd \\r \\r \\r\n.endm\ntwice $1\ntwice\n"),
+            vec!["6:0: Macro twice takes 1 argument(s), 0 given on line 7"]
+        );
+        assert_eq!(
+            messages(".data\n.code\nmov $1 $2\nb @end\nneg $1\nend: hlt\n"),
+            vec!["4:0: neg on line 5 is written neg $dst $src"]
+        );
+        assert_eq!(
+            messages(".data\n.code\n.quad #1\n"),
+            vec!["2:0: Invalid or unknown directive found. Directive name was quad"]
+        );
+    }
+
+    #[test]
+    fn test_include_errors() {
+        let dir = std::env::temp_dir().join(format!("synthia-lsp-include-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("io.sy"), ".code\nputs: load $0 #MISSING\n").unwrap();
+        let main = dir.join("main.sy").to_string_lossy().to_string();
+        let text = ".data\n.code\nprint @x\n.include 'io.sy'\n.include 'nope.sy'\nhlt\n";
+        let messages: Vec<String> = Analysis::for_file(text, Some(&main))
+            .diagnostics
+            .into_iter()
+            .map(|d| format!("{}: {}", d.range.start.line, d.message))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "2: Undefined label @x".to_string(),
+                "4: Cannot include nope.sy on line 5: file not found".to_string(),
+            ]
+        );
+        let messages: Vec<String> =
+            Analysis::for_file(&text.replace(".include 'nope.sy'\n", ""), Some(&main))
+                .diagnostics
+                .into_iter()
+                .map(|d| format!("{}: {}", d.range.start.line, d.message))
+                .collect();
+        assert_eq!(messages.len(), 2);
+        assert!(messages[1].starts_with("3: "), "{:?}", messages);
+        assert!(
+            messages[1].ends_with("io.sy: Undefined symbol MISSING on line 2"),
+            "{:?}",
+            messages
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_labels_in_conditional_branches() {
+        let text = ".data\n.ifdef DEBUG\nmsg: .asciiz 'on'\n.else\nmsg: .asciiz 'off'\n.endif\n.code\nprts @msg\nhlt\n";
+        assert_eq!(messages(text), Vec::<String>::new());
+        assert_eq!(
+            messages(".data\n.if #1\nx: .byte 1\n.if #0\nx: .byte 2\n.endif\n.endif\n.code\nhlt\n"),
+            vec!["4:0: Label x is already declared"]
+        );
+    }
+
+    #[test]
+    fn test_local_and_anonymous_labels() {
+        let text = ".data\n.code\nfirst: load $0 @.loop\n.loop: inc $0\nsecond: load $1 @.loop\n.loop: load $2 @1f\n1: load $2 @1b\nload $3 @.done\nhlt\n";
+        assert_eq!(messages(text), vec!["7:8: Undefined label @second.done"]);
+    }
+
+    #[test]
+    fn test_extern_labels() {
+        assert_eq!(
+            messages(".data\n.code\n.extern print\n.global main\nmain: load $0 @print\nload $1 #@print*2\nhlt\n"),
+            vec!["5:0: The value on line 6 cannot be relocated, use a label plus or minus a constant"]
+        );
+    }
+
+    #[test]
+    fn test_definition_and_references() {
+        let analysis = Analysis::new(SOURCE);
+        let start_declaration = range(4, 0, 5);
+        assert_eq!(
+            analysis.definition(position(3, 10)),
+            Some(start_declaration)
+        );
+        assert_eq!(analysis.definition(position(4, 2)), Some(start_declaration));
+        assert_eq!(analysis.definition(position(3, 1)), None);
+        assert_eq!(
+            analysis.references(position(1, 1), true),
+            vec![range(1, 0, 5), range(4, 12, 18)]
+        );
+        assert_eq!(
+            analysis.references(position(1, 1), false),
+            vec![range(4, 12, 18)]
+        );
+    }
+
+    #[test]
+    fn test_hover() {
+        let analysis = Analysis::new(SOURCE);
+        assert_eq!(
+            analysis.hover(position(3, 2)),
+            Some("`load $reg1 #value`\n\nLoad a 16-bit value into a register".to_string())
+        );
+        assert_eq!(
+            analysis.hover(position(0, 2)),
+            Some("`.data`\n\nStart the read-only data section".to_string())
+        );
+        assert_eq!(
+            analysis.hover(position(4, 14)),
+            Some("Data label `hello`, declared on line 2".to_string())
+        );
+        assert_eq!(analysis.hover(position(3, 6)), None);
+    }
+
+    #[test]
+    fn test_label_on_its_own_line() {
+        let text = ".data\n.code\nmain:\n  load $0 @main\nloop: ; next\n  load $1 @nowhere\n";
+        let analysis = Analysis::new(text);
+        assert_eq!(messages(text), vec!["5:10: Undefined label @nowhere"]);
+        assert_eq!(analysis.definition(position(3, 12)), Some(range(2, 0, 4)));
+        assert_eq!(
+            analysis.references(position(2, 1), false),
+            vec![range(3, 10, 15)]
+        );
+        assert_eq!(
+            analysis.hover(position(3, 3)),
+            Some("`load $reg1 #value`\n\nLoad a 16-bit value into a register".to_string())
+        );
+    }
+
+    #[test]
+    fn test_completions() {
+        let analysis = Analysis::new(".data\nhello: .asciiz 'Hi'\n.code\nprts @he\n.co\nlo");
+        let labels = analysis.completions(position(3, 8));
+        assert_eq!(labels.len(), 1);
+        assert_eq!(labels[0].label, "hello");
+        assert_eq!(labels[0].kind, CompletionKind::Constant);
+
+        let directives = analysis.completions(position(4, 3));
+        assert!(directives.iter().any(|c| c.label == "code"));
+
+        let mnemonics = analysis.completions(position(5, 2));
+        assert!(mnemonics
+            .iter()
+            .any(|c| c.label == "load" && c.detail == "load $reg1 #value"));
+    }
+
+    #[test]
+    fn test_columns_after_non_ascii_text() {
+        let analysis = Analysis::new(
+            ".data\nmsg: .asciiz \"é😀\" ; @msg\nptr: .ascii \"ü\", @msg\n.code\nhlt",
+        );
+        assert_eq!(
+            analysis.completions(position(1, 15)).len(),
+            Opcode::all().count() + 7
+        );
+        assert_eq!(
+            analysis.completions(position(1, 17)).len(),
+            Opcode::all().count() + 7
+        );
+        assert_eq!(
+            analysis.references(position(2, 19), false),
+            vec![range(2, 17, 21)]
+        );
+        assert_eq!(
+            messages(".data\nmsg: .asciiz \"é\\q\"\n.code\nhlt"),
+            vec!["1:15: Invalid escape \\q in string literal"]
+        );
+        assert_eq!(byte_offset("é😀x", 3), 6);
+        assert_eq!(utf16_column("é😀x", 6), 3);
+    }
+
+    #[test]
+    fn test_document_symbols() {
+        let symbols = Analysis::new(SOURCE).symbols();
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols[0].name, ".data");
+        assert_eq!(symbols[0].range.end.line, 1);
+        assert_eq!(symbols[0].children[0].name, "hello");
+        assert_eq!(symbols[1].name, ".code");
+        assert_eq!(symbols[1].children[0].name, "start");
+        assert_eq!(symbols[1].children[0].kind, SymbolKind::Function);
+    }
+}
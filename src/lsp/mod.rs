@@ -0,0 +1,315 @@
+pub mod analysis;
+
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Write},
+};
+
+use log::warn;
+use serde_json::{json, Value};
+
+use crate::dap::protocol::{read_message, write_message};
+
+use self::analysis::{Analysis, Position};
+
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// Language Server Protocol server for `.sy` files. Documents are synced in
+/// full and re-analysed on every change.
+pub struct LspServer<W: Write> {
+    writer: W,
+    documents: HashMap<String, Analysis>,
+    shutdown: bool,
+}
+
+impl<W: Write> LspServer<W> {
+    pub fn new(writer: W) -> LspServer<W> {
+        LspServer {
+            writer,
+            documents: HashMap::new(),
+            shutdown: false,
+        }
+    }
+
+    /// Serves messages from `reader` until `exit` or end of input.
+    pub fn run<R: BufRead>(&mut self, reader: &mut R) -> io::Result<()> {
+        while let Some(message) = read_message(reader)? {
+            match serde_json::from_str::<Value>(&message) {
+                Ok(message) => {
+                    if !self.handle(&message)? {
+                        return Ok(());
+                    }
+                }
+                Err(e) => warn!("Ignoring malformed LSP message: {}", e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles one message, returning false on `exit`.
+    fn handle(&mut self, message: &Value) -> io::Result<bool> {
+        let method = message["method"].as_str().unwrap_or("");
+        let params = &message["params"];
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            None => {
+                return self.notification(method, params);
+            }
+        };
+
+        let result = match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "completionProvider": { "triggerCharacters": ["@", "."] },
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "documentSymbolProvider": true,
+                },
+                "serverInfo": { "name": "synthia", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/definition" => self.with_position(params, |uri, analysis, position| {
+                match analysis.definition(position) {
+                    Some(range) => json!({ "uri": uri, "range": range }),
+                    None => Value::Null,
+                }
+            }),
+            "textDocument/references" => {
+                let include_declaration = params["context"]["includeDeclaration"]
+                    .as_bool()
+                    .unwrap_or(false);
+                self.with_position(params, |uri, analysis, position| {
+                    let locations: Vec<Value> = analysis
+                        .references(position, include_declaration)
+                        .into_iter()
+                        .map(|range| json!({ "uri": uri, "range": range }))
+                        .collect();
+                    json!(locations)
+                })
+            }
+            "textDocument/hover" => self.with_position(params, |_, analysis, position| {
+                match analysis.hover(position) {
+                    Some(text) => json!({ "contents": { "kind": "markdown", "value": text } }),
+                    None => Value::Null,
+                }
+            }),
+            "textDocument/completion" => self.with_position(params, |_, analysis, position| {
+                json!(analysis.completions(position))
+            }),
+            "textDocument/documentSymbol" => match self.document(params) {
+                Some((_, analysis)) => Ok(json!(analysis.symbols())),
+                None => Err((INVALID_PARAMS, "Unknown document".to_string())),
+            },
+            _ => Err((METHOD_NOT_FOUND, format!("Unsupported method {}", method))),
+        };
+
+        let response = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": code, "message": message },
+            }),
+        };
+        write_message(&mut self.writer, &response)?;
+        Ok(true)
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) -> io::Result<bool> {
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .unwrap_or("")
+            .to_string();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or("");
+                self.update(uri, text)?;
+            }
+            "textDocument/didChange" => {
+                // Full sync: the last change holds the whole document.
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes
+                    .and_then(|c| c.last())
+                    .and_then(|c| c["text"].as_str())
+                {
+                    self.update(uri, text)?;
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                self.publish_diagnostics(&uri, json!([]))?;
+            }
+            "exit" => return Ok(false),
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    fn update(&mut self, uri: String, text: &str) -> io::Result<()> {
+        let analysis = Analysis::for_file(text, uri.strip_prefix("file://"));
+        let diagnostics = json!(analysis.diagnostics);
+        self.documents.insert(uri.clone(), analysis);
+        self.publish_diagnostics(&uri, diagnostics)
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str, diagnostics: Value) -> io::Result<()> {
+        write_message(
+            &mut self.writer,
+            &json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/publishDiagnostics",
+                "params": { "uri": uri, "diagnostics": diagnostics },
+            }),
+        )
+    }
+
+    fn document<'a>(&'a self, params: &'a Value) -> Option<(&'a str, &'a Analysis)> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        Some((uri, self.documents.get(uri)?))
+    }
+
+    fn with_position<F>(&self, params: &Value, f: F) -> Result<Value, (i64, String)>
+    where
+        F: FnOnce(&str, &Analysis, Position) -> Value,
+    {
+        let (uri, analysis) = self
+            .document(params)
+            .ok_or((INVALID_PARAMS, "Unknown document".to_string()))?;
+        let position = Position {
+            line: params["position"]["line"].as_u64().unwrap_or(0) as u32,
+            character: params["position"]["character"].as_u64().unwrap_or(0) as u32,
+        };
+        Ok(f(uri, analysis, position))
+    }
+}
+
+/// Serves a language server session over stdin and stdout.
+pub fn serve_stdio() -> io::Result<()> {
+    let mut reader = BufReader::new(io::stdin());
+    LspServer::new(io::stdout()).run(&mut reader)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(messages: &[Value]) -> Vec<u8> {
+        let mut input = vec![];
+        for message in messages {
+            write_message(&mut input, message).unwrap();
+        }
+        input
+    }
+
+    fn session(messages: &[Value]) -> Vec<Value> {
+        let mut output = vec![];
+        let mut reader = io::Cursor::new(frame(messages));
+        LspServer::new(&mut output).run(&mut reader).unwrap();
+
+        let mut reader = io::Cursor::new(output);
+        let mut replies = vec![];
+        while let Some(message) = read_message(&mut reader).unwrap() {
+            replies.push(serde_json::from_str(&message).unwrap());
+        }
+        replies
+    }
+
+    fn open(text: &str) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": "file:///a.sy", "languageId": "synthia", "version": 1, "text": text } },
+        })
+    }
+
+    fn request(id: i64, method: &str, line: u32, character: u32) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": {
+                "textDocument": { "uri": "file:///a.sy" },
+                "position": { "line": line, "character": character },
+                "context": { "includeDeclaration": true },
+            },
+        })
+    }
+
+    #[test]
+    fn test_initialize_and_shutdown() {
+        let replies = session(&[
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+            json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "shutdown" }),
+            json!({ "jsonrpc": "2.0", "method": "exit" }),
+            json!({ "jsonrpc": "2.0", "id": 3, "method": "shutdown" }),
+        ]);
+        assert_eq!(replies.len(), 2);
+        assert_eq!(
+            replies[0]["result"]["capabilities"]["definitionProvider"],
+            true
+        );
+        assert_eq!(
+            replies[1],
+            json!({ "jsonrpc": "2.0", "id": 2, "result": null })
+        );
+    }
+
+    #[test]
+    fn test_diagnostics_are_published() {
+        let replies = session(&[
+            open(".data\n.code\nload $0 @missing\n"),
+            json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didChange",
+                "params": {
+                    "textDocument": { "uri": "file:///a.sy", "version": 2 },
+                    "contentChanges": [{ "text": ".data\n.code\nhlt\n" }],
+                },
+            }),
+        ]);
+        assert_eq!(replies[0]["method"], "textDocument/publishDiagnostics");
+        let diagnostics = &replies[0]["params"]["diagnostics"];
+        assert_eq!(
+            diagnostics[0],
+            json!({
+                "range": { "start": { "line": 2, "character": 8 }, "end": { "line": 2, "character": 16 } },
+                "severity": 1,
+                "source": "synthia",
+                "message": "Undefined label @missing",
+            })
+        );
+        assert_eq!(replies[1]["params"]["diagnostics"], json!([]));
+    }
+
+    #[test]
+    fn test_navigation_requests() {
+        let replies = session(&[
+            open(".data\n.code\nload $0 @end\nend: hlt\n"),
+            request(1, "textDocument/definition", 2, 10),
+            request(2, "textDocument/references", 3, 1),
+            request(3, "textDocument/hover", 3, 6),
+            request(4, "textDocument/completion", 2, 0),
+            json!({ "jsonrpc": "2.0", "id": 5, "method": "textDocument/documentSymbol", "params": { "textDocument": { "uri": "file:///a.sy" } } }),
+            json!({ "jsonrpc": "2.0", "id": 6, "method": "textDocument/rename", "params": {} }),
+        ]);
+        assert_eq!(
+            replies[1]["result"],
+            json!({ "uri": "file:///a.sy", "range": { "start": { "line": 3, "character": 0 }, "end": { "line": 3, "character": 3 } } })
+        );
+        assert_eq!(replies[2]["result"].as_array().unwrap().len(), 2);
+        assert_eq!(
+            replies[3]["result"]["contents"]["value"],
+            "`hlt`\n\nStop the program"
+        );
+        assert_eq!(replies[4]["result"].as_array().unwrap().len(), 56);
+        assert_eq!(replies[5]["result"][1]["children"][0]["name"], "end");
+        assert_eq!(replies[6]["error"]["code"], METHOD_NOT_FOUND);
+    }
+}
@@ -1,40 +1,614 @@
-use clap::{arg, Parser};
+use clap::{Parser, Subcommand};
 use log::info;
-use std::{fs::File, io::Read, path::Path};
+use std::{fs::File, io::Read, ops::Range, path::Path};
+
+use assembler::{debug_info::DebugInfo, object::ObjectFile, parse_define, PIE_CODE_START};
+use linker::archive::{standard_library, Archive, ArchiveMember};
+use vm::{
+    backtrace::Backtrace,
+    container::Container,
+    core_dump::CoreDump,
+    coverage::Coverage,
+    profiler::Profiler,
+    trace::{parse_range, TraceFilter, TraceFormat, Tracer},
+    vm_errors::VmError,
+};
 
 pub mod assembler;
+pub mod dap;
+pub mod disassembler;
+pub mod gdb;
 pub mod instruction;
+pub mod linker;
+pub mod lsp;
 pub mod repl;
 pub mod vm;
 
 #[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     #[arg(index = 1)]
     input_file: Option<String>,
 }
 
+#[derive(Subcommand)]
+enum Command {
+    /// Run a .sy source file or an assembled .syb program
+    Run(RunArgs),
+    /// Assemble a .sy file into a .syb program
+    Assemble(AssembleArgs),
+    /// Link object files written by `assemble -c` into a .syb program
+    Link(LinkArgs),
+    /// Bundle object files into a .sya static library archive
+    Ar(ArArgs),
+    /// Print the instructions of a .sy or .syb program
+    Disassemble { input_file: String },
+    /// Inspect a core dump written by a faulting program
+    Debug(DebugArgs),
+    /// Run a Debug Adapter Protocol server on stdin and stdout
+    Dap,
+    /// Run a Language Server Protocol server for .sy files on stdin and stdout
+    Lsp,
+    /// Rewrite .sy files in the canonical style
+    Fmt(FmtArgs),
+}
+
+#[derive(clap::Args)]
+struct FmtArgs {
+    #[arg(required = true)]
+    input_files: Vec<String>,
+
+    /// Report files that are not formatted instead of rewriting them, and
+    /// exit with status 1 if there are any
+    #[arg(long)]
+    check: bool,
+}
+
+#[derive(clap::Args)]
+struct DebugArgs {
+    /// Core file written when the program faulted
+    #[arg(long)]
+    core: String,
+
+    /// The .sy or .syb program that produced the core
+    input_file: String,
+}
+
+#[derive(clap::Args)]
+struct AssembleArgs {
+    input_file: String,
+
+    /// Output file, defaults to the input file with a .syb extension
+    #[arg(short, long)]
+    output: Option<String>,
+
+    /// Embed a debug section with symbols and source line mapping
+    #[arg(short = 'g', long)]
+    debug: bool,
+
+    /// Write the debug section to this file instead of embedding it
+    #[arg(long)]
+    debug_file: Option<String>,
+
+    /// Write a relocatable object file for `synthia link`, with a .o
+    /// extension by default
+    #[arg(short = 'c', long, conflicts_with_all = ["debug", "debug_file"])]
+    object: bool,
+
+    /// Write a listing of each source line with its address and bytes
+    #[arg(long)]
+    listing: Option<String>,
+
+    /// Write a map of every symbol with its section, address, size and type
+    #[arg(long)]
+    map: Option<String>,
+
+    #[command(flatten)]
+    source: SourceArgs,
+}
+
+#[derive(clap::Args)]
+struct LinkArgs {
+    /// Object files, linked in this order after the one defining the entry
+    /// symbol. Members of .sya archives are linked only if they define a
+    /// symbol that is still undefined
+    #[arg(required = true)]
+    input_files: Vec<String>,
+
+    /// Output file, defaults to the first input file with a .syb extension
+    #[arg(short, long)]
+    output: Option<String>,
+
+    /// Embed a debug section with the symbols and source lines of every
+    /// object
+    #[arg(short = 'g', long)]
+    debug: bool,
+
+    /// Link against the bundled library after any given archives
+    #[arg(long)]
+    std: bool,
+
+    /// Global label to start execution at, defaults to main or _start if
+    /// defined and otherwise the code of the first object
+    #[arg(long, value_name = "SYMBOL")]
+    entry: Option<String>,
+}
+
+#[derive(clap::Args)]
+struct ArArgs {
+    /// The archive to write, e.g. lib.sya
+    output: String,
+
+    /// Object files written by `assemble -c`
+    #[arg(required = true)]
+    input_files: Vec<String>,
+}
+
+/// How `.sy` source is assembled.
+#[derive(clap::Args, Default)]
+struct SourceArgs {
+    /// Define a constant for .if and expressions, NAME=value or NAME for 1
+    #[arg(short = 'D', value_name = "NAME=value", value_parser = parse_define)]
+    define: Vec<(String, i32)>,
+
+    /// Also look for .include files in this directory
+    #[arg(short = 'I', value_name = "DIR")]
+    include: Vec<String>,
+}
+
+#[derive(clap::Args, Default)]
+struct RunArgs {
+    input_file: String,
+
+    #[command(flatten)]
+    source: SourceArgs,
+
+    /// Log every executed instruction
+    #[arg(long)]
+    trace: bool,
+
+    /// Trace output format, text or json (one object per line)
+    #[arg(long, default_value = "text")]
+    trace_format: TraceFormat,
+
+    /// Write the trace to this file instead of stderr
+    #[arg(long)]
+    trace_output: Option<String>,
+
+    /// Only trace instructions whose address is in start..end
+    #[arg(long, value_parser = parse_range)]
+    trace_range: Option<Range<usize>>,
+
+    /// Only trace these opcodes, e.g. --trace-opcode load,add
+    #[arg(long, value_delimiter = ',')]
+    trace_opcode: Vec<String>,
+
+    /// Count executed instructions and print a hot-spot report at exit
+    #[arg(long)]
+    profile: bool,
+
+    /// Write profile data in collapsed-stack format for flamegraph tools
+    #[arg(long)]
+    profile_collapsed: Option<String>,
+
+    /// Record line and branch coverage and print an annotated summary
+    #[arg(long)]
+    coverage: bool,
+
+    /// Write the coverage report in lcov format
+    #[arg(long)]
+    coverage_output: Option<String>,
+
+    /// How to print the backtrace of a runtime error, text or json
+    #[arg(long, default_value = "text")]
+    backtrace_format: TraceFormat,
+
+    /// Where to write the core dump if the program faults, defaults to the
+    /// input file with a .core extension
+    #[arg(long)]
+    core_file: Option<String>,
+
+    /// Do not write a core dump when the program faults
+    #[arg(long, conflicts_with = "core_file")]
+    no_core: bool,
+
+    /// Wait for GDB to connect on this address, e.g. 127.0.0.1:1234, and let
+    /// it control the program
+    #[arg(long)]
+    gdb: Option<String>,
+}
+
 fn main() {
     env_logger::init();
     info!("Starting logging!");
     let args = Args::parse();
-    let input_file = args.input_file;
-
-    match input_file {
-        Some(filename) => {
-            let program = read_file(&filename);
-            let mut asm = assembler::Assembler::new();
-            let mut vm = vm::VM::new();
-            let program = asm.assemble(&program);
-            match program {
-                Ok(p) => {
-                    vm.add_bytes(p);
-                    vm.run();
-                    std::process::exit(0);
+
+    match args.command {
+        Some(Command::Run(run_args)) => run(run_args),
+        Some(Command::Assemble(assemble_args)) => assemble(assemble_args),
+        Some(Command::Link(link_args)) => link(link_args),
+        Some(Command::Ar(ar_args)) => ar(ar_args),
+        Some(Command::Disassemble { input_file }) => disassemble(&input_file),
+        Some(Command::Debug(debug_args)) => debug(debug_args),
+        Some(Command::Dap) => {
+            if let Err(e) = dap::serve_stdio() {
+                eprintln!("DAP server failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Lsp) => {
+            if let Err(e) = lsp::serve_stdio() {
+                eprintln!("Language server failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Fmt(fmt_args)) => fmt(fmt_args),
+        None => match args.input_file {
+            Some(input_file) => run(RunArgs {
+                input_file,
+                ..Default::default()
+            }),
+            None => start_repl(),
+        },
+    }
+}
+
+fn run(args: RunArgs) {
+    let container = load_program(&args.input_file, &args.source);
+    let mut vm = vm::VM::new();
+    vm.add_bytes(container.program);
+    vm.ro_data = container.ro_data;
+    vm.debug_info = container.debug_info;
+    if !args.no_core {
+        vm.core_file = Some(match &args.core_file {
+            Some(path) => path.clone(),
+            None => Path::new(&args.input_file)
+                .with_extension("core")
+                .to_string_lossy()
+                .to_string(),
+        });
+    }
+    if let Some(address) = &args.gdb {
+        if let Err(e) = gdb::listen(address, &mut vm) {
+            println!("GDB session failed: {}", e);
+            std::process::exit(1);
+        }
+        std::process::exit(0);
+    }
+    if args.trace {
+        vm.tracer = Some(build_tracer(&args));
+    }
+    if args.profile || args.profile_collapsed.is_some() {
+        vm.profiler = Some(match &vm.debug_info {
+            Some(info) => Profiler::with_debug_info(info),
+            None => Profiler::new(vec![]),
+        });
+    }
+    if args.coverage || args.coverage_output.is_some() {
+        if vm.debug_info.is_none() {
+            println!("Coverage needs debug info, assemble the program with --debug");
+            std::process::exit(1);
+        }
+        vm.coverage = Some(Coverage::new());
+    }
+
+    let result = vm.run();
+    if let Some(profiler) = &vm.profiler {
+        write_profile(&args, profiler, &vm.program);
+    }
+    if let (Some(coverage), Some(info)) = (&vm.coverage, &vm.debug_info) {
+        write_coverage(&args, coverage, info, &vm.program);
+    }
+    if let Err(e) = result {
+        report_fault(&vm, &e, args.backtrace_format);
+        std::process::exit(1);
+    }
+    std::process::exit(0);
+}
+
+fn report_fault(vm: &vm::VM, error: &VmError, format: TraceFormat) {
+    let backtrace = Backtrace::capture(vm, error);
+    match format {
+        TraceFormat::Text => print!("{}", backtrace),
+        TraceFormat::Json => println!("{}", backtrace.to_json()),
+    }
+}
+
+fn debug(args: DebugArgs) {
+    let core = match CoreDump::from_bytes(&read_bytes(&args.core)) {
+        Ok(core) => core,
+        Err(e) => {
+            println!("Unable to read core file {}: {}", args.core, e);
+            std::process::exit(1);
+        }
+    };
+    let container = load_program(&args.input_file, &SourceArgs::default());
+    let mut vm = vm::VM::new();
+    vm.add_bytes(container.program);
+    vm.ro_data = container.ro_data;
+    vm.debug_info = container.debug_info;
+    match repl::debugger::Debugger::new(vm, core) {
+        Ok(mut debugger) => debugger.run(),
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn assemble(args: AssembleArgs) {
+    let source = read_file(&args.input_file);
+    if args.object {
+        return assemble_object(&source, &args);
+    }
+    let (asm, program) = assemble_source(&source, &args.input_file, &args.source);
+    write_reports(&asm, &source, &args);
+    let debug_info = asm.debug_info(&args.input_file);
+
+    let embedded = if args.debug && args.debug_file.is_none() {
+        Some(debug_info.clone())
+    } else {
+        None
+    };
+    if let Some(path) = &args.debug_file {
+        write_output(path, debug_info.to_bytes());
+    }
+
+    let container = Container::new(program, asm.ro.clone(), embedded);
+    let output = match args.output {
+        Some(output) => output,
+        None => Path::new(&args.input_file)
+            .with_extension("syb")
+            .to_string_lossy()
+            .to_string(),
+    };
+    write_output(&output, container.to_bytes());
+}
+
+fn assemble_object(source: &str, args: &AssembleArgs) {
+    let mut asm = source_assembler(&args.input_file, &args.source);
+    let object = match asm.assemble_object(source, &args.input_file) {
+        Ok(object) => object,
+        Err(errors) => {
+            for error in errors {
+                println!("{}", error);
+            }
+            std::process::exit(1);
+        }
+    };
+    write_reports(&asm, source, args);
+    let output = match &args.output {
+        Some(output) => output.clone(),
+        None => Path::new(&args.input_file)
+            .with_extension("o")
+            .to_string_lossy()
+            .to_string(),
+    };
+    write_output(&output, object.to_bytes());
+}
+
+/// Writes the listing and symbol map asked for by `args`.
+fn write_reports(asm: &assembler::Assembler, source: &str, args: &AssembleArgs) {
+    if let Some(path) = &args.listing {
+        write_output(path, asm.listing(source));
+    }
+    if let Some(path) = &args.map {
+        write_output(path, asm.symbol_map());
+    }
+}
+
+fn link(args: LinkArgs) {
+    let mut objects = vec![];
+    let mut archives = vec![];
+    for path in &args.input_files {
+        let bytes = read_bytes(path);
+        if Archive::is_archive(&bytes) {
+            match Archive::from_bytes(&bytes) {
+                Ok(archive) => archives.push((path.clone(), archive)),
+                Err(e) => {
+                    println!("Unable to read archive {}: {}", path, e);
+                    std::process::exit(1);
                 }
-                Err(_) => {}
             }
+        } else {
+            objects.push((path.clone(), read_object(path, &bytes)));
+        }
+    }
+    if args.std {
+        archives.push(("std".to_string(), standard_library()));
+    }
+    let objects = linker::archive::pull_members(objects, &archives);
+    let mut container = match linker::link(&objects, args.entry.as_deref()) {
+        Ok(container) => container,
+        Err(errors) => {
+            for error in errors {
+                println!("{}", error);
+            }
+            std::process::exit(1);
+        }
+    };
+    if !args.debug {
+        container.debug_info = None;
+    }
+    let output = match args.output {
+        Some(output) => output,
+        None => Path::new(&args.input_files[0])
+            .with_extension("syb")
+            .to_string_lossy()
+            .to_string(),
+    };
+    write_output(&output, container.to_bytes());
+}
+
+fn ar(args: ArArgs) {
+    let members = args
+        .input_files
+        .iter()
+        .map(|path| ArchiveMember {
+            name: Path::new(path)
+                .file_name()
+                .map_or(path.clone(), |name| name.to_string_lossy().to_string()),
+            object: read_object(path, &read_bytes(path)),
+        })
+        .collect();
+    match Archive::new(members) {
+        Ok(archive) => write_output(&args.output, archive.to_bytes()),
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn read_object(path: &str, bytes: &[u8]) -> ObjectFile {
+    match ObjectFile::from_bytes(bytes) {
+        Ok(object) => object,
+        Err(e) => {
+            println!("Unable to read object file {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn fmt(args: FmtArgs) {
+    let mut failed = false;
+    for path in &args.input_files {
+        let source = read_file(path);
+        let formatted = match assembler::formatter::format_source(&source) {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                failed = true;
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+        if args.check {
+            println!("Would reformat {}", path);
+            failed = true;
+        } else {
+            write_output(path, formatted);
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+fn disassemble(input_file: &str) {
+    let container = load_program(input_file, &SourceArgs::default());
+    print!(
+        "{}",
+        disassembler::listing(
+            &container.program,
+            PIE_CODE_START,
+            container.debug_info.as_ref()
+        )
+    );
+}
+
+/// Loads a `.syb` container, or assembles `.sy` source with debug info.
+fn load_program(path: &str, args: &SourceArgs) -> Container {
+    let bytes = read_bytes(path);
+    if Container::is_container(&bytes) {
+        return match Container::from_bytes(&bytes) {
+            Ok(container) => container,
+            Err(e) => {
+                println!("{}", e);
+                std::process::exit(1);
+            }
+        };
+    }
+
+    let source = match String::from_utf8(bytes) {
+        Ok(source) => source,
+        Err(e) => {
+            println!("There was an error reading the file: {:?}", e);
+            std::process::exit(1);
+        }
+    };
+    let (asm, program) = assemble_source(&source, path, args);
+    Container::new(program, asm.ro.clone(), Some(asm.debug_info(path)))
+}
+
+fn assemble_source(source: &str, path: &str, args: &SourceArgs) -> (assembler::Assembler, Vec<u8>) {
+    let mut asm = source_assembler(path, args);
+    match asm.assemble(source) {
+        Ok(program) => (asm, program),
+        Err(errors) => {
+            for error in errors {
+                println!("{}", error);
+            }
+            std::process::exit(1);
+        }
+    }
+}
+
+/// An assembler for the source file at `path`.
+fn source_assembler(path: &str, args: &SourceArgs) -> assembler::Assembler {
+    let mut asm = assembler::Assembler::new();
+    asm.set_source_path(path);
+    for (name, value) in &args.define {
+        asm.define(name, *value);
+    }
+    for dir in &args.include {
+        asm.add_include_path(dir);
+    }
+    asm
+}
+
+fn build_tracer(args: &RunArgs) -> Tracer {
+    let mut filter = TraceFilter {
+        range: args.trace_range.clone(),
+        opcodes: vec![],
+    };
+    for name in &args.trace_opcode {
+        let opcode = instruction::Opcode::from(name.to_lowercase().as_str());
+        if opcode == instruction::Opcode::IGL {
+            println!("Unknown opcode in trace filter: {}", name);
+            std::process::exit(1);
         }
-        None => start_repl(),
+        filter.opcodes.push(opcode);
+    }
+
+    let format = args.trace_format;
+    match &args.trace_output {
+        Some(path) => match File::create(path) {
+            Ok(fh) => Tracer::new(format, filter, Box::new(std::io::BufWriter::new(fh))),
+            Err(e) => {
+                println!("Unable to create trace file: {:?}", e);
+                std::process::exit(1);
+            }
+        },
+        None => Tracer::stderr(format, filter),
+    }
+}
+
+fn write_profile(args: &RunArgs, profiler: &Profiler, program: &[u8]) {
+    if args.profile {
+        eprint!("{}", profiler.report(program));
+    }
+    if let Some(path) = &args.profile_collapsed {
+        write_output(path, profiler.collapsed_stacks());
+    }
+}
+
+fn write_coverage(args: &RunArgs, coverage: &Coverage, info: &DebugInfo, program: &[u8]) {
+    eprint!("{}", coverage.summary(&read_file(&info.file), info));
+    if let Some(path) = &args.coverage_output {
+        write_output(path, coverage.lcov(info, program));
+    }
+}
+
+fn write_output(path: &str, contents: impl AsRef<[u8]>) {
+    if let Err(e) = std::fs::write(path, contents) {
+        println!("Unable to write {}: {:?}", path, e);
+        std::process::exit(1);
     }
 }
 
@@ -43,6 +617,16 @@ fn start_repl() {
     repl.run();
 }
 
+fn read_bytes(path: &str) -> Vec<u8> {
+    match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            println!("File not found: {:?}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
 fn read_file(tmp: &str) -> String {
     let filename = Path::new(tmp);
     match File::open(Path::new(&filename)) {
@@ -0,0 +1,227 @@
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+};
+
+use crate::{
+    assembler::PIE_CODE_START,
+    disassembler::{self, INSTRUCTION_LENGTH},
+    repl::{register_format, register_table, RegisterFormat},
+    vm::{backtrace::Backtrace, core_dump::CoreDump, VM},
+};
+
+const DEFAULT_CONTEXT: usize = 5;
+const DEFAULT_HEAP_LENGTH: usize = 256;
+
+/// Read-only REPL over a core dump. Nothing is executed; every command only
+/// inspects the state the VM was in when it faulted.
+pub struct Debugger {
+    vm: VM,
+    core: CoreDump,
+}
+
+impl Debugger {
+    /// `vm` must already hold the program (and its read-only data and debug
+    /// info) that produced `core`.
+    pub fn new(mut vm: VM, core: CoreDump) -> Result<Debugger, String> {
+        core.restore(&mut vm)?;
+        Ok(Debugger { vm, core })
+    }
+
+    pub fn run(&mut self) {
+        println!("Post-mortem session, the core is read-only. Type .help for commands.");
+        println!("{}", self.execute(".fault"));
+        loop {
+            let mut buffer = String::new();
+            print!("(core) ");
+            io::stdout().flush().expect("Unable to flush stdout");
+            match io::stdin().read_line(&mut buffer) {
+                Ok(0) => return,
+                Ok(_) => {}
+                Err(e) => {
+                    println!("Unable to read line from user: {:?}", e);
+                    return;
+                }
+            }
+            match buffer.trim() {
+                ".quit" => return,
+                "" => continue,
+                command => print!("{}", self.execute(command)),
+            }
+        }
+    }
+
+    /// Runs one command and returns its output.
+    pub fn execute(&self, command: &str) -> String {
+        let mut words = command.split_whitespace();
+        let name = words.next().unwrap_or("");
+        let args: Vec<&str> = words.collect();
+        match name {
+            ".help" => HELP.to_string(),
+            ".fault" => format!(
+                "Runtime error: {}\n    at {}\n",
+                self.core.fault,
+                disassembler::describe(&self.vm.program, self.core.pc, self.vm.debug_info.as_ref())
+            ),
+            ".registers" => match register_format(command) {
+                Ok(format) => self.registers(format),
+                Err(e) => e,
+            },
+            ".backtrace" => Backtrace::capture(&self.vm, &self.core.fault).to_string(),
+            ".disassemble" => match parse_args(&args, [DEFAULT_CONTEXT]) {
+                Ok([context]) => self.disassemble(context),
+                Err(e) => e,
+            },
+            ".heap" => match parse_args(&args, [0, DEFAULT_HEAP_LENGTH]) {
+                Ok([offset, length]) => self.heap(offset, length),
+                Err(e) => e,
+            },
+            _ => format!(
+                "Unknown command {}, the core is read-only. Type .help for commands.\n",
+                name
+            ),
+        }
+    }
+
+    fn registers(&self, format: RegisterFormat) -> String {
+        let mut out = register_table(&self.core.registers, format);
+        let _ = writeln!(
+            out,
+            "pc  = {:#06x}  equal_flag = {}  remainder = {}",
+            self.core.pc, self.core.equal_flag, self.core.remainder
+        );
+        out
+    }
+
+    /// The instructions up to `context` before and after the faulting pc.
+    fn disassemble(&self, context: usize) -> String {
+        let pc = self.core.pc;
+        let start = pc
+            .saturating_sub(context * INSTRUCTION_LENGTH)
+            .max(PIE_CODE_START);
+        let end = (pc + (context + 1) * INSTRUCTION_LENGTH).min(self.vm.program.len());
+        let debug_info = self.vm.debug_info.as_ref();
+        let mut out = String::new();
+        for (address, _) in disassembler::disassemble(&self.vm.program[..end], start) {
+            let marker = if address == pc { "=>" } else { "  " };
+            let _ = writeln!(
+                out,
+                "{} {}",
+                marker,
+                disassembler::describe(&self.vm.program, address, debug_info)
+            );
+        }
+        out
+    }
+
+    /// Hex dump of `length` heap bytes from `offset`, 16 per line.
+    fn heap(&self, offset: usize, length: usize) -> String {
+        let heap = &self.core.heap;
+        if heap.is_empty() {
+            return "Heap is empty\n".to_string();
+        }
+        if offset >= heap.len() {
+            return format!(
+                "Heap is {} bytes, offset {} is out of range\n",
+                heap.len(),
+                offset
+            );
+        }
+        let end = (offset + length).min(heap.len());
+        let mut out = String::new();
+        for (line, chunk) in heap[offset..end].chunks(16).enumerate() {
+            let bytes: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+            let _ = writeln!(out, "{:#06x}: {}", offset + line * 16, bytes.join(" "));
+        }
+        out
+    }
+}
+
+const HELP: &str = "\
+.fault                    the error that stopped the program
+.registers [format]       register contents and flags, signed, unsigned or hex
+.backtrace                active calls at the fault
+.disassemble [context]    instructions around the faulting pc
+.heap [offset] [length]   hex dump of the heap
+.quit                     leave the debugger
+";
+
+/// Parses numeric arguments, keeping the defaults for any that are missing.
+fn parse_args<const N: usize>(args: &[&str], defaults: [usize; N]) -> Result<[usize; N], String> {
+    let mut values = defaults;
+    if args.len() > N {
+        return Err(format!("Expected at most {} arguments\n", N));
+    }
+    for (value, arg) in values.iter_mut().zip(args) {
+        let parsed = match arg.strip_prefix("0x") {
+            Some(hex) => usize::from_str_radix(hex, 16),
+            None => arg.parse(),
+        };
+        *value = parsed.map_err(|_| format!("Invalid number {}\n", arg))?;
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+
+    fn debugger() -> Debugger {
+        let source = ".data\n.code\nload $0 #20\naloc $0\nload $5 @fail\ncall $5\nhlt\nfail: load $1 #0\ndiv $0 $1 $2";
+        let mut asm = Assembler::new();
+        let program = asm.assemble(source).unwrap();
+        let mut vm = VM::new();
+        vm.add_bytes(program.clone());
+        let error = vm.run().unwrap_err();
+        let core = CoreDump::capture(&vm, &error);
+
+        let mut fresh = VM::new();
+        fresh.add_bytes(program);
+        fresh.debug_info = Some(asm.debug_info("fail.sy"));
+        Debugger::new(fresh, core).unwrap()
+    }
+
+    #[test]
+    fn test_fault_and_registers() {
+        let debugger = debugger();
+        assert_eq!(
+            debugger.execute(".fault"),
+            "Runtime error: Division by zero\n    at fail.sy:9: div $0 $1 $2\n"
+        );
+        let registers = debugger.execute(".registers");
+        assert!(registers
+            .starts_with("$0  = 20          $1  = 0           $2  = 0           $3  = 0\n"));
+        assert!(registers.ends_with("pc  = 0x0059  equal_flag = false  remainder = 0\n"));
+        assert!(debugger
+            .execute(".registers hex")
+            .starts_with("$0  = 0x00000014  $1  = 0x00000000"));
+    }
+
+    #[test]
+    fn test_disassemble_around_pc() {
+        assert_eq!(
+            debugger().execute(".disassemble 1"),
+            "   fail.sy:8: load $1 #0\n=> fail.sy:9: div $0 $1 $2\n"
+        );
+    }
+
+    #[test]
+    fn test_heap_and_backtrace() {
+        let debugger = debugger();
+        assert_eq!(
+            debugger.execute(".heap 0 20"),
+            "0x0000: 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00\n0x0010: 00 00 00 00\n"
+        );
+        assert_eq!(
+            debugger.execute(".heap 40"),
+            "Heap is 20 bytes, offset 40 is out of range\n"
+        );
+        assert!(debugger
+            .execute(".backtrace")
+            .contains("#1 0x004d at fail.sy:6: call $5"));
+        assert!(debugger
+            .execute("load $0 #1")
+            .starts_with("Unknown command"));
+    }
+}
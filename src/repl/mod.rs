@@ -1,3 +1,5 @@
+pub mod debugger;
+
 use std::{
     fs::File,
     io::{self, Read, Write},
@@ -11,6 +13,61 @@ use crate::{
     vm::VM,
 };
 
+/// How `.registers` shows register values.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum RegisterFormat {
+    #[default]
+    Signed,
+    Unsigned,
+    Hex,
+}
+
+impl RegisterFormat {
+    /// The format named `signed`, `unsigned` or `hex`.
+    pub fn parse(name: &str) -> Option<RegisterFormat> {
+        match name {
+            "signed" => Some(RegisterFormat::Signed),
+            "unsigned" => Some(RegisterFormat::Unsigned),
+            "hex" => Some(RegisterFormat::Hex),
+            _ => None,
+        }
+    }
+
+    pub fn format(&self, value: i32) -> String {
+        match self {
+            RegisterFormat::Signed => value.to_string(),
+            RegisterFormat::Unsigned => (value as u32).to_string(),
+            RegisterFormat::Hex => format!("{:#010x}", value as u32),
+        }
+    }
+}
+
+/// The registers four to a line, e.g. `$0  = 20          $1  = 0`.
+pub fn register_table(registers: &[i32], format: RegisterFormat) -> String {
+    let mut out = String::new();
+    for (index, value) in registers.iter().enumerate() {
+        out.push_str(&format!("${:<2} = {:<12}", index, format.format(*value)));
+        if index % 4 == 3 {
+            out = out.trim_end().to_string();
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// The format named by the argument of a `.registers` command.
+fn register_format(command: &str) -> Result<RegisterFormat, String> {
+    match command.split_whitespace().nth(1) {
+        None => Ok(RegisterFormat::default()),
+        Some(name) => RegisterFormat::parse(name).ok_or_else(|| {
+            format!(
+                "Unknown register format {}, use signed, unsigned or hex\n",
+                name
+            )
+        }),
+    }
+}
+
 pub struct REPL {
     command_buffer: Vec<String>,
     vm: VM,
@@ -58,10 +115,15 @@ impl REPL {
                     }
                     println!("End of Program Listing");
                 }
-                ".registers" => {
-                    println!("Listing registers and all contents");
-                    println!("{:#?}", self.vm.registers);
-                    println!("End of Register Listing");
+                command if command.split_whitespace().next() == Some(".registers") => {
+                    match register_format(command) {
+                        Ok(format) => {
+                            println!("Listing registers and all contents");
+                            print!("{}", register_table(&self.vm.registers, format));
+                            println!("End of Register Listing");
+                        }
+                        Err(e) => print!("{}", e),
+                    }
                 }
                 ".clear_program" => {
                     println!("Removing all bytes from program VM vector... ");
@@ -86,7 +148,9 @@ impl REPL {
                             println!("Sending assembled program to VM");
                             self.vm.program.append(&mut assembled_program);
                             print!("{:#?}", self.vm.program);
-                            self.vm.run();
+                            if let Err(e) = self.vm.run() {
+                                println!("{}", e);
+                            }
                         }
                         Err(errors) => {
                             println!("Unable to parse inputi: {:?}", errors);
@@ -130,3 +194,27 @@ impl REPL {
         Ok(results)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_formats() {
+        let registers = [-1, 255, 0, 7];
+        assert_eq!(
+            register_table(&registers, RegisterFormat::Signed),
+            "$0  = -1          $1  = 255         $2  = 0           $3  = 7\n"
+        );
+        assert_eq!(
+            register_table(&registers, RegisterFormat::Unsigned),
+            "$0  = 4294967295  $1  = 255         $2  = 0           $3  = 7\n"
+        );
+        assert_eq!(
+            register_table(&registers, RegisterFormat::Hex),
+            "$0  = 0xffffffff  $1  = 0x000000ff  $2  = 0x00000000  $3  = 0x00000007\n"
+        );
+        assert_eq!(register_format(".registers hex"), Ok(RegisterFormat::Hex));
+        assert!(register_format(".registers octal").is_err());
+    }
+}
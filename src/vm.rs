@@ -1,521 +0,0 @@
-use crate::{assembler::PIE_HEADER_PREFIX, instruction::Opcode};
-
-pub struct VM {
-    pub registers: [i32; 32],
-    pc: usize,
-    pub program: Vec<u8>,
-    heap: Vec<u8>,
-    remainder: usize,
-    equal_flag: bool,
-    pub ro_data: Vec<u8>,
-}
-
-impl VM {
-    pub fn new() -> VM {
-        VM {
-            registers: [0; 32],
-            program: vec![],
-            heap: vec![],
-            ro_data: vec![],
-            pc: 0,
-            remainder: 0,
-            equal_flag: false,
-        }
-    }
-
-    pub fn run(&mut self) {
-        if !self.verify_header() {
-            println!("Header incorrect");
-            std::process::exit(1);
-        }
-        self.pc = 65;
-        let mut is_done = false;
-        while !is_done {
-            is_done = self.execute_instructions();
-        }
-    }
-
-    pub fn run_once(&mut self) {
-        self.execute_instructions();
-    }
-
-    pub fn add_byte(&mut self, b: u8) {
-        self.program.push(b);
-    }
-
-    pub fn add_bytes(&mut self, mut b: Vec<u8>) {
-        self.program.append(&mut b);
-    }
-
-    pub fn execute_instructions(&mut self) -> bool {
-        if self.pc >= self.program.len() {
-            return true;
-        }
-
-        match self.decode_opcode() {
-            Opcode::LOAD => {
-                let register = self.next_8_bits() as usize;
-                let number = self.next_16_bits() as u32;
-                self.registers[register] = number as i32;
-            }
-            Opcode::ADD => {
-                let register1 = self.registers[self.next_8_bits() as usize];
-                let register2 = self.registers[self.next_8_bits() as usize];
-                self.registers[self.next_8_bits() as usize] = register1 + register2;
-            }
-            Opcode::SUB => {
-                let register1 = self.registers[self.next_8_bits() as usize];
-                let register2 = self.registers[self.next_8_bits() as usize];
-                self.registers[self.next_8_bits() as usize] = register1 - register2;
-            }
-            Opcode::MUL => {
-                let register1 = self.registers[self.next_8_bits() as usize];
-                let register2 = self.registers[self.next_8_bits() as usize];
-                self.registers[self.next_8_bits() as usize] = register1 * register2;
-            }
-            Opcode::DIV => {
-                let register1 = self.registers[self.next_8_bits() as usize];
-                let register2 = self.registers[self.next_8_bits() as usize];
-                self.registers[self.next_8_bits() as usize] = register1 / register2;
-                self.remainder = (register1 % register2) as usize;
-            }
-            Opcode::HLT => {
-                println!("HLT encountered");
-                return true;
-            }
-            Opcode::IGL => {
-                println!("Illegal instruction encountered");
-                return true;
-            }
-            Opcode::JMP => {
-                let target = self.registers[self.next_8_bits() as usize];
-                self.pc = target as usize;
-            }
-            Opcode::JMPF => {
-                let value = self.registers[self.next_8_bits() as usize];
-                self.pc += value as usize;
-            }
-            Opcode::JMPB => {
-                let value = self.registers[self.next_8_bits() as usize];
-                self.pc -= value as usize;
-            }
-            Opcode::EQ => {
-                let register1 = self.registers[self.next_8_bits() as usize];
-                let register2 = self.registers[self.next_8_bits() as usize];
-                if register1 == register2 {
-                    self.equal_flag = true;
-                } else {
-                    self.equal_flag = false;
-                }
-                self.next_8_bits();
-            }
-            Opcode::NEQ => {
-                let register1 = self.registers[self.next_8_bits() as usize];
-                let register2 = self.registers[self.next_8_bits() as usize];
-                if register1 != register2 {
-                    self.equal_flag = true;
-                } else {
-                    self.equal_flag = false;
-                }
-                self.next_8_bits();
-            }
-            Opcode::GT => {
-                let register1 = self.registers[self.next_8_bits() as usize];
-                let register2 = self.registers[self.next_8_bits() as usize];
-                if register1 > register2 {
-                    self.equal_flag = true;
-                } else {
-                    self.equal_flag = false;
-                }
-                self.next_8_bits();
-            }
-            Opcode::LT => {
-                let register1 = self.registers[self.next_8_bits() as usize];
-                let register2 = self.registers[self.next_8_bits() as usize];
-                if register1 < register2 {
-                    self.equal_flag = true;
-                } else {
-                    self.equal_flag = false;
-                }
-                self.next_8_bits();
-            }
-            Opcode::GTE => {
-                let register1 = self.registers[self.next_8_bits() as usize];
-                let register2 = self.registers[self.next_8_bits() as usize];
-                if register1 >= register2 {
-                    self.equal_flag = true;
-                } else {
-                    self.equal_flag = false;
-                }
-                self.next_8_bits();
-            }
-            Opcode::LTE => {
-                let register1 = self.registers[self.next_8_bits() as usize];
-                let register2 = self.registers[self.next_8_bits() as usize];
-                if register1 <= register2 {
-                    self.equal_flag = true;
-                } else {
-                    self.equal_flag = false;
-                }
-                self.next_8_bits();
-            }
-            Opcode::JEQ => {
-                let target = self.registers[self.next_8_bits() as usize];
-                if self.equal_flag {
-                    self.pc = target as usize;
-                }
-            }
-            Opcode::JNEQ => {
-                let target = self.registers[self.next_8_bits() as usize];
-                if !self.equal_flag {
-                    self.pc = target as usize;
-                }
-            }
-            Opcode::ALOC => {
-                let register = self.next_8_bits() as usize;
-                let bytes = self.registers[register];
-                let new_end = self.heap.len() as i32 + bytes;
-                self.heap.resize(new_end as usize, 0);
-            }
-            Opcode::INC => {
-                let register_number = self.next_8_bits() as usize;
-                self.registers[register_number] += 1;
-                self.next_8_bits();
-                self.next_8_bits();
-            }
-            Opcode::DEC => {
-                let register_number = self.next_8_bits() as usize;
-                self.registers[register_number] -= 1;
-                self.next_8_bits();
-                self.next_8_bits();
-            }
-            Opcode::DJMPE => {
-                let destination = self.next_8_bits();
-                if self.equal_flag {
-                    self.pc = destination as usize;
-                } else {
-                    self.next_8_bits();
-                }
-            }
-            Opcode::NOP => {
-                self.next_8_bits();
-                self.next_8_bits();
-                self.next_8_bits();
-            }
-            Opcode::PRTS => {
-                let starting_point = self.next_16_bits() as usize;
-                let mut ending_offset = starting_point;
-                let slice = self.ro_data.as_slice();
-
-                while slice[ending_offset] != 0 {
-                    ending_offset += 1;
-                }
-                let result = std::str::from_utf8(&slice[starting_point..ending_offset]);
-                match result {
-                    Ok(s) => {
-                        print!("{}", s);
-                    }
-                    Err(e) => {
-                        println!("Error decoding string for prts instruction: {:#?}", e)
-                    }
-                };
-            }
-        }
-        false
-    }
-
-    fn decode_opcode(&mut self) -> Opcode {
-        let opcode = Opcode::from(self.program[self.pc]);
-        self.pc += 1;
-        return opcode;
-    }
-
-    fn next_8_bits(&mut self) -> u8 {
-        let result = self.program[self.pc];
-        self.pc += 1;
-        result
-    }
-
-    fn next_16_bits(&mut self) -> u16 {
-        let result = ((self.program[self.pc] as u16) << 8) | self.program[self.pc + 1] as u16;
-        self.pc += 2;
-        result
-    }
-
-    fn verify_header(&self) -> bool {
-        // println!("{:?}", self.program);
-        if self.program[0..4] != PIE_HEADER_PREFIX {
-            return false;
-        }
-        true
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use std::io::Write;
-
-    use crate::assembler::PIE_HEADER_LENGTH;
-
-    use super::*;
-
-    fn get_test_vm() -> VM {
-        let mut test_vm = VM::new();
-        test_vm.registers[0] = 5;
-        test_vm.registers[1] = 10;
-        test_vm.pc = 64;
-        test_vm
-    }
-
-    fn prepend_header(mut b: Vec<u8>) -> Vec<u8> {
-        let mut prepension = vec![];
-        for byte in PIE_HEADER_PREFIX.into_iter() {
-            prepension.push(byte.clone());
-        }
-        while prepension.len() < PIE_HEADER_LENGTH {
-            prepension.push(0);
-        }
-        prepension.append(&mut b);
-        prepension
-    }
-
-    #[test]
-    fn test_create_vm() {
-        let test_vm = VM::new();
-        assert_eq!(test_vm.registers[0], 0)
-    }
-
-    #[test]
-    fn test_opcode_hlt() {
-        let mut test_vm = VM::new();
-        let test_bytes = vec![5, 0, 0, 0];
-        test_vm.program = test_bytes;
-        test_vm.run_once();
-        assert_eq!(test_vm.pc, 1);
-    }
-
-    #[test]
-    fn test_opcode_igl() {
-        let mut test_vm = VM::new();
-        let test_bytes = vec![200, 0, 0, 0];
-        test_vm.program = test_bytes;
-        test_vm.run_once();
-        assert_eq!(test_vm.pc, 1);
-    }
-
-    #[test]
-    fn test_load_opcode() {
-        let mut test_vm = get_test_vm();
-        test_vm.program = vec![0, 0, 1, 244];
-        test_vm.program = prepend_header(test_vm.program);
-        test_vm.run_once();
-        assert_eq!(test_vm.registers[0], 500);
-    }
-
-    #[test]
-    fn test_add_opcode() {
-        let mut test_vm = get_test_vm();
-        test_vm.program = vec![1, 0, 1, 2];
-        test_vm.program = prepend_header(test_vm.program);
-        println!("{:?}", &test_vm.program);
-        test_vm.run_once();
-        assert_eq!(test_vm.registers[2], 15);
-    }
-
-    #[test]
-    fn test_sub_opcode() {
-        let mut test_vm = get_test_vm();
-        test_vm.program = vec![2, 1, 0, 2];
-        test_vm.program = prepend_header(test_vm.program);
-        test_vm.run_once();
-        assert_eq!(test_vm.registers[2], 5);
-    }
-
-    #[test]
-    fn test_mul_opcode() {
-        let mut test_vm = get_test_vm();
-        test_vm.program = vec![3, 0, 1, 2];
-        test_vm.program = prepend_header(test_vm.program);
-        test_vm.run_once();
-        assert_eq!(test_vm.registers[2], 50);
-    }
-
-    #[test]
-    fn test_div_opcode() {
-        let mut test_vm = get_test_vm();
-        test_vm.program = vec![4, 1, 0, 2];
-        test_vm.program = prepend_header(test_vm.program);
-        test_vm.run_once();
-        assert_eq!(test_vm.registers[2], 2);
-    }
-
-    #[test]
-    fn test_jmp_opcode() {
-        let mut test_vm = get_test_vm();
-        test_vm.registers[0] = 4;
-        test_vm.program = vec![6, 0, 0, 0];
-        test_vm.program = prepend_header(test_vm.program);
-        test_vm.run_once();
-        assert_eq!(test_vm.pc, 4);
-    }
-
-    #[test]
-    fn test_jmpf_opcode() {
-        let mut test_vm = get_test_vm();
-        test_vm.registers[0] = 2;
-        test_vm.program = vec![7, 0, 0, 0, 6, 0, 0, 0];
-        test_vm.program = prepend_header(test_vm.program);
-        test_vm.run_once();
-        assert_eq!(test_vm.pc, 4 + 64);
-    }
-
-    #[test]
-    fn test_jmpb_opcode() {
-        let mut test_vm = get_test_vm();
-        test_vm.registers[1] = 6;
-        test_vm.program = vec![0, 0, 0, 10, 9, 1, 0, 0];
-        test_vm.program = prepend_header(test_vm.program);
-        test_vm.run_once();
-        assert_eq!(test_vm.pc, 0 + 68);
-    }
-
-    #[test]
-    fn test_eq_opcode() {
-        let mut test_vm = get_test_vm();
-        test_vm.registers[0] = 10;
-        test_vm.registers[1] = 10;
-        test_vm.program = vec![10, 0, 1, 0, 10, 0, 1, 0];
-        test_vm.program = prepend_header(test_vm.program);
-        test_vm.run_once();
-        assert_eq!(test_vm.equal_flag, true);
-        test_vm.registers[1] = 20;
-        test_vm.run_once();
-        assert_eq!(test_vm.equal_flag, false);
-    }
-
-    #[test]
-    fn test_neq_opcode() {
-        let mut test_vm = get_test_vm();
-        test_vm.registers[0] = 10;
-        test_vm.registers[1] = 11;
-        test_vm.program = vec![11, 0, 1, 0, 11, 0, 1, 0];
-        test_vm.program = prepend_header(test_vm.program);
-        test_vm.run_once();
-        assert_eq!(test_vm.equal_flag, true);
-        test_vm.registers[1] = 10;
-        test_vm.run_once();
-        assert_eq!(test_vm.equal_flag, false);
-    }
-
-    #[test]
-    fn test_gt_opcode() {
-        let mut test_vm = get_test_vm();
-        test_vm.registers[0] = 20;
-        test_vm.registers[1] = 10;
-        test_vm.program = vec![12, 0, 1, 0, 12, 0, 1, 0, 12, 0, 1, 0];
-        test_vm.program = prepend_header(test_vm.program);
-        test_vm.run_once();
-        assert_eq!(test_vm.equal_flag, true);
-        test_vm.registers[0] = 10;
-        test_vm.run_once();
-        assert_eq!(test_vm.equal_flag, false);
-        test_vm.registers[0] = 5;
-        test_vm.run_once();
-        assert_eq!(test_vm.equal_flag, false);
-    }
-
-    #[test]
-    fn test_lt_opcode() {
-        let mut test_vm = get_test_vm();
-        test_vm.registers[0] = 10;
-        test_vm.registers[1] = 9;
-        test_vm.program = vec![13, 0, 1, 0, 13, 0, 1, 0];
-        test_vm.program = prepend_header(test_vm.program);
-        test_vm.run_once();
-        assert_eq!(test_vm.equal_flag, false);
-        test_vm.registers[1] = 10;
-        test_vm.run_once();
-        assert_eq!(test_vm.equal_flag, false);
-    }
-
-    #[test]
-    fn test_gtq_opcode() {
-        let mut test_vm = get_test_vm();
-        test_vm.registers[0] = 10;
-        test_vm.registers[1] = 20;
-        test_vm.program = vec![14, 0, 1, 0, 14, 0, 1, 0, 14, 0, 1, 0];
-        test_vm.program = prepend_header(test_vm.program);
-        test_vm.run_once();
-        assert_eq!(test_vm.equal_flag, false);
-        test_vm.registers[1] = 10;
-        test_vm.run_once();
-        assert_eq!(test_vm.equal_flag, true);
-        test_vm.registers[1] = 5;
-        test_vm.run_once();
-        assert_eq!(test_vm.equal_flag, true);
-    }
-
-    #[test]
-    fn test_ltq_opcode() {
-        let mut test_vm = get_test_vm();
-        test_vm.registers[0] = 20;
-        test_vm.registers[1] = 10;
-        test_vm.program = vec![15, 0, 1, 0, 15, 0, 1, 0, 15, 0, 1, 0];
-        test_vm.program = prepend_header(test_vm.program);
-        test_vm.run_once();
-        assert_eq!(test_vm.equal_flag, false);
-        test_vm.registers[0] = 5;
-        test_vm.run_once();
-        assert_eq!(test_vm.equal_flag, true);
-        test_vm.registers[0] = 10;
-        test_vm.run_once();
-        assert_eq!(test_vm.equal_flag, true);
-    }
-
-    #[test]
-    fn test_jeq_opcode() {
-        let mut test_vm = get_test_vm();
-        test_vm.registers[0] = 7;
-        test_vm.equal_flag = true;
-        test_vm.program = vec![16, 0, 0, 0, 6, 0, 0, 0];
-        test_vm.program = prepend_header(test_vm.program);
-        test_vm.run_once();
-        assert_eq!(test_vm.pc, 7);
-    }
-
-    #[test]
-    fn test_jneq_opcode() {
-        let mut test_vm = get_test_vm();
-        test_vm.registers[0] = 7;
-        test_vm.equal_flag = false;
-        test_vm.program = vec![17, 0, 0, 0, 6, 0, 0, 0];
-        test_vm.program = prepend_header(test_vm.program);
-        test_vm.run_once();
-        assert_eq!(test_vm.pc, 7);
-    }
-
-    #[test]
-    fn test_aloc_opcode() {
-        let mut test_vm = get_test_vm();
-        test_vm.registers[0] = 1024;
-        test_vm.program = vec![18, 0, 0, 0];
-        test_vm.program = prepend_header(test_vm.program);
-        test_vm.run_once();
-        assert_eq!(test_vm.heap.len(), 1024);
-    }
-
-    #[test]
-    fn test_prts_opcode() {
-        let mut test_vm = get_test_vm();
-        test_vm.pc = 5;
-        test_vm.ro_data.append(&mut vec![72, 101, 108, 108, 111, 0]);
-        test_vm.program = vec![23, 0, 0, 0];
-        let mut output_buffer = Vec::new();
-        let _ = std::io::stdout().lock().write_all(&mut output_buffer);
-        let _ = std::io::stdout().lock().flush();
-        test_vm.run_once();
-        let output_str = std::str::from_utf8(&output_buffer).expect("Invalid output buffer");
-        println!("{:?} test", &output_buffer);
-        assert_eq!(output_str, "Hello");
-    }
-}
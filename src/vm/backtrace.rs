@@ -0,0 +1,154 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::{
+    assembler::debug_info::DebugInfo,
+    disassembler::DecodedInstruction,
+    vm::{vm_errors::VmError, CallFrame, VM},
+};
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct BacktraceFrame {
+    pub pc: usize,
+    pub function: Option<String>,
+    pub location: Option<String>,
+    pub instruction: String,
+}
+
+/// The chain of active calls at a fault, innermost frame first.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Backtrace {
+    pub error: String,
+    pub frames: Vec<BacktraceFrame>,
+}
+
+impl Backtrace {
+    /// Builds the backtrace for `error` from the VM's call stack.
+    pub fn capture(vm: &VM, error: &VmError) -> Backtrace {
+        Backtrace {
+            error: error.to_string(),
+            frames: match error.pc() {
+                Some(pc) => Backtrace::frames(vm, pc),
+                None => vec![],
+            },
+        }
+    }
+
+    /// The active calls with execution at `pc`. Frames past the first point
+    /// at the `call` instruction that entered the next one.
+    pub fn frames(vm: &VM, pc: usize) -> Vec<BacktraceFrame> {
+        let debug_info = vm.debug_info.as_ref();
+        let mut frames = vec![];
+        let mut pc = pc;
+        let mut stack: Vec<CallFrame> = vm.call_stack.clone();
+        loop {
+            let callee = stack.last().map(|frame| frame.callee);
+            frames.push(BacktraceFrame::new(&vm.program, pc, callee, debug_info));
+            match stack.pop() {
+                Some(frame) => pc = frame.return_address.saturating_sub(4),
+                None => return frames,
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Backtraces are always serializable")
+    }
+}
+
+impl BacktraceFrame {
+    fn new(
+        program: &[u8],
+        pc: usize,
+        callee: Option<usize>,
+        debug_info: Option<&DebugInfo>,
+    ) -> BacktraceFrame {
+        let instruction = DecodedInstruction::decode(program.get(pc..).unwrap_or(&[]));
+        BacktraceFrame {
+            pc,
+            function: debug_info.and_then(|info| function_name(info, pc, callee)),
+            location: debug_info.and_then(|info| info.location(pc as u32)),
+            instruction: instruction.to_symbolic_string(pc, debug_info),
+        }
+    }
+}
+
+/// Name of the function a frame is executing: the label that was called, or
+/// for the outermost frame, the label containing `pc`.
+fn function_name(info: &DebugInfo, pc: usize, callee: Option<usize>) -> Option<String> {
+    let address = callee.unwrap_or(pc) as u32;
+    info.code_labels()
+        .into_iter()
+        .find(|(_, start)| *start == address)
+        .map(|(name, _)| name)
+        .or_else(|| info.function_at(pc as u32).map(|s| s.name.clone()))
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Runtime error: {}", self.error)?;
+        for (index, frame) in self.frames.iter().enumerate() {
+            write!(f, "  #{} {:#06x}", index, frame.pc)?;
+            if let Some(function) = &frame.function {
+                write!(f, " in {}", function)?;
+            }
+            match &frame.location {
+                Some(location) => writeln!(f, " at {}: {}", location, frame.instruction)?,
+                None => writeln!(f, ": {}", frame.instruction)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+
+    const NESTED_CALLS: &str = ".data\n.code\nload $10 @outer\ncall $10\nhlt\nouter: load $11 @inner\ncall $11\nret\ninner: load $1 #0\ndiv $0 $1 $2\nret";
+
+    fn run_to_fault(debug: bool) -> (VM, VmError) {
+        let mut asm = Assembler::new();
+        let program = asm.assemble(NESTED_CALLS).unwrap();
+        let mut vm = VM::new();
+        vm.add_bytes(program);
+        if debug {
+            vm.debug_info = Some(asm.debug_info("calls.sy"));
+        }
+        let error = vm.run().unwrap_err();
+        (vm, error)
+    }
+
+    #[test]
+    fn test_backtrace_with_debug_info() {
+        let (vm, error) = run_to_fault(true);
+        let backtrace = Backtrace::capture(&vm, &error);
+        assert_eq!(
+            backtrace.to_string(),
+            "Runtime error: Division by zero\n  \
+             #0 0x005d in inner at calls.sy:10: div $0 $1 $2\n  \
+             #1 0x0051 in outer at calls.sy:7: call $11\n  \
+             #2 0x0045 at calls.sy:4: call $10\n"
+        );
+    }
+
+    #[test]
+    fn test_backtrace_without_debug_info() {
+        let (vm, error) = run_to_fault(false);
+        let backtrace = Backtrace::capture(&vm, &error);
+        assert_eq!(backtrace.frames.len(), 3);
+        assert_eq!(backtrace.frames[1].function, None);
+        assert!(backtrace.to_string().ends_with("  #2 0x0045: call $10\n"));
+    }
+
+    #[test]
+    fn test_backtrace_json() {
+        let (vm, error) = run_to_fault(true);
+        let json = Backtrace::capture(&vm, &error).to_json();
+        assert!(json.starts_with(
+            "{\"error\":\"Division by zero\",\"frames\":[{\"pc\":93,\"function\":\"inner\",\"location\":\"calls.sy:10\",\"instruction\":\"div $0 $1 $2\"}"
+        ));
+    }
+}
@@ -0,0 +1,127 @@
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::{
+    assembler::{debug_info::DebugInfo, PIE_CODE_START, PIE_HEADER_PREFIX},
+    vm::vm_errors::VmError,
+};
+
+const CODE_LENGTH_OFFSET: usize = 4;
+const RO_LENGTH_OFFSET: usize = 8;
+const DEBUG_LENGTH_OFFSET: usize = 12;
+
+/// A runnable program as stored in a `.syb` file: the header, followed by
+/// the code, the read-only data and an optional debug section. The header
+/// records the length of each part.
+#[derive(Debug, PartialEq)]
+pub struct Container {
+    pub program: Vec<u8>,
+    pub ro_data: Vec<u8>,
+    pub debug_info: Option<DebugInfo>,
+}
+
+impl Container {
+    /// `program` is the header and code as returned by `Assembler::assemble`.
+    pub fn new(program: Vec<u8>, ro_data: Vec<u8>, debug_info: Option<DebugInfo>) -> Container {
+        Container {
+            program,
+            ro_data,
+            debug_info,
+        }
+    }
+
+    pub fn is_container(bytes: &[u8]) -> bool {
+        bytes.len() >= PIE_CODE_START && bytes[0..4] == PIE_HEADER_PREFIX
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let debug = match &self.debug_info {
+            Some(info) => info.to_bytes(),
+            None => vec![],
+        };
+        let mut bytes = self.program.clone();
+        let code_length = (bytes.len() - PIE_CODE_START) as u32;
+        BigEndian::write_u32(&mut bytes[CODE_LENGTH_OFFSET..], code_length);
+        BigEndian::write_u32(&mut bytes[RO_LENGTH_OFFSET..], self.ro_data.len() as u32);
+        BigEndian::write_u32(&mut bytes[DEBUG_LENGTH_OFFSET..], debug.len() as u32);
+        bytes.extend_from_slice(&self.ro_data);
+        bytes.extend_from_slice(&debug);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Container, VmError> {
+        if !Container::is_container(bytes) {
+            return Err(VmError::InvalidHeader);
+        }
+        let code_length = BigEndian::read_u32(&bytes[CODE_LENGTH_OFFSET..]) as usize;
+        let ro_length = BigEndian::read_u32(&bytes[RO_LENGTH_OFFSET..]) as usize;
+        let debug_length = BigEndian::read_u32(&bytes[DEBUG_LENGTH_OFFSET..]) as usize;
+
+        let code_end = PIE_CODE_START + code_length;
+        let ro_end = code_end + ro_length;
+        if ro_end + debug_length != bytes.len() {
+            return Err(VmError::CorruptProgram {
+                reason: "section lengths do not match the file size".to_string(),
+            });
+        }
+
+        let mut program = bytes[..code_end].to_vec();
+        program[CODE_LENGTH_OFFSET..DEBUG_LENGTH_OFFSET + 4].fill(0);
+        let debug_info = if debug_length > 0 {
+            match DebugInfo::from_bytes(&bytes[ro_end..]) {
+                Ok(info) => Some(info),
+                Err(reason) => return Err(VmError::CorruptProgram { reason }),
+            }
+        } else {
+            None
+        };
+
+        Ok(Container {
+            program,
+            ro_data: bytes[code_end..ro_end].to_vec(),
+            debug_info,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+
+    fn assemble(source: &str, debug: bool) -> Container {
+        let mut asm = Assembler::new();
+        let program = asm.assemble(source).unwrap();
+        let debug_info = if debug {
+            Some(asm.debug_info("test.sy"))
+        } else {
+            None
+        };
+        Container::new(program, asm.ro.clone(), debug_info)
+    }
+
+    #[test]
+    fn test_container_round_trip() {
+        let source = ".data\nhello: .asciiz 'Hello'\n.code\nprts @hello\nhlt";
+        for debug in [false, true] {
+            let container = assemble(source, debug);
+            let bytes = container.to_bytes();
+            assert!(Container::is_container(&bytes));
+            assert_eq!(Container::from_bytes(&bytes), Ok(container));
+        }
+    }
+
+    #[test]
+    fn test_container_rejects_truncated_file() {
+        let bytes = assemble(".data\n.code\nhlt", false).to_bytes();
+        assert_eq!(
+            Container::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(VmError::CorruptProgram {
+                reason: "section lengths do not match the file size".to_string()
+            })
+        );
+        assert_eq!(
+            Container::from_bytes(&[1, 2, 3]),
+            Err(VmError::InvalidHeader)
+        );
+    }
+}
@@ -0,0 +1,147 @@
+use serde::{Deserialize, Serialize};
+
+use crate::vm::{vm_errors::VmError, CallFrame, VM};
+
+const CORE_FORMAT: &str = "synthia-core";
+const CORE_VERSION: u32 = 1;
+
+/// The state of a VM at the moment it faulted, written as JSON so it can be
+/// inspected later with `synthia debug --core`.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct CoreDump {
+    pub format: String,
+    pub version: u32,
+    pub program_hash: String,
+    pub fault: VmError,
+    pub pc: usize,
+    pub registers: [i32; 32],
+    pub equal_flag: bool,
+    pub remainder: usize,
+    pub heap: Vec<u8>,
+    pub call_stack: Vec<CallFrame>,
+}
+
+impl CoreDump {
+    pub fn capture(vm: &VM, fault: &VmError) -> CoreDump {
+        CoreDump {
+            format: CORE_FORMAT.to_string(),
+            version: CORE_VERSION,
+            program_hash: program_hash(&vm.program, &vm.ro_data),
+            fault: fault.clone(),
+            pc: fault.pc().unwrap_or(vm.pc),
+            registers: vm.registers,
+            equal_flag: vm.equal_flag,
+            remainder: vm.remainder,
+            heap: vm.heap.clone(),
+            call_stack: vm.call_stack.clone(),
+        }
+    }
+
+    /// Puts `vm` back into the state it was in when the core was written.
+    /// The program itself is not part of the core and must already be loaded.
+    pub fn restore(&self, vm: &mut VM) -> Result<(), String> {
+        let hash = program_hash(&vm.program, &vm.ro_data);
+        if hash != self.program_hash {
+            return Err(format!(
+                "The core was written by a different program (core {}, program {})",
+                self.program_hash, hash
+            ));
+        }
+        vm.pc = self.pc;
+        vm.instruction_pc = self.pc;
+        vm.registers = self.registers;
+        vm.equal_flag = self.equal_flag;
+        vm.remainder = self.remainder;
+        vm.heap = self.heap.clone();
+        vm.call_stack = self.call_stack.clone();
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("Core dumps are always serializable")
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<CoreDump, String> {
+        let core: CoreDump = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+        if core.format != CORE_FORMAT || core.version != CORE_VERSION {
+            return Err(format!(
+                "Unsupported core format {} version {}",
+                core.format, core.version
+            ));
+        }
+        Ok(core)
+    }
+}
+
+/// FNV-1a hash of the code and read-only data, used to check that a core is
+/// opened against the program that produced it.
+pub fn program_hash(program: &[u8], ro_data: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in program.iter().chain(ro_data) {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+
+    const FAULTING_PROGRAM: &str = ".data\n.code\nload $0 #64\naloc $0\nload $5 @fail\ncall $5\nhlt\nfail: load $1 #0\neq $1 $1\ndiv $0 $1 $2";
+
+    fn faulted_vm() -> (VM, VmError) {
+        let mut asm = Assembler::new();
+        let program = asm.assemble(FAULTING_PROGRAM).unwrap();
+        let mut vm = VM::new();
+        vm.add_bytes(program);
+        let error = vm.run().unwrap_err();
+        (vm, error)
+    }
+
+    #[test]
+    fn test_core_dump_captures_state() {
+        let (vm, error) = faulted_vm();
+        let core = CoreDump::capture(&vm, &error);
+        assert_eq!(core.fault, VmError::DivideByZero { pc: 93 });
+        assert_eq!(core.pc, 93);
+        assert_eq!(core.registers[0], 64);
+        assert_eq!(core.registers[5], 85);
+        assert!(core.equal_flag);
+        assert_eq!(core.heap.len(), 64);
+        assert_eq!(
+            core.call_stack,
+            vec![CallFrame {
+                return_address: 81,
+                callee: 85
+            }]
+        );
+    }
+
+    #[test]
+    fn test_core_dump_round_trip_and_restore() {
+        let (vm, error) = faulted_vm();
+        let core = CoreDump::capture(&vm, &error);
+        let loaded = CoreDump::from_bytes(&core.to_bytes()).unwrap();
+        assert_eq!(loaded, core);
+
+        let mut restored = VM::new();
+        restored.add_bytes(vm.program.clone());
+        loaded.restore(&mut restored).unwrap();
+        assert_eq!(restored.registers, vm.registers);
+        assert_eq!(restored.heap, vm.heap);
+        assert_eq!(restored.call_stack, vm.call_stack);
+        assert_eq!(restored.pc, 93);
+    }
+
+    #[test]
+    fn test_core_dump_rejects_other_program() {
+        let (vm, error) = faulted_vm();
+        let core = CoreDump::capture(&vm, &error);
+        let mut other = VM::new();
+        other.add_bytes(Assembler::new().assemble(".data\n.code\nhlt").unwrap());
+        assert!(core.restore(&mut other).is_err());
+        assert!(CoreDump::from_bytes(b"{}").is_err());
+    }
+}
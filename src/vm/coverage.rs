@@ -0,0 +1,236 @@
+use std::{collections::HashMap, fmt::Write};
+
+use crate::{
+    assembler::{debug_info::DebugInfo, source_map::SourceMap},
+    instruction::Opcode,
+    vm::profiler::BranchCount,
+};
+
+/// Records which instructions executed and which way conditional jumps went.
+#[derive(Debug, Default)]
+pub struct Coverage {
+    pub hits: HashMap<usize, u64>,
+    pub branches: HashMap<usize, BranchCount>,
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage::default()
+    }
+
+    pub fn record(&mut self, pc: usize, opcode: Opcode, next_pc: usize) {
+        *self.hits.entry(pc).or_insert(0) += 1;
+        if opcode.is_conditional_jump() {
+            let count = self.branches.entry(pc).or_default();
+            if next_pc == pc + 4 {
+                count.not_taken += 1;
+            } else {
+                count.taken += 1;
+            }
+        }
+    }
+
+    /// Hit count of a source line: the most any of its instructions ran.
+    pub fn line_hits(&self, source_map: &SourceMap, line: u32) -> u64 {
+        source_map
+            .pcs_for_line(line)
+            .iter()
+            .map(|pc| self.hits.get(&(*pc as usize)).copied().unwrap_or(0))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Coverage in lcov tracefile format. Code labels are reported as
+    /// functions.
+    pub fn lcov(&self, debug_info: &DebugInfo, program: &[u8]) -> String {
+        let source_map = &debug_info.source_map;
+        let mut out = String::new();
+        let _ = writeln!(out, "TN:");
+        let _ = writeln!(out, "SF:{}", debug_info.file);
+
+        let functions: Vec<(String, u32, u64)> = debug_info
+            .code_labels()
+            .into_iter()
+            .filter_map(|(name, pc)| {
+                let line = match source_map.location_for(pc)? {
+                    (0, line) => line,
+                    _ => return None,
+                };
+                let hits = self.hits.get(&(pc as usize)).copied().unwrap_or(0);
+                Some((name, line, hits))
+            })
+            .collect();
+        for (name, line, _) in &functions {
+            let _ = writeln!(out, "FN:{},{}", line, name);
+        }
+        for (name, _, hits) in &functions {
+            let _ = writeln!(out, "FNDA:{},{}", hits, name);
+        }
+        let _ = writeln!(out, "FNF:{}", functions.len());
+        let _ = writeln!(
+            out,
+            "FNH:{}",
+            functions.iter().filter(|(_, _, hits)| *hits > 0).count()
+        );
+
+        let (mut found, mut hit) = (0, 0);
+        for (block, entry) in source_map.entries().iter().enumerate() {
+            let pc = entry.pc as usize;
+            if entry.file != 0 || !Opcode::from(program[pc]).is_conditional_jump() {
+                continue;
+            }
+            let executed = self.hits.contains_key(&pc);
+            let count = self.branches.get(&pc).copied().unwrap_or_default();
+            for (branch, taken) in [(0, count.taken), (1, count.not_taken)] {
+                let taken = if executed {
+                    taken.to_string()
+                } else {
+                    "-".to_string()
+                };
+                let _ = writeln!(out, "BRDA:{},{},{},{}", entry.line, block, branch, taken);
+            }
+            found += 2;
+            hit += (count.taken > 0) as usize + (count.not_taken > 0) as usize;
+        }
+        let _ = writeln!(out, "BRF:{}", found);
+        let _ = writeln!(out, "BRH:{}", hit);
+
+        let lines = source_map.lines();
+        let mut lines_hit = 0;
+        for line in &lines {
+            let hits = self.line_hits(source_map, *line);
+            if hits > 0 {
+                lines_hit += 1;
+            }
+            let _ = writeln!(out, "DA:{},{}", line, hits);
+        }
+        let _ = writeln!(out, "LF:{}", lines.len());
+        let _ = writeln!(out, "LH:{}", lines_hit);
+        let _ = writeln!(out, "end_of_record");
+        out
+    }
+
+    /// Source listing annotated with hit counts. Lines that produced code but
+    /// never ran are marked with `#####`.
+    pub fn summary(&self, source: &str, debug_info: &DebugInfo) -> String {
+        let source_map = &debug_info.source_map;
+        let mut out = String::new();
+        let lines = source_map.lines();
+        let lines_hit = lines
+            .iter()
+            .filter(|line| self.line_hits(source_map, **line) > 0)
+            .count();
+        let branches_found = self.branches.len() * 2;
+        let branches_hit: usize = self
+            .branches
+            .values()
+            .map(|count| (count.taken > 0) as usize + (count.not_taken > 0) as usize)
+            .sum();
+
+        let _ = writeln!(
+            out,
+            "Coverage for {}: lines {}/{} ({:.2}%), branches {}/{} ({:.2}%)",
+            debug_info.file,
+            lines_hit,
+            lines.len(),
+            percent(lines_hit, lines.len()),
+            branches_hit,
+            branches_found,
+            percent(branches_hit, branches_found)
+        );
+        for (index, text) in source.lines().enumerate() {
+            let line = index as u32 + 1;
+            let count = if lines.contains(&line) {
+                match self.line_hits(source_map, line) {
+                    0 => "#####".to_string(),
+                    hits => hits.to_string(),
+                }
+            } else {
+                "-".to_string()
+            };
+            let _ = writeln!(out, "{:>9}: {:>4}: {}", count, line, text);
+        }
+        out
+    }
+}
+
+fn percent(hit: usize, found: usize) -> f64 {
+    if found == 0 {
+        return 100.0;
+    }
+    hit as f64 * 100.0 / found as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assembler::Assembler, vm::VM};
+
+    const TEST_PROGRAM: &str = ".data\n.code\nload $0 #0\nload $1 #2\nload $2 @loop\nload $3 @done\nloop: inc $0\neq $0 $1\njeq $3\njmp $2\ndone: hlt\nhlt";
+
+    fn run_with_coverage() -> (Assembler, VM) {
+        let mut asm = Assembler::new();
+        let program = asm.assemble(TEST_PROGRAM).unwrap();
+        let mut vm = VM::new();
+        vm.add_bytes(program);
+        vm.coverage = Some(Coverage::new());
+        vm.run().unwrap();
+        (asm, vm)
+    }
+
+    #[test]
+    fn test_coverage_hits() {
+        let (asm, vm) = run_with_coverage();
+        let coverage = vm.coverage.as_ref().unwrap();
+        assert_eq!(coverage.line_hits(&asm.source_map, 7), 2);
+        assert_eq!(coverage.line_hits(&asm.source_map, 12), 0);
+        assert_eq!(
+            coverage.branches[&89],
+            BranchCount {
+                taken: 1,
+                not_taken: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_labels_on_their_own_lines() {
+        let source = ".data\n.code\nmain:\nload $0 #0\nload $2 #2\nloop:\nadd $0 $2 $0\nhlt";
+        let mut asm = Assembler::new();
+        let mut vm = VM::new();
+        vm.add_bytes(asm.assemble(source).unwrap());
+        vm.coverage = Some(Coverage::new());
+        vm.run().unwrap();
+        let coverage = vm.coverage.as_ref().unwrap();
+        assert_eq!(coverage.line_hits(&asm.source_map, 3), 0);
+        assert_eq!(coverage.line_hits(&asm.source_map, 4), 1);
+        assert_eq!(coverage.line_hits(&asm.source_map, 6), 0);
+        assert_eq!(coverage.line_hits(&asm.source_map, 7), 1);
+    }
+
+    #[test]
+    fn test_lcov_report() {
+        let (asm, vm) = run_with_coverage();
+        let coverage = vm.coverage.as_ref().unwrap();
+        let lcov = coverage.lcov(&asm.debug_info("test.sy"), &vm.program);
+        assert!(lcov.starts_with("TN:\nSF:test.sy\n"));
+        assert!(lcov.contains("FN:7,loop\nFN:11,done\nFNDA:2,loop\nFNDA:1,done\nFNF:2\nFNH:2\n"));
+        assert!(lcov.contains("BRDA:9,6,0,1\nBRDA:9,6,1,1\nBRF:2\nBRH:2\n"));
+        assert!(lcov.contains("DA:11,1\nDA:12,0\nLF:10\nLH:9\nend_of_record\n"));
+    }
+
+    #[test]
+    fn test_summary() {
+        let (asm, vm) = run_with_coverage();
+        let coverage = vm.coverage.as_ref().unwrap();
+        let summary = coverage.summary(TEST_PROGRAM, &asm.debug_info("test.sy"));
+        let lines: Vec<&str> = summary.lines().collect();
+        assert_eq!(
+            lines[0],
+            "Coverage for test.sy: lines 9/10 (90.00%), branches 2/2 (100.00%)"
+        );
+        assert_eq!(lines[1], "        -:    1: .data");
+        assert_eq!(lines[7], "        2:    7: loop: inc $0");
+        assert_eq!(lines[12], "    #####:   12: hlt");
+    }
+}
@@ -0,0 +1,1071 @@
+pub mod backtrace;
+pub mod container;
+pub mod core_dump;
+pub mod coverage;
+pub mod profiler;
+pub mod trace;
+pub mod vm_errors;
+
+use std::io::{self, Write};
+
+use crate::{
+    assembler::{debug_info::DebugInfo, PIE_HEADER_PREFIX},
+    instruction::Opcode,
+};
+
+use serde::{Deserialize, Serialize};
+
+use self::{
+    core_dump::CoreDump,
+    coverage::Coverage,
+    profiler::Profiler,
+    trace::{TraceEntry, Tracer},
+    vm_errors::VmError,
+};
+
+/// Maximum number of nested calls before the VM reports a stack overflow.
+pub const MAX_CALL_DEPTH: usize = 4096;
+
+/// Pushed by `call` and popped by `ret`.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub struct CallFrame {
+    pub return_address: usize,
+    pub callee: usize,
+}
+
+pub struct VM {
+    pub registers: [i32; 32],
+    pc: usize,
+    instruction_pc: usize,
+    pub program: Vec<u8>,
+    heap: Vec<u8>,
+    remainder: usize,
+    equal_flag: bool,
+    pub call_stack: Vec<CallFrame>,
+    pub ro_data: Vec<u8>,
+    pub tracer: Option<Tracer>,
+    pub profiler: Option<Profiler>,
+    pub coverage: Option<Coverage>,
+    pub debug_info: Option<DebugInfo>,
+    /// Where to write a core dump if the program faults.
+    pub core_file: Option<String>,
+    /// Where `prts` writes, stdout unless replaced.
+    pub output: Box<dyn Write>,
+    /// Where the VM's own messages go, stdout unless replaced.
+    pub messages: Box<dyn Write>,
+}
+
+impl VM {
+    pub fn new() -> VM {
+        VM {
+            registers: [0; 32],
+            program: vec![],
+            heap: vec![],
+            ro_data: vec![],
+            pc: 0,
+            instruction_pc: 0,
+            remainder: 0,
+            equal_flag: false,
+            call_stack: vec![],
+            tracer: None,
+            profiler: None,
+            coverage: None,
+            debug_info: None,
+            core_file: None,
+            output: Box::new(io::stdout()),
+            messages: Box::new(io::stdout()),
+        }
+    }
+
+    pub fn run(&mut self) -> Result<(), VmError> {
+        self.start()?;
+        let result = self.run_until_done();
+        if let Some(tracer) = &mut self.tracer {
+            if let Err(e) = tracer.flush() {
+                println!("Unable to write trace output: {:?}", e);
+            }
+        }
+        if let (Err(e), Some(path)) = (&result, &self.core_file) {
+            match std::fs::write(path, CoreDump::capture(self, e).to_bytes()) {
+                Ok(_) => println!("Core dumped to {}", path),
+                Err(e) => println!("Unable to write core file {}: {:?}", path, e),
+            }
+        }
+        result
+    }
+
+    /// Checks the header and points the VM at the first instruction, ready
+    /// to be driven one instruction at a time with `execute_instructions`.
+    pub fn start(&mut self) -> Result<(), VmError> {
+        if !self.verify_header() {
+            return Err(VmError::InvalidHeader);
+        }
+        self.pc = 65;
+        Ok(())
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: usize) {
+        self.pc = pc;
+    }
+
+    pub fn equal_flag(&self) -> bool {
+        self.equal_flag
+    }
+
+    pub fn set_equal_flag(&mut self, equal_flag: bool) {
+        self.equal_flag = equal_flag;
+    }
+
+    pub fn remainder(&self) -> usize {
+        self.remainder
+    }
+
+    pub fn set_remainder(&mut self, remainder: usize) {
+        self.remainder = remainder;
+    }
+
+    pub fn heap(&self) -> &[u8] {
+        &self.heap
+    }
+
+    pub fn heap_mut(&mut self) -> &mut [u8] {
+        &mut self.heap
+    }
+
+    pub fn run_once(&mut self) {
+        if let Err(e) = self.execute_instructions() {
+            println!("{}", e);
+        }
+    }
+
+    pub fn add_byte(&mut self, b: u8) {
+        self.program.push(b);
+    }
+
+    pub fn add_bytes(&mut self, mut b: Vec<u8>) {
+        self.program.append(&mut b);
+    }
+
+    fn run_until_done(&mut self) -> Result<(), VmError> {
+        let mut is_done = false;
+        while !is_done {
+            is_done = self.execute_instructions()?;
+        }
+        Ok(())
+    }
+
+    pub fn execute_instructions(&mut self) -> Result<bool, VmError> {
+        if self.pc >= self.program.len() {
+            return Ok(true);
+        }
+
+        let pc = self.pc;
+        self.instruction_pc = pc;
+        let pending_trace = if trace::tracing_enabled(&self.tracer) {
+            Some(TraceEntry::begin(
+                pc,
+                &self.program,
+                &self.registers,
+                self.debug_info.as_ref(),
+            ))
+        } else {
+            None
+        };
+
+        let result = self.execute_instruction();
+
+        if let Some((mut entry, opcode)) = pending_trace {
+            entry.finish(&self.registers);
+            trace::emit(&mut self.tracer, &entry, opcode);
+        }
+        let opcode = Opcode::from(self.program[pc]);
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(pc, opcode, self.pc);
+        }
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(pc, opcode, self.pc);
+        }
+        result
+    }
+
+    fn execute_instruction(&mut self) -> Result<bool, VmError> {
+        let opcode = self.decode_opcode();
+        match opcode {
+            Opcode::LOAD => {
+                let register = self.next_register()?;
+                let number = self.next_16_bits()? as u32;
+                self.registers[register] = number as i32;
+            }
+            Opcode::ADD => {
+                let register1 = self.registers[self.next_register()?];
+                let register2 = self.registers[self.next_register()?];
+                self.registers[self.next_register()?] = register1.wrapping_add(register2);
+            }
+            Opcode::SUB => {
+                let register1 = self.registers[self.next_register()?];
+                let register2 = self.registers[self.next_register()?];
+                self.registers[self.next_register()?] = register1.wrapping_sub(register2);
+            }
+            Opcode::MUL => {
+                let register1 = self.registers[self.next_register()?];
+                let register2 = self.registers[self.next_register()?];
+                self.registers[self.next_register()?] = register1.wrapping_mul(register2);
+            }
+            Opcode::DIV => {
+                let register1 = self.registers[self.next_register()?];
+                let register2 = self.registers[self.next_register()?];
+                if register2 == 0 {
+                    return Err(VmError::DivideByZero {
+                        pc: self.instruction_pc,
+                    });
+                }
+                self.registers[self.next_register()?] = register1.wrapping_div(register2);
+                self.remainder = register1.wrapping_rem(register2) as usize;
+            }
+            Opcode::HLT => {
+                let _ = writeln!(self.messages, "HLT encountered");
+                return Ok(true);
+            }
+            Opcode::IGL => {
+                return Err(VmError::IllegalOpcode {
+                    pc: self.instruction_pc,
+                    opcode: self.program[self.instruction_pc],
+                });
+            }
+            Opcode::JMP => {
+                let target = self.registers[self.next_register()?];
+                self.jump_to(target as i64)?;
+            }
+            Opcode::JMPF => {
+                let value = self.registers[self.next_register()?];
+                self.jump_to(self.pc as i64 + value as i64)?;
+            }
+            Opcode::JMPB => {
+                let value = self.registers[self.next_register()?];
+                self.jump_to(self.pc as i64 - value as i64)?;
+            }
+            Opcode::EQ => {
+                let register1 = self.registers[self.next_register()?];
+                let register2 = self.registers[self.next_register()?];
+                self.equal_flag = register1 == register2;
+                self.next_8_bits()?;
+            }
+            Opcode::NEQ => {
+                let register1 = self.registers[self.next_register()?];
+                let register2 = self.registers[self.next_register()?];
+                self.equal_flag = register1 != register2;
+                self.next_8_bits()?;
+            }
+            Opcode::GT => {
+                let register1 = self.registers[self.next_register()?];
+                let register2 = self.registers[self.next_register()?];
+                self.equal_flag = register1 > register2;
+                self.next_8_bits()?;
+            }
+            Opcode::LT => {
+                let register1 = self.registers[self.next_register()?];
+                let register2 = self.registers[self.next_register()?];
+                self.equal_flag = register1 < register2;
+                self.next_8_bits()?;
+            }
+            Opcode::GTE => {
+                let register1 = self.registers[self.next_register()?];
+                let register2 = self.registers[self.next_register()?];
+                self.equal_flag = register1 >= register2;
+                self.next_8_bits()?;
+            }
+            Opcode::LTE => {
+                let register1 = self.registers[self.next_register()?];
+                let register2 = self.registers[self.next_register()?];
+                self.equal_flag = register1 <= register2;
+                self.next_8_bits()?;
+            }
+            Opcode::JEQ => {
+                let target = self.registers[self.next_register()?];
+                if self.equal_flag {
+                    self.jump_to(target as i64)?;
+                } else {
+                    self.next_8_bits()?;
+                    self.next_8_bits()?;
+                }
+            }
+            Opcode::JNEQ => {
+                let target = self.registers[self.next_register()?];
+                if !self.equal_flag {
+                    self.jump_to(target as i64)?;
+                } else {
+                    self.next_8_bits()?;
+                    self.next_8_bits()?;
+                }
+            }
+            Opcode::ALOC => {
+                let register = self.next_register()?;
+                let bytes = self.registers[register];
+                let new_end = self.heap.len() as i64 + bytes as i64;
+                if new_end < 0 {
+                    return Err(VmError::InvalidAllocation {
+                        pc: self.instruction_pc,
+                        bytes,
+                    });
+                }
+                self.heap.resize(new_end as usize, 0);
+                self.next_8_bits()?;
+                self.next_8_bits()?;
+            }
+            Opcode::INC => {
+                let register_number = self.next_register()?;
+                self.registers[register_number] = self.registers[register_number].wrapping_add(1);
+                self.next_8_bits()?;
+                self.next_8_bits()?;
+            }
+            Opcode::DEC => {
+                let register_number = self.next_register()?;
+                self.registers[register_number] = self.registers[register_number].wrapping_sub(1);
+                self.next_8_bits()?;
+                self.next_8_bits()?;
+            }
+            Opcode::DJMPE => {
+                let destination = self.next_8_bits()?;
+                if self.equal_flag {
+                    self.pc = destination as usize;
+                } else {
+                    self.next_8_bits()?;
+                    self.next_8_bits()?;
+                }
+            }
+            Opcode::NOP => {
+                self.next_8_bits()?;
+                self.next_8_bits()?;
+                self.next_8_bits()?;
+            }
+            Opcode::CALL => {
+                let target = self.registers[self.next_register()?];
+                self.next_8_bits()?;
+                self.next_8_bits()?;
+                if self.call_stack.len() >= MAX_CALL_DEPTH {
+                    return Err(VmError::CallStackOverflow {
+                        pc: self.instruction_pc,
+                    });
+                }
+                let return_address = self.pc;
+                self.jump_to(target as i64)?;
+                self.call_stack.push(CallFrame {
+                    return_address,
+                    callee: target as usize,
+                });
+            }
+            Opcode::RET => match self.call_stack.pop() {
+                Some(frame) => self.pc = frame.return_address,
+                None => {
+                    return Err(VmError::ReturnWithoutCall {
+                        pc: self.instruction_pc,
+                    })
+                }
+            },
+            Opcode::LB => {
+                let register = self.next_register()?;
+                let address = self.heap_address()?;
+                self.registers[register] = self.heap[address] as i32;
+                self.next_8_bits()?;
+            }
+            Opcode::SB => {
+                let value = self.registers[self.next_register()?];
+                let address = self.heap_address()?;
+                self.heap[address] = value as u8;
+                self.next_8_bits()?;
+            }
+            Opcode::LBR => {
+                let register = self.next_register()?;
+                let offset = self.registers[self.next_register()?];
+                match usize::try_from(offset)
+                    .ok()
+                    .and_then(|o| self.ro_data.get(o))
+                {
+                    Some(byte) => self.registers[register] = *byte as i32,
+                    None => {
+                        return Err(VmError::RoDataOutOfBounds {
+                            pc: self.instruction_pc,
+                            offset: offset as usize,
+                        })
+                    }
+                }
+                self.next_8_bits()?;
+            }
+            Opcode::ADDI
+            | Opcode::SUBI
+            | Opcode::MULI
+            | Opcode::DIVI
+            | Opcode::ANDI
+            | Opcode::ORI => {
+                let register = self.next_register()?;
+                let immediate = self.next_16_bits()?;
+                let value = self.registers[register];
+                let signed = immediate as i16 as i32;
+                self.registers[register] = match opcode {
+                    Opcode::ADDI => value.wrapping_add(signed),
+                    Opcode::SUBI => value.wrapping_sub(signed),
+                    Opcode::MULI => value.wrapping_mul(signed),
+                    Opcode::DIVI => {
+                        if signed == 0 {
+                            return Err(VmError::DivideByZero {
+                                pc: self.instruction_pc,
+                            });
+                        }
+                        self.remainder = value.wrapping_rem(signed) as usize;
+                        value.wrapping_div(signed)
+                    }
+                    Opcode::ANDI => value & immediate as i32,
+                    _ => value | immediate as i32,
+                };
+            }
+            Opcode::EQI
+            | Opcode::NEQI
+            | Opcode::GTI
+            | Opcode::LTI
+            | Opcode::GTEI
+            | Opcode::LTEI => {
+                let value = self.registers[self.next_register()?];
+                let immediate = self.next_16_bits()? as i16 as i32;
+                self.equal_flag = match opcode {
+                    Opcode::EQI => value == immediate,
+                    Opcode::NEQI => value != immediate,
+                    Opcode::GTI => value > immediate,
+                    Opcode::LTI => value < immediate,
+                    Opcode::GTEI => value >= immediate,
+                    _ => value <= immediate,
+                };
+            }
+            Opcode::DIVU | Opcode::REMU => {
+                let register1 = self.registers[self.next_register()?] as u32;
+                let register2 = self.registers[self.next_register()?] as u32;
+                if register2 == 0 {
+                    return Err(VmError::DivideByZero {
+                        pc: self.instruction_pc,
+                    });
+                }
+                let result = match opcode {
+                    Opcode::DIVU => {
+                        self.remainder = (register1 % register2) as usize;
+                        register1 / register2
+                    }
+                    _ => register1 % register2,
+                };
+                self.registers[self.next_register()?] = result as i32;
+            }
+            Opcode::GTU | Opcode::LTU | Opcode::GTEU | Opcode::LTEU => {
+                let register1 = self.registers[self.next_register()?] as u32;
+                let register2 = self.registers[self.next_register()?] as u32;
+                self.equal_flag = match opcode {
+                    Opcode::GTU => register1 > register2,
+                    Opcode::LTU => register1 < register2,
+                    Opcode::GTEU => register1 >= register2,
+                    _ => register1 <= register2,
+                };
+                self.next_8_bits()?;
+            }
+            Opcode::SHL | Opcode::SHR | Opcode::SAR => {
+                let value = self.registers[self.next_register()?];
+                // Only the low five bits of the shift count are used.
+                let shift = self.registers[self.next_register()?] as u32;
+                self.registers[self.next_register()?] = match opcode {
+                    Opcode::SHL => value.wrapping_shl(shift),
+                    Opcode::SHR => (value as u32).wrapping_shr(shift) as i32,
+                    _ => value.wrapping_shr(shift),
+                };
+            }
+            Opcode::PRTS => {
+                let starting_point = self.next_16_bits()? as usize;
+                self.next_8_bits()?;
+                let slice = self.ro_data.as_slice();
+                if starting_point >= slice.len() {
+                    return Err(VmError::RoDataOutOfBounds {
+                        pc: self.instruction_pc,
+                        offset: starting_point,
+                    });
+                }
+
+                let ending_offset = match slice[starting_point..].iter().position(|b| *b == 0) {
+                    Some(length) => starting_point + length,
+                    None => slice.len(),
+                };
+                let result = std::str::from_utf8(&slice[starting_point..ending_offset]);
+                match result {
+                    Ok(s) => {
+                        let _ = write!(self.output, "{}", s);
+                    }
+                    Err(_) => {
+                        return Err(VmError::InvalidString {
+                            pc: self.instruction_pc,
+                            offset: starting_point,
+                        });
+                    }
+                };
+            }
+        }
+        Ok(false)
+    }
+
+    /// The heap address held by the next register operand.
+    fn heap_address(&mut self) -> Result<usize, VmError> {
+        let address = self.registers[self.next_register()?];
+        match usize::try_from(address) {
+            Ok(index) if index < self.heap.len() => Ok(index),
+            _ => Err(VmError::HeapOutOfBounds {
+                pc: self.instruction_pc,
+                address,
+            }),
+        }
+    }
+
+    fn decode_opcode(&mut self) -> Opcode {
+        let opcode = Opcode::from(self.program[self.pc]);
+        self.pc += 1;
+        opcode
+    }
+
+    fn next_8_bits(&mut self) -> Result<u8, VmError> {
+        let result = match self.program.get(self.pc) {
+            Some(byte) => *byte,
+            None => {
+                return Err(VmError::UnexpectedEndOfProgram {
+                    pc: self.instruction_pc,
+                })
+            }
+        };
+        self.pc += 1;
+        Ok(result)
+    }
+
+    fn next_16_bits(&mut self) -> Result<u16, VmError> {
+        let high = self.next_8_bits()? as u16;
+        let low = self.next_8_bits()? as u16;
+        Ok((high << 8) | low)
+    }
+
+    fn next_register(&mut self) -> Result<usize, VmError> {
+        let register = self.next_8_bits()?;
+        if register as usize >= self.registers.len() {
+            return Err(VmError::InvalidRegister {
+                pc: self.instruction_pc,
+                register,
+            });
+        }
+        Ok(register as usize)
+    }
+
+    fn jump_to(&mut self, target: i64) -> Result<(), VmError> {
+        if target < 0 {
+            return Err(VmError::InvalidJump {
+                pc: self.instruction_pc,
+                target,
+            });
+        }
+        self.pc = target as usize;
+        Ok(())
+    }
+
+    fn verify_header(&self) -> bool {
+        // println!("{:?}", self.program);
+        if self.program.len() < 4 || self.program[0..4] != PIE_HEADER_PREFIX {
+            return false;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use crate::assembler::PIE_HEADER_LENGTH;
+
+    use super::*;
+
+    fn get_test_vm() -> VM {
+        let mut test_vm = VM::new();
+        test_vm.registers[0] = 5;
+        test_vm.registers[1] = 10;
+        test_vm.pc = 64;
+        test_vm
+    }
+
+    fn prepend_header(mut b: Vec<u8>) -> Vec<u8> {
+        let mut prepension = vec![];
+        for byte in PIE_HEADER_PREFIX.into_iter() {
+            prepension.push(byte.clone());
+        }
+        while prepension.len() < PIE_HEADER_LENGTH {
+            prepension.push(0);
+        }
+        prepension.append(&mut b);
+        prepension
+    }
+
+    #[test]
+    fn test_create_vm() {
+        let test_vm = VM::new();
+        assert_eq!(test_vm.registers[0], 0)
+    }
+
+    #[test]
+    fn test_opcode_hlt() {
+        let mut test_vm = VM::new();
+        let test_bytes = vec![5, 0, 0, 0];
+        test_vm.program = test_bytes;
+        test_vm.run_once();
+        assert_eq!(test_vm.pc, 1);
+    }
+
+    #[test]
+    fn test_opcode_igl() {
+        let mut test_vm = VM::new();
+        let test_bytes = vec![200, 0, 0, 0];
+        test_vm.program = test_bytes;
+        test_vm.run_once();
+        assert_eq!(test_vm.pc, 1);
+    }
+
+    #[test]
+    fn test_load_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![0, 0, 1, 244];
+        test_vm.program = prepend_header(test_vm.program);
+        test_vm.run_once();
+        assert_eq!(test_vm.registers[0], 500);
+    }
+
+    #[test]
+    fn test_add_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![1, 0, 1, 2];
+        test_vm.program = prepend_header(test_vm.program);
+        println!("{:?}", &test_vm.program);
+        test_vm.run_once();
+        assert_eq!(test_vm.registers[2], 15);
+    }
+
+    #[test]
+    fn test_sub_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![2, 1, 0, 2];
+        test_vm.program = prepend_header(test_vm.program);
+        test_vm.run_once();
+        assert_eq!(test_vm.registers[2], 5);
+    }
+
+    #[test]
+    fn test_mul_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![3, 0, 1, 2];
+        test_vm.program = prepend_header(test_vm.program);
+        test_vm.run_once();
+        assert_eq!(test_vm.registers[2], 50);
+    }
+
+    #[test]
+    fn test_div_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![4, 1, 0, 2];
+        test_vm.program = prepend_header(test_vm.program);
+        test_vm.run_once();
+        assert_eq!(test_vm.registers[2], 2);
+    }
+
+    #[test]
+    fn test_jmp_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 4;
+        test_vm.program = vec![6, 0, 0, 0];
+        test_vm.program = prepend_header(test_vm.program);
+        test_vm.run_once();
+        assert_eq!(test_vm.pc, 4);
+    }
+
+    #[test]
+    fn test_jmpf_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 2;
+        test_vm.program = vec![7, 0, 0, 0, 6, 0, 0, 0];
+        test_vm.program = prepend_header(test_vm.program);
+        test_vm.run_once();
+        assert_eq!(test_vm.pc, 4 + 64);
+    }
+
+    #[test]
+    fn test_jmpb_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[1] = 6;
+        test_vm.program = vec![0, 0, 0, 10, 9, 1, 0, 0];
+        test_vm.program = prepend_header(test_vm.program);
+        test_vm.run_once();
+        assert_eq!(test_vm.pc, 0 + 68);
+    }
+
+    #[test]
+    fn test_eq_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 10;
+        test_vm.registers[1] = 10;
+        test_vm.program = vec![10, 0, 1, 0, 10, 0, 1, 0];
+        test_vm.program = prepend_header(test_vm.program);
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, true);
+        test_vm.registers[1] = 20;
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, false);
+    }
+
+    #[test]
+    fn test_neq_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 10;
+        test_vm.registers[1] = 11;
+        test_vm.program = vec![11, 0, 1, 0, 11, 0, 1, 0];
+        test_vm.program = prepend_header(test_vm.program);
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, true);
+        test_vm.registers[1] = 10;
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, false);
+    }
+
+    #[test]
+    fn test_gt_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 20;
+        test_vm.registers[1] = 10;
+        test_vm.program = vec![12, 0, 1, 0, 12, 0, 1, 0, 12, 0, 1, 0];
+        test_vm.program = prepend_header(test_vm.program);
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, true);
+        test_vm.registers[0] = 10;
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, false);
+        test_vm.registers[0] = 5;
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, false);
+    }
+
+    #[test]
+    fn test_lt_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 10;
+        test_vm.registers[1] = 9;
+        test_vm.program = vec![13, 0, 1, 0, 13, 0, 1, 0];
+        test_vm.program = prepend_header(test_vm.program);
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, false);
+        test_vm.registers[1] = 10;
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, false);
+    }
+
+    #[test]
+    fn test_gtq_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 10;
+        test_vm.registers[1] = 20;
+        test_vm.program = vec![14, 0, 1, 0, 14, 0, 1, 0, 14, 0, 1, 0];
+        test_vm.program = prepend_header(test_vm.program);
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, false);
+        test_vm.registers[1] = 10;
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, true);
+        test_vm.registers[1] = 5;
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, true);
+    }
+
+    #[test]
+    fn test_ltq_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 20;
+        test_vm.registers[1] = 10;
+        test_vm.program = vec![15, 0, 1, 0, 15, 0, 1, 0, 15, 0, 1, 0];
+        test_vm.program = prepend_header(test_vm.program);
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, false);
+        test_vm.registers[0] = 5;
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, true);
+        test_vm.registers[0] = 10;
+        test_vm.run_once();
+        assert_eq!(test_vm.equal_flag, true);
+    }
+
+    #[test]
+    fn test_jeq_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 7;
+        test_vm.equal_flag = true;
+        test_vm.program = vec![16, 0, 0, 0, 6, 0, 0, 0];
+        test_vm.program = prepend_header(test_vm.program);
+        test_vm.run_once();
+        assert_eq!(test_vm.pc, 7);
+    }
+
+    #[test]
+    fn test_jeq_opcode_not_taken() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 7;
+        test_vm.equal_flag = false;
+        test_vm.program = vec![16, 0, 0, 0, 5, 0, 0, 0];
+        test_vm.program = prepend_header(test_vm.program);
+        test_vm.run_once();
+        assert_eq!(test_vm.pc, 68);
+    }
+
+    #[test]
+    fn test_div_by_zero() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[1] = 0;
+        test_vm.program = vec![4, 0, 1, 2];
+        test_vm.program = prepend_header(test_vm.program);
+        assert_eq!(
+            test_vm.execute_instructions(),
+            Err(VmError::DivideByZero { pc: 64 })
+        );
+    }
+
+    #[test]
+    fn test_invalid_register() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![1, 0, 40, 2];
+        test_vm.program = prepend_header(test_vm.program);
+        assert_eq!(
+            test_vm.execute_instructions(),
+            Err(VmError::InvalidRegister {
+                pc: 64,
+                register: 40
+            })
+        );
+    }
+
+    #[test]
+    fn test_truncated_instruction() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![0, 0];
+        test_vm.program = prepend_header(test_vm.program);
+        assert_eq!(
+            test_vm.execute_instructions(),
+            Err(VmError::UnexpectedEndOfProgram { pc: 64 })
+        );
+    }
+
+    #[test]
+    fn test_illegal_opcode_error() {
+        let mut test_vm = VM::new();
+        test_vm.program = vec![200, 0, 0, 0];
+        assert_eq!(
+            test_vm.execute_instructions(),
+            Err(VmError::IllegalOpcode { pc: 0, opcode: 200 })
+        );
+    }
+
+    #[test]
+    fn test_call_and_ret_opcodes() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 72;
+        test_vm.program = vec![24, 0, 0, 0, 5, 0, 0, 0, 25, 0, 0, 0];
+        test_vm.program = prepend_header(test_vm.program);
+        test_vm.run_once();
+        assert_eq!(test_vm.pc, 72);
+        assert_eq!(
+            test_vm.call_stack,
+            vec![CallFrame {
+                return_address: 68,
+                callee: 72
+            }]
+        );
+        test_vm.run_once();
+        assert_eq!(test_vm.pc, 68);
+        assert!(test_vm.call_stack.is_empty());
+    }
+
+    #[test]
+    fn test_call_to_invalid_target() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = -4;
+        test_vm.program = prepend_header(vec![24, 0, 0, 0]);
+        assert_eq!(
+            test_vm.execute_instructions(),
+            Err(VmError::InvalidJump { pc: 64, target: -4 })
+        );
+        assert!(test_vm.call_stack.is_empty());
+    }
+
+    #[test]
+    fn test_ret_without_call() {
+        let mut test_vm = get_test_vm();
+        test_vm.program = vec![25, 0, 0, 0];
+        test_vm.program = prepend_header(test_vm.program);
+        assert_eq!(
+            test_vm.execute_instructions(),
+            Err(VmError::ReturnWithoutCall { pc: 64 })
+        );
+    }
+
+    #[test]
+    fn test_jneq_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 7;
+        test_vm.equal_flag = false;
+        test_vm.program = vec![17, 0, 0, 0, 6, 0, 0, 0];
+        test_vm.program = prepend_header(test_vm.program);
+        test_vm.run_once();
+        assert_eq!(test_vm.pc, 7);
+    }
+
+    #[test]
+    fn test_aloc_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[0] = 1024;
+        test_vm.program = vec![18, 0, 0, 0];
+        test_vm.program = prepend_header(test_vm.program);
+        test_vm.run_once();
+        assert_eq!(test_vm.heap.len(), 1024);
+    }
+
+    #[test]
+    fn test_byte_memory_opcodes() {
+        let mut test_vm = get_test_vm();
+        test_vm.heap = vec![0; 8];
+        test_vm.ro_data = vec![7, 42];
+        test_vm.registers[2] = 3;
+        test_vm.registers[3] = 1;
+        test_vm.registers[4] = 300;
+        test_vm.program = vec![27, 4, 2, 0, 26, 5, 2, 0, 28, 6, 3, 0];
+        test_vm.program = prepend_header(test_vm.program);
+        for _ in 0..3 {
+            test_vm.execute_instructions().unwrap();
+        }
+        assert_eq!(test_vm.heap[3], 44);
+        assert_eq!(test_vm.registers[5], 44);
+        assert_eq!(test_vm.registers[6], 42);
+
+        test_vm.registers[2] = 8;
+        test_vm.program.extend([26, 5, 2, 0]);
+        assert_eq!(
+            test_vm.execute_instructions(),
+            Err(VmError::HeapOutOfBounds { pc: 76, address: 8 })
+        );
+    }
+
+    #[test]
+    fn test_byte_memory_edge_cases() {
+        let mut test_vm = get_test_vm();
+        test_vm.heap = vec![0xff, 0];
+        test_vm.ro_data = vec![0x80];
+        test_vm.registers[0] = 0;
+        test_vm.registers[1] = 1;
+        test_vm.registers[4] = 0x1234_5680;
+        test_vm.registers[7] = -1;
+        // lb $5 $0, sb $4 $1, lb $6 $1, lbr $8 $0
+        test_vm.program = prepend_header(vec![26, 5, 0, 0, 27, 4, 1, 0, 26, 6, 1, 0, 28, 8, 0, 0]);
+        for _ in 0..4 {
+            test_vm.execute_instructions().unwrap();
+        }
+        // Bytes are loaded as 0 to 255, not sign-extended.
+        assert_eq!(test_vm.registers[5], 255);
+        assert_eq!(test_vm.heap[1], 0x80);
+        assert_eq!(test_vm.registers[6], 128);
+        assert_eq!(test_vm.registers[8], 128);
+
+        // sb $0 $7, lb $0 $7 and lbr $0 $1 with $7 = -1 and $1 = 1.
+        for (instruction, error) in [
+            (
+                [27, 0, 7, 0],
+                VmError::HeapOutOfBounds {
+                    pc: 64,
+                    address: -1,
+                },
+            ),
+            (
+                [26, 0, 7, 0],
+                VmError::HeapOutOfBounds {
+                    pc: 64,
+                    address: -1,
+                },
+            ),
+            (
+                [28, 0, 1, 0],
+                VmError::RoDataOutOfBounds { pc: 64, offset: 1 },
+            ),
+        ] {
+            test_vm.program = prepend_header(instruction.to_vec());
+            test_vm.pc = 64;
+            assert_eq!(test_vm.execute_instructions(), Err(error));
+        }
+        assert_eq!(test_vm.heap, [0xff, 0x80]);
+    }
+
+    #[test]
+    fn test_immediate_opcodes() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[1] = 10;
+        test_vm.registers[2] = 0x0ff0;
+        // addi $1 #-3, muli $1 #4, divi $1 #5, andi $2 #0xff, ori $2 #0x8000,
+        // lti $1 #6
+        test_vm.program = vec![
+            29, 1, 255, 253, 31, 1, 0, 4, 32, 1, 0, 5, 33, 2, 0, 255, 34, 2, 128, 0, 38, 1, 0, 6,
+        ];
+        test_vm.program = prepend_header(test_vm.program);
+        for _ in 0..6 {
+            test_vm.execute_instructions().unwrap();
+        }
+        assert_eq!(test_vm.registers[1], 5);
+        assert_eq!(test_vm.remainder, 3);
+        assert_eq!(test_vm.registers[2], 0x80f0);
+        assert!(test_vm.equal_flag);
+
+        test_vm.program.extend([32, 1, 0, 0]);
+        assert_eq!(
+            test_vm.execute_instructions(),
+            Err(VmError::DivideByZero { pc: 88 })
+        );
+    }
+
+    #[test]
+    fn test_unsigned_opcodes() {
+        let mut test_vm = get_test_vm();
+        test_vm.registers[1] = -8;
+        test_vm.registers[2] = 3;
+        test_vm.registers[3] = 1;
+        // divu $1 $2 $4, remu $1 $2 $5, gtu $1 $2, shr $1 $3 $6, sar $1 $3 $7,
+        // shl $2 $3 $8
+        test_vm.program = vec![
+            41, 1, 2, 4, 42, 1, 2, 5, 43, 1, 2, 0, 48, 1, 3, 6, 49, 1, 3, 7, 47, 2, 3, 8,
+        ];
+        test_vm.program = prepend_header(test_vm.program);
+        for _ in 0..6 {
+            test_vm.execute_instructions().unwrap();
+        }
+        assert_eq!(test_vm.registers[4], ((u32::MAX - 7) / 3) as i32);
+        assert_eq!(test_vm.remainder, 2);
+        assert_eq!(test_vm.registers[5], 2);
+        assert!(test_vm.equal_flag);
+        assert_eq!(test_vm.registers[6], 0x7fff_fffc);
+        assert_eq!(test_vm.registers[7], -4);
+        assert_eq!(test_vm.registers[8], 6);
+    }
+
+    #[test]
+    fn test_prts_opcode() {
+        let mut test_vm = get_test_vm();
+        test_vm.pc = 5;
+        test_vm.ro_data.append(&mut vec![72, 101, 108, 108, 111, 0]);
+        test_vm.program = vec![23, 0, 0, 0];
+        let mut output_buffer = Vec::new();
+        let _ = std::io::stdout().lock().write_all(&mut output_buffer);
+        let _ = std::io::stdout().lock().flush();
+        test_vm.run_once();
+        let output_str = std::str::from_utf8(&output_buffer).expect("Invalid output buffer");
+        println!("{:?} test", &output_buffer);
+        assert_eq!(output_str, "Hello");
+    }
+}
@@ -0,0 +1,208 @@
+use std::{collections::HashMap, fmt::Write};
+
+use crate::{
+    assembler::debug_info::DebugInfo, disassembler::DecodedInstruction, instruction::Opcode,
+};
+
+const UNLABELED: &str = "[unlabeled]";
+
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct BranchCount {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+/// Collects execution counts while the VM runs. Addresses are attributed to
+/// the closest code label at or before them.
+#[derive(Debug, Default)]
+pub struct Profiler {
+    pub cycles: u64,
+    pub by_opcode: HashMap<Opcode, u64>,
+    pub by_pc: HashMap<usize, u64>,
+    pub branches: HashMap<usize, BranchCount>,
+    labels: Vec<(String, usize)>,
+}
+
+impl Profiler {
+    pub fn new(mut labels: Vec<(String, usize)>) -> Profiler {
+        labels.sort_by_key(|(_, offset)| *offset);
+        Profiler {
+            labels,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_debug_info(debug_info: &DebugInfo) -> Profiler {
+        let labels = debug_info
+            .code_labels()
+            .into_iter()
+            .map(|(name, offset)| (name, offset as usize))
+            .collect();
+        Profiler::new(labels)
+    }
+
+    /// Records one executed instruction at `pc`. `next_pc` is where execution
+    /// continues and decides whether a jump was taken.
+    pub fn record(&mut self, pc: usize, opcode: Opcode, next_pc: usize) {
+        self.cycles += 1;
+        *self.by_opcode.entry(opcode).or_insert(0) += 1;
+        *self.by_pc.entry(pc).or_insert(0) += 1;
+
+        if opcode.is_jump() {
+            let count = self.branches.entry(pc).or_default();
+            if next_pc == pc + 4 {
+                count.not_taken += 1;
+            } else {
+                count.taken += 1;
+            }
+        }
+    }
+
+    pub fn label_for(&self, pc: usize) -> &str {
+        self.labels
+            .iter()
+            .take_while(|(_, offset)| *offset <= pc)
+            .last()
+            .map(|(name, _)| name.as_str())
+            .unwrap_or(UNLABELED)
+    }
+
+    pub fn by_label(&self) -> Vec<(String, u64)> {
+        let mut counts: HashMap<&str, u64> = HashMap::new();
+        for (pc, count) in &self.by_pc {
+            *counts.entry(self.label_for(*pc)).or_insert(0) += count;
+        }
+        sorted_by_count(counts.into_iter().map(|(k, v)| (k.to_string(), v)))
+    }
+
+    /// Human readable hot-spot report, hottest entries first.
+    pub fn report(&self, program: &[u8]) -> String {
+        let mut out = String::new();
+        let percent = |count: u64| count as f64 * 100.0 / self.cycles.max(1) as f64;
+
+        let _ = writeln!(out, "Total cycles: {}", self.cycles);
+
+        let _ = writeln!(out, "\nHot spots by address:");
+        let by_pc = sorted_by_count(self.by_pc.iter().map(|(pc, count)| (*pc, *count)));
+        for (pc, count) in by_pc {
+            let instruction = DecodedInstruction::decode(&program[pc..]);
+            let _ = writeln!(
+                out,
+                "{:>10} {:>6.2}%  {:#06x}  {:<16} {}",
+                count,
+                percent(count),
+                pc,
+                self.label_for(pc),
+                instruction
+            );
+        }
+
+        let _ = writeln!(out, "\nHot spots by label:");
+        for (label, count) in self.by_label() {
+            let _ = writeln!(out, "{:>10} {:>6.2}%  {}", count, percent(count), label);
+        }
+
+        let _ = writeln!(out, "\nOpcodes:");
+        let by_opcode = sorted_by_count(self.by_opcode.iter().map(|(op, count)| (*op, *count)));
+        for (opcode, count) in by_opcode {
+            let _ = writeln!(
+                out,
+                "{:>10} {:>6.2}%  {}",
+                count,
+                percent(count),
+                opcode.mnemonic()
+            );
+        }
+
+        if !self.branches.is_empty() {
+            let _ = writeln!(out, "\nBranches:");
+            let mut branches: Vec<(&usize, &BranchCount)> = self.branches.iter().collect();
+            branches.sort_by_key(|(pc, _)| **pc);
+            for (pc, count) in branches {
+                let instruction = DecodedInstruction::decode(&program[*pc..]);
+                let _ = writeln!(
+                    out,
+                    "  {:#06x}  {:<16} taken {:>8}  not taken {:>8}",
+                    pc, instruction, count.taken, count.not_taken
+                );
+            }
+        }
+        out
+    }
+
+    /// Folded stacks, one `frame;frame count` line per stack, as consumed by
+    /// flamegraph tooling.
+    pub fn collapsed_stacks(&self) -> String {
+        let mut out = String::new();
+        let mut labels = self.by_label();
+        labels.sort();
+        for (label, count) in labels {
+            let _ = writeln!(out, "{} {}", label, count);
+        }
+        out
+    }
+}
+
+fn sorted_by_count<K: Ord>(counts: impl Iterator<Item = (K, u64)>) -> Vec<(K, u64)> {
+    let mut counts: Vec<(K, u64)> = counts.collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assembler::Assembler, vm::VM};
+
+    #[test]
+    fn test_record_counts() {
+        let mut profiler = Profiler::new(vec![("loop".to_string(), 69)]);
+        profiler.record(65, Opcode::LOAD, 69);
+        profiler.record(69, Opcode::JEQ, 65);
+        profiler.record(65, Opcode::LOAD, 69);
+        profiler.record(69, Opcode::JEQ, 73);
+        assert_eq!(profiler.cycles, 4);
+        assert_eq!(profiler.by_pc[&65], 2);
+        assert_eq!(profiler.by_opcode[&Opcode::JEQ], 2);
+        assert_eq!(
+            profiler.branches[&69],
+            BranchCount {
+                taken: 1,
+                not_taken: 1
+            }
+        );
+        assert_eq!(profiler.label_for(65), UNLABELED);
+        assert_eq!(profiler.label_for(80), "loop");
+        assert_eq!(profiler.collapsed_stacks(), "[unlabeled] 2\nloop 2\n");
+    }
+
+    #[test]
+    fn test_profile_program() {
+        let mut asm = Assembler::new();
+        let program = asm
+            .assemble(".data\n.code\nload $0 #0\nload $1 #3\nload $2 @loop\nloop: inc $0\nneq $0 $1\njeq $2\nhlt")
+            .unwrap();
+        let mut vm = VM::new();
+        vm.add_bytes(program);
+        vm.profiler = Some(Profiler::with_debug_info(&asm.debug_info("test.sy")));
+        vm.run().unwrap();
+
+        let profiler = vm.profiler.as_ref().unwrap();
+        assert_eq!(profiler.cycles, 13);
+        assert_eq!(profiler.by_opcode[&Opcode::INC], 3);
+        assert_eq!(
+            profiler.branches[&85],
+            BranchCount {
+                taken: 2,
+                not_taken: 1
+            }
+        );
+        assert_eq!(
+            profiler.by_label(),
+            vec![("loop".to_string(), 10), (UNLABELED.to_string(), 3)]
+        );
+        assert!(profiler
+            .report(&vm.program)
+            .contains("0x0055  loop             jeq $2"));
+    }
+}
@@ -0,0 +1,301 @@
+use std::{
+    fmt,
+    io::{self, Write},
+    ops::Range,
+    str::FromStr,
+};
+
+use log::{log_enabled, trace, Level};
+use serde::Serialize;
+
+use crate::{
+    assembler::debug_info::DebugInfo, disassembler::DecodedInstruction, instruction::Opcode,
+};
+
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum TraceFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(TraceFormat::Text),
+            "json" => Ok(TraceFormat::Json),
+            _ => Err(format!("Unknown trace format {}, expected text or json", s)),
+        }
+    }
+}
+
+/// Restricts which executed instructions end up in the trace. An empty
+/// filter lets everything through.
+#[derive(Debug, Default, Clone)]
+pub struct TraceFilter {
+    pub range: Option<Range<usize>>,
+    pub opcodes: Vec<Opcode>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, pc: usize, opcode: Opcode) -> bool {
+        if let Some(range) = &self.range {
+            if !range.contains(&pc) {
+                return false;
+            }
+        }
+        self.opcodes.is_empty() || self.opcodes.contains(&opcode)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct RegisterChange {
+    pub register: u8,
+    pub before: i32,
+    pub after: i32,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct TraceEntry {
+    pub pc: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    pub instruction: String,
+    pub registers: Vec<RegisterChange>,
+}
+
+impl TraceEntry {
+    /// Captures the state before the instruction at `pc` executes. The
+    /// `after` values are filled in by `finish`.
+    pub fn begin(
+        pc: usize,
+        program: &[u8],
+        registers: &[i32],
+        debug_info: Option<&DebugInfo>,
+    ) -> (TraceEntry, Opcode) {
+        let decoded = DecodedInstruction::decode(&program[pc..]);
+        let mut changes: Vec<RegisterChange> = vec![];
+        for register in decoded.registers() {
+            if changes.iter().any(|c| c.register == register) {
+                continue;
+            }
+            let value = registers.get(register as usize).copied().unwrap_or(0);
+            changes.push(RegisterChange {
+                register,
+                before: value,
+                after: value,
+            });
+        }
+        let entry = TraceEntry {
+            pc,
+            location: debug_info.and_then(|info| info.location(pc as u32)),
+            instruction: decoded.to_symbolic_string(pc, debug_info),
+            registers: changes,
+        };
+        (entry, decoded.opcode)
+    }
+
+    pub fn finish(&mut self, registers: &[i32]) {
+        for change in &mut self.registers {
+            change.after = registers
+                .get(change.register as usize)
+                .copied()
+                .unwrap_or(0);
+        }
+    }
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let instruction = match &self.location {
+            Some(location) => format!("{}: {}", location, self.instruction),
+            None => self.instruction.clone(),
+        };
+        let mut line = format!("{:#06x}  {:<32}", self.pc, instruction);
+        for change in &self.registers {
+            line.push_str(&format!(
+                " ${}: {} -> {}",
+                change.register, change.before, change.after
+            ));
+        }
+        f.write_str(line.trim_end())
+    }
+}
+
+/// Writes one line per executed instruction to `output`.
+pub struct Tracer {
+    format: TraceFormat,
+    filter: TraceFilter,
+    output: Box<dyn Write>,
+}
+
+impl Tracer {
+    pub fn new(format: TraceFormat, filter: TraceFilter, output: Box<dyn Write>) -> Tracer {
+        Tracer {
+            format,
+            filter,
+            output,
+        }
+    }
+
+    pub fn stderr(format: TraceFormat, filter: TraceFilter) -> Tracer {
+        Tracer::new(format, filter, Box::new(io::stderr()))
+    }
+
+    pub fn wants(&self, pc: usize, opcode: Opcode) -> bool {
+        self.filter.matches(pc, opcode)
+    }
+
+    pub fn record(&mut self, entry: &TraceEntry) -> io::Result<()> {
+        match self.format {
+            TraceFormat::Text => writeln!(self.output, "{}", entry),
+            TraceFormat::Json => {
+                serde_json::to_writer(&mut self.output, entry)?;
+                writeln!(self.output)
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// Whether per-instruction trace entries should be built at all, either for
+/// an attached `Tracer` or for the `log` crate at trace level.
+pub fn tracing_enabled(tracer: &Option<Tracer>) -> bool {
+    tracer.is_some() || log_enabled!(Level::Trace)
+}
+
+pub fn emit(tracer: &mut Option<Tracer>, entry: &TraceEntry, opcode: Opcode) {
+    match tracer {
+        Some(tracer) => {
+            if tracer.wants(entry.pc, opcode) {
+                if let Err(e) = tracer.record(entry) {
+                    println!("Unable to write trace output: {:?}", e);
+                }
+            }
+        }
+        None => trace!("{}", entry),
+    }
+}
+
+/// Parses an address range given as `start..end`, where either bound may be
+/// decimal or `0x` prefixed hex.
+pub fn parse_range(s: &str) -> Result<Range<usize>, String> {
+    let (start, end) = s
+        .split_once("..")
+        .ok_or_else(|| format!("Invalid address range {}, expected start..end", s))?;
+    Ok(parse_address(start)?..parse_address(end)?)
+}
+
+fn parse_address(s: &str) -> Result<usize, String> {
+    let s = s.trim();
+    let result = match s.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => s.parse::<usize>(),
+    };
+    result.map_err(|_| format!("Invalid address {}", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::VM;
+    use std::{cell::RefCell, rc::Rc};
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_trace_entry_registers() {
+        let mut registers = [0; 32];
+        registers[0] = 5;
+        registers[1] = 10;
+        let (mut entry, opcode) = TraceEntry::begin(0, &[1, 0, 1, 2], &registers, None);
+        assert_eq!(opcode, Opcode::ADD);
+        registers[2] = 15;
+        entry.finish(&registers);
+        assert_eq!(entry.instruction, "add $0 $1 $2");
+        assert_eq!(
+            entry.registers[2],
+            RegisterChange {
+                register: 2,
+                before: 0,
+                after: 15
+            }
+        );
+    }
+
+    #[test]
+    fn test_trace_filter() {
+        let filter = TraceFilter {
+            range: Some(65..73),
+            opcodes: vec![Opcode::LOAD],
+        };
+        assert!(filter.matches(65, Opcode::LOAD));
+        assert!(!filter.matches(65, Opcode::ADD));
+        assert!(!filter.matches(73, Opcode::LOAD));
+        assert!(TraceFilter::default().matches(1000, Opcode::HLT));
+    }
+
+    #[test]
+    fn test_tracer_json_lines() {
+        let buffer = SharedBuffer::default();
+        let mut tracer = Tracer::new(
+            TraceFormat::Json,
+            TraceFilter::default(),
+            Box::new(buffer.clone()),
+        );
+        let (mut entry, _) = TraceEntry::begin(0, &[0, 0, 0, 100], &[0; 32], None);
+        entry.finish(&[100; 32]);
+        tracer.record(&entry).unwrap();
+        let output = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+        assert_eq!(
+            output,
+            "{\"pc\":0,\"instruction\":\"load $0 #100\",\"registers\":[{\"register\":0,\"before\":0,\"after\":100}]}\n"
+        );
+    }
+
+    #[test]
+    fn test_vm_trace_respects_filter() {
+        let buffer = SharedBuffer::default();
+        let filter = TraceFilter {
+            range: None,
+            opcodes: vec![Opcode::ADD],
+        };
+        let mut vm = VM::new();
+        vm.program = vec![0, 0, 0, 7, 1, 0, 0, 1, 5, 0, 0, 0];
+        vm.tracer = Some(Tracer::new(
+            TraceFormat::Text,
+            filter,
+            Box::new(buffer.clone()),
+        ));
+        vm.run_once();
+        vm.run_once();
+        vm.run_once();
+        let output = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+        assert_eq!(output.lines().count(), 1);
+        assert!(output.starts_with("0x0004  add $0 $0 $1"));
+        assert!(output.ends_with("$0: 7 -> 7 $1: 0 -> 14\n"));
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("65..0x50"), Ok(65..80));
+        assert!(parse_range("65").is_err());
+        assert!(parse_range("a..b").is_err());
+    }
+}
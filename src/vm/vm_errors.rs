@@ -0,0 +1,106 @@
+use std::{error::Error, fmt};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub enum VmError {
+    InvalidHeader,
+    CorruptProgram { reason: String },
+    IllegalOpcode { pc: usize, opcode: u8 },
+    UnexpectedEndOfProgram { pc: usize },
+    InvalidRegister { pc: usize, register: u8 },
+    DivideByZero { pc: usize },
+    InvalidJump { pc: usize, target: i64 },
+    InvalidAllocation { pc: usize, bytes: i32 },
+    RoDataOutOfBounds { pc: usize, offset: usize },
+    HeapOutOfBounds { pc: usize, address: i32 },
+    InvalidString { pc: usize, offset: usize },
+    ReturnWithoutCall { pc: usize },
+    CallStackOverflow { pc: usize },
+}
+
+impl VmError {
+    /// Address of the instruction that faulted, if the error happened while
+    /// executing one.
+    pub fn pc(&self) -> Option<usize> {
+        match *self {
+            VmError::InvalidHeader | VmError::CorruptProgram { .. } => None,
+            VmError::IllegalOpcode { pc, .. }
+            | VmError::UnexpectedEndOfProgram { pc }
+            | VmError::InvalidRegister { pc, .. }
+            | VmError::DivideByZero { pc }
+            | VmError::InvalidJump { pc, .. }
+            | VmError::InvalidAllocation { pc, .. }
+            | VmError::RoDataOutOfBounds { pc, .. }
+            | VmError::HeapOutOfBounds { pc, .. }
+            | VmError::InvalidString { pc, .. }
+            | VmError::ReturnWithoutCall { pc }
+            | VmError::CallStackOverflow { pc } => Some(pc),
+        }
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VmError::InvalidHeader => f.write_str("Header incorrect"),
+            VmError::CorruptProgram { ref reason } => {
+                f.write_str(&format!("The program file is corrupt: {}", reason))
+            }
+            VmError::IllegalOpcode { opcode, .. } => f.write_str(&format!(
+                "Illegal instruction encountered. Opcode was {}",
+                opcode
+            )),
+            VmError::UnexpectedEndOfProgram { .. } => {
+                f.write_str("The program ended in the middle of an instruction")
+            }
+            VmError::InvalidRegister { register, .. } => {
+                f.write_str(&format!("Invalid register ${}", register))
+            }
+            VmError::DivideByZero { .. } => f.write_str("Division by zero"),
+            VmError::InvalidJump { target, .. } => {
+                f.write_str(&format!("Jump to invalid address {}", target))
+            }
+            VmError::InvalidAllocation { bytes, .. } => {
+                f.write_str(&format!("Heap cannot be resized by {} bytes", bytes))
+            }
+            VmError::RoDataOutOfBounds { offset, .. } => f.write_str(&format!(
+                "Read-only data offset {} is out of bounds",
+                offset
+            )),
+            VmError::HeapOutOfBounds { address, .. } => {
+                f.write_str(&format!("Heap address {} is out of bounds", address))
+            }
+            VmError::InvalidString { offset, .. } => f.write_str(&format!(
+                "String at read-only data offset {} is not valid UTF-8",
+                offset
+            )),
+            VmError::ReturnWithoutCall { .. } => {
+                f.write_str("ret executed with an empty call stack")
+            }
+            VmError::CallStackOverflow { .. } => f.write_str("Call stack overflow"),
+        }
+    }
+}
+
+impl Error for VmError {
+    fn description(&self) -> &str {
+        match self {
+            VmError::InvalidHeader => "The program header is incorrect",
+            VmError::CorruptProgram { .. } => "The program file is corrupt",
+            VmError::IllegalOpcode { .. } => "Illegal instruction encountered",
+            VmError::UnexpectedEndOfProgram { .. } => {
+                "The program ended in the middle of an instruction"
+            }
+            VmError::InvalidRegister { .. } => "Invalid register",
+            VmError::DivideByZero { .. } => "Division by zero",
+            VmError::InvalidJump { .. } => "Jump to an invalid address",
+            VmError::InvalidAllocation { .. } => "Invalid heap allocation",
+            VmError::RoDataOutOfBounds { .. } => "Read-only data offset out of bounds",
+            VmError::HeapOutOfBounds { .. } => "Heap address out of bounds",
+            VmError::InvalidString { .. } => "String is not valid UTF-8",
+            VmError::ReturnWithoutCall { .. } => "ret executed with an empty call stack",
+            VmError::CallStackOverflow { .. } => "Call stack overflow",
+        }
+    }
+}
@@ -0,0 +1,79 @@
+//! Replays a recorded Debug Adapter Protocol session against `synthia dap`.
+
+use std::{
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use serde_json::Value;
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/dap");
+
+/// Loads a recording, one `{"send": ...}` or `{"expect": ...}` message per
+/// line, with `${PROGRAM}` standing for the program under test.
+fn load_recording(name: &str, program: &Path) -> (Vec<Value>, Vec<Value>) {
+    let recording = std::fs::read_to_string(Path::new(FIXTURES).join(name)).unwrap();
+    let program = serde_json::to_string(program.to_str().unwrap()).unwrap();
+    let recording = recording.replace("\"${PROGRAM}\"", &program);
+
+    let (mut sent, mut expected) = (vec![], vec![]);
+    for line in recording.lines().filter(|line| !line.trim().is_empty()) {
+        let mut entry: Value = serde_json::from_str(line).unwrap();
+        if let Some(message) = entry.get_mut("send") {
+            sent.push(message.take());
+        } else if let Some(message) = entry.get_mut("expect") {
+            expected.push(message.take());
+        } else {
+            panic!("Recording line is neither send nor expect: {}", line);
+        }
+    }
+    (sent, expected)
+}
+
+fn parse_messages(mut output: &[u8]) -> Vec<Value> {
+    let mut messages = vec![];
+    while !output.is_empty() {
+        let header_end = output
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .expect("Message without a header");
+        let header = std::str::from_utf8(&output[..header_end]).unwrap();
+        let length: usize = header
+            .strip_prefix("Content-Length: ")
+            .expect("Header without Content-Length")
+            .parse()
+            .unwrap();
+        let body = &output[header_end + 4..header_end + 4 + length];
+        messages.push(serde_json::from_slice(body).unwrap());
+        output = &output[header_end + 4 + length..];
+    }
+    messages
+}
+
+#[test]
+fn test_recorded_breakpoint_session() {
+    let program = Path::new(FIXTURES).join("square.sy");
+    let (sent, expected) = load_recording("square_session.jsonl", &program);
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_synthia"))
+        .arg("dap")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    for message in &sent {
+        let body = message.to_string();
+        write!(stdin, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+    }
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+
+    let received = parse_messages(&output.stdout);
+    assert_eq!(received.len(), expected.len());
+    for (received, expected) in received.iter().zip(&expected) {
+        assert_eq!(received, expected);
+    }
+}